@@ -0,0 +1,240 @@
+//! Native messaging host manifests
+//!
+//! Browsers only start a native messaging host named by a manifest in their
+//! own per-user directory, and only for the extensions the manifest allows.
+//! Chromium-based browsers identify an unpacked extension by an ID derived
+//! from its path, so that ID has to be passed in; Firefox's is fixed.
+
+use anyhow::{bail, Context, Result};
+use colored::Colorize;
+use fingerpain_core::browsers::{FIREFOX_EXTENSION_ID, NATIVE_HOST_NAME};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+
+/// Where one browser looks for host manifests
+struct Target {
+    browser: &'static str,
+    /// Browser's own directory; the browser counts as installed if it exists
+    root: PathBuf,
+    /// Manifest directory inside `root`
+    dir: &'static str,
+    firefox: bool,
+}
+
+#[cfg(target_os = "linux")]
+fn targets(home: &Path) -> Vec<Target> {
+    let config = home.join(".config");
+    let chromium = |browser, root: &str| Target {
+        browser,
+        root: config.join(root),
+        dir: "NativeMessagingHosts",
+        firefox: false,
+    };
+    vec![
+        chromium("Chrome", "google-chrome"),
+        chromium("Chromium", "chromium"),
+        chromium("Brave", "BraveSoftware/Brave-Browser"),
+        chromium("Edge", "microsoft-edge"),
+        Target {
+            browser: "Firefox",
+            root: home.join(".mozilla"),
+            dir: "native-messaging-hosts",
+            firefox: true,
+        },
+    ]
+}
+
+#[cfg(target_os = "macos")]
+fn targets(home: &Path) -> Vec<Target> {
+    let support = home.join("Library/Application Support");
+    let target = |browser, root: &str, firefox| Target {
+        browser,
+        root: support.join(root),
+        dir: "NativeMessagingHosts",
+        firefox,
+    };
+    vec![
+        target("Chrome", "Google/Chrome", false),
+        target("Chromium", "Chromium", false),
+        target("Brave", "BraveSoftware/Brave-Browser", false),
+        target("Edge", "Microsoft Edge", false),
+        target("Firefox", "Mozilla", true),
+    ]
+}
+
+/// Windows finds manifests through the registry, which the user is told to update
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn targets(_home: &Path) -> Vec<Target> {
+    let root = fingerpain_core::data_dir();
+    let target = |browser, dir, firefox| Target {
+        browser,
+        root: root.clone(),
+        dir,
+        firefox,
+    };
+    vec![
+        target("Chrome", "native-messaging/chrome", false),
+        target("Firefox", "native-messaging/firefox", true),
+    ]
+}
+
+/// Manifest allowing `extension_id` (ignored for Firefox) to start the host
+fn manifest(host: &Path, firefox: bool, extension_id: &str) -> Value {
+    let mut manifest = json!({
+        "name": NATIVE_HOST_NAME,
+        "description": "FingerPain browser context",
+        "path": host,
+        "type": "stdio",
+    });
+    if firefox {
+        manifest["allowed_extensions"] = json!([FIREFOX_EXTENSION_ID]);
+    } else {
+        manifest["allowed_origins"] = json!([format!("chrome-extension://{}/", extension_id)]);
+    }
+    manifest
+}
+
+/// Chromium extension IDs are 32 letters from a to p
+fn is_extension_id(id: &str) -> bool {
+    id.len() == 32 && id.bytes().all(|b| (b'a'..=b'p').contains(&b))
+}
+
+/// Host binary installed next to this one
+fn host_path() -> Result<PathBuf> {
+    let host = std::env::current_exe()?.with_file_name(format!(
+        "fingerpain-browser-host{}",
+        std::env::consts::EXE_SUFFIX
+    ));
+    if !host.exists() {
+        bail!(
+            "{} not found; build it with `cargo build --release -p fingerpain-host`",
+            host.display()
+        );
+    }
+    Ok(host)
+}
+
+fn home_dir() -> Result<PathBuf> {
+    directories::BaseDirs::new()
+        .map(|dirs| dirs.home_dir().to_path_buf())
+        .context("could not find the home directory")
+}
+
+/// Write the manifest for every installed browser, plus into `extra_dir`
+/// for a Chromium-based browser that isn't known here
+pub fn install(extension_id: Option<&str>, extra_dir: Option<&Path>) -> Result<()> {
+    if let Some(id) = extension_id {
+        if !is_extension_id(id) {
+            bail!(
+                "'{}' is not an extension ID (32 letters a-p, see chrome://extensions)",
+                id
+            );
+        }
+    }
+    let host = host_path()?;
+
+    let mut targets = targets(&home_dir()?);
+    if let Some(dir) = extra_dir {
+        targets.push(Target {
+            browser: "Custom",
+            root: dir.to_path_buf(),
+            dir: "",
+            firefox: false,
+        });
+    }
+
+    let mut installed = 0;
+    for target in targets {
+        if !target.root.exists() {
+            continue;
+        }
+        let id = match (target.firefox, extension_id) {
+            (true, _) => "",
+            (false, Some(id)) => id,
+            (false, None) => {
+                println!(
+                    "{} {} skipped: pass --extension-id from its extensions page",
+                    "-".yellow(),
+                    target.browser
+                );
+                continue;
+            }
+        };
+
+        let dir = target.root.join(target.dir);
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("{}.json", NATIVE_HOST_NAME));
+        let text = serde_json::to_string_pretty(&manifest(&host, target.firefox, id))?;
+        std::fs::write(&path, text + "\n")
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        println!("{} {}: {}", "✓".green(), target.browser, path.display());
+        installed += 1;
+
+        if cfg!(windows) {
+            let key = if target.firefox {
+                "Mozilla\\NativeMessagingHosts"
+            } else {
+                "Google\\Chrome\\NativeMessagingHosts"
+            };
+            println!(
+                "  register it with: reg add \"HKCU\\Software\\{}\\{}\" /ve /t REG_SZ /d \"{}\" /f",
+                key,
+                NATIVE_HOST_NAME,
+                path.display()
+            );
+        }
+    }
+
+    if installed == 0 {
+        println!("{}", "No browser manifest was installed".yellow());
+    } else {
+        println!("Reload the extension for it to use the host.");
+    }
+    Ok(())
+}
+
+/// Remove every manifest this command may have written
+pub fn uninstall() -> Result<()> {
+    for target in targets(&home_dir()?) {
+        let path = target
+            .root
+            .join(target.dir)
+            .join(format!("{}.json", NATIVE_HOST_NAME));
+        if path.exists() {
+            std::fs::remove_file(&path)?;
+            println!(
+                "{} {}: removed {}",
+                "✓".green(),
+                target.browser,
+                path.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifests() {
+        let host = Path::new("/usr/bin/fingerpain-browser-host");
+        let id = "abcdefghijklmnopabcdefghijklmnop";
+        assert!(is_extension_id(id));
+        assert!(!is_extension_id("not-an-id"));
+
+        let chrome = manifest(host, false, id);
+        assert_eq!(chrome["name"], NATIVE_HOST_NAME);
+        assert_eq!(chrome["path"], "/usr/bin/fingerpain-browser-host");
+        assert_eq!(
+            chrome["allowed_origins"][0],
+            format!("chrome-extension://{}/", id)
+        );
+        assert!(chrome.get("allowed_extensions").is_none());
+
+        let firefox = manifest(host, true, "");
+        assert_eq!(firefox["allowed_extensions"][0], FIREFOX_EXTENSION_ID);
+        assert!(firefox.get("allowed_origins").is_none());
+    }
+}
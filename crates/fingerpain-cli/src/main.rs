@@ -2,18 +2,24 @@
 //!
 //! Command-line interface for viewing typing statistics.
 
+mod browser_host;
+
 use anyhow::Result;
 use chrono::{DateTime, NaiveDate, Utc};
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use colored::Colorize;
 use fingerpain_core::{
-    db::Database,
+    config::Config,
+    control::{ControlClient, ControlError},
+    db::{Database, RecordFilter},
+    domains::DomainLevel,
     export::{ExportFormat, Exporter},
     metrics::{Metrics, TimeRange},
 };
+use fingerpain_listener::TitleRules;
 use std::fs::File;
-use std::io::{self, Write};
-use std::path::PathBuf;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
 use tabled::{settings::Style, Table, Tabled};
 
 #[derive(Parser)]
@@ -23,6 +29,10 @@ use tabled::{settings::Style, Table, Tabled};
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// Only count typing tagged with this project
+    #[arg(long, global = true)]
+    tag: Option<String>,
 }
 
 #[derive(Subcommand)]
@@ -66,6 +76,56 @@ enum Commands {
         /// Time range (today, week, month, year, all)
         #[arg(short, long, default_value = "week")]
         range: String,
+
+        /// List the busiest domains under each browser
+        #[arg(short, long)]
+        domains: bool,
+
+        /// With --domains, list hosts instead of registrable domains
+        #[arg(long, requires = "domains")]
+        hosts: bool,
+    },
+
+    /// Rank domains typed in across all browsers
+    Domains {
+        /// Time range (today, week, month, year, all)
+        #[arg(short, long, default_value = "week")]
+        range: String,
+
+        /// Number of domains to show
+        #[arg(short, long, default_value = "20")]
+        limit: usize,
+
+        /// List hosts (mail.google.com) instead of registrable domains (google.com)
+        #[arg(long)]
+        hosts: bool,
+    },
+
+    /// Show typing by category (Coding, Communication, Writing, ...)
+    Categories {
+        /// Time range (today, week, month, year, all)
+        #[arg(short, long, default_value = "week")]
+        range: String,
+    },
+
+    /// Show typing per project, from editor plugins or window titles
+    Projects {
+        /// Time range (today, week, month, year, all)
+        #[arg(short, long, default_value = "week")]
+        range: String,
+    },
+
+    /// Show typing per programming language reported by editor plugins
+    Langs {
+        /// Time range (today, week, month, year, all)
+        #[arg(short, long, default_value = "week")]
+        range: String,
+    },
+
+    /// Try out the `[projects]` window-title rules
+    Rules {
+        #[command(subcommand)]
+        command: RulesCommand,
     },
 
     /// Export data to CSV or JSON
@@ -95,6 +155,120 @@ enum Commands {
 
     /// Stop the daemon
     Stop,
+
+    /// Pause recording, e.g. `pause 30m` or `pause 1h30m` (until resumed if omitted)
+    Pause {
+        /// How long to pause for (s, m, h units)
+        duration: Option<String>,
+    },
+
+    /// Resume recording after a pause
+    Resume,
+
+    /// Tag typing with a project, e.g. `tag start client-x`
+    Tag {
+        #[command(subcommand)]
+        command: TagCommand,
+    },
+
+    /// Pair the browser extension or issue tokens for the web API
+    Auth {
+        #[command(subcommand)]
+        command: AuthCommand,
+    },
+
+    /// Show, edit or validate the configuration file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+
+    /// Print the prompt hook for a shell, e.g. `eval "$(fingerpain init zsh)"`
+    Init { shell: Shell },
+
+    /// Let the browser extension reach the daemon without fingerpain-web
+    InstallBrowserHost {
+        /// ID of the unpacked extension, from chrome://extensions (not needed for Firefox)
+        #[arg(long)]
+        extension_id: Option<String>,
+        /// Also install into this NativeMessagingHosts directory, for other browsers
+        #[arg(long)]
+        dir: Option<PathBuf>,
+        /// Remove the manifests instead
+        #[arg(long)]
+        uninstall: bool,
+    },
+
+    /// Tell the daemon which git project the shell is in (run by the prompt hook)
+    #[command(hide = true)]
+    ShellHook {
+        /// Directory to look in (defaults to the current one)
+        #[arg(long)]
+        dir: Option<PathBuf>,
+        /// Process ID of the shell, to tell open shells apart
+        #[arg(long)]
+        pid: Option<u32>,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+#[derive(Subcommand)]
+enum TagCommand {
+    /// Tag typing from now on (several tags can be active)
+    Start {
+        /// Tag name: letters, digits, '-', '_' or '.'
+        tag: String,
+    },
+    /// Stop one tag, or all of them if none is given
+    Stop { tag: Option<String> },
+    /// Show typing per tag
+    List {
+        /// Time range (today, week, month, year, all)
+        #[arg(short, long, default_value = "week")]
+        range: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum AuthCommand {
+    /// Print a one-time code to enter in the browser extension's options
+    Pair,
+    /// Create a token for a script or editor plugin, replacing one of the same name
+    Token { name: String },
+    /// List tokens and when they were last used
+    List,
+    /// Revoke a token
+    Revoke { name: String },
+}
+
+#[derive(Subcommand)]
+enum RulesCommand {
+    /// Show the project each sample title gets, without touching any data
+    Test {
+        /// Window titles to try (read one per line from stdin if none given)
+        titles: Vec<String>,
+        /// App name or ID the titles belong to, for rules limited to an app
+        #[arg(long)]
+        app: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print the effective configuration, including environment overrides
+    Show,
+    /// Open the config file in $VISUAL/$EDITOR, creating it if needed
+    Edit,
+    /// Check the config file and environment overrides for errors
+    Validate,
+    /// Make the running daemon re-read the config now
+    Reload,
 }
 
 #[derive(Tabled)]
@@ -109,6 +283,8 @@ struct StatRow {
 struct AppRow {
     #[tabled(rename = "App")]
     app: String,
+    #[tabled(rename = "Category")]
+    category: String,
     #[tabled(rename = "Characters")]
     chars: String,
     #[tabled(rename = "Words")]
@@ -117,6 +293,94 @@ struct AppRow {
     percentage: String,
 }
 
+#[derive(Tabled)]
+struct TokenRow {
+    #[tabled(rename = "Name")]
+    name: String,
+    #[tabled(rename = "Extension")]
+    origin: String,
+    #[tabled(rename = "Created")]
+    created: String,
+    #[tabled(rename = "Last used")]
+    last_used: String,
+}
+
+#[derive(Tabled)]
+struct DomainRow {
+    #[tabled(rename = "Domain")]
+    domain: String,
+    #[tabled(rename = "Characters")]
+    chars: String,
+    #[tabled(rename = "Words")]
+    words: String,
+    #[tabled(rename = "%")]
+    percentage: String,
+}
+
+#[derive(Tabled)]
+struct CategoryRow {
+    #[tabled(rename = "Category")]
+    category: String,
+    #[tabled(rename = "Characters")]
+    chars: String,
+    #[tabled(rename = "Words")]
+    words: String,
+    #[tabled(rename = "%")]
+    percentage: String,
+    #[tabled(rename = "Top apps")]
+    apps: String,
+}
+
+#[derive(Tabled)]
+struct TagRow {
+    #[tabled(rename = "Tag")]
+    tag: String,
+    #[tabled(rename = "Characters")]
+    chars: String,
+    #[tabled(rename = "Words")]
+    words: String,
+    #[tabled(rename = "Time")]
+    time: String,
+    #[tabled(rename = "%")]
+    percentage: String,
+}
+
+#[derive(Tabled)]
+struct ProjectRow {
+    #[tabled(rename = "Project")]
+    project: String,
+    #[tabled(rename = "Characters")]
+    chars: String,
+    #[tabled(rename = "Words")]
+    words: String,
+    #[tabled(rename = "Time")]
+    time: String,
+    #[tabled(rename = "%")]
+    percentage: String,
+}
+
+#[derive(Tabled)]
+struct LanguageRow {
+    #[tabled(rename = "Language")]
+    language: String,
+    #[tabled(rename = "Characters")]
+    chars: String,
+    #[tabled(rename = "Words")]
+    words: String,
+    #[tabled(rename = "Time")]
+    time: String,
+    #[tabled(rename = "%")]
+    percentage: String,
+}
+
+#[derive(Tabled)]
+struct RuleTestRow {
+    #[tabled(rename = "Title")]
+    title: String,
+    #[tabled(rename = "Project")]
+    project: String,
+}
+
 #[derive(Tabled)]
 struct PeakRow {
     #[tabled(rename = "Time")]
@@ -130,9 +394,68 @@ struct PeakRow {
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
+    // Config commands must work even when the config is broken
+    if let Commands::Config { command } = &cli.command {
+        return match command {
+            ConfigCommand::Show => show_config(),
+            ConfigCommand::Edit => edit_config(),
+            ConfigCommand::Validate => validate_config(),
+            ConfigCommand::Reload => reload_config(),
+        };
+    }
+    // Neither needs the config or database; prompt hooks must also be quick
+    match &cli.command {
+        Commands::Init { shell } => {
+            print!("{}", shell_init(*shell));
+            return Ok(());
+        }
+        Commands::ShellHook { dir, pid } => {
+            shell_hook(dir.clone(), *pid);
+            return Ok(());
+        }
+        Commands::InstallBrowserHost {
+            extension_id,
+            dir,
+            uninstall,
+        } => {
+            return if *uninstall {
+                browser_host::uninstall()
+            } else {
+                browser_host::install(extension_id.as_deref(), dir.as_deref())
+            };
+        }
+        _ => {}
+    }
+    let config = Config::load()?;
+    if let Commands::Rules {
+        command: RulesCommand::Test { titles, app },
+    } = &cli.command
+    {
+        return test_rules(&config, titles, app.as_deref());
+    }
+    if let Some(tag) = &cli.tag {
+        if !fingerpain_core::is_valid_tag(tag) {
+            anyhow::bail!("Invalid tag '{}': use letters, digits, '-', '_' or '.'", tag);
+        }
+    }
+    let filter = RecordFilter {
+        tag: cli.tag.clone(),
+        ..RecordFilter::default()
+    };
+
     // Open database
-    let db = Database::open_default()?;
-    let metrics = Metrics::new(&db);
+    let db = Database::open(config.db_path())?;
+    let metrics = Metrics::new(&db)
+        .with_app_rules(config.app_classifier())
+        .with_filter(filter.clone())
+        .with_browsers(config.browser_registry());
+    let domain_level = |hosts| {
+        if hosts {
+            DomainLevel::Host
+        } else {
+            DomainLevel::Site
+        }
+    };
 
     match cli.command {
         Commands::Today => show_stats(&metrics, TimeRange::Today, "Today"),
@@ -166,9 +489,39 @@ fn main() -> Result<()> {
             show_peak(&metrics, time_range, limit)
         }
 
-        Commands::Apps { range } => {
+        Commands::Apps {
+            range,
+            domains,
+            hosts,
+        } => {
+            let time_range = TimeRange::parse(&range).unwrap_or(TimeRange::ThisWeek);
+            let metrics = metrics.with_domains(config.domain_groups(), domain_level(hosts));
+            show_apps(&metrics, time_range, domains)
+        }
+
+        Commands::Domains {
+            range,
+            limit,
+            hosts,
+        } => {
+            let time_range = TimeRange::parse(&range).unwrap_or(TimeRange::ThisWeek);
+            let metrics = metrics.with_domains(config.domain_groups(), domain_level(hosts));
+            show_domains(&metrics, time_range, limit)
+        }
+
+        Commands::Categories { range } => {
             let time_range = TimeRange::parse(&range).unwrap_or(TimeRange::ThisWeek);
-            show_apps(&metrics, time_range)
+            show_categories(&metrics, time_range)
+        }
+
+        Commands::Projects { range } => {
+            let time_range = TimeRange::parse(&range).unwrap_or(TimeRange::ThisWeek);
+            show_projects(&metrics, time_range)
+        }
+
+        Commands::Langs { range } => {
+            let time_range = TimeRange::parse(&range).unwrap_or(TimeRange::ThisWeek);
+            show_languages(&metrics, time_range)
         }
 
         Commands::Export {
@@ -178,8 +531,10 @@ fn main() -> Result<()> {
             summary,
         } => {
             let time_range = TimeRange::parse(&range).unwrap_or(TimeRange::AllTime);
-            let export_format = ExportFormat::from_str(&format).unwrap_or(ExportFormat::Json);
-            let exporter = Exporter::new(&db);
+            let export_format = ExportFormat::parse(&format).unwrap_or(ExportFormat::Json);
+            let exporter = Exporter::new(&db)
+                .with_filter(filter)
+                .with_domains(config.browser_registry(), config.domain_groups());
 
             let writer: Box<dyn Write> = match output {
                 Some(path) => Box::new(File::create(path)?),
@@ -198,6 +553,36 @@ fn main() -> Result<()> {
         Commands::Status => show_daemon_status(),
         Commands::Start => start_daemon(),
         Commands::Stop => stop_daemon(),
+        Commands::Pause { duration } => pause_daemon(duration.as_deref()),
+        Commands::Resume => resume_daemon(),
+        Commands::Tag { command } => match command {
+            TagCommand::Start { tag } => start_tag(&tag),
+            TagCommand::Stop { tag } => stop_tag(tag.as_deref()),
+            TagCommand::List { range } => {
+                let time_range = TimeRange::parse(&range).unwrap_or(TimeRange::ThisWeek);
+                show_tags(&metrics, time_range)
+            }
+        },
+        Commands::Auth { command } => match command {
+            AuthCommand::Pair => pair_extension(&db, &config),
+            AuthCommand::Token { name } => create_token(&db, &name),
+            AuthCommand::List => list_tokens(&db),
+            AuthCommand::Revoke { name } => {
+                if db.revoke_api_token(&name)? {
+                    println!("{} Revoked '{}'", "✓".green(), name);
+                } else {
+                    println!("{}", format!("No token named '{}'", name).yellow());
+                }
+                Ok(())
+            }
+        },
+        Commands::Config { .. }
+        | Commands::Rules { .. }
+        | Commands::Init { .. }
+        | Commands::ShellHook { .. }
+        | Commands::InstallBrowserHost { .. } => {
+            unreachable!("handled before opening the database")
+        }
     }
 }
 
@@ -250,10 +635,22 @@ fn show_stats(metrics: &Metrics, range: TimeRange, label: &str) -> Result<()> {
     let table = Table::new(rows).with(Style::rounded()).to_string();
     println!("{}", table);
 
+    if stats.paused_minutes > 0 || stats.excluded_minutes > 0 {
+        println!(
+            "{}",
+            format!(
+                "Not recorded: {} paused, {} excluded",
+                Metrics::format_duration(stats.paused_minutes),
+                Metrics::format_duration(stats.excluded_minutes)
+            )
+            .dimmed()
+        );
+    }
+
     Ok(())
 }
 
-fn show_apps(metrics: &Metrics, range: TimeRange) -> Result<()> {
+fn show_apps(metrics: &Metrics, range: TimeRange, domains: bool) -> Result<()> {
     let apps = metrics.app_stats(range)?;
 
     if apps.is_empty() {
@@ -264,14 +661,89 @@ fn show_apps(metrics: &Metrics, range: TimeRange) -> Result<()> {
     println!("\n{}", "📱 App Breakdown".bold().cyan());
     println!("{}", "─".repeat(60));
 
-    let rows: Vec<AppRow> = apps
-        .into_iter()
-        .take(10)
-        .map(|app| AppRow {
+    let mut rows = Vec::new();
+    for app in apps.into_iter().take(10) {
+        rows.push(AppRow {
             app: app.app_name,
+            category: app.category.as_str().to_string(),
             chars: Metrics::format_chars(app.total_chars),
             words: Metrics::format_words(app.total_words),
             percentage: format!("{:.1}%", app.percentage),
+        });
+        if !domains {
+            continue;
+        }
+        // Percentages of a domain are of its browser
+        for domain in app.browser_domains.into_iter().flatten().take(5) {
+            rows.push(AppRow {
+                app: format!("  → {}", domain.domain),
+                category: String::new(),
+                chars: Metrics::format_chars(domain.total_chars),
+                words: Metrics::format_words(domain.total_words),
+                percentage: format!("{:.1}%", domain.percentage),
+            });
+        }
+    }
+
+    let table = Table::new(rows).with(Style::rounded()).to_string();
+    println!("{}", table);
+
+    Ok(())
+}
+
+fn show_domains(metrics: &Metrics, range: TimeRange, limit: usize) -> Result<()> {
+    let domains = metrics.domain_stats(range)?;
+
+    if domains.is_empty() {
+        println!("\n{}", "No browser typing in this period.".yellow());
+        return Ok(());
+    }
+
+    println!("\n{}", "🌐 Typing by Domain".bold().cyan());
+    println!("{}", "─".repeat(60));
+
+    let rows: Vec<DomainRow> = domains
+        .into_iter()
+        .take(limit)
+        .map(|domain| DomainRow {
+            domain: domain.domain,
+            chars: Metrics::format_chars(domain.total_chars),
+            words: Metrics::format_words(domain.total_words),
+            percentage: format!("{:.1}%", domain.percentage),
+        })
+        .collect();
+
+    let table = Table::new(rows).with(Style::rounded()).to_string();
+    println!("{}", table);
+
+    Ok(())
+}
+
+fn show_categories(metrics: &Metrics, range: TimeRange) -> Result<()> {
+    let categories = metrics.category_stats(range)?;
+
+    if categories.is_empty() {
+        println!("\n{}", "No app data available for this period.".yellow());
+        return Ok(());
+    }
+
+    println!("\n{}", "🗂  Category Breakdown".bold().cyan());
+    println!("{}", "─".repeat(60));
+
+    let rows: Vec<CategoryRow> = categories
+        .into_iter()
+        .map(|category| CategoryRow {
+            category: category.category.as_str().to_string(),
+            chars: Metrics::format_chars(category.total_chars),
+            words: Metrics::format_words(category.total_words),
+            percentage: format!("{:.1}%", category.percentage),
+            apps: category
+                .apps
+                .iter()
+                .take(3)
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(", "),
         })
         .collect();
 
@@ -308,132 +780,541 @@ fn show_peak(metrics: &Metrics, range: TimeRange, limit: usize) -> Result<()> {
 }
 
 fn show_daemon_status() -> Result<()> {
-    // Check if daemon is running by looking for PID file or process
-    #[cfg(unix)]
-    {
-        use std::process::Command;
+    let status = match ControlClient::connect().and_then(|mut client| client.status()) {
+        Ok(status) => status,
+        Err(ControlError::NotRunning) => {
+            println!("{}", "✗ Daemon is not running".red());
+            return Ok(());
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    if let Some(until) = status.paused_until {
+        let until = until.with_timezone(&chrono::Local).format("%H:%M");
+        println!(
+            "{} (PID: {})",
+            format!("⏸ Daemon is paused until {}", until).yellow(),
+            status.pid
+        );
+    } else if status.paused {
+        println!("{} (PID: {})", "⏸ Daemon is paused".yellow(), status.pid);
+    } else {
+        println!("{} (PID: {})", "✓ Daemon is running".green(), status.pid);
+    }
 
-        let output = Command::new("pgrep")
-            .args(["-f", "fingerpain-daemon"])
-            .output();
+    let last_flush = status
+        .last_flush
+        .map(|t| {
+            t.with_timezone(&chrono::Local)
+                .format("%H:%M:%S")
+                .to_string()
+        })
+        .unwrap_or_else(|| "never".to_string());
+    let rows = vec![
+        StatRow {
+            metric: "Version".to_string(),
+            value: status.version,
+        },
+        StatRow {
+            metric: "Uptime".to_string(),
+            value: Metrics::format_duration((status.uptime_secs / 60) as u32),
+        },
+        StatRow {
+            metric: "Events Seen".to_string(),
+            value: status.events_seen.to_string(),
+        },
+        StatRow {
+            metric: "Events Dropped".to_string(),
+            value: status.events_dropped.to_string(),
+        },
+        StatRow {
+            metric: "Last Flush".to_string(),
+            value: last_flush,
+        },
+        StatRow {
+            metric: "Current App".to_string(),
+            value: status.current_app.unwrap_or_else(|| "-".to_string()),
+        },
+        StatRow {
+            metric: "Current WPM".to_string(),
+            value: format!("{:.1}", status.current_wpm),
+        },
+        StatRow {
+            metric: "Recording".to_string(),
+            value: match status.not_recording {
+                Some(reason) => format!("no ({})", reason.as_str()),
+                None => "yes".to_string(),
+            },
+        },
+        StatRow {
+            metric: "Tags".to_string(),
+            value: if status.tags.is_empty() {
+                "-".to_string()
+            } else {
+                status.tags.join(", ")
+            },
+        },
+    ];
 
-        match output {
-            Ok(out) if !out.stdout.is_empty() => {
-                let pid = String::from_utf8_lossy(&out.stdout);
-                println!("{} (PID: {})", "✓ Daemon is running".green(), pid.trim());
-            }
-            _ => {
-                println!("{}", "✗ Daemon is not running".red());
-            }
-        }
+    let table = Table::new(rows).with(Style::rounded()).to_string();
+    println!("{}", table);
+
+    Ok(())
+}
+
+fn start_daemon() -> Result<()> {
+    use std::process::Command;
+
+    // Without a control socket (Windows), the daemon's instance lock keeps a
+    // second copy from starting
+    if ControlClient::connect().is_ok() {
+        println!("{}", "Daemon is already running".yellow());
+        return Ok(());
     }
 
+    #[cfg(unix)]
+    let result = Command::new("fingerpain-daemon").spawn();
+
     #[cfg(windows)]
-    {
-        use std::process::Command;
+    let result = Command::new("cmd")
+        .args(["/C", "start", "/B", "fingerpain-daemon.exe"])
+        .spawn();
 
-        let output = Command::new("tasklist")
-            .args(["/FI", "IMAGENAME eq fingerpain-daemon.exe"])
-            .output();
+    match result {
+        Ok(_) => println!("{}", "✓ Daemon started".green()),
+        Err(e) => println!("{}: {}", "✗ Failed to start daemon".red(), e),
+    }
 
-        match output {
-            Ok(out) => {
-                let stdout = String::from_utf8_lossy(&out.stdout);
-                if stdout.contains("fingerpain-daemon.exe") {
-                    println!("{}", "✓ Daemon is running".green());
-                } else {
-                    println!("{}", "✗ Daemon is not running".red());
-                }
-            }
-            Err(_) => {
-                println!("{}", "✗ Daemon is not running".red());
-            }
-        }
+    Ok(())
+}
+
+fn stop_daemon() -> Result<()> {
+    match ControlClient::connect().and_then(|mut client| client.shutdown()) {
+        Ok(()) => println!("{}", "✓ Daemon stopped".green()),
+        Err(ControlError::NotRunning) => println!("{}", "Daemon was not running".yellow()),
+        Err(e) => return Err(e.into()),
     }
 
     Ok(())
 }
 
-fn start_daemon() -> Result<()> {
-    #[cfg(unix)]
-    {
-        use std::process::Command;
+fn pause_daemon(duration: Option<&str>) -> Result<()> {
+    let duration = match duration {
+        Some(text) => Some(parse_duration(text).ok_or_else(|| {
+            anyhow::anyhow!("Invalid duration '{}', expected e.g. 30m or 1h30m", text)
+        })?),
+        None => None,
+    };
+
+    match ControlClient::connect().and_then(|mut client| client.pause(duration)) {
+        Ok(()) => match duration {
+            Some(duration) => println!(
+                "{} for {}",
+                "⏸ Recording paused".yellow(),
+                Metrics::format_duration((duration.as_secs() / 60) as u32)
+            ),
+            None => println!(
+                "{} until `fingerpain resume`",
+                "⏸ Recording paused".yellow()
+            ),
+        },
+        Err(ControlError::NotRunning) => println!("{}", "Daemon is not running".yellow()),
+        Err(e) => return Err(e.into()),
+    }
 
-        // Check if already running
-        let check = Command::new("pgrep")
-            .args(["-f", "fingerpain-daemon"])
-            .output();
+    Ok(())
+}
 
-        if let Ok(out) = check {
-            if !out.stdout.is_empty() {
-                println!("{}", "Daemon is already running".yellow());
-                return Ok(());
-            }
-        }
+fn resume_daemon() -> Result<()> {
+    match ControlClient::connect().and_then(|mut client| client.resume()) {
+        Ok(()) => println!("{}", "✓ Recording resumed".green()),
+        Err(ControlError::NotRunning) => println!("{}", "Daemon is not running".yellow()),
+        Err(e) => return Err(e.into()),
+    }
 
-        // Start the daemon
-        let result = Command::new("fingerpain-daemon")
-            .spawn();
+    Ok(())
+}
 
-        match result {
-            Ok(_) => println!("{}", "✓ Daemon started".green()),
-            Err(e) => println!("{}: {}", "✗ Failed to start daemon".red(), e),
-        }
+fn start_tag(tag: &str) -> Result<()> {
+    if !fingerpain_core::is_valid_tag(tag) {
+        anyhow::bail!("Invalid tag '{}': use letters, digits, '-', '_' or '.'", tag);
     }
 
-    #[cfg(windows)]
-    {
-        use std::process::Command;
+    match ControlClient::connect().and_then(|mut client| client.tag_start(tag)) {
+        Ok(tags) => println!("{} {}", "🏷  Tagging typing with".green(), tags.join(", ")),
+        Err(ControlError::NotRunning) => println!("{}", "Daemon is not running".yellow()),
+        Err(e) => return Err(e.into()),
+    }
 
-        let result = Command::new("cmd")
-            .args(["/C", "start", "/B", "fingerpain-daemon.exe"])
-            .spawn();
+    Ok(())
+}
 
-        match result {
-            Ok(_) => println!("{}", "✓ Daemon started".green()),
-            Err(e) => println!("{}: {}", "✗ Failed to start daemon".red(), e),
-        }
+fn stop_tag(tag: Option<&str>) -> Result<()> {
+    match ControlClient::connect().and_then(|mut client| client.tag_stop(tag)) {
+        Ok(tags) if tags.is_empty() => println!("{}", "✓ No tags active".green()),
+        Ok(tags) => println!("{} {}", "✓ Still tagging with".green(), tags.join(", ")),
+        Err(ControlError::NotRunning) => println!("{}", "Daemon is not running".yellow()),
+        Err(e) => return Err(e.into()),
     }
 
     Ok(())
 }
 
-fn stop_daemon() -> Result<()> {
-    #[cfg(unix)]
-    {
-        use std::process::Command;
+fn pair_extension(db: &Database, config: &Config) -> Result<()> {
+    let code = db.create_pairing_code(chrono::Duration::minutes(5))?;
+    println!("\n  {}\n", code.bold());
+    println!(
+        "Enter this code in the extension's options within 5 minutes, with the address {}.",
+        fingerpain_core::discovery::web_url(&config.web)
+    );
+    Ok(())
+}
 
-        let result = Command::new("pkill")
-            .args(["-f", "fingerpain-daemon"])
-            .output();
+fn create_token(db: &Database, name: &str) -> Result<()> {
+    if !fingerpain_core::is_plausible_name(name) {
+        anyhow::bail!("Invalid token name '{}'", name);
+    }
+    let token = db.create_api_token(name, None)?;
+    println!("{}", token);
+    eprintln!(
+        "Send it as 'Authorization: Bearer <token>'. It is not shown again; \
+         `fingerpain auth token {}` replaces it.",
+        name
+    );
+    Ok(())
+}
 
-        match result {
-            Ok(out) if out.status.success() => {
-                println!("{}", "✓ Daemon stopped".green());
-            }
-            _ => {
-                println!("{}", "Daemon was not running".yellow());
-            }
+fn list_tokens(db: &Database) -> Result<()> {
+    let tokens = db.list_api_tokens()?;
+    if tokens.is_empty() {
+        println!("{}", "No API tokens. Pair the extension with `fingerpain auth pair`.".yellow());
+        return Ok(());
+    }
+
+    let rows: Vec<TokenRow> = tokens
+        .into_iter()
+        .map(|token| TokenRow {
+            name: token.name,
+            origin: token.origin.unwrap_or_default(),
+            created: token.created_at.format("%Y-%m-%d").to_string(),
+            last_used: token
+                .last_used
+                .map(|at| at.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_else(|| "never".to_string()),
+        })
+        .collect();
+    println!("{}", Table::new(rows).with(Style::rounded()));
+    Ok(())
+}
+
+fn show_tags(metrics: &Metrics, range: TimeRange) -> Result<()> {
+    let tags = metrics.tag_stats(range)?;
+
+    if tags.is_empty() {
+        println!("\n{}", "No tagged typing in this period.".yellow());
+        return Ok(());
+    }
+
+    println!("\n{}", "🏷  Typing by Tag".bold().cyan());
+    println!("{}", "─".repeat(60));
+
+    let rows: Vec<TagRow> = tags
+        .into_iter()
+        .map(|tag| TagRow {
+            tag: tag.tag,
+            chars: Metrics::format_chars(tag.total_chars),
+            words: Metrics::format_words(tag.total_words),
+            time: Metrics::format_duration(tag.active_minutes),
+            percentage: format!("{:.1}%", tag.percentage),
+        })
+        .collect();
+
+    let table = Table::new(rows).with(Style::rounded()).to_string();
+    println!("{}", table);
+
+    Ok(())
+}
+
+fn show_projects(metrics: &Metrics, range: TimeRange) -> Result<()> {
+    let projects = metrics.project_stats(range)?;
+
+    if projects.is_empty() {
+        println!("\n{}", "No project typing in this period.".yellow());
+        println!("Projects come from editor plugins or [[projects.rules]] in the config.");
+        return Ok(());
+    }
+
+    println!("\n{}", "📁 Typing by Project".bold().cyan());
+    println!("{}", "─".repeat(60));
+
+    let rows: Vec<ProjectRow> = projects
+        .into_iter()
+        .map(|project| ProjectRow {
+            project: project.project,
+            chars: Metrics::format_chars(project.total_chars),
+            words: Metrics::format_words(project.total_words),
+            time: Metrics::format_duration(project.active_minutes),
+            percentage: format!("{:.1}%", project.percentage),
+        })
+        .collect();
+
+    let table = Table::new(rows).with(Style::rounded()).to_string();
+    println!("{}", table);
+
+    Ok(())
+}
+
+fn show_languages(metrics: &Metrics, range: TimeRange) -> Result<()> {
+    let languages = metrics.language_stats(range)?;
+
+    if languages.is_empty() {
+        println!("\n{}", "No language data in this period.".yellow());
+        println!("Languages are reported by editor plugins through /api/editor-context.");
+        return Ok(());
+    }
+
+    println!("\n{}", "💻 Typing by Language".bold().cyan());
+    println!("{}", "─".repeat(60));
+
+    let rows: Vec<LanguageRow> = languages
+        .into_iter()
+        .map(|language| LanguageRow {
+            language: language.language,
+            chars: Metrics::format_chars(language.total_chars),
+            words: Metrics::format_words(language.total_words),
+            time: Metrics::format_duration(language.active_minutes),
+            percentage: format!("{:.1}%", language.percentage),
+        })
+        .collect();
+
+    let table = Table::new(rows).with(Style::rounded()).to_string();
+    println!("{}", table);
+
+    Ok(())
+}
+
+fn test_rules(config: &Config, titles: &[String], app: Option<&str>) -> Result<()> {
+    if config.projects.rules.is_empty() {
+        println!("{}", "No [[projects.rules]] in the config".yellow());
+        return Ok(());
+    }
+    let rules = TitleRules::new(&config.projects)?;
+
+    let titles = if titles.is_empty() {
+        io::stdin().lock().lines().collect::<io::Result<Vec<_>>>()?
+    } else {
+        titles.to_vec()
+    };
+
+    // Without --app only the rules that aren't limited to an app can match
+    let app = app.unwrap_or_default();
+    let rows: Vec<RuleTestRow> = titles
+        .into_iter()
+        .filter(|title| !title.trim().is_empty())
+        .map(|title| RuleTestRow {
+            project: rules
+                .project(app, app, &title)
+                .unwrap_or_else(|| "-".to_string()),
+            title,
+        })
+        .collect();
+
+    let table = Table::new(rows).with(Style::rounded()).to_string();
+    println!("{}", table);
+    if app.is_empty() && config.projects.rules.iter().any(|rule| rule.app.is_some()) {
+        println!("Rules limited to an app were skipped; pass --app to include them.");
+    }
+
+    Ok(())
+}
+
+fn show_config() -> Result<()> {
+    let path = Config::path();
+    let config = Config::load()?;
+
+    if path.exists() {
+        println!("# Config file: {}", path.display());
+    } else {
+        println!("# Config file: {} (not found, using defaults)", path.display());
+    }
+    println!("# Data directory: {}", config.data_dir().display());
+    println!();
+    print!("{}", config.to_toml());
+
+    Ok(())
+}
+
+fn edit_config() -> Result<()> {
+    use std::process::Command;
+
+    let path = Config::path();
+    if !path.exists() {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
         }
+        std::fs::write(&path, Config::default().to_toml())?;
+        println!("Created {}", path.display());
     }
 
-    #[cfg(windows)]
-    {
-        use std::process::Command;
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| if cfg!(windows) { "notepad" } else { "vi" }.to_string());
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().unwrap_or("vi");
+    let status = Command::new(program).args(parts).arg(&path).status()?;
+    if !status.success() {
+        anyhow::bail!("{} exited with {}", program, status);
+    }
 
-        let result = Command::new("taskkill")
-            .args(["/IM", "fingerpain-daemon.exe", "/F"])
-            .output();
+    validate_config()
+}
 
-        match result {
-            Ok(out) if out.status.success() => {
-                println!("{}", "✓ Daemon stopped".green());
-            }
-            _ => {
-                println!("{}", "Daemon was not running".yellow());
+fn validate_config() -> Result<()> {
+    let path = Config::path();
+    match Config::load() {
+        Ok(_) => {
+            println!("{} {}", "✓ Config is valid:".green(), path.display());
+            Ok(())
+        }
+        Err(e) => {
+            println!("{} {}", "✗".red(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn reload_config() -> Result<()> {
+    match ControlClient::connect().and_then(|mut client| client.reload_config()) {
+        Ok(changes) if changes.is_empty() => {
+            println!("{}", "✓ Config reloaded, nothing changed".green())
+        }
+        Ok(changes) => {
+            println!("{}", "✓ Config reloaded".green());
+            for change in changes {
+                println!("  {}", change);
             }
         }
+        Err(ControlError::NotRunning) => println!("{}", "Daemon is not running".yellow()),
+        Err(ControlError::Rpc { message, .. }) => {
+            println!("{} {}", "✗ Daemon kept its old config:".red(), message);
+            std::process::exit(1);
+        }
+        Err(e) => return Err(e.into()),
     }
 
     Ok(())
 }
+
+/// Prompt hook that reports the project in the background after each command
+fn shell_init(shell: Shell) -> &'static str {
+    match shell {
+        Shell::Zsh => {
+            r#"_fingerpain_hook() { fingerpain shell-hook --pid $$ >/dev/null 2>&1 &! }
+if (( ! ${precmd_functions[(I)_fingerpain_hook]} )); then
+  precmd_functions+=(_fingerpain_hook)
+fi
+"#
+        }
+        Shell::Bash => {
+            r#"_fingerpain_hook() { (fingerpain shell-hook --pid $$ >/dev/null 2>&1 &) }
+if [[ ";${PROMPT_COMMAND:-};" != *";_fingerpain_hook;"* ]]; then
+  PROMPT_COMMAND="_fingerpain_hook${PROMPT_COMMAND:+;$PROMPT_COMMAND}"
+fi
+"#
+        }
+        Shell::Fish => {
+            r#"function _fingerpain_hook --on-event fish_prompt
+    fingerpain shell-hook --pid $fish_pid >/dev/null 2>&1 &
+    disown 2>/dev/null
+end
+"#
+        }
+    }
+}
+
+/// Report the repository the shell is in; outside one, report no project.
+/// Errors are ignored so a stopped daemon never disturbs the prompt.
+fn shell_hook(dir: Option<PathBuf>, pid: Option<u32>) {
+    let Some(dir) = dir.or_else(|| std::env::current_dir().ok()) else {
+        return;
+    };
+    let project = git_project(&dir);
+    if let Ok(mut client) = ControlClient::connect() {
+        let _ = client.shell_context(pid, project.as_deref());
+    }
+}
+
+/// Name of the git repository containing `dir`, from its top-level directory
+fn git_project(dir: &Path) -> Option<String> {
+    // `.git` is a file in worktrees and submodules
+    let root = dir.ancestors().find(|d| d.join(".git").exists())?;
+    let name = root.file_name()?.to_string_lossy().into_owned();
+    fingerpain_core::is_plausible_name(&name).then_some(name)
+}
+
+/// Parse durations like `45s`, `30m`, `2h` or `1h30m`
+fn parse_duration(text: &str) -> Option<std::time::Duration> {
+    let mut total = 0u64;
+    let mut number = String::new();
+
+    for c in text.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let value: u64 = number.parse().ok()?;
+        number.clear();
+        let seconds = match c {
+            's' => value,
+            'm' => value.checked_mul(60)?,
+            'h' => value.checked_mul(3600)?,
+            _ => return None,
+        };
+        total = total.checked_add(seconds)?;
+    }
+
+    // A bare number means minutes
+    if !number.is_empty() {
+        let minutes = number.parse::<u64>().ok()?;
+        total = total.checked_add(minutes.checked_mul(60)?)?;
+    }
+
+    (total > 0).then(|| std::time::Duration::from_secs(total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30m").unwrap().as_secs(), 1800);
+        assert_eq!(parse_duration("1h30m").unwrap().as_secs(), 5400);
+        assert_eq!(parse_duration("45s").unwrap().as_secs(), 45);
+        assert_eq!(parse_duration("10").unwrap().as_secs(), 600);
+        assert!(parse_duration("soon").is_none());
+        assert!(parse_duration("0m").is_none());
+    }
+
+    #[test]
+    fn test_parse_duration_rejects_overflow() {
+        assert!(parse_duration("99999999999999999h").is_none());
+        assert!(parse_duration("99999999999999999999s").is_none());
+        assert!(parse_duration("307445734561825861").is_none());
+        assert!(parse_duration("18446744073709551615s1s").is_none());
+    }
+
+    #[test]
+    fn test_git_project() {
+        let root = std::env::temp_dir().join(format!("fingerpain-git-{}", std::process::id()));
+        let repo = root.join("my-repo");
+        std::fs::create_dir_all(repo.join(".git")).unwrap();
+        std::fs::create_dir_all(repo.join("src/deep")).unwrap();
+
+        assert_eq!(git_project(&repo.join("src/deep")).as_deref(), Some("my-repo"));
+        assert_eq!(git_project(&repo).as_deref(), Some("my-repo"));
+        assert_eq!(git_project(&root), None);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
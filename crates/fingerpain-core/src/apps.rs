@@ -0,0 +1,232 @@
+//! App aliases and categories
+//!
+//! The same app is often recorded under several names or IDs, e.g.
+//! `Google-chrome` and `google-chrome`, or an Electron app under a generic
+//! name. Rules map those to one canonical app and to a category. They are
+//! applied when stats are read, so editing them reclassifies all history.
+
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+/// What kind of work an app is used for
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub enum Category {
+    Coding,
+    Communication,
+    Writing,
+    Browsing,
+    Terminal,
+    #[default]
+    Other,
+}
+
+impl Category {
+    pub const ALL: [Category; 6] = [
+        Category::Coding,
+        Category::Communication,
+        Category::Writing,
+        Category::Browsing,
+        Category::Terminal,
+        Category::Other,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Category::Coding => "Coding",
+            Category::Communication => "Communication",
+            Category::Writing => "Writing",
+            Category::Browsing => "Browsing",
+            Category::Terminal => "Terminal",
+            Category::Other => "Other",
+        }
+    }
+
+    /// Parse a category name, ignoring case
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|category| category.as_str().eq_ignore_ascii_case(s))
+    }
+}
+
+/// One `[[apps.rules]]` entry
+///
+/// `match` is a case-insensitive regex tested against both the app name and
+/// its ID. The first matching rule with a `name` decides the app, the first
+/// with a `category` decides the category.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AppRule {
+    #[serde(rename = "match")]
+    pub pattern: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<Category>,
+}
+
+/// User rules from the `[apps]` section, checked before the built-in ones
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppRules {
+    pub rules: Vec<AppRule>,
+}
+
+/// Built-in rules: (pattern, canonical name, category)
+const BUILTIN_RULES: &[(&str, Option<&str>, Category)] = &[
+    (
+        r"^(google-chrome|com\.google\.chrome)$",
+        Some("Google Chrome"),
+        Category::Browsing,
+    ),
+    (
+        r"^(code|code-oss|vscodium|com\.microsoft\.vscode)$",
+        Some("VS Code"),
+        Category::Coding,
+    ),
+    (
+        r"(visual studio code|vscodium|cursor|^zed$|dev\.zed|jetbrains|intellij|pycharm|webstorm|goland|rustrover|clion|xcode|sublime|neovim|nvim|^vim$|gvim|emacs|android studio|helix)",
+        None,
+        Category::Coding,
+    ),
+    (
+        r"(terminal|iterm|alacritty|kitty|wezterm|ghostty|konsole|xterm|warp|^foot$|tilix|terminator)",
+        None,
+        Category::Terminal,
+    ),
+    (
+        r"(slack|discord|teams|zoom|telegram|signal|whatsapp|messages|^mail$|com\.apple\.mail|thunderbird|outlook|element)",
+        None,
+        Category::Communication,
+    ),
+    (
+        r"(obsidian|notion|libreoffice|microsoft word|^word$|^pages$|iwork\.pages|bear|typora|logseq|textedit|gedit|gnome-text-editor)",
+        None,
+        Category::Writing,
+    ),
+    (
+        r"(chrome|chromium|firefox|safari|helium|brave|microsoft edge|msedge|^arc$|vivaldi|opera|librewolf|zen browser)",
+        None,
+        Category::Browsing,
+    ),
+];
+
+#[derive(Clone)]
+struct CompiledRule {
+    regex: Regex,
+    name: Option<String>,
+    category: Option<Category>,
+}
+
+/// Compiled rules that map a recorded app to its canonical name and category
+#[derive(Clone)]
+pub struct AppClassifier {
+    rules: Vec<CompiledRule>,
+}
+
+impl Default for AppClassifier {
+    /// Built-in rules only
+    fn default() -> Self {
+        Self::new(&AppRules::default()).expect("built-in app rules are valid")
+    }
+}
+
+impl AppClassifier {
+    pub fn new(user_rules: &AppRules) -> Result<Self, regex::Error> {
+        let user = user_rules
+            .rules
+            .iter()
+            .map(|rule| (rule.pattern.as_str(), rule.name.clone(), rule.category));
+        let builtin = BUILTIN_RULES
+            .iter()
+            .map(|(pattern, name, category)| (*pattern, name.map(String::from), Some(*category)));
+
+        let rules = user
+            .chain(builtin)
+            .map(|(pattern, name, category)| {
+                Ok(CompiledRule {
+                    regex: compile(pattern)?,
+                    name,
+                    category,
+                })
+            })
+            .collect::<Result<_, regex::Error>>()?;
+        Ok(Self { rules })
+    }
+
+    /// Canonical name and category for an app as it was recorded
+    pub fn classify(&self, app_name: &str, bundle_id: &str) -> (String, Category) {
+        let matching = || {
+            self.rules
+                .iter()
+                .filter(|rule| rule.regex.is_match(app_name) || rule.regex.is_match(bundle_id))
+        };
+
+        let name = matching()
+            .find_map(|rule| rule.name.clone())
+            .unwrap_or_else(|| app_name.to_string());
+        let category = matching()
+            .find_map(|rule| rule.category)
+            // An alias may only be known to a later rule, e.g. a renamed app
+            .or_else(|| {
+                self.rules
+                    .iter()
+                    .filter(|rule| rule.regex.is_match(&name))
+                    .find_map(|rule| rule.category)
+            })
+            .unwrap_or_default();
+        (name, category)
+    }
+}
+
+/// Compile a rule pattern the way `AppClassifier` uses it
+pub(crate) fn compile(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern).case_insensitive(true).build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aliases_and_categories() {
+        let rules: AppRules = toml::from_str(
+            r#"
+            [[rules]]
+            match = "^electron$"
+            name = "Linear"
+            category = "Communication"
+
+            [[rules]]
+            match = "^code$"
+            category = "Writing"
+            "#,
+        )
+        .unwrap();
+        let classifier = AppClassifier::new(&rules).unwrap();
+
+        // User rules win over built-in ones, which still fill in the rest
+        assert_eq!(
+            classifier.classify("Electron", "electron"),
+            ("Linear".to_string(), Category::Communication)
+        );
+        assert_eq!(
+            classifier.classify("Code", "code"),
+            ("VS Code".to_string(), Category::Writing)
+        );
+
+        let classifier = AppClassifier::default();
+        assert_eq!(
+            classifier.classify("Google-chrome", "Google-chrome"),
+            classifier.classify("google-chrome", "google-chrome")
+        );
+        assert_eq!(classifier.classify("kitty", "kitty").1, Category::Terminal);
+        assert_eq!(
+            classifier.classify("Some Game", "com.example.game"),
+            ("Some Game".to_string(), Category::Other)
+        );
+    }
+}
@@ -0,0 +1,118 @@
+//! Browser registry
+//!
+//! The extension reports tabs under a browser name such as `Chrome`, while the
+//! focus watcher sees a platform-specific app ID: the bundle ID on macOS, the
+//! WM_CLASS on Linux and the executable on Windows. The registry maps the IDs
+//! of every platform to those names. Entries in `[browsers]` are added on top
+//! of the built-in ones and win over them.
+
+use std::collections::{BTreeMap, HashMap};
+
+/// Name browsers know the native messaging host by
+pub const NATIVE_HOST_NAME: &str = "com.fingerpain.browser_host";
+
+/// ID of the Firefox extension, fixed in its manifest
+pub const FIREFOX_EXTENSION_ID: &str = "browser-tracker@fingerpain";
+
+/// Built-in app IDs and the name the extension reports for them
+///
+/// The extension can't tell Chromium from Chrome, so both report `Chrome`.
+const BUILTIN_BROWSERS: &[(&str, &str)] = &[
+    // macOS bundle IDs
+    ("com.google.Chrome", "Chrome"),
+    ("org.chromium.Chromium", "Chrome"),
+    ("com.JadeApps.Helium", "Helium"),
+    ("com.brave.Browser", "Brave"),
+    ("com.microsoft.edgemac", "Edge"),
+    ("org.mozilla.firefox", "Firefox"),
+    ("com.apple.Safari", "Safari"),
+    // Linux WM_CLASS
+    ("Google-chrome", "Chrome"),
+    ("Chromium", "Chrome"),
+    ("Chromium-browser", "Chrome"),
+    ("helium", "Helium"),
+    ("Brave-browser", "Brave"),
+    ("Microsoft-edge", "Edge"),
+    ("firefox", "Firefox"),
+    ("firefox-esr", "Firefox"),
+    // Windows executables
+    ("chrome.exe", "Chrome"),
+    ("brave.exe", "Brave"),
+    ("msedge.exe", "Edge"),
+    ("firefox.exe", "Firefox"),
+];
+
+/// App IDs of known browsers, matched ignoring case
+#[derive(Debug, Clone, PartialEq)]
+pub struct BrowserRegistry {
+    names: HashMap<String, String>,
+}
+
+impl Default for BrowserRegistry {
+    /// Built-in browsers only
+    fn default() -> Self {
+        Self::new(&BTreeMap::new())
+    }
+}
+
+impl BrowserRegistry {
+    pub fn new(user_browsers: &BTreeMap<String, String>) -> Self {
+        let builtin = BUILTIN_BROWSERS
+            .iter()
+            .map(|(id, name)| (id.to_string(), name.to_string()));
+        let user = user_browsers
+            .iter()
+            .map(|(id, name)| (id.clone(), name.clone()));
+
+        // Later entries win, so user ones go last
+        let names = builtin
+            .chain(user)
+            .map(|(id, name)| (id.to_lowercase(), name))
+            .collect();
+        Self { names }
+    }
+
+    /// Name the extension reports for an app, if the app is a browser
+    pub fn name(&self, app_id: &str) -> Option<&str> {
+        self.names.get(&app_id.to_lowercase()).map(String::as_str)
+    }
+
+    pub fn is_browser(&self, app_id: &str) -> bool {
+        self.name(app_id).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_linux_window_classes() {
+        let browsers = BrowserRegistry::default();
+
+        // WM_CLASS as reported by X11, in whatever case the browser uses
+        assert_eq!(browsers.name("Google-chrome"), Some("Chrome"));
+        assert_eq!(browsers.name("google-chrome"), Some("Chrome"));
+        assert_eq!(browsers.name("chromium"), Some("Chrome"));
+        assert_eq!(browsers.name("firefox"), Some("Firefox"));
+        assert_eq!(browsers.name("Brave-browser"), Some("Brave"));
+        assert!(!browsers.is_browser("kitty"));
+
+        // Other platforms keep working alongside
+        assert_eq!(browsers.name("com.google.Chrome"), Some("Chrome"));
+        assert_eq!(browsers.name("firefox.exe"), Some("Firefox"));
+    }
+
+    #[test]
+    fn test_user_entries_add_and_override() {
+        let user = [("vivaldi-stable", "Chrome"), ("firefox", "Nightly")]
+            .into_iter()
+            .map(|(id, name)| (id.to_string(), name.to_string()))
+            .collect();
+        let browsers = BrowserRegistry::new(&user);
+
+        assert_eq!(browsers.name("Vivaldi-stable"), Some("Chrome"));
+        assert_eq!(browsers.name("firefox"), Some("Nightly"));
+        assert_eq!(browsers.name("Google-chrome"), Some("Chrome"));
+    }
+}
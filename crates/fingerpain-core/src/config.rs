@@ -0,0 +1,639 @@
+//! Configuration shared by every FingerPain binary
+//!
+//! Read from `config.toml` in the platform config directory (or the file named
+//! by `FINGERPAIN_CONFIG`). Every setting has a default, so the file is
+//! optional. Environment variables override the file:
+//!
+//! | Variable | Setting |
+//! |----------|---------|
+//! | `FINGERPAIN_DATA_DIR` | `data_dir` |
+//! | `FINGERPAIN_IDLE_TIMEOUT_SECS` | `daemon.idle_timeout_secs` |
+//! | `FINGERPAIN_FOCUS_POLL_MS` | `daemon.focus_poll_ms` |
+//! | `FINGERPAIN_WEB_BIND` | `web.bind` |
+//! | `FINGERPAIN_WEB_PORT` | `web.port` |
+
+use crate::apps::{AppClassifier, AppRules};
+use crate::browsers::BrowserRegistry;
+use crate::domains::DomainGroups;
+use crate::privacy::ExclusionRules;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use thiserror::Error;
+use tracing::warn;
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Failed to read {path:?}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Invalid config file {path:?}: {source}")]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("Invalid value for {name}: {message}")]
+    Env { name: String, message: String },
+    #[error("Invalid config:\n  - {}", .0.join("\n  - "))]
+    Invalid(Vec<String>),
+}
+
+pub type Result<T> = std::result::Result<T, ConfigError>;
+
+/// Settings for the background daemon
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    /// Seconds without typing before a session ends
+    pub idle_timeout_secs: u64,
+    /// How often the focused app is polled where the platform can't notify us
+    pub focus_poll_ms: u64,
+    /// Seconds a project reported by the shell hook stays valid without a new prompt
+    pub shell_context_ttl_secs: u64,
+    /// Seconds a browser tab stays attributed without a report from the extension
+    pub browser_context_ttl_secs: u64,
+    /// Seconds an editor's language and project stay attributed without a report
+    pub editor_context_ttl_secs: u64,
+    /// Serve Prometheus metrics on this localhost port (off if unset)
+    pub metrics_port: Option<u16>,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout_secs: 5,
+            focus_poll_ms: 250,
+            shell_context_ttl_secs: 900,
+            browser_context_ttl_secs: 300,
+            editor_context_ttl_secs: 1800,
+            metrics_port: None,
+        }
+    }
+}
+
+/// Settings for the web dashboard and the API used by the extension
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebConfig {
+    pub bind: String,
+    pub port: u16,
+    /// Try the next few ports when `port` is taken, instead of failing
+    pub port_fallback: bool,
+    /// Serve on this Unix socket instead of TCP
+    pub unix_socket: Option<PathBuf>,
+    /// Require an API token to read stats, not only to change anything
+    pub read_auth: bool,
+}
+
+impl Default for WebConfig {
+    fn default() -> Self {
+        Self {
+            bind: "127.0.0.1".to_string(),
+            port: 7890,
+            port_fallback: true,
+            unix_socket: None,
+            read_auth: false,
+        }
+    }
+}
+
+impl WebConfig {
+    pub fn socket_addr(&self) -> Option<SocketAddr> {
+        let ip: IpAddr = self.bind.parse().ok()?;
+        Some(SocketAddr::new(ip, self.port))
+    }
+
+    /// Unix socket to serve on, with `~` expanded
+    pub fn unix_socket_path(&self) -> Option<PathBuf> {
+        self.unix_socket.as_deref().map(expand_home)
+    }
+
+    /// Address to open in a browser
+    pub fn url(&self) -> String {
+        match self.socket_addr() {
+            Some(addr) => crate::discovery::browser_url(&addr),
+            None => format!("http://{}:{}", self.bind, self.port),
+        }
+    }
+}
+
+/// One `[[projects.rules]]` entry
+///
+/// `title` is a case-insensitive regex applied to the focused window's title;
+/// its `project` group (or else its first group) is the project name. `app`,
+/// if set, limits the rule to apps whose name or ID it matches. Whatever the
+/// group captures is stored, so it should match only the project name.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectRule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app: Option<String>,
+    pub title: String,
+}
+
+/// Rules that name the project from window titles; empty means titles are never read
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProjectRules {
+    pub rules: Vec<ProjectRule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Where the database and daemon state live (platform data dir if unset)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_dir: Option<PathBuf>,
+    pub daemon: DaemonConfig,
+    pub web: WebConfig,
+    /// Browser app IDs mapped to the name the extension reports, on top of
+    /// the built-in ones in `BrowserRegistry`
+    pub browsers: BTreeMap<String, String>,
+    /// Editor app IDs or names mapped to the name their plugin reports
+    pub editors: BTreeMap<String, String>,
+    /// Group names mapped to the domains they cover, for domain stats
+    pub domain_groups: BTreeMap<String, Vec<String>>,
+    /// Apps and domains that are never recorded
+    pub privacy: ExclusionRules,
+    /// App aliases and categories, applied when stats are read
+    pub apps: AppRules,
+    /// Window-title rules that attribute typing to a project
+    pub projects: ProjectRules,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let editors = [
+            ("Code", "vscode"),
+            ("code-oss", "vscode"),
+            ("VSCodium", "vscode"),
+            ("com.microsoft.VSCode", "vscode"),
+            ("Cursor", "cursor"),
+            ("neovide", "neovim"),
+        ]
+        .into_iter()
+        .map(|(id, name)| (id.to_string(), name.to_string()))
+        .collect();
+
+        Self {
+            data_dir: None,
+            daemon: DaemonConfig::default(),
+            web: WebConfig::default(),
+            browsers: BTreeMap::new(),
+            editors,
+            domain_groups: BTreeMap::new(),
+            privacy: ExclusionRules::default(),
+            apps: AppRules::default(),
+            projects: ProjectRules::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Path of the config file, honouring `FINGERPAIN_CONFIG`
+    pub fn path() -> PathBuf {
+        std::env::var_os("FINGERPAIN_CONFIG")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| config_dir().join("config.toml"))
+    }
+
+    /// Load, apply environment overrides and validate
+    pub fn load() -> Result<Self> {
+        Self::load_from(&Self::path())
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self> {
+        let mut config = Self::read(path)?;
+        config.apply_env(|name| std::env::var(name).ok())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Parse a config file without environment overrides; missing is not an error
+    pub fn read(path: &Path) -> Result<Self> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(source) => {
+                return Err(ConfigError::Io {
+                    path: path.to_path_buf(),
+                    source,
+                })
+            }
+        };
+
+        toml::from_str(&text).map_err(|source| ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    /// Override settings from environment variables looked up through `var`
+    pub fn apply_env<F>(&mut self, var: F) -> Result<()>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        fn parse<T: std::str::FromStr>(name: &str, value: String) -> Result<T> {
+            value.trim().parse().map_err(|_| ConfigError::Env {
+                name: name.to_string(),
+                message: format!("'{}' is not a valid number", value),
+            })
+        }
+
+        if let Some(dir) = var("FINGERPAIN_DATA_DIR") {
+            self.data_dir = Some(PathBuf::from(dir));
+        }
+        if let Some(value) = var("FINGERPAIN_IDLE_TIMEOUT_SECS") {
+            self.daemon.idle_timeout_secs = parse("FINGERPAIN_IDLE_TIMEOUT_SECS", value)?;
+        }
+        if let Some(value) = var("FINGERPAIN_FOCUS_POLL_MS") {
+            self.daemon.focus_poll_ms = parse("FINGERPAIN_FOCUS_POLL_MS", value)?;
+        }
+        if let Some(bind) = var("FINGERPAIN_WEB_BIND") {
+            self.web.bind = bind;
+        }
+        if let Some(value) = var("FINGERPAIN_WEB_PORT") {
+            self.web.port = parse("FINGERPAIN_WEB_PORT", value)?;
+        }
+
+        Ok(())
+    }
+
+    /// Check every setting, reporting all problems at once
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+
+        if let Some(dir) = &self.data_dir {
+            if !expand_home(dir).is_absolute() {
+                problems.push(format!("data_dir must be an absolute path, got {:?}", dir));
+            }
+        }
+        if !(1..=3600).contains(&self.daemon.idle_timeout_secs) {
+            problems.push(format!(
+                "daemon.idle_timeout_secs must be between 1 and 3600, got {}",
+                self.daemon.idle_timeout_secs
+            ));
+        }
+        if !(50..=10_000).contains(&self.daemon.focus_poll_ms) {
+            problems.push(format!(
+                "daemon.focus_poll_ms must be between 50 and 10000, got {}",
+                self.daemon.focus_poll_ms
+            ));
+        }
+        if !(10..=86_400).contains(&self.daemon.shell_context_ttl_secs) {
+            problems.push(format!(
+                "daemon.shell_context_ttl_secs must be between 10 and 86400, got {}",
+                self.daemon.shell_context_ttl_secs
+            ));
+        }
+        // The extension repeats the focused tab every minute
+        if !(90..=86_400).contains(&self.daemon.browser_context_ttl_secs) {
+            problems.push(format!(
+                "daemon.browser_context_ttl_secs must be between 90 and 86400, got {}",
+                self.daemon.browser_context_ttl_secs
+            ));
+        }
+        if !(60..=86_400).contains(&self.daemon.editor_context_ttl_secs) {
+            problems.push(format!(
+                "daemon.editor_context_ttl_secs must be between 60 and 86400, got {}",
+                self.daemon.editor_context_ttl_secs
+            ));
+        }
+        if self.daemon.metrics_port == Some(0) {
+            problems.push("daemon.metrics_port must not be 0".to_string());
+        }
+        match self.web.bind.parse::<IpAddr>() {
+            Err(_) => problems.push(format!(
+                "web.bind must be an IP address such as 127.0.0.1, got '{}'",
+                self.web.bind
+            )),
+            // Any machine that can reach the address could read every stat
+            Ok(ip)
+                if !ip.is_loopback() && !self.web.read_auth && self.web.unix_socket.is_none() =>
+            {
+                problems.push(format!(
+                    "web.bind {} is reachable from other machines; \
+                     set web.read_auth = true to listen on it",
+                    ip
+                ))
+            }
+            Ok(_) => {}
+        }
+        if self.web.port == 0 {
+            problems.push("web.port must not be 0".to_string());
+        }
+        if self.web.unix_socket.is_some() && !cfg!(unix) {
+            problems.push("web.unix_socket is only supported on Unix".to_string());
+        }
+        for (section, map) in [("browsers", &self.browsers), ("editors", &self.editors)] {
+            for (id, name) in map {
+                if id.trim().is_empty() || name.trim().is_empty() {
+                    problems.push(format!(
+                        "{}: empty app ID or name in '{}' = '{}'",
+                        section, id, name
+                    ));
+                }
+            }
+        }
+        if self.privacy.domains.iter().any(|d| d.contains('/')) {
+            problems.push("privacy.domains must be domains, not URLs".to_string());
+        }
+        for (group, domains) in &self.domain_groups {
+            if group.trim().is_empty() {
+                problems.push("domain_groups: empty group name".to_string());
+            }
+            if domains.iter().any(|d| d.trim().is_empty() || d.contains('/')) {
+                problems.push(format!(
+                    "domain_groups: '{}' must list domains, not URLs",
+                    group
+                ));
+            }
+        }
+        for rule in &self.apps.rules {
+            if let Err(e) = crate::apps::compile(&rule.pattern) {
+                problems.push(format!("apps.rules: invalid match '{}': {}", rule.pattern, e));
+            }
+            if rule.name.is_none() && rule.category.is_none() {
+                problems.push(format!(
+                    "apps.rules: '{}' needs a name or a category",
+                    rule.pattern
+                ));
+            }
+        }
+        for rule in &self.projects.rules {
+            if let Some(app) = &rule.app {
+                if let Err(e) = crate::apps::compile(app) {
+                    problems.push(format!("projects.rules: invalid app '{}': {}", app, e));
+                }
+            }
+            match crate::apps::compile(&rule.title) {
+                Ok(regex) if regex.captures_len() < 2 => problems.push(format!(
+                    "projects.rules: title '{}' needs a group such as (?P<project>...)",
+                    rule.title
+                )),
+                Ok(_) => {}
+                Err(e) => problems.push(format!(
+                    "projects.rules: invalid title '{}': {}",
+                    rule.title, e
+                )),
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(problems))
+        }
+    }
+
+    /// Effective data directory
+    pub fn data_dir(&self) -> PathBuf {
+        self.data_dir
+            .as_deref()
+            .map(expand_home)
+            .unwrap_or_else(default_data_dir)
+    }
+
+    /// Database file in the data directory
+    pub fn db_path(&self) -> PathBuf {
+        self.data_dir().join("fingerpain.db")
+    }
+
+    /// Built-in browsers plus the `[browsers]` section
+    pub fn browser_registry(&self) -> BrowserRegistry {
+        BrowserRegistry::new(&self.browsers)
+    }
+
+    /// The `[domain_groups]` section, for domain stats
+    pub fn domain_groups(&self) -> DomainGroups {
+        DomainGroups::new(&self.domain_groups)
+    }
+
+    /// Compiled `[apps]` rules; the config must have been validated
+    pub fn app_classifier(&self) -> AppClassifier {
+        AppClassifier::new(&self.apps).expect("validated config has valid app rules")
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).expect("config is always serializable")
+    }
+
+    /// Settings that differ from `other`, one `key: old -> new` line each
+    pub fn diff(&self, other: &Config) -> Vec<String> {
+        let (mut old, mut new) = (BTreeMap::new(), BTreeMap::new());
+        flatten("", &toml::Value::try_from(self).unwrap(), &mut old);
+        flatten("", &toml::Value::try_from(other).unwrap(), &mut new);
+
+        let keys: std::collections::BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+        keys.into_iter()
+            .filter_map(|key| {
+                let unset = || "(unset)".to_string();
+                match (old.get(key), new.get(key)) {
+                    (a, b) if a == b => None,
+                    (a, b) => Some(format!(
+                        "{}: {} -> {}",
+                        key,
+                        a.cloned().unwrap_or_else(unset),
+                        b.cloned().unwrap_or_else(unset)
+                    )),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Collect the leaves of a TOML value under dotted keys
+fn flatten(prefix: &str, value: &toml::Value, out: &mut BTreeMap<String, String>) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten(&key, value, out);
+            }
+        }
+        other => {
+            out.insert(prefix.to_string(), other.to_string());
+        }
+    }
+}
+
+/// Platform config directory for FingerPain
+pub fn config_dir() -> PathBuf {
+    directories::ProjectDirs::from("com", "fingerpain", "fingerpain")
+        .map(|dirs| dirs.config_dir().to_path_buf())
+        .unwrap_or_else(|| {
+            directories::BaseDirs::new()
+                .map(|d| d.home_dir().join(".fingerpain"))
+                .unwrap_or_else(|| PathBuf::from(".fingerpain"))
+        })
+}
+
+/// Platform data directory, ignoring any configuration
+pub(crate) fn default_data_dir() -> PathBuf {
+    directories::ProjectDirs::from("com", "fingerpain", "fingerpain")
+        .map(|dirs| dirs.data_dir().to_path_buf())
+        .unwrap_or_else(|| {
+            directories::BaseDirs::new()
+                .map(|d| d.home_dir().join(".fingerpain"))
+                .unwrap_or_else(|| PathBuf::from(".fingerpain"))
+        })
+}
+
+/// Data directory from the configuration, read once per process
+///
+/// For clients that only need to find the daemon or web server; anything
+/// that loaded a `Config` uses its `data_dir` instead.
+pub(crate) fn configured_data_dir() -> PathBuf {
+    static DATA_DIR: OnceLock<PathBuf> = OnceLock::new();
+    DATA_DIR
+        .get_or_init(|| match Config::load() {
+            Ok(config) => config.data_dir(),
+            Err(e) => {
+                warn!("Using the default data directory: {}", e);
+                Config::default().data_dir()
+            }
+        })
+        .clone()
+}
+
+fn expand_home(path: &Path) -> PathBuf {
+    match path.strip_prefix("~") {
+        Ok(rest) => directories::BaseDirs::new()
+            .map(|d| d.home_dir().join(rest))
+            .unwrap_or_else(|| path.to_path_buf()),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_file_keeps_defaults() {
+        let config: Config = toml::from_str(
+            r#"
+            [web]
+            port = 8080
+
+            [privacy]
+            domains = ["bank.com"]
+            url_policy = "templated"
+
+            [domain_groups]
+            "Google Workspace" = ["docs.google.com", "mail.google.com"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.web.port, 8080);
+        assert_eq!(config.web.bind, "127.0.0.1");
+        assert!(config.web.port_fallback);
+        assert_eq!(config.daemon, DaemonConfig::default());
+        assert_eq!(config.browser_registry().name("Google-chrome"), Some("Chrome"));
+        assert!(config.privacy.excludes_domain("bank.com"));
+        assert_eq!(config.privacy.url_policy, crate::UrlPolicy::Templated);
+        assert_eq!(
+            config.domain_groups().name("mail.google.com", crate::DomainLevel::Site),
+            "Google Workspace"
+        );
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_unknown_keys_are_rejected() {
+        let err = toml::from_str::<Config>("[daemon]\nidle_timeout = 5\n").unwrap_err();
+        assert!(err.to_string().contains("idle_timeout"));
+    }
+
+    #[test]
+    fn test_env_overrides_and_validation() {
+        let mut config = Config::default();
+        config
+            .apply_env(|name| match name {
+                "FINGERPAIN_WEB_PORT" => Some("9000".to_string()),
+                "FINGERPAIN_IDLE_TIMEOUT_SECS" => Some("0".to_string()),
+                "FINGERPAIN_WEB_BIND" => Some("localhost".to_string()),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(config.web.port, 9000);
+
+        let ConfigError::Invalid(problems) = config.validate().unwrap_err() else {
+            panic!("expected validation errors");
+        };
+        assert_eq!(problems.len(), 2);
+
+        let err = Config::default()
+            .apply_env(|name| (name == "FINGERPAIN_WEB_PORT").then(|| "http".to_string()))
+            .unwrap_err();
+        assert!(matches!(err, ConfigError::Env { .. }));
+    }
+
+    #[test]
+    fn test_non_loopback_bind_needs_read_auth() {
+        let mut config = Config::default();
+        config.web.bind = "0.0.0.0".to_string();
+        let ConfigError::Invalid(problems) = config.validate().unwrap_err() else {
+            panic!("expected validation errors");
+        };
+        assert!(problems[0].contains("web.read_auth"), "{:?}", problems);
+
+        config.web.bind = "192.168.1.20".to_string();
+        assert!(config.validate().is_err());
+        config.web.read_auth = true;
+        assert!(config.validate().is_ok());
+
+        config.web.read_auth = false;
+        config.web.bind = "::1".to_string();
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_project_rules_need_a_group() {
+        let config: Config = toml::from_str(
+            r#"
+            [[projects.rules]]
+            app = "code"
+            title = " - (?P<project>[^-]+) - Visual Studio Code$"
+
+            [[projects.rules]]
+            title = "Visual Studio Code"
+            "#,
+        )
+        .unwrap();
+
+        let ConfigError::Invalid(problems) = config.validate().unwrap_err() else {
+            panic!("expected validation errors");
+        };
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("needs a group"));
+    }
+
+    #[test]
+    fn test_diff_lists_changed_settings() {
+        let old = Config::default();
+        let mut new = old.clone();
+        new.daemon.idle_timeout_secs = 10;
+        new.privacy.domains.push("bank.com".to_string());
+        new.data_dir = Some(PathBuf::from("/data"));
+
+        assert!(old.diff(&old).is_empty());
+        assert_eq!(
+            old.diff(&new),
+            vec![
+                "daemon.idle_timeout_secs: 5 -> 10".to_string(),
+                "data_dir: (unset) -> \"/data\"".to_string(),
+                "privacy.domains: [] -> [\"bank.com\"]".to_string(),
+            ]
+        );
+    }
+}
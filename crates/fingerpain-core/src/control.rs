@@ -0,0 +1,431 @@
+//! Daemon control protocol
+//!
+//! The daemon listens on a per-user Unix domain socket and speaks JSON-RPC 2.0,
+//! one request or response object per line. `ControlClient` is the typed
+//! client used by the CLI and tray.
+//!
+//! After a `live` request the daemon sends a `live` notification with a
+//! [`LiveSnapshot`] each second, and the connection carries nothing else.
+
+use crate::apps::Category;
+use crate::privacy::GapReason;
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::PathBuf;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ControlError {
+    #[error("Daemon is not running")]
+    NotRunning,
+    #[error("Control socket is not supported on this platform")]
+    Unsupported,
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid message: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Daemon error {code}: {message}")]
+    Rpc { code: i64, message: String },
+}
+
+pub type Result<T> = std::result::Result<T, ControlError>;
+
+/// JSON-RPC error codes
+pub mod codes {
+    pub const PARSE_ERROR: i64 = -32700;
+    pub const INVALID_REQUEST: i64 = -32600;
+    pub const METHOD_NOT_FOUND: i64 = -32601;
+    pub const INVALID_PARAMS: i64 = -32602;
+    pub const INTERNAL_ERROR: i64 = -32603;
+}
+
+/// Method names understood by the daemon
+pub mod methods {
+    pub const STATUS: &str = "status";
+    pub const PAUSE: &str = "pause";
+    pub const RESUME: &str = "resume";
+    pub const FLUSH: &str = "flush";
+    pub const RELOAD_CONFIG: &str = "reload_config";
+    pub const TAG_START: &str = "tag_start";
+    pub const TAG_STOP: &str = "tag_stop";
+    pub const EDITOR_CONTEXT: &str = "editor_context";
+    pub const SHELL_CONTEXT: &str = "shell_context";
+    pub const BROWSER_CONTEXT: &str = "browser_context";
+    pub const SHUTDOWN: &str = "shutdown";
+    pub const LIVE: &str = "live";
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcRequest {
+    pub jsonrpc: String,
+    pub id: Value,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+impl RpcRequest {
+    pub fn new(id: u64, method: &str, params: Value) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            id: Value::from(id),
+            method: method.to_string(),
+            params,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcResponse {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl RpcResponse {
+    pub fn success(id: Value, result: Value) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: Some(result),
+            error: None,
+        }
+    }
+
+    pub fn failure(id: Value, code: i64, message: impl Into<String>) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: None,
+            error: Some(RpcError {
+                code,
+                message: message.into(),
+            }),
+        }
+    }
+}
+
+/// Message sent without a request, such as each second of the live feed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcNotification {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+impl RpcNotification {
+    pub fn new(method: &str, params: Value) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            method: method.to_string(),
+            params,
+        }
+    }
+}
+
+/// Snapshot of the running daemon
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DaemonStatus {
+    pub pid: u32,
+    pub version: String,
+    pub started_at: DateTime<Utc>,
+    pub uptime_secs: u64,
+    /// Input events accepted from the keyboard hook
+    pub events_seen: u64,
+    /// Input events dropped because the queue was full
+    pub events_dropped: u64,
+    pub last_flush: Option<DateTime<Utc>>,
+    pub current_app: Option<String>,
+    pub current_wpm: f64,
+    pub paused: bool,
+    /// End of a timed pause
+    #[serde(default)]
+    pub paused_until: Option<DateTime<Utc>>,
+    /// Why input is currently being discarded, if it is
+    #[serde(default)]
+    pub not_recording: Option<GapReason>,
+    /// Project tags stamped on the minutes being recorded
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// What the daemon sees this second, before anything reaches the database
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LiveSnapshot {
+    pub at: DateTime<Utc>,
+    /// Focused app, with configured aliases applied
+    pub current_app: Option<String>,
+    pub category: Option<Category>,
+    /// Keys recorded in the second before `at`
+    pub keys_last_second: u32,
+    /// Words per minute over the open session's last minute
+    pub wpm: f64,
+    /// Typing session in progress, if any
+    pub session: Option<LiveSession>,
+    pub paused: bool,
+    /// Why input is currently being discarded, if it is
+    pub not_recording: Option<GapReason>,
+}
+
+/// Typing session in progress
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LiveSession {
+    pub started_at: DateTime<Utc>,
+    pub char_count: u32,
+    pub word_count: u32,
+    pub peak_wpm: f64,
+}
+
+/// Result of the tag methods
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TagsResult {
+    /// Tags active after the call
+    pub tags: Vec<String>,
+}
+
+/// Path of the control socket for the current user
+///
+/// Uses `$XDG_RUNTIME_DIR` when available, otherwise the data directory.
+pub fn socket_path() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(|dir| PathBuf::from(dir).join("fingerpain.sock"))
+        .unwrap_or_else(|| crate::data_dir().join("daemon.sock"))
+}
+
+/// Typed client for the daemon control socket
+pub struct ControlClient {
+    #[cfg(unix)]
+    stream: std::io::BufReader<std::os::unix::net::UnixStream>,
+    next_id: u64,
+}
+
+impl ControlClient {
+    /// Connect to the daemon at the default socket path
+    pub fn connect() -> Result<Self> {
+        Self::connect_to(socket_path())
+    }
+
+    #[cfg(unix)]
+    pub fn connect_to(path: PathBuf) -> Result<Self> {
+        use std::io::ErrorKind;
+        use std::os::unix::net::UnixStream;
+        use std::time::Duration;
+
+        let stream = UnixStream::connect(&path).map_err(|e| match e.kind() {
+            ErrorKind::NotFound | ErrorKind::ConnectionRefused => ControlError::NotRunning,
+            _ => ControlError::Io(e),
+        })?;
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+        Ok(Self {
+            stream: std::io::BufReader::new(stream),
+            next_id: 1,
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn connect_to(_path: PathBuf) -> Result<Self> {
+        Err(ControlError::Unsupported)
+    }
+
+    /// Send a raw request and wait for its result
+    #[cfg(unix)]
+    pub fn call<T: DeserializeOwned>(&mut self, method: &str, params: Value) -> Result<T> {
+        use std::io::{BufRead, Write};
+
+        let request = RpcRequest::new(self.next_id, method, params);
+        self.next_id += 1;
+
+        let mut line = serde_json::to_string(&request)?;
+        line.push('\n');
+        self.stream.get_mut().write_all(line.as_bytes())?;
+
+        let mut reply = String::new();
+        if self.stream.read_line(&mut reply)? == 0 {
+            return Err(ControlError::NotRunning);
+        }
+
+        let response: RpcResponse = serde_json::from_str(&reply)?;
+        if let Some(error) = response.error {
+            return Err(ControlError::Rpc {
+                code: error.code,
+                message: error.message,
+            });
+        }
+        Ok(serde_json::from_value(
+            response.result.unwrap_or(Value::Null),
+        )?)
+    }
+
+    #[cfg(not(unix))]
+    pub fn call<T: DeserializeOwned>(&mut self, _method: &str, _params: Value) -> Result<T> {
+        Err(ControlError::Unsupported)
+    }
+
+    pub fn status(&mut self) -> Result<DaemonStatus> {
+        self.call(methods::STATUS, Value::Null)
+    }
+
+    /// Stop recording for `duration`, or until `resume` if `None`
+    pub fn pause(&mut self, duration: Option<std::time::Duration>) -> Result<()> {
+        let params = match duration {
+            Some(duration) => serde_json::json!({ "seconds": duration.as_secs() }),
+            None => Value::Null,
+        };
+        self.call::<Value>(methods::PAUSE, params).map(|_| ())
+    }
+
+    pub fn resume(&mut self) -> Result<()> {
+        self.call::<Value>(methods::RESUME, Value::Null).map(|_| ())
+    }
+
+    /// Write the in-memory minute to the database now
+    pub fn flush(&mut self) -> Result<()> {
+        self.call::<Value>(methods::FLUSH, Value::Null).map(|_| ())
+    }
+
+    /// Tag typing from now on with `tag`, returning the active tags
+    pub fn tag_start(&mut self, tag: &str) -> Result<Vec<String>> {
+        self.call::<TagsResult>(methods::TAG_START, serde_json::json!({ "tag": tag }))
+            .map(|result| result.tags)
+    }
+
+    /// Stop tagging with `tag`, or with every tag if `None`
+    pub fn tag_stop(&mut self, tag: Option<&str>) -> Result<Vec<String>> {
+        let params = match tag {
+            Some(tag) => serde_json::json!({ "tag": tag }),
+            None => Value::Null,
+        };
+        self.call::<TagsResult>(methods::TAG_STOP, params)
+            .map(|result| result.tags)
+    }
+
+    /// Report what an editor is editing, the same as `POST /api/editor-context`
+    pub fn editor_context(&mut self, ctx: &crate::EditorContext) -> Result<()> {
+        self.call::<Value>(methods::EDITOR_CONTEXT, serde_json::to_value(ctx)?)
+            .map(|_| ())
+    }
+
+    /// Report the focused tab, the same as `POST /api/browser-context`
+    pub fn browser_context(&mut self, report: &crate::TabReport) -> Result<()> {
+        self.call::<Value>(methods::BROWSER_CONTEXT, serde_json::to_value(report)?)
+            .map(|_| ())
+    }
+
+    /// Report the project the prompt of shell `pid` is in, or `None` outside
+    /// any project
+    pub fn shell_context(&mut self, pid: Option<u32>, project: Option<&str>) -> Result<()> {
+        self.call::<Value>(
+            methods::SHELL_CONTEXT,
+            serde_json::json!({ "pid": pid, "project": project }),
+        )
+        .map(|_| ())
+    }
+
+    /// Re-read the config file, returning the settings that changed
+    pub fn reload_config(&mut self) -> Result<Vec<String>> {
+        #[derive(Deserialize)]
+        struct Reloaded {
+            #[serde(default)]
+            changes: Vec<String>,
+        }
+
+        self.call::<Reloaded>(methods::RELOAD_CONFIG, Value::Null)
+            .map(|reloaded| reloaded.changes)
+    }
+
+    /// Flush and stop the daemon
+    pub fn shutdown(&mut self) -> Result<()> {
+        self.call::<Value>(methods::SHUTDOWN, Value::Null)
+            .map(|_| ())
+    }
+
+    /// Switch this connection to the live feed
+    pub fn live(mut self) -> Result<LiveFeed> {
+        self.call::<Value>(methods::LIVE, Value::Null)?;
+        Ok(LiveFeed { client: self })
+    }
+}
+
+/// Snapshots from the daemon, one a second, until it goes away
+pub struct LiveFeed {
+    #[cfg_attr(not(unix), allow(dead_code))]
+    client: ControlClient,
+}
+
+impl Iterator for LiveFeed {
+    type Item = Result<LiveSnapshot>;
+
+    #[cfg(unix)]
+    fn next(&mut self) -> Option<Self::Item> {
+        use std::io::BufRead;
+
+        let mut line = String::new();
+        match self.client.stream.read_line(&mut line) {
+            Ok(0) => None,
+            Ok(_) => Some(
+                serde_json::from_str::<RpcNotification>(&line)
+                    .and_then(|notification| serde_json::from_value(notification.params))
+                    .map_err(ControlError::from),
+            ),
+            Err(e) => Some(Err(e.into())),
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn next(&mut self) -> Option<Self::Item> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_response_wire_format() {
+        let ok =
+            serde_json::to_value(RpcResponse::success(Value::from(1), Value::Bool(true))).unwrap();
+        assert_eq!(
+            ok,
+            serde_json::json!({"jsonrpc": "2.0", "id": 1, "result": true})
+        );
+
+        let err = RpcResponse::failure(Value::from(2), codes::METHOD_NOT_FOUND, "Unknown method");
+        let err = serde_json::to_value(err).unwrap();
+        assert_eq!(err["error"]["code"], codes::METHOD_NOT_FOUND);
+        assert!(err.get("result").is_none());
+    }
+
+    #[test]
+    fn test_request_params_default() {
+        let request: RpcRequest =
+            serde_json::from_str(r#"{"jsonrpc":"2.0","id":7,"method":"status"}"#).unwrap();
+        assert_eq!(request.method, methods::STATUS);
+        assert!(request.params.is_null());
+    }
+
+    #[test]
+    fn test_live_notification() {
+        let line = r#"{"jsonrpc":"2.0","method":"live","params":{"at":"2026-10-18T09:00:01Z",
+            "current_app":"Code","category":"Coding","keys_last_second":7,"wpm":64.5,
+            "session":null,"paused":false,"not_recording":null}}"#;
+        let notification: RpcNotification = serde_json::from_str(line).unwrap();
+        assert_eq!(notification.method, methods::LIVE);
+        let snapshot: LiveSnapshot = serde_json::from_value(notification.params).unwrap();
+        assert_eq!(snapshot.category, Some(Category::Coding));
+        assert_eq!(snapshot.keys_last_second, 7);
+    }
+}
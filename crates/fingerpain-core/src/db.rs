@@ -2,9 +2,15 @@
 //!
 //! Handles all SQLite operations including schema creation, inserts, and queries.
 
-use crate::{AggregatedStats, AppStats, BrowserContext, DomainStats, HourlyStats, KeystrokeRecord, PeakInfo, TypingSession};
+use crate::apps::Category;
+use crate::browsers::BrowserRegistry;
+use crate::domains::{DomainGroups, DomainLevel};
+use crate::privacy::{Gap, GapReason, UrlPolicy, UrlRedactor};
+use crate::{AggregatedStats, ApiToken, AppStats, BrowserContext, DomainStats, EditorContext, HourlyStats, KeystrokeRecord, LanguageStats, PeakInfo, ProjectStats, TagStats, TypingSession};
 use chrono::{DateTime, TimeZone, Utc};
-use rusqlite::{params, Connection, Result as SqliteResult};
+use rusqlite::{params, Connection, ErrorCode, OptionalExtension, Result as SqliteResult};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::path::Path;
 use thiserror::Error;
 use tracing::info;
@@ -19,8 +25,121 @@ pub enum DbError {
     NotFound,
 }
 
+impl DbError {
+    /// Another connection held the database past the busy timeout
+    pub fn is_busy(&self) -> bool {
+        matches!(
+            self,
+            DbError::Sqlite(rusqlite::Error::SqliteFailure(e, _))
+                if matches!(e.code, ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked)
+        )
+    }
+}
+
 pub type Result<T> = std::result::Result<T, DbError>;
 
+/// Narrows which keystroke records a query includes
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecordFilter {
+    /// Only minutes stamped with this project tag
+    pub tag: Option<String>,
+    /// Only typing in this app, by bundle ID or name
+    pub app: Option<String>,
+    /// Only minutes with typing on this domain or its subdomains
+    pub domain: Option<String>,
+}
+
+impl RecordFilter {
+    pub fn tag(tag: impl Into<String>) -> Self {
+        Self {
+            tag: Some(tag.into()),
+            ..Self::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tag.is_none() && self.app.is_none() && self.domain.is_none()
+    }
+
+    /// Condition on the `keystrokes` row `row`, with the tag, app and domain
+    /// bound as parameters `first`, `first + 1` and `first + 2`
+    ///
+    /// A minute split by domain matches if any of its domains does; an older
+    /// minute matches on the domain it was stamped with.
+    fn sql(row: &str, first: usize) -> String {
+        let (tag, app, domain) = (first, first + 1, first + 2);
+        let split_match = domain_match("md.domain", domain);
+        let legacy_match = domain_match(&format!("{row}.browser_domain"), domain);
+        format!(
+            r#"(?{tag} IS NULL OR {row}.timestamp IN
+                (SELECT timestamp FROM minute_tags WHERE tag = ?{tag}))
+              AND (?{app} IS NULL OR {row}.app_bundle_id = ?{app} OR {row}.app_name = ?{app})
+              AND (?{domain} IS NULL
+                OR EXISTS (
+                    SELECT 1 FROM minute_domains md
+                    WHERE md.timestamp = {row}.timestamp
+                        AND md.app_bundle_id = {row}.app_bundle_id
+                        AND {split_match}
+                )
+                OR ({legacy_match} AND NOT EXISTS (
+                    SELECT 1 FROM minute_domains md
+                    WHERE md.timestamp = {row}.timestamp
+                        AND md.app_bundle_id = {row}.app_bundle_id
+                )))"#
+        )
+    }
+
+    /// The `keystrokes` table as `alias`, with the domain bound as parameter
+    /// `first + 2`
+    ///
+    /// Under a domain filter, minutes split by domain only count the typing on
+    /// matching domains. Paragraphs and backspaces aren't split, so they are
+    /// shared out by characters.
+    fn source(alias: &str, first: usize) -> String {
+        let domain = first + 2;
+        let split_match = domain_match("md.domain", domain);
+        format!(
+            r#"(SELECT id, timestamp, app_name, app_bundle_id, browser_domain, browser_url,
+                    project, language,
+                    CASE WHEN split THEN chars ELSE char_count END AS char_count,
+                    CASE WHEN split THEN words ELSE word_count END AS word_count,
+                    CASE WHEN split THEN paragraph_count * chars / MAX(char_count, 1)
+                        ELSE paragraph_count END AS paragraph_count,
+                    CASE WHEN split THEN backspace_count * chars / MAX(char_count, 1)
+                        ELSE backspace_count END AS backspace_count
+                FROM (
+                    SELECT r.*,
+                        ?{domain} IS NOT NULL AND EXISTS (
+                            SELECT 1 FROM minute_domains md
+                            WHERE md.timestamp = r.timestamp AND md.app_bundle_id = r.app_bundle_id
+                        ) AS split,
+                        CASE WHEN ?{domain} IS NULL THEN 0 ELSE (
+                            SELECT COALESCE(SUM(md.char_count), 0) FROM minute_domains md
+                            WHERE md.timestamp = r.timestamp AND md.app_bundle_id = r.app_bundle_id
+                                AND {split_match}
+                        ) END AS chars,
+                        CASE WHEN ?{domain} IS NULL THEN 0 ELSE (
+                            SELECT COALESCE(SUM(md.word_count), 0) FROM minute_domains md
+                            WHERE md.timestamp = r.timestamp AND md.app_bundle_id = r.app_bundle_id
+                                AND {split_match}
+                        ) END AS words
+                    FROM keystrokes r
+                )) AS {alias}"#
+        )
+    }
+}
+
+/// Condition that `column` is the domain bound as parameter `param`, or one
+/// of its subdomains
+///
+/// `%` and `_` in the domain are matched literally.
+fn domain_match(column: &str, param: usize) -> String {
+    format!(
+        r#"({column} = ?{param} OR {column} LIKE '%.' || replace(replace(replace(?{param},
+            '\', '\\'), '%', '\%'), '_', '\_') ESCAPE '\')"#
+    )
+}
+
 pub struct Database {
     conn: Connection,
 }
@@ -34,14 +153,16 @@ impl Database {
         }
 
         let conn = Connection::open(path)?;
+        // The daemon writer, web server and CLI share the file
+        conn.busy_timeout(std::time::Duration::from_secs(5))?;
         let db = Self { conn };
         db.init_schema()?;
         Ok(db)
     }
 
-    /// Open the default database
-    pub fn open_default() -> Result<Self> {
-        Self::open(crate::db_path())
+    /// How long to wait for another connection's write before failing as busy
+    pub fn set_busy_timeout(&self, timeout: std::time::Duration) -> Result<()> {
+        Ok(self.conn.busy_timeout(timeout)?)
     }
 
     /// Initialize database schema
@@ -88,60 +209,366 @@ impl Database {
                 avg_wpm REAL,
                 peak_wpm REAL
             );
+
+            -- Time during which nothing was recorded (paused, excluded, private)
+            CREATE TABLE IF NOT EXISTS gaps (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                start_time INTEGER NOT NULL,
+                end_time INTEGER NOT NULL,
+                reason TEXT NOT NULL,
+                UNIQUE(start_time, reason)
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_gaps_end ON gaps(end_time);
+
+            -- Project tags active during each recorded minute
+            CREATE TABLE IF NOT EXISTS minute_tags (
+                timestamp INTEGER NOT NULL,
+                tag TEXT NOT NULL,
+                PRIMARY KEY(timestamp, tag)
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_minute_tags_tag ON minute_tags(tag, timestamp);
+
+            -- Latest context reported by each editor's plugin
+            CREATE TABLE IF NOT EXISTS editor_context (
+                editor TEXT PRIMARY KEY,
+                language TEXT,
+                project TEXT,
+                last_updated INTEGER NOT NULL
+            );
+
+            -- Every tab change reported by the browser extension, per window, for
+            -- a day (times in milliseconds; replaces the single-row browser_context)
+            CREATE TABLE IF NOT EXISTS browser_context_log (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                browser_name TEXT NOT NULL,
+                profile TEXT NOT NULL DEFAULT '',
+                window_id INTEGER,
+                url TEXT NOT NULL,
+                domain TEXT NOT NULL,
+                page_title TEXT NOT NULL,
+                private INTEGER NOT NULL DEFAULT 0,
+                timestamp INTEGER NOT NULL,
+                last_seen INTEGER NOT NULL,
+                excluded INTEGER NOT NULL DEFAULT 0
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_browser_context_log_seen
+                ON browser_context_log(browser_name, last_seen);
+
+            -- Browser typing per domain within each minute ('' when unknown)
+            CREATE TABLE IF NOT EXISTS minute_domains (
+                timestamp INTEGER NOT NULL,
+                app_bundle_id TEXT NOT NULL,
+                domain TEXT NOT NULL,
+                char_count INTEGER NOT NULL DEFAULT 0,
+                word_count INTEGER NOT NULL DEFAULT 0,
+                PRIMARY KEY(timestamp, app_bundle_id, domain)
+            );
+
+            -- Values chosen once per database, such as the URL hash salt
+            CREATE TABLE IF NOT EXISTS settings (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            );
+
+            -- Web API tokens, by name; only a SHA-256 hash of each is kept
+            CREATE TABLE IF NOT EXISTS api_tokens (
+                name TEXT PRIMARY KEY,
+                token_hash TEXT NOT NULL UNIQUE,
+                origin TEXT,
+                created_at INTEGER NOT NULL,
+                last_used INTEGER
+            );
+
+            -- One-time codes a browser extension trades for a token
+            CREATE TABLE IF NOT EXISTS pairing_codes (
+                code TEXT PRIMARY KEY,
+                expires_at INTEGER NOT NULL
+            );
+
+            -- One-time data migrations that have already run
+            CREATE TABLE IF NOT EXISTS schema_migrations (
+                name TEXT PRIMARY KEY,
+                applied_at INTEGER NOT NULL
+            );
             "#,
         )?;
 
-        // Run migrations
+        // Run migrations. v2 (app names) is run by the daemon, which can
+        // resolve window classes; v3 added `browser_context.private` and was
+        // dropped along with that table, which v7 moves into the log.
         self.migrate_v1_browser_tracking()?;
+        self.migrate_v4_projects()?;
+        self.migrate_v5_languages()?;
+        self.migrate_v6_excluded_tabs()?;
+        self.migrate_v7_drop_browser_context()?;
 
+        self.prune_browser_context(Utc::now())?;
         Ok(())
     }
 
     /// Migrate to browser tracking (v1)
     fn migrate_v1_browser_tracking(&self) -> Result<()> {
-        // Check if browser_context table exists
-        let table_exists: bool = self
-            .conn
-            .query_row(
-                "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name='browser_context'",
-                [],
-                |row| row.get::<_, i64>(0).map(|count| count > 0),
-            )
-            .unwrap_or(false);
+        // Add browser columns to keystrokes table if they don't exist
+        if !self.column_exists("keystrokes", "browser_domain")? {
+            self.conn
+                .execute("ALTER TABLE keystrokes ADD COLUMN browser_domain TEXT", [])?;
+            self.conn
+                .execute("ALTER TABLE keystrokes ADD COLUMN browser_url TEXT", [])?;
+        }
 
-        if !table_exists {
-            info!("Running migration: browser tracking v1");
+        Ok(())
+    }
 
+    /// Record the project found in window titles for each minute (v4)
+    fn migrate_v4_projects(&self) -> Result<()> {
+        if !self.column_exists("keystrokes", "project")? {
+            info!("Running migration: projects v4");
             self.conn.execute_batch(
                 r#"
-                CREATE TABLE browser_context (
-                    id INTEGER PRIMARY KEY AUTOINCREMENT,
-                    timestamp INTEGER NOT NULL,
-                    browser_name TEXT NOT NULL,
-                    url TEXT,
-                    domain TEXT,
-                    page_title TEXT,
-                    last_updated INTEGER NOT NULL,
-                    UNIQUE(browser_name)
-                );
+                ALTER TABLE keystrokes ADD COLUMN project TEXT;
+                CREATE INDEX idx_keystrokes_project ON keystrokes(project, timestamp);
+                "#,
+            )?;
+        }
 
-                CREATE INDEX idx_browser_context_browser ON browser_context(browser_name);
-                CREATE INDEX idx_browser_context_updated ON browser_context(last_updated);
+        Ok(())
+    }
+
+    /// Record the language reported by editor plugins for each minute (v5)
+    fn migrate_v5_languages(&self) -> Result<()> {
+        if !self.column_exists("keystrokes", "language")? {
+            info!("Running migration: languages v5");
+            self.conn.execute_batch(
+                r#"
+                ALTER TABLE keystrokes ADD COLUMN language TEXT;
+                CREATE INDEX idx_keystrokes_language ON keystrokes(language, timestamp);
                 "#,
             )?;
         }
 
-        // Add browser columns to keystrokes table if they don't exist
-        if !self.column_exists("keystrokes", "browser_domain")? {
-            self.conn
-                .execute("ALTER TABLE keystrokes ADD COLUMN browser_domain TEXT", [])?;
-            self.conn
-                .execute("ALTER TABLE keystrokes ADD COLUMN browser_url TEXT", [])?;
+        Ok(())
+    }
+
+    /// Mark tabs on excluded domains instead of logging them (v6)
+    fn migrate_v6_excluded_tabs(&self) -> Result<()> {
+        if !self.column_exists("browser_context_log", "excluded")? {
+            info!("Running migration: excluded tabs v6");
+            self.conn.execute(
+                "ALTER TABLE browser_context_log ADD COLUMN excluded INTEGER NOT NULL DEFAULT 0",
+                [],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Move the last tab of each browser from the single-row table that
+    /// `browser_context_log` replaced into the log, and drop the table (v7)
+    fn migrate_v7_drop_browser_context(&self) -> Result<()> {
+        let table_exists: bool = self.conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name='browser_context'",
+            [],
+            |row| row.get::<_, i64>(0).map(|count| count > 0),
+        )?;
+        if !table_exists {
+            return Ok(());
         }
 
+        info!("Running migration: drop browser_context v7");
+        // The table only has `private` if the removed v3 migration (private
+        // windows) ran on it
+        let private = if self.column_exists("browser_context", "private")? {
+            "private"
+        } else {
+            "0"
+        };
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute_batch(&format!(
+            r#"
+            INSERT INTO browser_context_log
+                (browser_name, url, domain, page_title, private, timestamp, last_seen)
+            SELECT browser_name, COALESCE(url, ''), COALESCE(domain, ''),
+                COALESCE(page_title, ''), {private}, timestamp * 1000, last_updated * 1000
+            FROM browser_context;
+            DROP TABLE browser_context;
+            "#
+        ))?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Rewrite app names recorded from window titles (Linux, pre-v2)
+    ///
+    /// Older Linux builds stored `_NET_WM_NAME` as the app name. This replaces
+    /// every `app_name` with the name resolved for its `app_bundle_id`, falling
+    /// back to the bundle ID itself so no title survives. Runs only once.
+    pub fn migrate_v2_app_names<F>(&self, resolve: F) -> Result<usize>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        const MIGRATION: &str = "v2_app_names";
+
+        if self.migration_applied(MIGRATION)? {
+            return Ok(0);
+        }
+
+        info!("Running migration: app names v2");
+
+        let tx = self.conn.unchecked_transaction()?;
+        let bundle_ids: Vec<String> = {
+            let mut stmt = tx.prepare(
+                "SELECT DISTINCT app_bundle_id FROM keystrokes WHERE app_bundle_id IS NOT NULL",
+            )?;
+            let rows = stmt.query_map([], |row| row.get(0))?;
+            rows.collect::<SqliteResult<Vec<_>>>()?
+        };
+
+        let mut updated = 0;
+        for bundle_id in bundle_ids {
+            let name = resolve(&bundle_id).unwrap_or_else(|| bundle_id.clone());
+            updated += tx.execute(
+                "UPDATE keystrokes SET app_name = ?1 WHERE app_bundle_id = ?2 AND app_name IS NOT ?1",
+                params![name, bundle_id],
+            )?;
+        }
+
+        tx.execute(
+            "INSERT INTO schema_migrations (name, applied_at) VALUES (?1, ?2)",
+            params![MIGRATION, Utc::now().timestamp()],
+        )?;
+        tx.commit()?;
+
+        Ok(updated)
+    }
+
+    /// Cut every stored URL and page title down to what `urls` allows
+    ///
+    /// Rows written before the URL policy existed hold full URLs, query strings
+    /// included. This runs whenever the policy differs from the one stored
+    /// rows were last redacted with, and does nothing otherwise.
+    pub fn redact_urls(&self, urls: &UrlRedactor) -> Result<usize> {
+        let policy = urls.policy().as_str();
+        if self.setting(URL_POLICY_KEY)?.as_deref() == Some(policy) {
+            return Ok(0);
+        }
+
+        info!("Redacting stored URLs to policy '{}'", policy);
+
+        let tx = self.conn.unchecked_transaction()?;
+        let mut updated = 0;
+        for (table, column) in [
+            ("browser_context_log", "url"),
+            ("keystrokes", "browser_url"),
+        ] {
+            let stored: Vec<String> = {
+                let mut stmt = tx.prepare(&format!(
+                    "SELECT DISTINCT {1} FROM {0} WHERE {1} IS NOT NULL",
+                    table, column
+                ))?;
+                let rows = stmt.query_map([], |row| row.get(0))?;
+                rows.collect::<SqliteResult<Vec<_>>>()?
+            };
+
+            for url in stored {
+                let redacted = urls.redact(&url);
+                if redacted != url {
+                    // keystrokes keeps NULL for minutes without a URL
+                    let keep = table != "keystrokes" || !redacted.is_empty();
+                    updated += tx.execute(
+                        &format!("UPDATE {0} SET {1} = ?1 WHERE {1} = ?2", table, column),
+                        params![keep.then_some(redacted), url],
+                    )?;
+                }
+            }
+        }
+
+        let titles: Vec<String> = {
+            let mut stmt = tx.prepare("SELECT DISTINCT page_title FROM browser_context_log")?;
+            let rows = stmt.query_map([], |row| row.get(0))?;
+            rows.collect::<SqliteResult<Vec<_>>>()?
+        };
+        for title in titles {
+            let redacted = urls.redact_title(&title);
+            if redacted != title {
+                updated += tx.execute(
+                    "UPDATE browser_context_log SET page_title = ?1 WHERE page_title = ?2",
+                    params![redacted, title],
+                )?;
+            }
+        }
+
+        tx.execute(
+            "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+            params![URL_POLICY_KEY, policy],
+        )?;
+        tx.commit()?;
+
+        Ok(updated)
+    }
+
+    /// Policy the stored URLs were last redacted with, i.e. the one in effect
+    /// for whoever applied a config last
+    pub fn url_policy(&self) -> Result<Option<UrlPolicy>> {
+        let stored = self.setting(URL_POLICY_KEY)?;
+        Ok(UrlPolicy::ALL
+            .into_iter()
+            .find(|policy| stored.as_deref() == Some(policy.as_str())))
+    }
+
+    /// Record `privacy.domains`, so every process logs tabs on them the same way
+    pub fn set_excluded_domains(&self, domains: &[String]) -> Result<()> {
+        let domains = serde_json::to_string(domains).expect("strings serialize");
+        self.conn.execute(
+            "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+            params![EXCLUDED_DOMAINS_KEY, domains],
+        )?;
         Ok(())
     }
 
+    /// `privacy.domains` as recorded by whoever applied a config last
+    pub fn excluded_domains(&self) -> Result<Option<Vec<String>>> {
+        Ok(self
+            .setting(EXCLUDED_DOMAINS_KEY)?
+            .and_then(|domains| serde_json::from_str(&domains).ok()))
+    }
+
+    /// Redactor for `policy`, with this database's salt, generated on first use
+    pub fn url_redactor(&self, policy: UrlPolicy) -> Result<UrlRedactor> {
+        const SALT_KEY: &str = "url_salt";
+
+        self.conn.execute(
+            "INSERT OR IGNORE INTO settings (key, value) VALUES (?1, lower(hex(randomblob(32))))",
+            params![SALT_KEY],
+        )?;
+        let salt = self.setting(SALT_KEY)?.ok_or(DbError::NotFound)?;
+        Ok(UrlRedactor::new(policy, salt))
+    }
+
+    fn setting(&self, key: &str) -> Result<Option<String>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT value FROM settings WHERE key = ?1",
+                params![key],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// Check if a one-time migration has been recorded
+    fn migration_applied(&self, name: &str) -> Result<bool> {
+        let count: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM schema_migrations WHERE name = ?1",
+            params![name],
+            |row| row.get(0),
+        )?;
+        Ok(count > 0)
+    }
+
     /// Check if a column exists in a table
     fn column_exists(&self, table: &str, column: &str) -> Result<bool> {
         let mut stmt = self
@@ -163,20 +590,75 @@ impl Database {
 
     /// Insert or update a keystroke record for the current minute
     pub fn upsert_keystroke(&self, record: &KeystrokeRecord) -> Result<i64> {
+        Self::upsert_keystroke_on(&self.conn, record)?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Insert or update a batch of keystroke records in a single transaction
+    pub fn upsert_keystrokes(&self, records: &[KeystrokeRecord]) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        for record in records {
+            Self::upsert_keystroke_on(&tx, record)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Commit a numbered batch of records, remembering the number in the same
+    /// transaction
+    ///
+    /// A batch numbered at or below the last committed one is skipped, so a
+    /// batch can be replayed until its commit is confirmed without being
+    /// counted twice. Returns whether the batch was written.
+    pub fn upsert_keystroke_batch(&self, batch: u64, records: &[KeystrokeRecord]) -> Result<bool> {
+        let tx = self.conn.unchecked_transaction()?;
+        if batch <= Self::committed_batch_on(&tx)? {
+            return Ok(false);
+        }
+        for record in records {
+            Self::upsert_keystroke_on(&tx, record)?;
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+            params![COMMITTED_BATCH_KEY, batch.to_string()],
+        )?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Number of the last batch committed by `upsert_keystroke_batch`, or 0
+    pub fn committed_batch(&self) -> Result<u64> {
+        Self::committed_batch_on(&self.conn)
+    }
+
+    fn committed_batch_on(conn: &Connection) -> Result<u64> {
+        let value: Option<String> = conn
+            .query_row(
+                "SELECT value FROM settings WHERE key = ?1",
+                params![COMMITTED_BATCH_KEY],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(value.and_then(|value| value.parse().ok()).unwrap_or(0))
+    }
+
+    fn upsert_keystroke_on(conn: &Connection, record: &KeystrokeRecord) -> Result<()> {
         let timestamp = record.timestamp.timestamp();
         let minute_timestamp = (timestamp / 60) * 60; // Round to minute
 
-        self.conn.execute(
+        conn.execute(
             r#"
-            INSERT INTO keystrokes (timestamp, app_name, app_bundle_id, char_count, word_count, paragraph_count, backspace_count, browser_domain, browser_url)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+            INSERT INTO keystrokes (timestamp, app_name, app_bundle_id, char_count, word_count, paragraph_count, backspace_count, browser_domain, browser_url, project, language)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
             ON CONFLICT(timestamp, app_bundle_id) DO UPDATE SET
                 char_count = char_count + excluded.char_count,
                 word_count = word_count + excluded.word_count,
                 paragraph_count = paragraph_count + excluded.paragraph_count,
                 backspace_count = backspace_count + excluded.backspace_count,
                 browser_domain = COALESCE(excluded.browser_domain, browser_domain),
-                browser_url = COALESCE(excluded.browser_url, browser_url)
+                browser_url = COALESCE(excluded.browser_url, browser_url),
+                project = COALESCE(excluded.project, project),
+                language = COALESCE(excluded.language, language)
             "#,
             params![
                 minute_timestamp,
@@ -188,10 +670,40 @@ impl Database {
                 record.backspace_count,
                 record.browser_domain,
                 record.browser_url,
+                record.project,
+                record.language,
             ],
         )?;
 
-        Ok(self.conn.last_insert_rowid())
+        for tag in &record.tags {
+            conn.execute(
+                "INSERT OR IGNORE INTO minute_tags (timestamp, tag) VALUES (?1, ?2)",
+                params![minute_timestamp, tag],
+            )?;
+        }
+
+        if let Some(bundle_id) = &record.app_bundle_id {
+            for domain in &record.domains {
+                conn.execute(
+                    r#"
+                    INSERT INTO minute_domains (timestamp, app_bundle_id, domain, char_count, word_count)
+                    VALUES (?1, ?2, ?3, ?4, ?5)
+                    ON CONFLICT(timestamp, app_bundle_id, domain) DO UPDATE SET
+                        char_count = char_count + excluded.char_count,
+                        word_count = word_count + excluded.word_count
+                    "#,
+                    params![
+                        minute_timestamp,
+                        bundle_id,
+                        domain.domain,
+                        domain.char_count,
+                        domain.word_count,
+                    ],
+                )?;
+            }
+        }
+
+        Ok(())
     }
 
     /// Insert a new typing session
@@ -242,11 +754,18 @@ impl Database {
     }
 
     /// Get aggregated stats for a time range
-    pub fn get_stats(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<AggregatedStats> {
+    pub fn get_stats(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        filter: &RecordFilter,
+    ) -> Result<AggregatedStats> {
         let start_ts = start.timestamp();
         let end_ts = end.timestamp();
+        let filter_sql = RecordFilter::sql("keystrokes", 3);
+        let source = RecordFilter::source("keystrokes", 3);
 
-        let mut stmt = self.conn.prepare(
+        let mut stmt = self.conn.prepare(&format!(
             r#"
             SELECT
                 COALESCE(SUM(char_count), 0) as total_chars,
@@ -254,18 +773,20 @@ impl Database {
                 COALESCE(SUM(paragraph_count), 0) as total_paragraphs,
                 COALESCE(SUM(backspace_count), 0) as total_backspaces,
                 COUNT(DISTINCT timestamp) as active_minutes
-            FROM keystrokes
+            FROM {source}
             WHERE timestamp >= ?1 AND timestamp < ?2
-            "#,
-        )?;
+              AND {filter_sql}
+            "#
+        ))?;
 
+        let params = params![start_ts, end_ts, filter.tag, filter.app, filter.domain];
         let (total_chars, total_words, total_paragraphs, total_backspaces, active_minutes): (
             i64,
             i64,
             i64,
             i64,
             i64,
-        ) = stmt.query_row(params![start_ts, end_ts], |row| {
+        ) = stmt.query_row(params, |row| {
             Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?))
         })?;
 
@@ -275,11 +796,36 @@ impl Database {
             SELECT AVG(wpm_avg), MAX(wpm_peak)
             FROM sessions
             WHERE start_time >= ?1 AND start_time < ?2 AND wpm_avg IS NOT NULL
+              AND (?3 IS NULL OR (start_time / 60) * 60 IN
+                   (SELECT timestamp FROM minute_tags WHERE tag = ?3))
             "#,
         )?;
 
+        // Sessions span apps and domains, so their speed can't be narrowed to one
         let (avg_wpm, peak_wpm): (Option<f64>, Option<f64>) =
-            wpm_stmt.query_row(params![start_ts, end_ts], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            if filter.app.is_some() || filter.domain.is_some() {
+                (None, None)
+            } else {
+                wpm_stmt.query_row(params![start_ts, end_ts, filter.tag], |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })?
+            };
+
+        // Untracked time belongs to no project, app or domain
+        let gaps = if !filter.is_empty() {
+            Vec::new()
+        } else {
+            self.get_gaps(start, end)?
+        };
+        let mut paused_minutes = 0;
+        let mut excluded_minutes = 0;
+        for gap in gaps {
+            let minutes = gap.minutes_within(start, end);
+            match gap.reason {
+                GapReason::Paused => paused_minutes += minutes,
+                GapReason::Excluded | GapReason::Private => excluded_minutes += minutes,
+            }
+        }
 
         Ok(AggregatedStats {
             period_start: start,
@@ -292,6 +838,8 @@ impl Database {
             avg_wpm,
             peak_wpm,
             active_minutes: active_minutes as u32,
+            paused_minutes,
+            excluded_minutes,
         })
     }
 
@@ -300,14 +848,23 @@ impl Database {
         &self,
         start: DateTime<Utc>,
         end: DateTime<Utc>,
+        filter: &RecordFilter,
     ) -> Result<Vec<AppStats>> {
         let start_ts = start.timestamp();
         let end_ts = end.timestamp();
+        let filter_sql = RecordFilter::sql("keystrokes", 3);
+        let source = RecordFilter::source("keystrokes", 3);
 
         // First get total chars for percentage calculation
         let total: i64 = self.conn.query_row(
-            "SELECT COALESCE(SUM(char_count), 0) FROM keystrokes WHERE timestamp >= ?1 AND timestamp < ?2",
-            params![start_ts, end_ts],
+            &format!(
+                r#"
+            SELECT COALESCE(SUM(char_count), 0) FROM {source}
+            WHERE timestamp >= ?1 AND timestamp < ?2
+              AND {filter_sql}
+                "#
+            ),
+            params![start_ts, end_ts, filter.tag, filter.app, filter.domain],
             |row| row.get(0),
         )?;
 
@@ -315,21 +872,23 @@ impl Database {
             return Ok(Vec::new());
         }
 
-        let mut stmt = self.conn.prepare(
+        let mut stmt = self.conn.prepare(&format!(
             r#"
             SELECT
                 COALESCE(app_name, 'Unknown') as app_name,
                 COALESCE(app_bundle_id, 'unknown') as app_bundle_id,
                 SUM(char_count) as total_chars,
                 SUM(word_count) as total_words
-            FROM keystrokes
+            FROM {source}
             WHERE timestamp >= ?1 AND timestamp < ?2
+              AND {filter_sql}
             GROUP BY app_bundle_id
             ORDER BY total_chars DESC
-            "#,
-        )?;
+            "#
+        ))?;
 
-        let rows = stmt.query_map(params![start_ts, end_ts], |row| {
+        let params = params![start_ts, end_ts, filter.tag, filter.app, filter.domain];
+        let rows = stmt.query_map(params, |row| {
             let chars: i64 = row.get(2)?;
             Ok(AppStats {
                 app_name: row.get(0)?,
@@ -337,6 +896,7 @@ impl Database {
                 total_chars: chars as u64,
                 total_words: row.get::<_, i64>(3)? as u64,
                 percentage: (chars as f64 / total as f64) * 100.0,
+                category: Category::Other,
                 browser_domains: None,
             })
         })?;
@@ -345,25 +905,34 @@ impl Database {
     }
 
     /// Get hourly breakdown for heatmap
-    pub fn get_hourly_stats(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Vec<HourlyStats>> {
+    pub fn get_hourly_stats(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        filter: &RecordFilter,
+    ) -> Result<Vec<HourlyStats>> {
         let start_ts = start.timestamp();
         let end_ts = end.timestamp();
+        let filter_sql = RecordFilter::sql("keystrokes", 3);
+        let source = RecordFilter::source("keystrokes", 3);
 
-        let mut stmt = self.conn.prepare(
+        let mut stmt = self.conn.prepare(&format!(
             r#"
             SELECT
                 CAST(strftime('%H', timestamp, 'unixepoch', 'localtime') AS INTEGER) as hour,
                 CAST(strftime('%w', timestamp, 'unixepoch', 'localtime') AS INTEGER) as dow,
                 AVG(char_count) as avg_chars,
                 AVG(word_count) as avg_words
-            FROM keystrokes
+            FROM {source}
             WHERE timestamp >= ?1 AND timestamp < ?2
+              AND {filter_sql}
             GROUP BY hour, dow
             ORDER BY dow, hour
-            "#,
-        )?;
+            "#
+        ))?;
 
-        let rows = stmt.query_map(params![start_ts, end_ts], |row| {
+        let params = params![start_ts, end_ts, filter.tag, filter.app, filter.domain];
+        let rows = stmt.query_map(params, |row| {
             Ok(HourlyStats {
                 hour: row.get::<_, i64>(0)? as u8,
                 day_of_week: row.get::<_, i64>(1)? as u8,
@@ -381,25 +950,37 @@ impl Database {
         start: DateTime<Utc>,
         end: DateTime<Utc>,
         limit: usize,
+        filter: &RecordFilter,
     ) -> Result<Vec<PeakInfo>> {
         let start_ts = start.timestamp();
         let end_ts = end.timestamp();
+        let filter_sql = RecordFilter::sql("keystrokes", 4);
+        let source = RecordFilter::source("keystrokes", 4);
 
-        let mut stmt = self.conn.prepare(
+        let mut stmt = self.conn.prepare(&format!(
             r#"
             SELECT
                 timestamp,
                 SUM(char_count) as total_chars,
                 SUM(word_count) as total_words
-            FROM keystrokes
+            FROM {source}
             WHERE timestamp >= ?1 AND timestamp < ?2
+              AND {filter_sql}
             GROUP BY timestamp
             ORDER BY total_chars DESC
             LIMIT ?3
-            "#,
-        )?;
-
-        let rows = stmt.query_map(params![start_ts, end_ts, limit as i64], |row| {
+            "#
+        ))?;
+
+        let params = params![
+            start_ts,
+            end_ts,
+            limit as i64,
+            filter.tag,
+            filter.app,
+            filter.domain
+        ];
+        let rows = stmt.query_map(params, |row| {
             let ts: i64 = row.get(0)?;
             Ok(PeakInfo {
                 timestamp: Utc.timestamp_opt(ts, 0).unwrap(),
@@ -417,24 +998,29 @@ impl Database {
         &self,
         start: DateTime<Utc>,
         end: DateTime<Utc>,
+        filter: &RecordFilter,
     ) -> Result<Vec<(DateTime<Utc>, u64, u64)>> {
         let start_ts = start.timestamp();
         let end_ts = end.timestamp();
+        let filter_sql = RecordFilter::sql("keystrokes", 3);
+        let source = RecordFilter::source("keystrokes", 3);
 
-        let mut stmt = self.conn.prepare(
+        let mut stmt = self.conn.prepare(&format!(
             r#"
             SELECT
                 date(timestamp, 'unixepoch', 'localtime') as day,
                 SUM(char_count) as chars,
                 SUM(word_count) as words
-            FROM keystrokes
+            FROM {source}
             WHERE timestamp >= ?1 AND timestamp < ?2
+              AND {filter_sql}
             GROUP BY day
             ORDER BY day
-            "#,
-        )?;
+            "#
+        ))?;
 
-        let rows = stmt.query_map(params![start_ts, end_ts], |row| {
+        let params = params![start_ts, end_ts, filter.tag, filter.app, filter.domain];
+        let rows = stmt.query_map(params, |row| {
             let day_str: String = row.get(0)?;
             let chars: i64 = row.get(1)?;
             let words: i64 = row.get(2)?;
@@ -459,20 +1045,26 @@ impl Database {
         &self,
         start: DateTime<Utc>,
         end: DateTime<Utc>,
+        filter: &RecordFilter,
     ) -> Result<Vec<KeystrokeRecord>> {
         let start_ts = start.timestamp();
         let end_ts = end.timestamp();
+        let filter_sql = RecordFilter::sql("keystrokes", 3);
+        let source = RecordFilter::source("keystrokes", 3);
 
-        let mut stmt = self.conn.prepare(
+        let mut stmt = self.conn.prepare(&format!(
             r#"
-            SELECT id, timestamp, app_name, app_bundle_id, char_count, word_count, paragraph_count, backspace_count, browser_domain, browser_url
-            FROM keystrokes
+            SELECT id, timestamp, app_name, app_bundle_id, char_count, word_count, paragraph_count, backspace_count, browser_domain, browser_url, project, language,
+                (SELECT GROUP_CONCAT(tag, ',') FROM minute_tags t WHERE t.timestamp = keystrokes.timestamp)
+            FROM {source}
             WHERE timestamp >= ?1 AND timestamp < ?2
+              AND {filter_sql}
             ORDER BY timestamp
-            "#,
-        )?;
+            "#
+        ))?;
 
-        let rows = stmt.query_map(params![start_ts, end_ts], |row| {
+        let params = params![start_ts, end_ts, filter.tag, filter.app, filter.domain];
+        let rows = stmt.query_map(params, |row| {
             let ts: i64 = row.get(1)?;
             Ok(KeystrokeRecord {
                 id: Some(row.get(0)?),
@@ -485,65 +1077,229 @@ impl Database {
                 backspace_count: row.get::<_, i64>(7)? as u32,
                 browser_domain: row.get(8)?,
                 browser_url: row.get(9)?,
+                project: row.get(10)?,
+                language: row.get(11)?,
+                domains: Vec::new(),
+                tags: row
+                    .get::<_, Option<String>>(12)?
+                    .map(|tags| tags.split(',').map(String::from).collect())
+                    .unwrap_or_default(),
             })
         })?;
 
         rows.collect::<SqliteResult<Vec<_>>>().map_err(DbError::from)
     }
 
-    /// Upsert browser context
-    pub fn upsert_browser_context(
+    /// Get typing per project tag, busiest first
+    ///
+    /// A minute with several tags counts towards each of them.
+    pub fn get_tag_stats(
         &self,
-        browser_name: &str,
-        url: &str,
-        domain: &str,
-        title: &str,
-    ) -> Result<()> {
-        let now = Utc::now().timestamp();
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        filter: &RecordFilter,
+    ) -> Result<Vec<TagStats>> {
+        let start_ts = start.timestamp();
+        let end_ts = end.timestamp();
+        // Every tag is reported, so only the app and domain narrow the typing
+        let filter = RecordFilter {
+            tag: None,
+            ..filter.clone()
+        };
+        let filter_sql = RecordFilter::sql("k", 3);
+        let source = RecordFilter::source("k", 3);
 
-        self.conn.execute(
-            r#"
-            INSERT INTO browser_context (browser_name, url, domain, page_title, timestamp, last_updated)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
-            ON CONFLICT(browser_name) DO UPDATE SET
-                url = excluded.url,
-                domain = excluded.domain,
-                page_title = excluded.page_title,
-                last_updated = excluded.last_updated
-            "#,
-            params![browser_name, url, domain, title, now, now],
+        let total: i64 = self.conn.query_row(
+            &format!(
+                "SELECT COALESCE(SUM(char_count), 0) FROM {source}
+                WHERE timestamp >= ?1 AND timestamp < ?2 AND {filter_sql}"
+            ),
+            params![start_ts, end_ts, filter.tag, filter.app, filter.domain],
+            |row| row.get(0),
         )?;
 
-        Ok(())
+        let mut stmt = self.conn.prepare(&format!(
+            r#"
+            SELECT
+                t.tag,
+                SUM(k.char_count) as total_chars,
+                SUM(k.word_count) as total_words,
+                COUNT(DISTINCT k.timestamp) as active_minutes
+            FROM minute_tags t
+            JOIN {source} ON k.timestamp = t.timestamp
+            WHERE t.timestamp >= ?1 AND t.timestamp < ?2
+              AND {filter_sql}
+            GROUP BY t.tag
+            ORDER BY total_chars DESC
+            "#
+        ))?;
+
+        let params = params![start_ts, end_ts, filter.tag, filter.app, filter.domain];
+        let rows = stmt.query_map(params, |row| {
+            let chars: i64 = row.get(1)?;
+            Ok(TagStats {
+                tag: row.get(0)?,
+                total_chars: chars as u64,
+                total_words: row.get::<_, i64>(2)? as u64,
+                active_minutes: row.get::<_, i64>(3)? as u32,
+                percentage: if total > 0 {
+                    (chars as f64 / total as f64) * 100.0
+                } else {
+                    0.0
+                },
+            })
+        })?;
+
+        rows.collect::<SqliteResult<Vec<_>>>().map_err(DbError::from)
     }
 
-    /// Get browser context for a specific browser bundle ID
-    pub fn get_browser_context(&self, bundle_id: &str) -> Result<Option<BrowserContext>> {
-        // Map bundle ID to browser name
-        let browser_name = match bundle_id {
-            "com.JadeApps.Helium" => "Helium",
-            "com.google.Chrome" => "Chrome",
-            "org.mozilla.firefox" => "Firefox",
-            "com.apple.Safari" => "Safari",
-            _ => return Ok(None),
-        };
+    /// Get typing per project, busiest first
+    pub fn get_project_stats(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        filter: &RecordFilter,
+    ) -> Result<Vec<ProjectStats>> {
+        self.group_by_column(
+            "project",
+            start,
+            end,
+            filter,
+            |project, chars, words, minutes, percentage| ProjectStats {
+                project,
+                total_chars: chars,
+                total_words: words,
+                active_minutes: minutes,
+                percentage,
+            },
+        )
+    }
 
-        let mut stmt = self.conn.prepare(
+    /// Get typing per language reported by editors, busiest first
+    pub fn get_language_stats(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        filter: &RecordFilter,
+    ) -> Result<Vec<LanguageStats>> {
+        self.group_by_column(
+            "language",
+            start,
+            end,
+            filter,
+            |language, chars, words, minutes, percentage| LanguageStats {
+                language,
+                total_chars: chars,
+                total_words: words,
+                active_minutes: minutes,
+                percentage,
+            },
+        )
+    }
+
+    /// Sum typing per value of a keystroke column, skipping minutes without one
+    ///
+    /// `make` gets the value, characters, words, active minutes and the share
+    /// of all typing in the range.
+    fn group_by_column<T, F>(
+        &self,
+        column: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        filter: &RecordFilter,
+        make: F,
+    ) -> Result<Vec<T>>
+    where
+        F: Fn(String, u64, u64, u32, f64) -> T,
+    {
+        let start_ts = start.timestamp();
+        let end_ts = end.timestamp();
+        let filter_sql = RecordFilter::sql("keystrokes", 3);
+        let source = RecordFilter::source("keystrokes", 3);
+
+        let total: i64 = self.conn.query_row(
+            &format!(
+                r#"
+            SELECT COALESCE(SUM(char_count), 0) FROM {source}
+            WHERE timestamp >= ?1 AND timestamp < ?2
+              AND {filter_sql}
+                "#
+            ),
+            params![start_ts, end_ts, filter.tag, filter.app, filter.domain],
+            |row| row.get(0),
+        )?;
+
+        let mut stmt = self.conn.prepare(&format!(
             r#"
-            SELECT domain, url, page_title, last_updated
-            FROM browser_context
-            WHERE browser_name = ?1
-            LIMIT 1
+            SELECT
+                {column},
+                SUM(char_count) as total_chars,
+                SUM(word_count) as total_words,
+                COUNT(DISTINCT timestamp) as active_minutes
+            FROM {source}
+            WHERE timestamp >= ?1 AND timestamp < ?2 AND {column} IS NOT NULL
+              AND {filter_sql}
+            GROUP BY {column}
+            ORDER BY total_chars DESC
+            "#
+        ))?;
+
+        let params = params![start_ts, end_ts, filter.tag, filter.app, filter.domain];
+        let rows = stmt.query_map(params, |row| {
+            let chars: i64 = row.get(1)?;
+            let percentage = if total > 0 {
+                (chars as f64 / total as f64) * 100.0
+            } else {
+                0.0
+            };
+            Ok(make(
+                row.get(0)?,
+                chars as u64,
+                row.get::<_, i64>(2)? as u64,
+                row.get::<_, i64>(3)? as u32,
+                percentage,
+            ))
+        })?;
+
+        rows.collect::<SqliteResult<Vec<_>>>().map_err(DbError::from)
+    }
+
+    /// Store the latest context reported by an editor plugin
+    pub fn upsert_editor_context(&self, ctx: &EditorContext) -> Result<()> {
+        self.conn.execute(
+            r#"
+            INSERT INTO editor_context (editor, language, project, last_updated)
+            VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT(editor) DO UPDATE SET
+                language = excluded.language,
+                project = excluded.project,
+                last_updated = excluded.last_updated
             "#,
+            params![ctx.editor, ctx.language, ctx.project, Utc::now().timestamp()],
         )?;
 
-        let result = stmt.query_row(params![browser_name], |row| {
-            Ok(BrowserContext {
-                domain: row.get(0)?,
-                url: row.get(1)?,
-                title: row.get(2)?,
-            })
-        });
+        Ok(())
+    }
+
+    /// Get the latest context for an editor, by the name its plugin reports,
+    /// if it was reported at or after `since`
+    pub fn get_editor_context(
+        &self,
+        editor: &str,
+        since: DateTime<Utc>,
+    ) -> Result<Option<EditorContext>> {
+        let result = self.conn.query_row(
+            "SELECT editor, language, project FROM editor_context
+            WHERE editor = ?1 AND last_updated >= ?2",
+            params![editor, since.timestamp()],
+            |row| {
+                Ok(EditorContext {
+                    editor: row.get(0)?,
+                    language: row.get(1)?,
+                    project: row.get(2)?,
+                })
+            },
+        );
 
         match result {
             Ok(ctx) => Ok(Some(ctx)),
@@ -552,48 +1308,271 @@ impl Database {
         }
     }
 
-    /// Get domain statistics for a browser within a time range
+    /// Log a browser context report
+    ///
+    /// A report of the tab a window already shows only refreshes its
+    /// `last_seen`, so the log grows by tab change rather than by report.
+    pub fn log_browser_context(&self, ctx: &BrowserContext) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        let latest: Option<(i64, String, String, bool, bool)> = tx
+            .query_row(
+                r#"
+                SELECT id, url, domain, private, excluded
+                FROM browser_context_log
+                WHERE browser_name = ?1 AND profile = ?2 AND window_id IS ?3
+                ORDER BY id DESC
+                LIMIT 1
+                "#,
+                params![ctx.browser_name, ctx.profile, ctx.window_id],
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                    ))
+                },
+            )
+            .optional()?;
+
+        // The URL alone can't tell tabs apart once the policy empties it
+        match latest {
+            Some((id, url, domain, private, excluded))
+                if url == ctx.url
+                    && domain == ctx.domain
+                    && private == ctx.private
+                    && excluded == ctx.excluded =>
+            {
+                tx.execute(
+                    "UPDATE browser_context_log SET page_title = ?2, last_seen = ?3 WHERE id = ?1",
+                    params![id, ctx.title, ctx.last_seen.timestamp_millis()],
+                )?;
+            }
+            _ => {
+                tx.execute(
+                    r#"
+                    INSERT INTO browser_context_log
+                        (browser_name, profile, window_id, url, domain, page_title, private,
+                         timestamp, last_seen, excluded)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+                    "#,
+                    params![
+                        ctx.browser_name,
+                        ctx.profile,
+                        ctx.window_id,
+                        ctx.url,
+                        ctx.domain,
+                        ctx.title,
+                        ctx.private,
+                        ctx.started_at.timestamp_millis(),
+                        ctx.last_seen.timestamp_millis(),
+                        ctx.excluded,
+                    ],
+                )?;
+                Self::prune_browser_context_on(&tx, ctx.last_seen)?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Forget tabs last seen more than `BROWSER_CONTEXT_RETENTION` before `now`
+    pub fn prune_browser_context(&self, now: DateTime<Utc>) -> Result<usize> {
+        Self::prune_browser_context_on(&self.conn, now)
+    }
+
+    fn prune_browser_context_on(conn: &Connection, now: DateTime<Utc>) -> Result<usize> {
+        let cutoff = now - BROWSER_CONTEXT_RETENTION;
+        Ok(conn.execute(
+            "DELETE FROM browser_context_log WHERE last_seen < ?1",
+            params![cutoff.timestamp_millis()],
+        )?)
+    }
+
+    /// Most recently reported tab of the browser with an app ID, unless none
+    /// was seen since `since` or the app is no browser
+    pub fn get_browser_context(
+        &self,
+        browsers: &BrowserRegistry,
+        app_id: &str,
+        since: DateTime<Utc>,
+    ) -> Result<Option<BrowserContext>> {
+        let Some(browser_name) = browsers.name(app_id) else {
+            return Ok(None);
+        };
+        self.conn
+            .query_row(
+                &format!(
+                    "{} WHERE browser_name = ?1 AND last_seen >= ?2
+                    ORDER BY last_seen DESC, id DESC LIMIT 1",
+                    BROWSER_CONTEXT_SELECT
+                ),
+                params![browser_name, since.timestamp_millis()],
+                browser_context_from_row,
+            )
+            .optional()
+            .map_err(DbError::from)
+    }
+
+    /// Tabs of every browser that were focused at some point in a time range
+    pub fn get_browser_context_history(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<BrowserContext>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "{} WHERE last_seen >= ?1 AND timestamp < ?2 ORDER BY timestamp, id",
+            BROWSER_CONTEXT_SELECT
+        ))?;
+        let rows = stmt.query_map(
+            params![start.timestamp_millis(), end.timestamp_millis()],
+            browser_context_from_row,
+        )?;
+
+        rows.collect::<SqliteResult<Vec<_>>>().map_err(DbError::from)
+    }
+
+    /// Get domain statistics for browsers within a time range, busiest first
+    ///
+    /// Typing is stored per host; `level` and `groups` decide what the hosts
+    /// are reported as. Unknown tabs count as "Other". Percentages are of all
+    /// typing in the given browsers.
     pub fn get_browser_domains(
         &self,
-        bundle_id: &str,
+        bundle_ids: &[&str],
         start: DateTime<Utc>,
         end: DateTime<Utc>,
-        browser_total: u64,
+        level: DomainLevel,
+        groups: &DomainGroups,
+        filter: &RecordFilter,
     ) -> Result<Vec<DomainStats>> {
         let start_ts = start.timestamp();
         let end_ts = end.timestamp();
+        let bundle_ids = serde_json::to_string(bundle_ids).expect("strings serialize");
+        let domain_sql = domain_match("domain", 6);
 
-        let mut stmt = self.conn.prepare(
+        let mut stmt = self.conn.prepare(&format!(
             r#"
             SELECT
-                COALESCE(browser_domain, 'Other') as domain,
-                SUM(char_count) as total_chars,
-                SUM(word_count) as total_words
-            FROM keystrokes
-            WHERE app_bundle_id = ?1
-                AND timestamp >= ?2
-                AND timestamp < ?3
-            GROUP BY browser_domain
-            ORDER BY total_chars DESC
-            LIMIT 20
-            "#,
-        )?;
+                COALESCE(domain, '') as domain,
+                SUM(chars) as total_chars,
+                SUM(words) as total_words
+            FROM (
+                SELECT app_bundle_id, domain, char_count AS chars, word_count AS words
+                FROM minute_domains
+                WHERE app_bundle_id IN (SELECT value FROM json_each(?1))
+                    AND timestamp >= ?2 AND timestamp < ?3
+                    AND (?4 IS NULL OR timestamp IN
+                        (SELECT timestamp FROM minute_tags WHERE tag = ?4))
+                UNION ALL
+                -- Minutes recorded before typing was split by domain
+                SELECT k.app_bundle_id, k.browser_domain, k.char_count, k.word_count
+                FROM keystrokes k
+                WHERE k.app_bundle_id IN (SELECT value FROM json_each(?1))
+                    AND k.timestamp >= ?2 AND k.timestamp < ?3
+                    AND (?4 IS NULL OR k.timestamp IN
+                        (SELECT timestamp FROM minute_tags WHERE tag = ?4))
+                    AND NOT EXISTS (
+                        SELECT 1 FROM minute_domains d
+                        WHERE d.timestamp = k.timestamp AND d.app_bundle_id = k.app_bundle_id
+                    )
+            )
+            WHERE (?5 IS NULL OR app_bundle_id = ?5 OR app_bundle_id IN
+                    (SELECT app_bundle_id FROM keystrokes WHERE app_name = ?5))
+                AND (?6 IS NULL OR {domain_sql})
+            GROUP BY 1
+            "#
+        ))?;
+
+        let params = params![bundle_ids, start_ts, end_ts, filter.tag, filter.app, filter.domain];
+        let rows = stmt.query_map(params, |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1)? as u64,
+                row.get::<_, i64>(2)? as u64,
+            ))
+        })?;
 
-        let rows = stmt.query_map(params![bundle_id, start_ts, end_ts], |row| {
-            let chars: i64 = row.get(1)?;
-            Ok(DomainStats {
-                domain: row.get(0)?,
-                total_chars: chars as u64,
-                total_words: row.get::<_, i64>(2)? as u64,
+        let mut totals: HashMap<String, (u64, u64)> = HashMap::new();
+        for row in rows {
+            let (host, chars, words) = row?;
+            let total = totals.entry(groups.name(&host, level)).or_default();
+            total.0 += chars;
+            total.1 += words;
+        }
+
+        let browser_total: u64 = totals.values().map(|(chars, _)| chars).sum();
+        let mut domains: Vec<DomainStats> = totals
+            .into_iter()
+            .map(|(domain, (chars, words))| DomainStats {
+                domain,
+                total_chars: chars,
+                total_words: words,
                 percentage: if browser_total > 0 {
                     (chars as f64 / browser_total as f64) * 100.0
                 } else {
                     0.0
                 },
             })
+            .collect();
+        domains.sort_by(|a, b| {
+            b.total_chars
+                .cmp(&a.total_chars)
+                .then_with(|| a.domain.cmp(&b.domain))
+        });
+        Ok(domains)
+    }
+
+    /// Insert a gap, or extend it if one with the same start and reason exists
+    pub fn upsert_gap(&self, gap: &Gap) -> Result<()> {
+        self.conn.execute(
+            r#"
+            INSERT INTO gaps (start_time, end_time, reason)
+            VALUES (?1, ?2, ?3)
+            ON CONFLICT(start_time, reason) DO UPDATE SET
+                end_time = MAX(end_time, excluded.end_time)
+            "#,
+            params![
+                gap.start_time.timestamp(),
+                gap.end_time.timestamp(),
+                gap.reason.as_str()
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Get gaps overlapping a time range, oldest first
+    pub fn get_gaps(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Vec<Gap>> {
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT start_time, end_time, reason
+            FROM gaps
+            WHERE end_time > ?1 AND start_time < ?2
+            ORDER BY start_time
+            "#,
+        )?;
+
+        let rows = stmt.query_map(params![start.timestamp(), end.timestamp()], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, row.get::<_, String>(2)?))
         })?;
 
-        rows.collect::<SqliteResult<Vec<_>>>().map_err(DbError::from)
+        let mut gaps = Vec::new();
+        for row in rows {
+            let (start_time, end_time, reason) = row?;
+            let Some(reason) = GapReason::parse(&reason) else {
+                continue;
+            };
+            gaps.push(Gap {
+                start_time: Utc.timestamp_opt(start_time, 0).unwrap(),
+                end_time: Utc.timestamp_opt(end_time, 0).unwrap(),
+                reason,
+            });
+        }
+
+        Ok(gaps)
     }
 
     /// Get the current active session (if any)
@@ -628,17 +1607,188 @@ impl Database {
             Err(e) => Err(DbError::from(e)),
         }
     }
+
+    /// Number of sessions that started in a time range
+    pub fn count_sessions(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<u64> {
+        let count: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM sessions WHERE start_time >= ?1 AND start_time < ?2",
+            params![start.timestamp(), end.timestamp()],
+            |row| row.get(0),
+        )?;
+        Ok(count as u64)
+    }
+
+    /// Issue a pairing code that can be traded for a token once, within `ttl`
+    pub fn create_pairing_code(&self, ttl: chrono::Duration) -> Result<String> {
+        let code: String = self
+            .conn
+            .query_row("SELECT upper(hex(randomblob(4)))", [], |row| row.get(0))?;
+        self.conn.execute(
+            "INSERT INTO pairing_codes (code, expires_at) VALUES (?1, ?2)",
+            params![code, (Utc::now() + ttl).timestamp()],
+        )?;
+        Ok(format!("{}-{}", &code[..4], &code[4..]))
+    }
+
+    /// Trade a pairing code for a new token named `name`
+    ///
+    /// Returns `None` for an unknown or expired code. A wrong guess also
+    /// voids every outstanding code, so codes can't be found by trying.
+    pub fn redeem_pairing_code(
+        &self,
+        code: &str,
+        name: &str,
+        origin: Option<&str>,
+    ) -> Result<Option<String>> {
+        let code: String = code
+            .chars()
+            .filter(char::is_ascii_alphanumeric)
+            .collect::<String>()
+            .to_uppercase();
+
+        let tx = self.conn.unchecked_transaction()?;
+        let valid = tx.execute(
+            "DELETE FROM pairing_codes WHERE code = ?1 AND expires_at > ?2",
+            params![code, Utc::now().timestamp()],
+        )? > 0;
+        if !valid {
+            tx.execute("DELETE FROM pairing_codes", [])?;
+            tx.commit()?;
+            return Ok(None);
+        }
+        let token = Self::insert_api_token(&tx, name, origin)?;
+        tx.commit()?;
+        Ok(Some(token))
+    }
+
+    /// New token named `name`, replacing any earlier token of that name
+    pub fn create_api_token(&self, name: &str, origin: Option<&str>) -> Result<String> {
+        Self::insert_api_token(&self.conn, name, origin)
+    }
+
+    fn insert_api_token(conn: &Connection, name: &str, origin: Option<&str>) -> Result<String> {
+        let token: String =
+            conn.query_row("SELECT lower(hex(randomblob(32)))", [], |row| row.get(0))?;
+        conn.execute(
+            r#"
+            INSERT OR REPLACE INTO api_tokens (name, token_hash, origin, created_at)
+            VALUES (?1, ?2, ?3, ?4)
+            "#,
+            params![name, token_hash(&token), origin, Utc::now().timestamp()],
+        )?;
+        Ok(token)
+    }
+
+    /// The token `token` belongs to, if it is valid; notes that it was used
+    pub fn verify_api_token(&self, token: &str) -> Result<Option<ApiToken>> {
+        let hash = token_hash(token);
+        self.conn.execute(
+            "UPDATE api_tokens SET last_used = ?2 WHERE token_hash = ?1",
+            params![hash, Utc::now().timestamp()],
+        )?;
+        Ok(self
+            .conn
+            .query_row(
+                &format!("{} WHERE token_hash = ?1", API_TOKEN_SELECT),
+                params![hash],
+                api_token_from_row,
+            )
+            .optional()?)
+    }
+
+    pub fn list_api_tokens(&self) -> Result<Vec<ApiToken>> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!("{} ORDER BY created_at", API_TOKEN_SELECT))?;
+        let rows = stmt.query_map([], api_token_from_row)?;
+        rows.collect::<SqliteResult<Vec<_>>>().map_err(DbError::from)
+    }
+
+    /// Revoke the token named `name`; false if there is none
+    pub fn revoke_api_token(&self, name: &str) -> Result<bool> {
+        let deleted = self
+            .conn
+            .execute("DELETE FROM api_tokens WHERE name = ?1", params![name])?;
+        Ok(deleted > 0)
+    }
+
+    /// Origins of the browser extensions that hold a token
+    pub fn paired_origins(&self) -> Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT DISTINCT origin FROM api_tokens WHERE origin IS NOT NULL")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        rows.collect::<SqliteResult<Vec<_>>>().map_err(DbError::from)
+    }
+}
+
+fn token_hash(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Columns read into an `ApiToken` by `api_token_from_row`
+const API_TOKEN_SELECT: &str = "SELECT name, origin, created_at, last_used FROM api_tokens";
+
+fn api_token_from_row(row: &rusqlite::Row) -> SqliteResult<ApiToken> {
+    let time = |ts: i64| Utc.timestamp_opt(ts, 0).unwrap();
+    Ok(ApiToken {
+        name: row.get(0)?,
+        origin: row.get(1)?,
+        created_at: time(row.get(2)?),
+        last_used: row.get::<_, Option<i64>>(3)?.map(time),
+    })
+}
+
+/// How long logged tabs are kept; the longest `daemon.browser_context_ttl_secs`
+/// allowed, so a tab is never forgotten while it could still be credited
+const BROWSER_CONTEXT_RETENTION: chrono::Duration = chrono::Duration::days(1);
+
+/// Setting holding the `privacy.url_policy` stored URLs were redacted with
+const URL_POLICY_KEY: &str = "url_policy";
+
+/// Setting holding `privacy.domains` as a JSON array
+const EXCLUDED_DOMAINS_KEY: &str = "excluded_domains";
+
+/// Setting holding the number of the last committed batch of records
+const COMMITTED_BATCH_KEY: &str = "committed_batch";
+
+/// Columns read into a `BrowserContext` by `browser_context_from_row`
+const BROWSER_CONTEXT_SELECT: &str = r#"
+    SELECT browser_name, profile, window_id, domain, url, page_title, private, timestamp,
+        last_seen, excluded
+    FROM browser_context_log"#;
+
+fn browser_context_from_row(row: &rusqlite::Row) -> SqliteResult<BrowserContext> {
+    let millis = |ms: i64| Utc.timestamp_millis_opt(ms).unwrap();
+    Ok(BrowserContext {
+        browser_name: row.get(0)?,
+        profile: row.get(1)?,
+        window_id: row.get(2)?,
+        domain: row.get(3)?,
+        url: row.get(4)?,
+        title: row.get(5)?,
+        private: row.get(6)?,
+        excluded: row.get(9)?,
+        started_at: millis(row.get(7)?),
+        last_seen: millis(row.get(8)?),
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::DomainCount;
     use chrono::Duration;
 
     #[test]
     fn test_database_creation() {
         let db = Database::open(":memory:").unwrap();
-        assert!(db.get_stats(Utc::now() - Duration::hours(1), Utc::now()).is_ok());
+        assert!(db
+            .get_stats(Utc::now() - Duration::hours(1), Utc::now(), &RecordFilter::default())
+            .is_ok());
     }
 
     #[test]
@@ -656,13 +1806,620 @@ mod tests {
             backspace_count: 5,
             browser_domain: None,
             browser_url: None,
+            tags: Vec::new(),
+            project: None,
+            language: None,
+            domains: Vec::new(),
         };
 
         let id = db.upsert_keystroke(&record).unwrap();
         assert!(id > 0);
 
-        let stats = db.get_stats(Utc::now() - Duration::hours(1), Utc::now() + Duration::hours(1)).unwrap();
+        let stats = db
+            .get_stats(
+                Utc::now() - Duration::hours(1),
+                Utc::now() + Duration::hours(1),
+                &RecordFilter::default(),
+            )
+            .unwrap();
         assert_eq!(stats.total_chars, 100);
         assert_eq!(stats.total_words, 20);
     }
+
+    #[test]
+    fn test_keystroke_batch_merges_same_minute() {
+        let db = Database::open(":memory:").unwrap();
+        let now = Utc::now();
+
+        let mut record = KeystrokeRecord::new(now)
+            .with_app(Some("Editor".to_string()), Some("com.test.editor".to_string()));
+        record.char_count = 10;
+        record.word_count = 2;
+        db.upsert_keystrokes(&[record.clone(), record]).unwrap();
+
+        let records = db
+            .get_all_records(
+                now - Duration::hours(1),
+                now + Duration::hours(1),
+                &RecordFilter::default(),
+            )
+            .unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].char_count, 20);
+        assert_eq!(records[0].word_count, 4);
+    }
+
+    const SECRET_URL: &str =
+        "https://mail.example.com/u/0/search?q=my+diagnosis&token=hunter2#access_token=s3cret";
+
+    /// Every text value in every table, to search for leaks
+    fn stored_text(db: &Database) -> String {
+        let tables: Vec<String> = {
+            let mut stmt = db
+                .conn
+                .prepare("SELECT name FROM sqlite_master WHERE type = 'table'")
+                .unwrap();
+            let rows = stmt.query_map([], |row| row.get(0)).unwrap();
+            rows.collect::<SqliteResult<_>>().unwrap()
+        };
+
+        let mut text = String::new();
+        for table in tables {
+            let mut stmt = db.conn.prepare(&format!("SELECT * FROM {}", table)).unwrap();
+            let columns = stmt.column_count();
+            let mut rows = stmt.query([]).unwrap();
+            while let Some(row) = rows.next().unwrap() {
+                for i in 0..columns {
+                    if let Ok(Some(value)) = row.get::<_, Option<String>>(i) {
+                        text.push_str(&value);
+                        text.push('\n');
+                    }
+                }
+            }
+        }
+        text
+    }
+
+    fn assert_no_secrets(db: &Database) {
+        let text = stored_text(db);
+        for secret in ["diagnosis", "hunter2", "s3cret"] {
+            assert!(!text.contains(secret), "{} stored in:\n{}", secret, text);
+        }
+    }
+
+    #[test]
+    fn test_query_string_secrets_never_stored() {
+        for policy in UrlPolicy::ALL {
+            let db = Database::open(":memory:").unwrap();
+            let urls = db.url_redactor(policy).unwrap();
+            let report: crate::TabReport = serde_json::from_value(serde_json::json!({
+                "url": SECRET_URL,
+                "title": "Inbox",
+                "browser_name": "Chrome",
+                "window_id": 1,
+            }))
+            .unwrap();
+            let ctx = report
+                .into_context(Utc::now(), &urls, &crate::ExclusionRules::default())
+                .unwrap();
+            assert_eq!(ctx.domain, "mail.example.com");
+            // Titles go wherever URLs do
+            let title_kept = !matches!(policy, UrlPolicy::Domain | UrlPolicy::Hash);
+            assert_eq!(ctx.title == "Inbox", title_kept);
+            db.log_browser_context(&ctx).unwrap();
+
+            // The tracker stamps minutes with the logged context's URL
+            let mut record = KeystrokeRecord::new(Utc::now())
+                .with_app(Some("Chrome".to_string()), Some("chrome".to_string()));
+            record.browser_domain = Some(ctx.domain.clone());
+            record.browser_url = Some(ctx.url.clone()).filter(|url| !url.is_empty());
+            db.upsert_keystroke(&record).unwrap();
+
+            assert_no_secrets(&db);
+        }
+    }
+
+    #[test]
+    fn test_redact_urls_rewrites_existing_rows() {
+        let db = Database::open(":memory:").unwrap();
+        let now = Utc::now();
+
+        // Rows as written before there was a URL policy
+        db.log_browser_context(&BrowserContext {
+            browser_name: "Chrome".to_string(),
+            profile: String::new(),
+            window_id: Some(1),
+            domain: "mail.example.com".to_string(),
+            url: SECRET_URL.to_string(),
+            title: "Inbox".to_string(),
+            private: false,
+            excluded: false,
+            started_at: now,
+            last_seen: now,
+        })
+        .unwrap();
+        let mut record = KeystrokeRecord::new(now);
+        record.browser_url = Some(SECRET_URL.to_string());
+        db.upsert_keystroke(&record).unwrap();
+        assert!(stored_text(&db).contains("hunter2"));
+
+        let no_query = db.url_redactor(UrlPolicy::NoQuery).unwrap();
+        assert_eq!(db.redact_urls(&no_query).unwrap(), 2);
+        assert_eq!(db.redact_urls(&no_query).unwrap(), 0);
+        assert_no_secrets(&db);
+        assert!(stored_text(&db).contains("https://mail.example.com/u/0/search\n"));
+
+        // A stricter policy applies on top of the earlier one
+        assert_eq!(
+            db.redact_urls(&db.url_redactor(UrlPolicy::Domain).unwrap())
+                .unwrap(),
+            3
+        );
+        assert_eq!(db.url_policy().unwrap(), Some(UrlPolicy::Domain));
+        let record = &db
+            .get_all_records(
+                now - Duration::hours(1),
+                now + Duration::hours(1),
+                &RecordFilter::default(),
+            )
+            .unwrap()[0];
+        assert_eq!(record.browser_url, None);
+        assert!(!stored_text(&db).contains("/u/0/search"));
+        assert!(!stored_text(&db).contains("Inbox"));
+    }
+
+    #[test]
+    fn test_pairing_and_api_tokens() {
+        let db = Database::open(":memory:").unwrap();
+        let origin = "chrome-extension://abcdefghijklmnopabcdefghijklmnop";
+
+        // A wrong guess voids outstanding codes
+        let code = db.create_pairing_code(Duration::minutes(5)).unwrap();
+        assert_eq!(db.redeem_pairing_code("0000-0000", "Chrome", None).unwrap(), None);
+        assert_eq!(db.redeem_pairing_code(&code, "Chrome", None).unwrap(), None);
+
+        let code = db.create_pairing_code(Duration::minutes(5)).unwrap();
+        let token = db
+            .redeem_pairing_code(&code.to_lowercase(), "Chrome", Some(origin))
+            .unwrap()
+            .unwrap();
+        assert_eq!(db.redeem_pairing_code(&code, "Chrome", Some(origin)).unwrap(), None);
+        let expired = db.create_pairing_code(Duration::minutes(-1)).unwrap();
+        assert_eq!(db.redeem_pairing_code(&expired, "Chrome", None).unwrap(), None);
+
+        let paired = db.verify_api_token(&token).unwrap().unwrap();
+        assert_eq!((paired.name.as_str(), paired.origin.as_deref()), ("Chrome", Some(origin)));
+        assert!(paired.last_used.is_some());
+        assert_eq!(db.verify_api_token("not-a-token").unwrap(), None);
+        assert_eq!(db.paired_origins().unwrap(), [origin]);
+        assert!(!stored_text(&db).contains(&token));
+
+        // Issuing a token under the same name replaces the old one
+        let cli = db.create_api_token("editor", None).unwrap();
+        let replaced = db.create_api_token("editor", None).unwrap();
+        assert_eq!(db.verify_api_token(&cli).unwrap(), None);
+        assert!(db.verify_api_token(&replaced).unwrap().is_some());
+        assert_eq!(db.list_api_tokens().unwrap().len(), 2);
+
+        assert!(db.revoke_api_token("Chrome").unwrap());
+        assert!(!db.revoke_api_token("Chrome").unwrap());
+        assert_eq!(db.verify_api_token(&token).unwrap(), None);
+    }
+
+    #[test]
+    fn test_migrate_v2_app_names() {
+        let db = Database::open(":memory:").unwrap();
+
+        for (i, title) in ["Inbox - Secret subject", "report.docx"].iter().enumerate() {
+            let record = KeystrokeRecord::new(Utc::now() - Duration::minutes(i as i64))
+                .with_app(Some(title.to_string()), Some("Thunderbird".to_string()));
+            db.upsert_keystroke(&record).unwrap();
+        }
+        let unresolved = KeystrokeRecord::new(Utc::now())
+            .with_app(Some("~/notes.txt - gedit".to_string()), Some("Gedit".to_string()));
+        db.upsert_keystroke(&unresolved).unwrap();
+
+        let resolve = |id: &str| (id == "Thunderbird").then(|| "Thunderbird Mail".to_string());
+        assert_eq!(db.migrate_v2_app_names(resolve).unwrap(), 3);
+        assert_eq!(db.migrate_v2_app_names(resolve).unwrap(), 0);
+
+        let names: Vec<String> = db
+            .get_all_records(
+                Utc::now() - Duration::hours(1),
+                Utc::now() + Duration::hours(1),
+                &RecordFilter::default(),
+            )
+            .unwrap()
+            .into_iter()
+            .filter_map(|r| r.app_name)
+            .collect();
+        assert_eq!(names.iter().filter(|n| *n == "Thunderbird Mail").count(), 2);
+        assert!(names.contains(&"Gedit".to_string()));
+    }
+
+    #[test]
+    fn test_gaps_are_reported_in_stats() {
+        let db = Database::open(":memory:").unwrap();
+        let start = Utc.timestamp_opt(Utc::now().timestamp(), 0).unwrap() - Duration::hours(1);
+
+        let mut gap = Gap {
+            start_time: start,
+            end_time: start + Duration::minutes(5),
+            reason: GapReason::Paused,
+        };
+        db.upsert_gap(&gap).unwrap();
+        // Extending an open gap updates it in place
+        gap.end_time = start + Duration::minutes(20);
+        db.upsert_gap(&gap).unwrap();
+        db.upsert_gap(&Gap {
+            start_time: start + Duration::minutes(30),
+            end_time: start + Duration::minutes(40),
+            reason: GapReason::Private,
+        })
+        .unwrap();
+
+        assert_eq!(db.get_gaps(start, Utc::now()).unwrap().len(), 2);
+        let stats = db
+            .get_stats(start, Utc::now(), &RecordFilter::default())
+            .unwrap();
+        assert_eq!(stats.paused_minutes, 20);
+        assert_eq!(stats.excluded_minutes, 10);
+    }
+
+    #[test]
+    fn test_count_sessions() {
+        let db = Database::open(":memory:").unwrap();
+        let start = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        for minutes in [0, 10, 60] {
+            db.insert_session(&TypingSession {
+                id: None,
+                start_time: start + Duration::minutes(minutes),
+                end_time: None,
+                char_count: 10,
+                word_count: 2,
+                wpm_avg: None,
+                wpm_peak: None,
+            })
+            .unwrap();
+        }
+
+        let hour = start + Duration::hours(1);
+        assert_eq!(db.count_sessions(start, hour).unwrap(), 2);
+        assert_eq!(db.count_sessions(start, Utc::now()).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_tagged_minutes_filter_and_group() {
+        let db = Database::open(":memory:").unwrap();
+        let now = Utc::now();
+
+        let mut tagged = KeystrokeRecord::new(now - Duration::minutes(2))
+            .with_app(Some("Editor".to_string()), Some("editor".to_string()));
+        tagged.char_count = 30;
+        tagged.tags = vec!["client-x".to_string(), "billable".to_string()];
+        let mut untagged = KeystrokeRecord::new(now)
+            .with_app(Some("Editor".to_string()), Some("editor".to_string()));
+        untagged.char_count = 10;
+        db.upsert_keystrokes(&[tagged, untagged]).unwrap();
+
+        let (start, end) = (now - Duration::hours(1), now + Duration::hours(1));
+        let stats = db
+            .get_stats(start, end, &RecordFilter::tag("client-x"))
+            .unwrap();
+        assert_eq!(stats.total_chars, 30);
+        assert_eq!(stats.active_minutes, 1);
+
+        let records = db.get_all_records(start, end, &RecordFilter::default()).unwrap();
+        assert_eq!(records[0].tags.len(), 2);
+        assert!(records[1].tags.is_empty());
+
+        let tags = db.get_tag_stats(start, end, &RecordFilter::default()).unwrap();
+        assert_eq!(tags.len(), 2);
+        assert!(tags.iter().all(|t| t.total_chars == 30 && t.percentage == 75.0));
+    }
+
+    #[test]
+    fn test_project_stats() {
+        let db = Database::open(":memory:").unwrap();
+        let now = Utc::now();
+
+        let mut first = KeystrokeRecord::new(now - Duration::minutes(2))
+            .with_app(Some("Code".to_string()), Some("code".to_string()));
+        first.char_count = 30;
+        // A later batch in the same minute names the project
+        let mut second = first.clone();
+        second.project = Some("fingerpain".to_string());
+        let mut third = first.clone();
+        third.char_count = 0;
+        let mut other = KeystrokeRecord::new(now)
+            .with_app(Some("Slack".to_string()), Some("slack".to_string()));
+        other.char_count = 40;
+        db.upsert_keystrokes(&[first, second, third, other]).unwrap();
+
+        let (start, end) = (now - Duration::hours(1), now + Duration::hours(1));
+        let projects = db
+            .get_project_stats(start, end, &RecordFilter::default())
+            .unwrap();
+        assert_eq!(projects.len(), 1);
+        assert_eq!(projects[0].project, "fingerpain");
+        assert_eq!(projects[0].total_chars, 60);
+        assert_eq!(projects[0].percentage, 60.0);
+    }
+
+    #[test]
+    fn test_editor_context_and_language_stats() {
+        let db = Database::open(":memory:").unwrap();
+        let ctx = EditorContext {
+            editor: "vscode".to_string(),
+            language: Some("rust".to_string()),
+            project: None,
+        };
+        db.upsert_editor_context(&ctx).unwrap();
+        let since = Utc::now() - Duration::minutes(30);
+        assert_eq!(db.get_editor_context("vscode", since).unwrap(), Some(ctx));
+        assert_eq!(db.get_editor_context("neovim", since).unwrap(), None);
+        // Not reported since
+        let later = Utc::now() + Duration::minutes(1);
+        assert_eq!(db.get_editor_context("vscode", later).unwrap(), None);
+
+        let now = Utc::now();
+        let mut rust = KeystrokeRecord::new(now)
+            .with_app(Some("Code".to_string()), Some("Code".to_string()));
+        rust.char_count = 75;
+        rust.language = Some("rust".to_string());
+        let mut chat = KeystrokeRecord::new(now)
+            .with_app(Some("Slack".to_string()), Some("Slack".to_string()));
+        chat.char_count = 25;
+        db.upsert_keystrokes(&[rust, chat]).unwrap();
+
+        let languages = db
+            .get_language_stats(
+                now - Duration::hours(1),
+                now + Duration::hours(1),
+                &RecordFilter::default(),
+            )
+            .unwrap();
+        assert_eq!(languages.len(), 1);
+        assert_eq!(languages[0].language, "rust");
+        assert_eq!(languages[0].percentage, 75.0);
+    }
+
+    #[test]
+    fn test_browser_context_log() {
+        let db = Database::open(":memory:").unwrap();
+        let now = Utc::now();
+        let tab = |window_id: i64, url: &str, at: DateTime<Utc>| BrowserContext {
+            browser_name: "Chrome".to_string(),
+            profile: "work".to_string(),
+            window_id: Some(window_id),
+            domain: url.split('/').nth(2).unwrap().to_string(),
+            url: url.to_string(),
+            title: String::new(),
+            private: false,
+            excluded: false,
+            started_at: at,
+            last_seen: at,
+        };
+
+        db.log_browser_context(&tab(1, "https://github.com/", now - Duration::minutes(30)))
+            .unwrap();
+        db.log_browser_context(&tab(2, "https://docs.rs/", now - Duration::minutes(20)))
+            .unwrap();
+        // Repeating the tab of window 1 refreshes it instead of adding a row
+        db.log_browser_context(&tab(1, "https://github.com/", now)).unwrap();
+
+        let browsers = BrowserRegistry::default();
+        let current = db
+            .get_browser_context(&browsers, "Google-chrome", now - Duration::minutes(5))
+            .unwrap()
+            .unwrap();
+        assert_eq!((current.window_id, current.domain.as_str()), (Some(1), "github.com"));
+        assert_eq!(current.started_at.timestamp(), (now - Duration::minutes(30)).timestamp());
+        assert_eq!(db.get_browser_context(&browsers, "firefox", now).unwrap(), None);
+        assert_eq!(db.get_browser_context(&browsers, "kitty", now).unwrap(), None);
+
+        // Nothing reported within the TTL means no context
+        assert_eq!(
+            db.get_browser_context(&browsers, "com.google.Chrome", now + Duration::minutes(1))
+                .unwrap(),
+            None
+        );
+
+        let history = db
+            .get_browser_context_history(now - Duration::hours(1), now + Duration::hours(1))
+            .unwrap();
+        let domains: Vec<&str> = history.iter().map(|ctx| ctx.domain.as_str()).collect();
+        assert_eq!(domains, ["github.com", "docs.rs"]);
+        // A day later only the tab logged then is kept
+        let tomorrow = now + Duration::days(1) + Duration::minutes(10);
+        db.log_browser_context(&tab(2, "https://crates.io/", tomorrow))
+            .unwrap();
+        let history = db
+            .get_browser_context_history(now - Duration::days(2), tomorrow + Duration::hours(1))
+            .unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].domain, "crates.io");
+    }
+
+    #[test]
+    fn test_legacy_browser_context_moves_to_log() {
+        let path = std::env::temp_dir().join(format!(
+            "fingerpain-legacy-context-{}.db",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let now = Utc::now().timestamp();
+        {
+            // The table as builds from before the log created it
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(
+                r#"
+                CREATE TABLE browser_context (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    timestamp INTEGER NOT NULL,
+                    browser_name TEXT NOT NULL,
+                    url TEXT,
+                    domain TEXT,
+                    page_title TEXT,
+                    last_updated INTEGER NOT NULL,
+                    UNIQUE(browser_name)
+                );
+                "#,
+            )
+            .unwrap();
+            conn.execute(
+                r#"
+                INSERT INTO browser_context (timestamp, browser_name, url, domain, last_updated)
+                VALUES (?1, 'Chrome', ?2, 'mail.example.com', ?1)
+                "#,
+                params![now, SECRET_URL],
+            )
+            .unwrap();
+        }
+
+        let db = Database::open(&path).unwrap();
+        let history = db
+            .get_browser_context_history(Utc::now() - Duration::hours(1), Utc::now())
+            .unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].domain, "mail.example.com");
+        assert!(!db.column_exists("browser_context", "url").unwrap());
+
+        // URLs from the old table are redacted with the rest
+        let no_query = db.url_redactor(UrlPolicy::NoQuery).unwrap();
+        assert_eq!(db.redact_urls(&no_query).unwrap(), 1);
+        assert_no_secrets(&db);
+
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_browser_domains_split_minutes() {
+        let db = Database::open(":memory:").unwrap();
+        let now = Utc::now();
+        let chrome = || {
+            KeystrokeRecord::new(now)
+                .with_app(Some("Chrome".to_string()), Some("chrome".to_string()))
+        };
+
+        let mut split = chrome();
+        split.char_count = 50;
+        split.domains = vec![
+            DomainCount {
+                domain: "gist.github.com".to_string(),
+                char_count: 30,
+                word_count: 5,
+            },
+            DomainCount {
+                domain: String::new(),
+                char_count: 20,
+                word_count: 3,
+            },
+        ];
+        // A minute from before the split only has its primary domain
+        let mut legacy = chrome();
+        legacy.timestamp = now - Duration::minutes(5);
+        legacy.char_count = 10;
+        legacy.browser_domain = Some("github.com".to_string());
+        db.upsert_keystrokes(&[split.clone(), legacy]).unwrap();
+        // Recovering the same minute again adds to it
+        db.upsert_keystrokes(&[split]).unwrap();
+
+        let totals = |level| -> Vec<(String, u64)> {
+            db.get_browser_domains(
+                &["chrome"],
+                now - Duration::hours(1),
+                now + Duration::hours(1),
+                level,
+                &DomainGroups::default(),
+                &RecordFilter::default(),
+            )
+            .unwrap()
+            .into_iter()
+            .map(|d| (d.domain, d.total_chars))
+            .collect()
+        };
+        let expected = |rows: &[(&str, u64)]| -> Vec<(String, u64)> {
+            rows.iter().map(|(d, n)| (d.to_string(), *n)).collect()
+        };
+        assert_eq!(
+            totals(DomainLevel::Site),
+            expected(&[("github.com", 70), ("Other", 40)])
+        );
+        assert_eq!(
+            totals(DomainLevel::Host),
+            expected(&[("gist.github.com", 60), ("Other", 40), ("github.com", 10)])
+        );
+    }
+
+    #[test]
+    fn test_app_and_domain_filters() {
+        let db = Database::open(":memory:").unwrap();
+        let now = Utc::now();
+
+        let mut browsing = KeystrokeRecord::new(now - Duration::minutes(2))
+            .with_app(Some("Chrome".to_string()), Some("chrome".to_string()));
+        browsing.char_count = 50;
+        browsing.domains = vec![
+            DomainCount {
+                domain: "gist.github.com".to_string(),
+                char_count: 30,
+                word_count: 5,
+            },
+            DomainCount {
+                domain: "docs.rs".to_string(),
+                char_count: 20,
+                word_count: 3,
+            },
+        ];
+        browsing.tags = vec!["client-x".to_string()];
+        let mut editing = KeystrokeRecord::new(now - Duration::minutes(1))
+            .with_app(Some("Editor".to_string()), Some("editor".to_string()));
+        editing.char_count = 10;
+        editing.tags = vec!["client-x".to_string()];
+        // Recorded before typing was split by domain
+        let mut legacy = KeystrokeRecord::new(now - Duration::minutes(3))
+            .with_app(Some("Chrome".to_string()), Some("chrome".to_string()));
+        legacy.char_count = 7;
+        legacy.browser_domain = Some("github.com".to_string());
+        db.upsert_keystrokes(&[browsing, editing, legacy]).unwrap();
+
+        let (start, end) = (now - Duration::hours(1), now + Duration::hours(1));
+        let chars = |filter: RecordFilter| db.get_stats(start, end, &filter).unwrap().total_chars;
+        let app = |app: &str| RecordFilter {
+            app: Some(app.to_string()),
+            ..RecordFilter::default()
+        };
+        let domain = |domain: &str| RecordFilter {
+            domain: Some(domain.to_string()),
+            ..RecordFilter::default()
+        };
+        assert_eq!(chars(app("Editor")), 10);
+        assert_eq!(chars(app("chrome")), 57);
+        assert_eq!(chars(domain("github.com")), 37);
+        assert_eq!(chars(domain("docs.rs")), 20);
+        assert_eq!(chars(domain("hub.com")), 0);
+        // Wildcards in the filter are matched literally
+        assert_eq!(chars(domain("_ithub.com")), 0);
+        assert_eq!(chars(domain("%")), 0);
+
+        let domains = db
+            .get_browser_domains(
+                &["chrome"],
+                start,
+                end,
+                DomainLevel::Host,
+                &DomainGroups::default(),
+                &domain("github.com"),
+            )
+            .unwrap();
+        assert_eq!(domains.len(), 2);
+        assert_eq!(domains[0].total_chars, 30);
+        assert_eq!(domains[1].total_chars, 7);
+
+        let tags = db.get_tag_stats(start, end, &app("Editor")).unwrap();
+        assert_eq!(tags[0].total_chars, 10);
+        assert_eq!(tags[0].percentage, 100.0);
+    }
 }
@@ -0,0 +1,128 @@
+//! Where fingerpain-web is listening
+//!
+//! The web server may end up on another port than configured, or on a Unix
+//! socket, so it writes the address it got to `web.json` in the data dir and
+//! removes the file on exit. The tray, the CLI and the native messaging host
+//! read it back. A server that was killed leaves the file behind, so readers
+//! only trust it while something still accepts connections there.
+
+use crate::config::WebConfig;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How long to wait for a connection when checking the file isn't stale
+const PROBE_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// Address of a running fingerpain-web
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WebEndpoint {
+    /// Address to open in a browser; none when serving on a Unix socket only
+    pub url: Option<String>,
+    pub unix_socket: Option<PathBuf>,
+    pub pid: u32,
+    pub started_at: DateTime<Utc>,
+}
+
+/// Path of the discovery file
+pub fn discovery_path() -> PathBuf {
+    crate::data_dir().join("web.json")
+}
+
+impl WebEndpoint {
+    /// Endpoint of this process, serving on `addr` or `unix_socket`
+    pub fn new(addr: Option<SocketAddr>, unix_socket: Option<&Path>) -> Self {
+        Self {
+            url: addr.map(|addr| browser_url(&addr)),
+            unix_socket: unix_socket.map(Path::to_path_buf),
+            pid: std::process::id(),
+            started_at: Utc::now(),
+        }
+    }
+
+    /// Write the discovery file, replacing it in one step
+    pub fn write(&self) -> io::Result<()> {
+        let path = discovery_path();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(self)? + "\n")?;
+        std::fs::rename(tmp, path)
+    }
+
+    /// Remove the discovery file if it still describes this process
+    pub fn remove(&self) {
+        if Self::read().is_some_and(|endpoint| endpoint.pid == self.pid) {
+            let _ = std::fs::remove_file(discovery_path());
+        }
+    }
+
+    /// Contents of the discovery file, stale or not
+    pub fn read() -> Option<Self> {
+        let text = std::fs::read_to_string(discovery_path()).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    /// The running server's endpoint, if there is one
+    pub fn discover() -> Option<Self> {
+        Self::read().filter(Self::is_reachable)
+    }
+
+    fn is_reachable(&self) -> bool {
+        if let Some(url) = &self.url {
+            let addrs = url
+                .strip_prefix("http://")
+                .and_then(|host| host.to_socket_addrs().ok());
+            if let Some(mut addrs) = addrs {
+                return addrs.any(|addr| TcpStream::connect_timeout(&addr, PROBE_TIMEOUT).is_ok());
+            }
+        }
+        #[cfg(unix)]
+        if let Some(socket) = &self.unix_socket {
+            return std::os::unix::net::UnixStream::connect(socket).is_ok();
+        }
+        false
+    }
+}
+
+/// `http://` address for a listening socket, with unspecified IPs made local
+pub fn browser_url(addr: &SocketAddr) -> String {
+    if addr.ip().is_unspecified() {
+        format!("http://127.0.0.1:{}", addr.port())
+    } else {
+        format!("http://{}", addr)
+    }
+}
+
+/// Address of the dashboard: the running server's, or else the configured one
+pub fn web_url(config: &WebConfig) -> String {
+    WebEndpoint::discover()
+        .and_then(|endpoint| endpoint.url)
+        .unwrap_or_else(|| config.url())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_reachable_endpoints() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let endpoint = WebEndpoint::new(Some(addr), None);
+        assert_eq!(endpoint.url, Some(format!("http://{}", addr)));
+        assert!(endpoint.is_reachable());
+
+        // Left behind by a server that was killed
+        drop(listener);
+        assert!(!endpoint.is_reachable());
+
+        let any: SocketAddr = "0.0.0.0:7891".parse().unwrap();
+        assert_eq!(browser_url(&any), "http://127.0.0.1:7891");
+    }
+}
@@ -0,0 +1,151 @@
+//! Domain grouping
+//!
+//! Browser typing is stored per host, as the extension reports it. Stats can
+//! show those hosts, or fold them into registrable domains (eTLD+1) using the
+//! bundled public suffix list: `mail.google.com` and `docs.google.com` become
+//! `google.com`, while `alice.github.io` and `bob.github.io` stay apart since
+//! `github.io` is itself a public suffix. Domain groups from the config name
+//! sets of domains on top of that.
+
+use publicsuffix::{List, Psl};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::OnceLock;
+
+/// Snapshot of https://publicsuffix.org/list/public_suffix_list.dat
+const PUBLIC_SUFFIX_LIST: &str = include_str!("../data/public_suffix_list.dat");
+
+/// Shown for typing in a browser whose tab was unknown
+pub const UNKNOWN_DOMAIN: &str = "Other";
+
+fn public_suffixes() -> &'static List {
+    static LIST: OnceLock<List> = OnceLock::new();
+    LIST.get_or_init(|| {
+        PUBLIC_SUFFIX_LIST
+            .parse()
+            .expect("bundled public suffix list is valid")
+    })
+}
+
+/// Registrable domain of a host, or the host itself if it has none
+///
+/// IP addresses, `localhost` and public suffixes are kept as they are.
+pub fn registrable_domain(host: &str) -> String {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    if host.parse::<IpAddr>().is_ok() || host.starts_with('[') {
+        return host;
+    }
+    match public_suffixes().domain(host.as_bytes()) {
+        Some(domain) => String::from_utf8_lossy(domain.as_bytes()).into_owned(),
+        None => host,
+    }
+}
+
+/// How finely browser typing is broken down
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum DomainLevel {
+    /// Hosts as reported, e.g. `mail.google.com`
+    Host,
+    /// Registrable domains such as `google.com`, with domain groups applied
+    #[default]
+    Site,
+}
+
+/// Names for sets of domains (`[domain_groups]` in the config)
+///
+/// A domain in a group also covers its subdomains. When several groups
+/// cover a host, the one naming the longest domain wins.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DomainGroups {
+    /// Domain and the group it belongs to, longest domain first
+    rules: Vec<(String, String)>,
+}
+
+impl DomainGroups {
+    pub fn new(groups: &BTreeMap<String, Vec<String>>) -> Self {
+        let mut rules: Vec<(String, String)> = groups
+            .iter()
+            .flat_map(|(group, domains)| {
+                domains.iter().map(move |domain| {
+                    let domain = domain.trim_start_matches("*.").trim_matches('.');
+                    (domain.to_ascii_lowercase(), group.clone())
+                })
+            })
+            .collect();
+        rules.sort_by_key(|(domain, _)| std::cmp::Reverse(domain.len()));
+        Self { rules }
+    }
+
+    /// Name to report typing on `host` under at `level`
+    pub fn name(&self, host: &str, level: DomainLevel) -> String {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        if host.is_empty() {
+            return UNKNOWN_DOMAIN.to_string();
+        }
+        if level == DomainLevel::Host {
+            return host;
+        }
+
+        let group = self.rules.iter().find(|(domain, _)| {
+            host == *domain
+                || host
+                    .strip_suffix(domain.as_str())
+                    .is_some_and(|prefix| prefix.ends_with('.'))
+        });
+        match group {
+            Some((_, group)) => group.clone(),
+            None => registrable_domain(&host),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registrable_domains() {
+        assert_eq!(registrable_domain("mail.google.com"), "google.com");
+        assert_eq!(registrable_domain("www.google.com"), "google.com");
+        assert_eq!(registrable_domain("news.bbc.co.uk"), "bbc.co.uk");
+        assert_eq!(registrable_domain("Docs.Google.com."), "google.com");
+
+        // Private suffixes keep their users apart
+        assert_eq!(registrable_domain("alice.github.io"), "alice.github.io");
+        assert_eq!(registrable_domain("bob.github.io"), "bob.github.io");
+
+        assert_eq!(registrable_domain("localhost"), "localhost");
+        assert_eq!(registrable_domain("192.168.1.10"), "192.168.1.10");
+        assert_eq!(registrable_domain("co.uk"), "co.uk");
+    }
+
+    #[test]
+    fn test_domain_groups() {
+        let groups = [
+            ("Google Workspace", vec!["docs.google.com", "mail.google.com"]),
+            ("Google", vec!["google.com", "youtube.com"]),
+        ]
+        .into_iter()
+        .map(|(group, domains)| {
+            let domains = domains.into_iter().map(String::from).collect();
+            (group.to_string(), domains)
+        })
+        .collect();
+        let groups = DomainGroups::new(&groups);
+
+        let site = |host| groups.name(host, DomainLevel::Site);
+        assert_eq!(site("docs.google.com"), "Google Workspace");
+        assert_eq!(site("www.google.com"), "Google");
+        assert_eq!(site("m.youtube.com"), "Google");
+        assert_eq!(site("gist.github.com"), "github.com");
+        assert_eq!(site(""), UNKNOWN_DOMAIN);
+
+        assert_eq!(
+            groups.name("docs.google.com", DomainLevel::Host),
+            "docs.google.com"
+        );
+    }
+}
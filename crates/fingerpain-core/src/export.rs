@@ -1,6 +1,9 @@
 //! Export functionality for CSV and JSON formats
 
-use crate::{db::Database, AggregatedStats, AppStats, KeystrokeRecord, TimeRange};
+use crate::browsers::BrowserRegistry;
+use crate::db::{Database, RecordFilter};
+use crate::domains::{DomainGroups, DomainLevel};
+use crate::{AggregatedStats, AppStats, KeystrokeRecord, Metrics, TagStats, TimeRange};
 use chrono::{DateTime, Utc};
 use serde::Serialize;
 use std::io::Write;
@@ -27,7 +30,7 @@ pub enum ExportFormat {
 }
 
 impl ExportFormat {
-    pub fn from_str(s: &str) -> Option<Self> {
+    pub fn parse(s: &str) -> Option<Self> {
         match s.to_lowercase().as_str() {
             "csv" => Some(ExportFormat::Csv),
             "json" => Some(ExportFormat::Json),
@@ -51,16 +54,38 @@ pub struct ExportData {
     pub period_end: DateTime<Utc>,
     pub summary: AggregatedStats,
     pub app_breakdown: Vec<AppStats>,
+    pub tag_breakdown: Vec<TagStats>,
     pub records: Vec<KeystrokeRecord>,
 }
 
 pub struct Exporter<'a> {
     db: &'a Database,
+    filter: RecordFilter,
+    browsers: BrowserRegistry,
+    domain_groups: DomainGroups,
 }
 
 impl<'a> Exporter<'a> {
     pub fn new(db: &'a Database) -> Self {
-        Self { db }
+        Self {
+            db,
+            filter: RecordFilter::default(),
+            browsers: BrowserRegistry::default(),
+            domain_groups: DomainGroups::default(),
+        }
+    }
+
+    /// Only export records matching `filter`, e.g. one project tag
+    pub fn with_filter(mut self, filter: RecordFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Use configured browser app IDs and domain groups for domain breakdowns
+    pub fn with_domains(mut self, browsers: BrowserRegistry, groups: DomainGroups) -> Self {
+        self.browsers = browsers;
+        self.domain_groups = groups;
+        self
     }
 
     /// Export data for a time range to a writer
@@ -84,7 +109,7 @@ impl<'a> Exporter<'a> {
         start: DateTime<Utc>,
         end: DateTime<Utc>,
     ) -> Result<()> {
-        let records = self.db.get_all_records(start, end)?;
+        let records = self.db.get_all_records(start, end, &self.filter)?;
 
         let mut csv_writer = csv::Writer::from_writer(writer);
 
@@ -97,6 +122,9 @@ impl<'a> Exporter<'a> {
             "word_count",
             "paragraph_count",
             "backspace_count",
+            "tags",
+            "project",
+            "language",
         ])?;
 
         // Write records
@@ -109,6 +137,9 @@ impl<'a> Exporter<'a> {
                 record.word_count.to_string(),
                 record.paragraph_count.to_string(),
                 record.backspace_count.to_string(),
+                record.tags.join(";"),
+                record.project.unwrap_or_default(),
+                record.language.unwrap_or_default(),
             ])?;
         }
 
@@ -122,9 +153,10 @@ impl<'a> Exporter<'a> {
         start: DateTime<Utc>,
         end: DateTime<Utc>,
     ) -> Result<()> {
-        let summary = self.db.get_stats(start, end)?;
-        let app_breakdown = self.db.get_app_stats(start, end)?;
-        let records = self.db.get_all_records(start, end)?;
+        let summary = self.db.get_stats(start, end, &self.filter)?;
+        let app_breakdown = self.app_breakdown(start, end)?;
+        let tag_breakdown = self.tag_breakdown(start, end)?;
+        let records = self.db.get_all_records(start, end, &self.filter)?;
 
         let export_data = ExportData {
             exported_at: Utc::now(),
@@ -132,6 +164,7 @@ impl<'a> Exporter<'a> {
             period_end: end,
             summary,
             app_breakdown,
+            tag_breakdown,
             records,
         };
 
@@ -148,8 +181,9 @@ impl<'a> Exporter<'a> {
         format: ExportFormat,
     ) -> Result<()> {
         let (start, end) = range.to_range();
-        let summary = self.db.get_stats(start, end)?;
-        let app_breakdown = self.db.get_app_stats(start, end)?;
+        let summary = self.db.get_stats(start, end, &self.filter)?;
+        let app_breakdown = self.app_breakdown(start, end)?;
+        let tag_breakdown = self.tag_breakdown(start, end)?;
 
         match format {
             ExportFormat::Csv => {
@@ -170,6 +204,20 @@ impl<'a> Exporter<'a> {
                 if let Some(wpm) = summary.peak_wpm {
                     csv_writer.write_record(["peak_wpm", &format!("{:.1}", wpm)])?;
                 }
+                for tag in &tag_breakdown {
+                    csv_writer.write_record([format!("tag:{}", tag.tag), tag.total_chars.to_string()])?;
+                }
+                let domains = Metrics::new(self.db)
+                    .with_filter(self.filter.clone())
+                    .with_browsers(self.browsers.clone())
+                    .with_domains(self.domain_groups.clone(), DomainLevel::Site)
+                    .domain_stats(range)?;
+                for domain in &domains {
+                    csv_writer.write_record([
+                        format!("domain:{}", domain.domain),
+                        domain.total_chars.to_string(),
+                    ])?;
+                }
                 csv_writer.flush()?;
             }
             ExportFormat::Json => {
@@ -180,6 +228,7 @@ impl<'a> Exporter<'a> {
                     period_end: DateTime<Utc>,
                     summary: AggregatedStats,
                     app_breakdown: Vec<AppStats>,
+                    tag_breakdown: Vec<TagStats>,
                 }
 
                 let export = SummaryExport {
@@ -188,6 +237,7 @@ impl<'a> Exporter<'a> {
                     period_end: end,
                     summary,
                     app_breakdown,
+                    tag_breakdown,
                 };
 
                 let json = serde_json::to_string_pretty(&export)?;
@@ -197,4 +247,31 @@ impl<'a> Exporter<'a> {
 
         Ok(())
     }
+
+    /// Per-app totals, with browser typing broken down by domain
+    fn app_breakdown(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Vec<AppStats>> {
+        let mut apps = self.db.get_app_stats(start, end, &self.filter)?;
+        for app in &mut apps {
+            if self.browsers.is_browser(&app.app_bundle_id) {
+                app.browser_domains = Some(self.db.get_browser_domains(
+                    &[app.app_bundle_id.as_str()],
+                    start,
+                    end,
+                    DomainLevel::Site,
+                    &self.domain_groups,
+                    &self.filter,
+                )?);
+            }
+        }
+        Ok(apps)
+    }
+
+    /// Per-tag totals, limited to the filtered tag if there is one
+    fn tag_breakdown(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Vec<TagStats>> {
+        let mut tags = self.db.get_tag_stats(start, end, &self.filter)?;
+        if let Some(tag) = &self.filter.tag {
+            tags.retain(|stats| &stats.tag == tag);
+        }
+        Ok(tags)
+    }
 }
@@ -3,14 +3,28 @@
 //! Provides database storage, metrics aggregation, and export functionality
 //! for the FingerPain typing analytics tracker.
 
+pub mod apps;
+pub mod browsers;
+pub mod config;
+pub mod control;
 pub mod db;
+pub mod discovery;
+pub mod domains;
 pub mod export;
 pub mod metrics;
+pub mod privacy;
+pub mod prometheus;
 pub mod session;
 
+pub use apps::{AppClassifier, Category};
+pub use browsers::BrowserRegistry;
+pub use config::Config;
+pub use control::{ControlClient, DaemonStatus, LiveSnapshot};
 pub use db::Database;
+pub use domains::{DomainGroups, DomainLevel};
 pub use export::{ExportFormat, Exporter};
 pub use metrics::{Metrics, TimeRange};
+pub use privacy::{ExclusionRules, Gap, GapReason, UrlPolicy, UrlRedactor};
 pub use session::SessionTracker;
 
 use chrono::{DateTime, Utc};
@@ -29,6 +43,26 @@ pub struct KeystrokeRecord {
     pub backspace_count: u32,
     pub browser_domain: Option<String>,
     pub browser_url: Option<String>,
+    /// Project tags that were active during this minute
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Project reported by an editor plugin or named in the window title
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+    /// Language of the file being edited, as reported by an editor plugin
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Browser typing split by the domain focused at each keystroke
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub domains: Vec<DomainCount>,
+}
+
+/// Typing in one domain within a minute; an empty domain means unknown
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DomainCount {
+    pub domain: String,
+    pub char_count: u32,
+    pub word_count: u32,
 }
 
 impl KeystrokeRecord {
@@ -44,6 +78,10 @@ impl KeystrokeRecord {
             backspace_count: 0,
             browser_domain: None,
             browser_url: None,
+            tags: Vec::new(),
+            project: None,
+            language: None,
+            domains: Vec::new(),
         }
     }
 
@@ -82,6 +120,7 @@ impl TypingSession {
 
 /// Aggregated stats for a time period
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct AggregatedStats {
     pub period_start: DateTime<Utc>,
     pub period_end: DateTime<Utc>,
@@ -93,22 +132,42 @@ pub struct AggregatedStats {
     pub avg_wpm: Option<f64>,
     pub peak_wpm: Option<f64>,
     pub active_minutes: u32,
+    /// Minutes not recorded because recording was paused
+    pub paused_minutes: u32,
+    /// Minutes not recorded because of exclusion rules or private windows
+    pub excluded_minutes: u32,
 }
 
 /// Per-app statistics
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct AppStats {
     pub app_name: String,
     pub app_bundle_id: String,
     pub total_chars: u64,
     pub total_words: u64,
     pub percentage: f64,
+    #[serde(default)]
+    pub category: Category,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub browser_domains: Option<Vec<DomainStats>>,
 }
 
+/// Per-category statistics
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CategoryStats {
+    pub category: Category,
+    pub total_chars: u64,
+    pub total_words: u64,
+    pub percentage: f64,
+    /// Apps in this category, busiest first
+    pub apps: Vec<String>,
+}
+
 /// Domain statistics within a browser
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct DomainStats {
     pub domain: String,
     pub total_chars: u64,
@@ -116,12 +175,141 @@ pub struct DomainStats {
     pub percentage: f64,
 }
 
-/// Browser context from active tab
-#[derive(Debug, Clone)]
+/// Active tab of one browser window, as logged from the extension's reports
+#[derive(Debug, Clone, PartialEq)]
 pub struct BrowserContext {
+    /// Name the extension reports, as the `BrowserRegistry` maps app IDs to
+    pub browser_name: String,
+    /// Browser profile, empty if the extension doesn't report one
+    pub profile: String,
+    pub window_id: Option<i64>,
     pub domain: String,
+    /// URL as `privacy.url_policy` allows it to be stored, possibly empty
+    pub url: String,
+    pub title: String,
+    /// Reported from a private/incognito window
+    pub private: bool,
+    /// On a domain in `privacy.domains`; nothing else about the tab is kept
+    pub excluded: bool,
+    /// When the tab was first reported
+    pub started_at: DateTime<Utc>,
+    /// Latest report of the same tab, refreshed while it stays focused
+    pub last_seen: DateTime<Utc>,
+}
+
+/// The focused tab as the browser extension reports it, over HTTP or
+/// native messaging
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TabReport {
+    #[serde(default)]
     pub url: String,
+    #[serde(default)]
     pub title: String,
+    pub browser_name: String,
+    /// Browser profile the extension runs in, so profiles don't overwrite each other
+    #[serde(default)]
+    pub profile: String,
+    #[serde(default)]
+    pub window_id: Option<i64>,
+    /// Tab belongs to a private/incognito window
+    #[serde(default)]
+    pub incognito: bool,
+    /// Sent by the extension (ms since epoch); the receiver's clock is used instead
+    #[serde(default)]
+    pub timestamp: i64,
+}
+
+impl TabReport {
+    /// Context to log for a report received at `now`
+    ///
+    /// The URL and title are cut down by `urls` before they go anywhere.
+    /// Nothing about a private window or a tab on an excluded domain is kept,
+    /// only that one is focused.
+    pub fn into_context(
+        self,
+        now: DateTime<Utc>,
+        urls: &UrlRedactor,
+        rules: &ExclusionRules,
+    ) -> Result<BrowserContext, String> {
+        if !is_plausible_name(&self.browser_name) || self.profile.chars().count() > MAX_NAME_LEN {
+            return Err("browser_name and profile must be short names".to_string());
+        }
+
+        let mut ctx = BrowserContext {
+            browser_name: self.browser_name.trim().to_string(),
+            profile: self.profile,
+            window_id: self.window_id,
+            domain: String::new(),
+            url: String::new(),
+            title: String::new(),
+            private: self.incognito,
+            excluded: false,
+            started_at: now,
+            last_seen: now,
+        };
+        if self.incognito {
+            return Ok(ctx);
+        }
+        let domain = url::Url::parse(&self.url)
+            .ok()
+            .and_then(|url| url.host_str().map(String::from))
+            .unwrap_or_else(|| "unknown".to_string());
+        if rules.excludes_domain(&domain) {
+            ctx.excluded = true;
+        } else {
+            ctx.domain = domain;
+            ctx.url = urls.redact(&self.url);
+            ctx.title = urls.redact_title(&self.title);
+        }
+        Ok(ctx)
+    }
+}
+
+/// What an editor plugin reports about the file being edited
+///
+/// Never includes file names or contents.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EditorContext {
+    /// Editor name as mapped in `[editors]`, e.g. `vscode` or `neovim`
+    pub editor: String,
+    /// Language ID such as `rust` or `typescriptreact`
+    #[serde(default)]
+    pub language: Option<String>,
+    #[serde(default)]
+    pub project: Option<String>,
+}
+
+impl EditorContext {
+    /// Trim every field, lowercase the language and drop empty values
+    ///
+    /// Fails without an editor name or with values that can't be names.
+    pub fn normalized(self) -> Result<Self, String> {
+        let clean = |value: Option<String>| {
+            value
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+        let ctx = Self {
+            editor: self.editor.trim().to_string(),
+            language: clean(self.language).map(|language| language.to_lowercase()),
+            project: clean(self.project),
+        };
+
+        if ctx.editor.is_empty() {
+            return Err("editor is required".to_string());
+        }
+        let values = [Some(&ctx.editor), ctx.language.as_ref(), ctx.project.as_ref()];
+        for value in values.into_iter().flatten() {
+            if !is_plausible_name(value) {
+                return Err(format!(
+                    "'{}' is not a plausible name (at most {} characters, no control characters)",
+                    value.escape_debug(),
+                    MAX_NAME_LEN
+                ));
+            }
+        }
+        Ok(ctx)
+    }
 }
 
 /// Hourly breakdown for heatmap
@@ -133,8 +321,88 @@ pub struct HourlyStats {
     pub avg_words: f64,
 }
 
+/// A token for the web API, as listed; the token itself is never stored
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiToken {
+    pub name: String,
+    /// Origin of the browser extension that paired for it, if any
+    pub origin: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+/// Typing attributed to one project tag
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct TagStats {
+    pub tag: String,
+    pub total_chars: u64,
+    pub total_words: u64,
+    pub active_minutes: u32,
+    pub percentage: f64,
+}
+
+/// Typing attributed to one project, from an editor plugin or a window title
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ProjectStats {
+    pub project: String,
+    pub total_chars: u64,
+    pub total_words: u64,
+    pub active_minutes: u32,
+    pub percentage: f64,
+}
+
+/// Typing attributed to one programming language reported by an editor
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct LanguageStats {
+    pub language: String,
+    pub total_chars: u64,
+    pub total_words: u64,
+    pub active_minutes: u32,
+    pub percentage: f64,
+}
+
+/// Longest editor, language or project name accepted from other programs
+pub const MAX_NAME_LEN: usize = 100;
+
+/// Whether a name reported by a plugin or hook looks like a name
+pub fn is_plausible_name(name: &str) -> bool {
+    !name.trim().is_empty()
+        && name.chars().count() <= MAX_NAME_LEN
+        && !name.chars().any(char::is_control)
+}
+
+/// Whether an HTTP `Host` header names `localhost` or an IP address, with or
+/// without a port
+///
+/// Local servers refuse other names, which stops a DNS name rebound to
+/// 127.0.0.1 from reaching them with a web page's origin.
+pub fn is_local_host(host: &str) -> bool {
+    let name = match host.strip_prefix('[') {
+        // [::1]:7890
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    name.eq_ignore_ascii_case("localhost") || name.parse::<std::net::IpAddr>().is_ok()
+}
+
+/// Whether `tag` can be used as a project tag
+///
+/// Tags are short names such as `client-x` or `fingerpain.web`: letters,
+/// digits, `-`, `_` and `.`, at most 64 characters.
+pub fn is_valid_tag(tag: &str) -> bool {
+    !tag.is_empty()
+        && tag.len() <= 64
+        && tag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
 /// Peak typing time info
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct PeakInfo {
     pub timestamp: DateTime<Utc>,
     pub char_count: u64,
@@ -143,17 +411,9 @@ pub struct PeakInfo {
 }
 
 /// Get the data directory for FingerPain
+///
+/// `data_dir` in the config file or `FINGERPAIN_DATA_DIR` override the
+/// platform default.
 pub fn data_dir() -> std::path::PathBuf {
-    directories::ProjectDirs::from("com", "fingerpain", "fingerpain")
-        .map(|dirs| dirs.data_dir().to_path_buf())
-        .unwrap_or_else(|| {
-            directories::BaseDirs::new()
-                .map(|d| d.home_dir().join(".fingerpain"))
-                .unwrap_or_else(|| std::path::PathBuf::from(".fingerpain"))
-        })
-}
-
-/// Get the database file path
-pub fn db_path() -> std::path::PathBuf {
-    data_dir().join("fingerpain.db")
+    config::configured_data_dir()
 }
@@ -1,7 +1,15 @@
 //! Metrics aggregation and time range utilities
 
-use crate::{db::Database, AggregatedStats, AppStats, HourlyStats, PeakInfo};
+use crate::apps::AppClassifier;
+use crate::browsers::BrowserRegistry;
+use crate::db::{Database, RecordFilter};
+use crate::domains::{DomainGroups, DomainLevel};
+use crate::{
+    AggregatedStats, AppStats, CategoryStats, DomainStats, Gap, HourlyStats, LanguageStats,
+    PeakInfo, ProjectStats, TagStats,
+};
 use chrono::{DateTime, Datelike, Duration, NaiveTime, TimeZone, Utc};
+use std::collections::HashMap;
 
 /// Time range for querying stats
 #[derive(Debug, Clone, Copy)]
@@ -122,41 +130,203 @@ impl TimeRange {
 /// High-level metrics API
 pub struct Metrics<'a> {
     db: &'a Database,
+    apps: AppClassifier,
+    filter: RecordFilter,
+    browsers: BrowserRegistry,
+    domain_groups: DomainGroups,
+    domain_level: DomainLevel,
 }
 
 impl<'a> Metrics<'a> {
+    /// Metrics using the built-in app rules
     pub fn new(db: &'a Database) -> Self {
-        Self { db }
+        Self {
+            db,
+            apps: AppClassifier::default(),
+            filter: RecordFilter::default(),
+            browsers: BrowserRegistry::default(),
+            domain_groups: DomainGroups::default(),
+            domain_level: DomainLevel::default(),
+        }
+    }
+
+    /// Use configured app aliases and categories
+    pub fn with_app_rules(mut self, apps: AppClassifier) -> Self {
+        self.apps = apps;
+        self
+    }
+
+    /// Only include records matching `filter`, e.g. one project tag
+    pub fn with_filter(mut self, filter: RecordFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Use configured browser app IDs, whose typing is broken down by domain
+    pub fn with_browsers(mut self, browsers: BrowserRegistry) -> Self {
+        self.browsers = browsers;
+        self
+    }
+
+    /// Report domains as hosts or as registrable domains with these groups
+    pub fn with_domains(mut self, groups: DomainGroups, level: DomainLevel) -> Self {
+        self.domain_groups = groups;
+        self.domain_level = level;
+        self
     }
 
     /// Get aggregated statistics for a time range
     pub fn stats(&self, range: TimeRange) -> crate::db::Result<AggregatedStats> {
         let (start, end) = range.to_range();
-        self.db.get_stats(start, end)
+        self.db.get_stats(start, end, &self.filter)
     }
 
-    /// Get per-app statistics
+    /// Get per-app statistics, with aliased apps merged and browser typing
+    /// broken down by domain
     pub fn app_stats(&self, range: TimeRange) -> crate::db::Result<Vec<AppStats>> {
         let (start, end) = range.to_range();
-        self.db.get_app_stats(start, end)
+        let raw = self.db.get_app_stats(start, end, &self.filter)?;
+
+        // Names differing only in case are the same app
+        let mut merged: Vec<(AppStats, Vec<String>)> = Vec::new();
+        let mut index: HashMap<String, usize> = HashMap::new();
+        for app in raw {
+            let (name, category) = self.apps.classify(&app.app_name, &app.app_bundle_id);
+            match index.get(&name.to_lowercase()) {
+                Some(&i) => {
+                    let (existing, bundle_ids) = &mut merged[i];
+                    existing.total_chars += app.total_chars;
+                    existing.total_words += app.total_words;
+                    existing.percentage += app.percentage;
+                    bundle_ids.push(app.app_bundle_id);
+                }
+                None => {
+                    index.insert(name.to_lowercase(), merged.len());
+                    let bundle_ids = vec![app.app_bundle_id.clone()];
+                    let app = AppStats {
+                        app_name: name,
+                        category,
+                        ..app
+                    };
+                    merged.push((app, bundle_ids));
+                }
+            }
+        }
+
+        let mut apps = Vec::with_capacity(merged.len());
+        for (mut app, bundle_ids) in merged {
+            let browsers: Vec<&str> = bundle_ids
+                .iter()
+                .map(String::as_str)
+                .filter(|id| self.browsers.is_browser(id))
+                .collect();
+            if !browsers.is_empty() {
+                app.browser_domains = Some(self.db.get_browser_domains(
+                    &browsers,
+                    start,
+                    end,
+                    self.domain_level,
+                    &self.domain_groups,
+                    &self.filter,
+                )?);
+            }
+            apps.push(app);
+        }
+
+        apps.sort_by_key(|stats| std::cmp::Reverse(stats.total_chars));
+        Ok(apps)
+    }
+
+    /// Get typing per domain across all browsers, busiest first
+    pub fn domain_stats(&self, range: TimeRange) -> crate::db::Result<Vec<DomainStats>> {
+        let (start, end) = range.to_range();
+        let apps = self.db.get_app_stats(start, end, &self.filter)?;
+        let browsers: Vec<&str> = apps
+            .iter()
+            .map(|app| app.app_bundle_id.as_str())
+            .filter(|id| self.browsers.is_browser(id))
+            .collect();
+        self.db.get_browser_domains(
+            &browsers,
+            start,
+            end,
+            self.domain_level,
+            &self.domain_groups,
+            &self.filter,
+        )
+    }
+
+    /// Get per-category statistics, busiest first
+    pub fn category_stats(&self, range: TimeRange) -> crate::db::Result<Vec<CategoryStats>> {
+        let mut categories: Vec<CategoryStats> = Vec::new();
+        for app in self.app_stats(range)? {
+            match categories.iter_mut().find(|c| c.category == app.category) {
+                Some(stats) => {
+                    stats.total_chars += app.total_chars;
+                    stats.total_words += app.total_words;
+                    stats.percentage += app.percentage;
+                    stats.apps.push(app.app_name);
+                }
+                None => categories.push(CategoryStats {
+                    category: app.category,
+                    total_chars: app.total_chars,
+                    total_words: app.total_words,
+                    percentage: app.percentage,
+                    apps: vec![app.app_name],
+                }),
+            }
+        }
+
+        categories.sort_by_key(|stats| std::cmp::Reverse(stats.total_chars));
+        Ok(categories)
     }
 
     /// Get hourly breakdown for heatmap
     pub fn hourly_stats(&self, range: TimeRange) -> crate::db::Result<Vec<HourlyStats>> {
         let (start, end) = range.to_range();
-        self.db.get_hourly_stats(start, end)
+        self.db.get_hourly_stats(start, end, &self.filter)
     }
 
     /// Get peak typing times
     pub fn peak_times(&self, range: TimeRange, limit: usize) -> crate::db::Result<Vec<PeakInfo>> {
         let (start, end) = range.to_range();
-        self.db.get_peak_times(start, end, limit)
+        self.db.get_peak_times(start, end, limit, &self.filter)
     }
 
     /// Get daily totals for charting
     pub fn daily_totals(&self, range: TimeRange) -> crate::db::Result<Vec<(DateTime<Utc>, u64, u64)>> {
         let (start, end) = range.to_range();
-        self.db.get_daily_totals(start, end)
+        self.db.get_daily_totals(start, end, &self.filter)
+    }
+
+    /// Get typing per project, from editor plugins or window titles
+    pub fn project_stats(&self, range: TimeRange) -> crate::db::Result<Vec<ProjectStats>> {
+        let (start, end) = range.to_range();
+        self.db.get_project_stats(start, end, &self.filter)
+    }
+
+    /// Get typing per programming language reported by editor plugins
+    pub fn language_stats(&self, range: TimeRange) -> crate::db::Result<Vec<LanguageStats>> {
+        let (start, end) = range.to_range();
+        self.db.get_language_stats(start, end, &self.filter)
+    }
+
+    /// Get typing per project tag; ignores the tag filter
+    pub fn tag_stats(&self, range: TimeRange) -> crate::db::Result<Vec<TagStats>> {
+        let (start, end) = range.to_range();
+        self.db.get_tag_stats(start, end, &self.filter)
+    }
+
+    /// Get periods during which nothing was recorded
+    pub fn gaps(&self, range: TimeRange) -> crate::db::Result<Vec<Gap>> {
+        let (start, end) = range.to_range();
+        self.db.get_gaps(start, end)
+    }
+
+    /// Get the number of typing sessions started; ignores filters
+    pub fn session_count(&self, range: TimeRange) -> crate::db::Result<u64> {
+        let (start, end) = range.to_range();
+        self.db.count_sessions(start, end)
     }
 
     /// Format character count for display
@@ -196,3 +366,110 @@ impl<'a> Metrics<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apps::{AppRules, Category};
+    use crate::KeystrokeRecord;
+
+    #[test]
+    fn test_app_and_category_stats_apply_rules() {
+        let db = Database::open(":memory:").unwrap();
+        let now = Utc::now();
+        for (i, (name, id, chars)) in [
+            ("Google-chrome", "Google-chrome", 10),
+            ("google-chrome", "google-chrome", 20),
+            ("Electron", "electron", 30),
+            ("kitty", "kitty", 40),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let mut record = KeystrokeRecord::new(now - Duration::minutes(i as i64))
+                .with_app(Some(name.to_string()), Some(id.to_string()));
+            record.char_count = chars;
+            db.upsert_keystroke(&record).unwrap();
+        }
+
+        let rules: AppRules = toml::from_str(
+            "[[rules]]\nmatch = \"^electron$\"\nname = \"Linear\"\ncategory = \"Communication\"\n",
+        )
+        .unwrap();
+        let metrics = Metrics::new(&db).with_app_rules(AppClassifier::new(&rules).unwrap());
+        let range = TimeRange::Custom {
+            start: now - Duration::hours(1),
+            end: now + Duration::hours(1),
+        };
+
+        let apps = metrics.app_stats(range).unwrap();
+        let names: Vec<_> = apps.iter().map(|a| a.app_name.as_str()).collect();
+        assert_eq!(names, ["kitty", "Linear", "Google Chrome"]);
+        assert_eq!(apps[2].total_chars, 30);
+
+        // Built-in browsers get a domain breakdown, merged across their IDs
+        assert!(apps[0].browser_domains.is_none());
+        let domains = apps[2].browser_domains.as_ref().unwrap();
+        assert_eq!(domains.len(), 1);
+        assert_eq!((domains[0].domain.as_str(), domains[0].total_chars), ("Other", 30));
+
+        let categories = metrics.category_stats(range).unwrap();
+        let totals: Vec<_> = categories
+            .iter()
+            .map(|c| (c.category, c.total_chars))
+            .collect();
+        assert_eq!(
+            totals,
+            [
+                (Category::Terminal, 40),
+                (Category::Communication, 30),
+                (Category::Browsing, 30)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_domain_stats_across_browsers() {
+        let db = Database::open(":memory:").unwrap();
+        let now = Utc::now();
+        for (i, (id, domain, chars)) in [
+            ("Google-chrome", "mail.google.com", 10),
+            ("firefox", "docs.google.com", 20),
+            ("firefox", "github.com", 50),
+            ("kitty", "", 100),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let mut record = KeystrokeRecord::new(now - Duration::minutes(i as i64))
+                .with_app(Some(id.to_string()), Some(id.to_string()));
+            record.char_count = chars;
+            record.browser_domain = Some(domain.to_string()).filter(|d| !d.is_empty());
+            db.upsert_keystroke(&record).unwrap();
+        }
+        let range = TimeRange::Custom {
+            start: now - Duration::hours(1),
+            end: now + Duration::hours(1),
+        };
+
+        let totals = |metrics: Metrics| -> Vec<(String, u64)> {
+            let domains = metrics.domain_stats(range).unwrap();
+            domains.into_iter().map(|d| (d.domain, d.total_chars)).collect()
+        };
+        let expected = |rows: &[(&str, u64)]| -> Vec<(String, u64)> {
+            rows.iter().map(|(d, n)| (d.to_string(), *n)).collect()
+        };
+        assert_eq!(
+            totals(Metrics::new(&db)),
+            expected(&[("github.com", 50), ("google.com", 30)])
+        );
+        assert_eq!(
+            totals(Metrics::new(&db).with_domains(DomainGroups::default(), DomainLevel::Host)),
+            expected(&[
+                ("github.com", 50),
+                ("docs.google.com", 20),
+                ("mail.google.com", 10)
+            ])
+        );
+    }
+}
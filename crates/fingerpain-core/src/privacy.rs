@@ -0,0 +1,411 @@
+//! Privacy rules and recording gaps
+//!
+//! Apps and domains on the blocklist are never recorded. Time spent in them,
+//! in private browser windows, or while paused is stored as a gap so stats can
+//! show it instead of silently missing it. Page URLs are cut down by the URL
+//! policy before they are stored.
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+/// Why nothing was recorded for a stretch of time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum GapReason {
+    /// Recording was paused by the user
+    Paused,
+    /// An app or domain on the blocklist was focused
+    Excluded,
+    /// A private/incognito browser window was focused
+    Private,
+}
+
+impl GapReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GapReason::Paused => "paused",
+            GapReason::Excluded => "excluded",
+            GapReason::Private => "private",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "paused" => Some(GapReason::Paused),
+            "excluded" => Some(GapReason::Excluded),
+            "private" => Some(GapReason::Private),
+            _ => None,
+        }
+    }
+}
+
+/// A stretch of time during which nothing was recorded
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Gap {
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub reason: GapReason,
+}
+
+impl Gap {
+    /// Whole minutes of this gap that fall inside `[start, end)`
+    pub fn minutes_within(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> u32 {
+        let from = self.start_time.max(start);
+        let to = self.end_time.min(end);
+        if to <= from {
+            return 0;
+        }
+        ((to - from).num_seconds() / 60) as u32
+    }
+}
+
+/// Apps and domains for which nothing is recorded (`[privacy]` in the config)
+///
+/// Apps match on name or bundle ID, case-insensitively. Domains match exactly
+/// or as a parent domain, so `bank.com` also covers `login.bank.com`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExclusionRules {
+    pub apps: Vec<String>,
+    pub domains: Vec<String>,
+    /// How much of each page URL is stored
+    pub url_policy: UrlPolicy,
+}
+
+impl Default for ExclusionRules {
+    fn default() -> Self {
+        Self {
+            apps: [
+                "1Password",
+                "Bitwarden",
+                "KeePassXC",
+                "Keychain Access",
+                "Passwords",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+            domains: Vec::new(),
+            url_policy: UrlPolicy::default(),
+        }
+    }
+}
+
+impl ExclusionRules {
+    pub fn excludes_app(&self, name: &str, bundle_id: &str) -> bool {
+        self.apps
+            .iter()
+            .any(|app| app.eq_ignore_ascii_case(name) || app.eq_ignore_ascii_case(bundle_id))
+    }
+
+    pub fn excludes_domain(&self, domain: &str) -> bool {
+        let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+        self.domains.iter().any(|rule| {
+            let rule = rule
+                .trim_start_matches("*.")
+                .trim_matches('.')
+                .to_ascii_lowercase();
+            domain == rule
+                || domain
+                    .strip_suffix(&rule)
+                    .is_some_and(|prefix| prefix.ends_with('.'))
+        })
+    }
+}
+
+/// How much of a page URL is stored (`privacy.url_policy`)
+///
+/// Query strings, fragments and credentials are dropped under every policy:
+/// they often hold tokens and search terms. The domain is stored on its own.
+/// Page titles follow the policy too: kept, templated or dropped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UrlPolicy {
+    /// No URL at all, only the domain
+    Domain,
+    /// Scheme, host and path
+    #[default]
+    NoQuery,
+    /// Scheme, host and path, with ID-like path segments replaced by `:id`
+    Templated,
+    /// Salted hash of scheme, host and path, telling pages apart without naming them
+    Hash,
+}
+
+impl UrlPolicy {
+    pub const ALL: [UrlPolicy; 4] = [
+        UrlPolicy::Domain,
+        UrlPolicy::NoQuery,
+        UrlPolicy::Templated,
+        UrlPolicy::Hash,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UrlPolicy::Domain => "domain",
+            UrlPolicy::NoQuery => "no-query",
+            UrlPolicy::Templated => "templated",
+            UrlPolicy::Hash => "hash",
+        }
+    }
+}
+
+/// Prefix of URLs stored under [`UrlPolicy::Hash`]
+const HASH_PREFIX: &str = "hash:";
+
+/// Applies a [`UrlPolicy`] to URLs before they are stored
+///
+/// The salt is generated once per database (see `Database::url_redactor`), so
+/// hashes can't be matched against a list of known URLs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UrlRedactor {
+    policy: UrlPolicy,
+    salt: String,
+}
+
+impl UrlRedactor {
+    pub fn new(policy: UrlPolicy, salt: impl Into<String>) -> Self {
+        Self {
+            policy,
+            salt: salt.into(),
+        }
+    }
+
+    pub fn policy(&self) -> UrlPolicy {
+        self.policy
+    }
+
+    /// What may be stored of `url`; empty if nothing may
+    ///
+    /// Applying the same policy again leaves the result unchanged. Anything
+    /// that isn't a URL with a host, including output of another policy, is
+    /// dropped.
+    pub fn redact(&self, url: &str) -> String {
+        if self.policy == UrlPolicy::Domain {
+            return String::new();
+        }
+        if self.policy == UrlPolicy::Hash && is_hash(url) {
+            return url.to_string();
+        }
+        let Ok(url) = url::Url::parse(url) else {
+            return String::new();
+        };
+        let Some(host) = url.host_str() else {
+            return String::new();
+        };
+
+        let port = url
+            .port()
+            .map(|port| format!(":{}", port))
+            .unwrap_or_default();
+        let path = match self.policy {
+            UrlPolicy::Templated => template_path(url.path()),
+            _ => url.path().to_string(),
+        };
+        let page = format!("{}://{}{}{}", url.scheme(), host, port, path);
+
+        match self.policy {
+            UrlPolicy::Hash => format!("{}{}", HASH_PREFIX, self.hash(&page)),
+            _ => page,
+        }
+    }
+
+    /// What may be stored of a page title; empty if nothing may
+    ///
+    /// Titles name what the URL would: they are dropped where the URL is,
+    /// and lose ID-like words where its path does.
+    pub fn redact_title(&self, title: &str) -> String {
+        match self.policy {
+            UrlPolicy::Domain | UrlPolicy::Hash => String::new(),
+            UrlPolicy::NoQuery => title.to_string(),
+            UrlPolicy::Templated => title
+                .split(' ')
+                .map(|word| {
+                    let id = word.trim_matches(|c: char| !c.is_alphanumeric() && c != '@');
+                    if looks_like_id(id) {
+                        word.replacen(id, ":id", 1)
+                    } else {
+                        word.to_string()
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(" "),
+        }
+    }
+
+    /// First 128 bits of the salted HMAC-SHA256, in hex
+    fn hash(&self, page: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.salt.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(page.as_bytes());
+        mac.finalize().into_bytes()[..16]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+fn is_hash(url: &str) -> bool {
+    url.strip_prefix(HASH_PREFIX)
+        .is_some_and(|hex| hex.len() == 32 && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+/// Replace path segments that look like IDs with `:id`
+fn template_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| {
+            if looks_like_id(segment) {
+                ":id"
+            } else {
+                segment
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Numbers, UUIDs, hex digests, long random tokens and email addresses
+fn looks_like_id(segment: &str) -> bool {
+    let count = |class: fn(&u8) -> bool| segment.bytes().filter(class).count();
+    let digits = count(u8::is_ascii_digit);
+    let letters = count(u8::is_ascii_alphabetic);
+    let only = |class: fn(u8) -> bool| segment.bytes().all(class);
+
+    let number = digits > 0 && digits == segment.len();
+    let hex = digits > 0
+        && count(u8::is_ascii_hexdigit) >= 8
+        && only(|b| b.is_ascii_hexdigit() || b == b'-');
+    let token = segment.len() >= 16
+        && digits > 0
+        && letters > 0
+        && only(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    number || hex || token || segment.contains('@')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_exclusion_matching() {
+        let rules: ExclusionRules = toml::from_str(
+            r#"
+            apps = ["1Password", "org.keepassxc.KeePassXC"]
+            domains = ["bank.com", "*.paypal.com"]
+            "#,
+        )
+        .unwrap();
+
+        assert!(rules.excludes_app("1password", "com.1password.1password"));
+        assert!(rules.excludes_app("KeePassXC", "org.keepassxc.KeePassXC"));
+        assert!(!rules.excludes_app("Terminal", "org.gnome.Terminal"));
+
+        assert!(rules.excludes_domain("bank.com"));
+        assert!(rules.excludes_domain("login.bank.com"));
+        assert!(rules.excludes_domain("www.paypal.com"));
+        assert!(!rules.excludes_domain("notbank.com"));
+        assert!(!rules.excludes_domain("bank.com.evil.io"));
+    }
+
+    #[test]
+    fn test_url_policies() {
+        let url = "https://user:pw@mail.example.com:8443/u/0/inbox?token=hunter2#msg-42";
+        let redact = |policy| UrlRedactor::new(policy, "salt").redact(url);
+
+        assert_eq!(redact(UrlPolicy::Domain), "");
+        assert_eq!(
+            redact(UrlPolicy::NoQuery),
+            "https://mail.example.com:8443/u/0/inbox"
+        );
+        assert_eq!(
+            redact(UrlPolicy::Templated),
+            "https://mail.example.com:8443/u/:id/inbox"
+        );
+
+        let hash = redact(UrlPolicy::Hash);
+        assert!(is_hash(&hash));
+        assert!(!hash.contains("example"));
+        // Same page, same hash; another salt, another hash
+        let other_query = "https://mail.example.com:8443/u/0/inbox?q=other";
+        assert_eq!(
+            UrlRedactor::new(UrlPolicy::Hash, "salt").redact(other_query),
+            hash
+        );
+        assert_ne!(
+            UrlRedactor::new(UrlPolicy::Hash, "pepper").redact(other_query),
+            hash
+        );
+
+        // Every policy is idempotent and drops what isn't a web URL
+        for policy in UrlPolicy::ALL {
+            let urls = UrlRedactor::new(policy, "salt");
+            let once = urls.redact(url);
+            assert_eq!(urls.redact(&once), once, "{:?}", policy);
+            assert_eq!(urls.redact("data:text/plain,secret"), "");
+            assert_eq!(urls.redact("not a url"), "");
+        }
+    }
+
+    #[test]
+    fn test_title_policies() {
+        let title = "Invoice #48213 for jane@example.com - Billing";
+        let redact = |policy| UrlRedactor::new(policy, "salt").redact_title(title);
+
+        assert_eq!(redact(UrlPolicy::NoQuery), title);
+        assert_eq!(
+            redact(UrlPolicy::Templated),
+            "Invoice #:id for :id - Billing"
+        );
+        assert_eq!(redact(UrlPolicy::Domain), "");
+        assert_eq!(redact(UrlPolicy::Hash), "");
+
+        let templated = UrlRedactor::new(UrlPolicy::Templated, "salt");
+        let once = templated.redact_title(title);
+        assert_eq!(templated.redact_title(&once), once);
+    }
+
+    #[test]
+    fn test_path_templates() {
+        let cases = [
+            ("/user/12345", "/user/:id"),
+            (
+                "/issues/0b8f6c1e-4a7d-4c2e-9f31-5d2a8b7e6c10/edit",
+                "/issues/:id/edit",
+            ),
+            ("/commit/3f9a2c7d1e", "/commit/:id"),
+            ("/reset/aB3dE5fG7hJ9kL1mN0pQ", "/reset/:id"),
+            ("/people/jane@example.com", "/people/:id"),
+            ("/blog/2024-in-review", "/blog/2024-in-review"),
+            ("/docs/getting-started/", "/docs/getting-started/"),
+            ("/", "/"),
+        ];
+        for (path, template) in cases {
+            assert_eq!(template_path(path), template, "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_gap_minutes_are_clipped_to_range() {
+        let start = Utc::now();
+        let gap = Gap {
+            start_time: start,
+            end_time: start + Duration::minutes(30),
+            reason: GapReason::Paused,
+        };
+
+        assert_eq!(gap.minutes_within(start, start + Duration::hours(1)), 30);
+        assert_eq!(
+            gap.minutes_within(start + Duration::minutes(20), start + Duration::hours(1)),
+            10
+        );
+        assert_eq!(
+            gap.minutes_within(start + Duration::hours(1), start + Duration::hours(2)),
+            0
+        );
+    }
+}
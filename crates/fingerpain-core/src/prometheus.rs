@@ -0,0 +1,175 @@
+//! Prometheus text exposition
+//!
+//! Just enough of the text format (version 0.0.4) for the daemon's and the
+//! dashboard's `/metrics`: families with help and type lines, labelled
+//! samples, and histograms with fixed buckets.
+
+use std::fmt::Write;
+
+/// Content type of a scrape response
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricType {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl MetricType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            MetricType::Counter => "counter",
+            MetricType::Gauge => "gauge",
+            MetricType::Histogram => "histogram",
+        }
+    }
+}
+
+/// A scrape response being written
+#[derive(Debug, Default)]
+pub struct Exposition {
+    text: String,
+}
+
+impl Exposition {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start a family; its samples must follow before the next one starts
+    pub fn family(&mut self, name: &str, kind: MetricType, help: &str) -> &mut Self {
+        let help = help.replace('\\', r"\\").replace('\n', r"\n");
+        let _ = writeln!(self.text, "# HELP {} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE {} {}", name, kind.as_str());
+        self
+    }
+
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) -> &mut Self {
+        self.text.push_str(name);
+        if !labels.is_empty() {
+            self.text.push('{');
+            for (i, (label, value)) in labels.iter().enumerate() {
+                if i > 0 {
+                    self.text.push(',');
+                }
+                let _ = write!(self.text, "{}=\"{}\"", label, escape(value));
+            }
+            self.text.push('}');
+        }
+        let _ = writeln!(self.text, " {}", format_value(value));
+        self
+    }
+
+    /// A family with one unlabelled sample
+    pub fn single(&mut self, name: &str, kind: MetricType, help: &str, value: f64) -> &mut Self {
+        self.family(name, kind, help).sample(name, &[], value)
+    }
+
+    /// A histogram family with one unlabelled series
+    pub fn histogram(&mut self, name: &str, help: &str, histogram: &Histogram) -> &mut Self {
+        self.family(name, MetricType::Histogram, help);
+        let bucket = format!("{}_bucket", name);
+        for (bound, count) in histogram.bounds.iter().zip(&histogram.counts) {
+            let le = format_value(*bound);
+            self.sample(&bucket, &[("le", &le)], *count as f64);
+        }
+        self.sample(&bucket, &[("le", "+Inf")], histogram.count as f64);
+        self.sample(&format!("{}_sum", name), &[], histogram.sum);
+        self.sample(&format!("{}_count", name), &[], histogram.count as f64)
+    }
+
+    pub fn finish(self) -> String {
+        self.text
+    }
+}
+
+/// Observations counted into fixed, cumulative buckets
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    bounds: Vec<f64>,
+    /// Observations at or below each bound
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    /// A histogram with these upper bounds, in ascending order
+    pub fn new(bounds: &[f64]) -> Self {
+        Self {
+            bounds: bounds.to_vec(),
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        for (bound, count) in self.bounds.iter().zip(&mut self.counts) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exposition() {
+        let mut out = Exposition::new();
+        out.family("fp_chars_total", MetricType::Counter, "Characters typed")
+            .sample("fp_chars_total", &[("category", "Coding")], 42.0)
+            .sample("fp_chars_total", &[("app", "say \"hi\"\n")], 0.5);
+        out.single("fp_up", MetricType::Gauge, "Whether we are up", 1.0);
+        assert_eq!(
+            out.finish(),
+            "# HELP fp_chars_total Characters typed\n\
+             # TYPE fp_chars_total counter\n\
+             fp_chars_total{category=\"Coding\"} 42\n\
+             fp_chars_total{app=\"say \\\"hi\\\"\\n\"} 0.5\n\
+             # HELP fp_up Whether we are up\n\
+             # TYPE fp_up gauge\n\
+             fp_up 1\n"
+        );
+    }
+
+    #[test]
+    fn test_histogram() {
+        let mut latency = Histogram::new(&[0.01, 0.1]);
+        latency.observe(0.005);
+        latency.observe(0.05);
+        latency.observe(2.0);
+
+        let mut out = Exposition::new();
+        out.histogram("fp_flush_seconds", "Flush time", &latency);
+        let text = out.finish();
+        assert!(text.contains("fp_flush_seconds_bucket{le=\"0.01\"} 1\n"));
+        assert!(text.contains("fp_flush_seconds_bucket{le=\"0.1\"} 2\n"));
+        assert!(text.contains("fp_flush_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(text.contains("fp_flush_seconds_sum 2.055\n"));
+        assert!(text.contains("fp_flush_seconds_count 3\n"));
+    }
+}
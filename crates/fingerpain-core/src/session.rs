@@ -2,11 +2,11 @@
 
 use crate::{db::Database, TypingSession};
 use chrono::{DateTime, Duration, Utc};
-use std::sync::{Arc, Mutex};
+use std::sync::Mutex;
 
 /// Tracks typing sessions and calculates WPM
 pub struct SessionTracker {
-    db: Arc<Database>,
+    db: Database,
     current_session: Mutex<Option<ActiveSession>>,
     /// Idle timeout before ending a session (default: 5 seconds)
     idle_timeout: Duration,
@@ -22,7 +22,7 @@ struct ActiveSession {
 }
 
 impl SessionTracker {
-    pub fn new(db: Arc<Database>) -> Self {
+    pub fn new(db: Database) -> Self {
         Self {
             db,
             current_session: Mutex::new(None),
@@ -35,6 +35,11 @@ impl SessionTracker {
         self
     }
 
+    /// Change the idle timeout; applies to the open session too
+    pub fn set_idle_timeout(&mut self, timeout: Duration) {
+        self.idle_timeout = timeout;
+    }
+
     /// Record a keystroke event
     pub fn record_keystroke(&self, char_count: u32, word_count: u32) -> crate::db::Result<()> {
         let now = Utc::now();
@@ -129,6 +134,15 @@ impl SessionTracker {
             .unwrap_or(0.0)
     }
 
+    /// The open session so far, with its peak WPM
+    pub fn current_session(&self) -> Option<TypingSession> {
+        self.current_session.lock().unwrap().as_ref().map(|active| {
+            let mut session = active.session.clone();
+            session.wpm_peak = Some(active.peak_wpm);
+            session
+        })
+    }
+
     /// Check for idle and end session if needed
     pub fn check_idle(&self) -> crate::db::Result<()> {
         let now = Utc::now();
@@ -0,0 +1,307 @@
+//! Aggregation thread
+//!
+//! Consumes input events, keeps the current minute in memory, snapshots it to
+//! the journal, and hands completed minutes to the writer. Handed-off minutes
+//! stay in the journal until the writer confirms their commit. A timer tick makes
+//! sure a minute is written when it ends, not when the next key arrives.
+//!
+//! Input arriving while paused or while an excluded app, domain or private
+//! window is focused is discarded, and the time is recorded as a gap.
+
+use crate::journal::{Journal, Pending};
+use crate::metrics::DbWrite;
+use crate::state::DaemonState;
+use crate::tracker::KeystrokeTracker;
+use crate::writer::WriteRequest;
+use chrono::{DateTime, Utc};
+use fingerpain_core::control::LiveSession;
+use fingerpain_core::{Config, Gap, GapReason, KeystrokeRecord, SessionTracker};
+use fingerpain_listener::{FocusEvent, InputEvent, KeyEvent, KeyEventType};
+use std::collections::VecDeque;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+/// How often the aggregator wakes up without input
+const TICK: Duration = Duration::from_millis(500);
+/// How often pending records are snapshotted to the journal
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(2);
+/// How often an open gap is extended in the database
+const GAP_WRITE_INTERVAL: Duration = Duration::from_secs(60);
+/// How long shutdown waits for the writer to confirm the last batches
+const SHUTDOWN_ACK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Requests from outside the input stream
+#[derive(Debug, Clone, PartialEq)]
+pub enum Control {
+    /// Write the in-memory minute now and close the open session
+    Flush,
+    /// Switch to a new, already validated configuration
+    Reconfigure(Box<Config>),
+    /// Flush, then stop the thread
+    Shutdown,
+}
+
+pub struct Aggregator {
+    pub tracker: KeystrokeTracker,
+    pub sessions: SessionTracker,
+    pub journal: Journal,
+    /// What the journal holds: batches not yet confirmed and the current minute
+    pub pending: Pending,
+    pub writer: Sender<WriteRequest>,
+    /// Numbers of the batches the writer has committed
+    pub acks: Receiver<u64>,
+    pub state: Arc<DaemonState>,
+    /// Gap currently being recorded, and when it was last written
+    pub open_gap: Option<(Gap, Instant)>,
+}
+
+impl Aggregator {
+    /// Run until the event queue closes or `Control::Shutdown` arrives
+    ///
+    /// Focus changes are applied in timestamp order relative to key events, so
+    /// a key pressed just before a switch is still credited to the previous app.
+    pub fn run(
+        mut self,
+        events: Receiver<InputEvent>,
+        focus_events: Receiver<FocusEvent>,
+        control: Receiver<Control>,
+    ) {
+        let mut pending_focus: VecDeque<FocusEvent> = VecDeque::new();
+        let mut last_checkpoint = Instant::now();
+
+        loop {
+            match events.recv_timeout(TICK) {
+                Ok(InputEvent::Key(key)) => {
+                    self.state.metrics.key_processed();
+                    pending_focus.extend(focus_events.try_iter());
+                    self.apply_focus(&mut pending_focus, key.timestamp);
+
+                    let completed = self.tracker.roll_over(key.timestamp);
+                    self.hand_off(completed);
+
+                    if !self.update_gap(key.timestamp) {
+                        self.record(key);
+                    }
+                }
+                Ok(InputEvent::Focus(focus)) => {
+                    self.state.metrics.focus_processed();
+                    self.tracker.set_focus(focus);
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+
+            // Switches while no keys are pressed still open and close gaps
+            pending_focus.extend(focus_events.try_iter());
+            self.apply_focus(&mut pending_focus, Utc::now());
+
+            // Timer-driven work, also reached while typing
+            self.confirm(self.acks.try_iter().max());
+            let completed = self.tracker.roll_over(Utc::now());
+            self.hand_off(completed);
+            let recording = !self.update_gap(Utc::now());
+            if let Err(e) = self.sessions.check_idle() {
+                error!("Failed to close idle session: {}", e);
+                self.state.metrics.db_error(DbWrite::Sessions);
+            }
+            self.state.set_wpm(self.sessions.current_wpm());
+            // The live feed never names an app that isn't being recorded
+            let focused = self.tracker.focused().filter(|_| recording);
+            self.state.set_live(focused, self.live_session());
+
+            if last_checkpoint.elapsed() >= CHECKPOINT_INTERVAL {
+                self.checkpoint();
+                last_checkpoint = Instant::now();
+            }
+
+            match control.try_recv() {
+                Ok(Control::Flush) => self.flush(),
+                Ok(Control::Reconfigure(config)) => self.reconfigure(&config),
+                Ok(Control::Shutdown) => break,
+                Err(_) => {}
+            }
+        }
+
+        self.flush();
+        // Give the writer a moment so a clean stop leaves no journal behind
+        let deadline = Instant::now() + SHUTDOWN_ACK_TIMEOUT;
+        while !self.pending.batches.is_empty() {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match self.acks.recv_timeout(timeout) {
+                Ok(batch) => self.confirm(Some(batch)),
+                Err(_) => break,
+            }
+        }
+        info!("Aggregator stopped");
+    }
+
+    /// Apply focus changes up to `until`, so a gap starts or ends at the
+    /// switch rather than at the next key or tick
+    fn apply_focus(&mut self, pending: &mut VecDeque<FocusEvent>, until: DateTime<Utc>) {
+        while let Some(focus) = pending.pop_front() {
+            if focus.timestamp > until {
+                pending.push_front(focus);
+                break;
+            }
+            let at = focus.timestamp;
+            self.tracker.set_focus(focus);
+            self.update_gap(at);
+        }
+    }
+
+    fn record(&mut self, key: KeyEvent) {
+        self.tracker.set_tags(self.state.tags());
+        let terminal = self.tracker.focused_pid();
+        self.tracker
+            .set_shell_context(self.state.shell_context(terminal));
+        self.state.record_key(key.timestamp);
+        let backspace = key.event_type == KeyEventType::Backspace;
+        let (chars, words) = self.tracker.process(key);
+        let category = self
+            .tracker
+            .focused()
+            .map(|(_, category)| category)
+            .unwrap_or_default();
+        self.state
+            .metrics
+            .record_typing(category, chars, words, backspace);
+        if chars > 0 {
+            let session = self.sessions.current_session().map(|s| s.start_time);
+            if let Err(e) = self.sessions.record_keystroke(chars, words) {
+                error!("Failed to record session: {}", e);
+                self.state.metrics.db_error(DbWrite::Sessions);
+            }
+            if self.sessions.current_session().map(|s| s.start_time) != session {
+                self.state.metrics.session_started(category);
+            }
+            self.state.set_wpm(self.sessions.current_wpm());
+        }
+    }
+
+    fn live_session(&self) -> Option<LiveSession> {
+        self.sessions.current_session().map(|session| LiveSession {
+            started_at: session.start_time,
+            char_count: session.char_count,
+            word_count: session.word_count,
+            peak_wpm: session.wpm_peak.unwrap_or_default(),
+        })
+    }
+
+    /// Write everything in memory and close the open session
+    fn flush(&mut self) {
+        let records = self.tracker.flush();
+        self.hand_off(records);
+        if let Some((mut gap, _)) = self.open_gap.take() {
+            gap.end_time = Utc::now();
+            self.write_gap(gap);
+        }
+        if let Err(e) = self.sessions.end_session() {
+            error!("Failed to close session: {}", e);
+            self.state.metrics.db_error(DbWrite::Sessions);
+        }
+        self.state.set_wpm(0.0);
+    }
+
+    /// Apply new exclusion rules and timeouts from the next event on
+    fn reconfigure(&mut self, config: &Config) {
+        self.tracker.apply_config(config);
+        self.sessions.set_idle_timeout(chrono::Duration::seconds(
+            config.daemon.idle_timeout_secs as i64,
+        ));
+        // An app that was just excluded (or allowed) takes effect immediately
+        self.update_gap(Utc::now());
+    }
+
+    /// Open, extend or close the current gap; returns whether input is discarded
+    fn update_gap(&mut self, now: DateTime<Utc>) -> bool {
+        let reason = if self.state.is_paused() {
+            Some(GapReason::Paused)
+        } else {
+            self.tracker.exclusion()
+        };
+        self.state.set_not_recording(reason);
+
+        match (&mut self.open_gap, reason) {
+            (Some((gap, written)), Some(reason)) if gap.reason == reason => {
+                gap.end_time = gap.end_time.max(now);
+                if written.elapsed() >= GAP_WRITE_INTERVAL {
+                    *written = Instant::now();
+                    let gap = gap.clone();
+                    self.write_gap(gap);
+                }
+            }
+            _ => {
+                if let Some((mut gap, _)) = self.open_gap.take() {
+                    gap.end_time = gap.end_time.max(now);
+                    info!("Recording again after {} time", gap.reason.as_str());
+                    self.write_gap(gap);
+                }
+                if let Some(reason) = reason {
+                    info!("Not recording: {}", reason.as_str());
+                    let gap = Gap {
+                        start_time: now,
+                        end_time: now,
+                        reason,
+                    };
+                    self.write_gap(gap.clone());
+                    self.open_gap = Some((gap, Instant::now()));
+                }
+            }
+        }
+
+        reason.is_some()
+    }
+
+    fn write_gap(&mut self, gap: Gap) {
+        if self.writer.send(WriteRequest::Gap(gap)).is_err() {
+            error!("Writer thread has stopped");
+        }
+    }
+
+    fn checkpoint(&mut self) {
+        if let Some(current) = self.tracker.snapshot() {
+            self.pending.current = current;
+            self.save_journal();
+        }
+    }
+
+    fn hand_off(&mut self, records: Vec<KeystrokeRecord>) {
+        if records.is_empty() {
+            return;
+        }
+
+        // Journaled as a batch until the writer confirms it
+        let batch = self.pending.next_batch;
+        self.pending.next_batch += 1;
+        self.pending.batches.push((batch, records.clone()));
+        self.pending.current.clear();
+        self.save_journal();
+        if self
+            .writer
+            .send(WriteRequest::Records { batch, records })
+            .is_err()
+        {
+            error!("Writer thread has stopped");
+        }
+    }
+
+    /// Drop batches up to `acked` from the journal
+    fn confirm(&mut self, acked: Option<u64>) {
+        let Some(acked) = acked else {
+            return;
+        };
+        let before = self.pending.batches.len();
+        self.pending.batches.retain(|(batch, _)| *batch > acked);
+        if self.pending.batches.len() != before {
+            self.save_journal();
+        }
+    }
+
+    fn save_journal(&self) {
+        if let Err(e) = self.journal.save(&self.pending) {
+            error!("Failed to write journal: {}", e);
+        }
+    }
+}
@@ -0,0 +1,326 @@
+//! Control socket server
+//!
+//! Serves the JSON-RPC protocol from `fingerpain_core::control` on a per-user
+//! Unix domain socket. Each connection may send any number of requests, until
+//! a `live` request turns it into a feed of one snapshot a second.
+
+use crate::metrics::{ContextSource, DbWrite};
+use crate::state::{DaemonState, Workers};
+use chrono::{DateTime, Duration, Utc};
+use fingerpain_core::control::{codes, methods, RpcNotification, RpcRequest, RpcResponse};
+use fingerpain_core::{EditorContext, TabReport};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::Duration as StdDuration;
+use tracing::info;
+
+/// How often the live feed sends a snapshot
+const LIVE_INTERVAL: StdDuration = StdDuration::from_secs(1);
+
+/// What a connection does once a request is answered
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Then {
+    Continue,
+    Shutdown,
+    StreamLive,
+}
+
+/// Handle a single request; `Err` carries a JSON-RPC error code and message
+fn dispatch(
+    state: &DaemonState,
+    workers: &Workers,
+    request: &RpcRequest,
+) -> Result<Value, (i64, String)> {
+    let db_error = |e: fingerpain_core::db::DbError| {
+        state.metrics.db_error(DbWrite::Context);
+        (codes::INTERNAL_ERROR, e.to_string())
+    };
+    match request.method.as_str() {
+        methods::STATUS => {
+            serde_json::to_value(state.status()).map_err(|e| (codes::INTERNAL_ERROR, e.to_string()))
+        }
+        methods::PAUSE => {
+            let until = match request.params.get("seconds") {
+                None => None,
+                Some(seconds) => Some(pause_until(seconds, Utc::now())?),
+            };
+            state.pause(until);
+            match until {
+                Some(until) => info!("Recording paused until {}", until),
+                None => info!("Recording paused"),
+            }
+            Ok(json!({ "paused": true, "until": until }))
+        }
+        methods::RESUME => {
+            state.resume();
+            info!("Recording resumed");
+            Ok(json!({ "paused": false }))
+        }
+        methods::FLUSH => {
+            workers.flush();
+            Ok(json!({ "flushed": true }))
+        }
+        methods::RELOAD_CONFIG => {
+            fingerpain_listener::platform::clear_app_cache();
+            let changes = crate::reload::reload(state, workers)
+                .map_err(|e| (codes::INVALID_PARAMS, e.to_string()))?;
+            Ok(json!({ "reloaded": true, "changes": changes }))
+        }
+        methods::TAG_START => {
+            let tag = tag_param(request)?.ok_or((
+                codes::INVALID_PARAMS,
+                "tag is required".to_string(),
+            ))?;
+            let tags = state.start_tag(&tag);
+            info!("Tagging typing with {}", tags.join(", "));
+            Ok(json!({ "tags": tags }))
+        }
+        methods::TAG_STOP => {
+            let tag = tag_param(request)?;
+            let tags = state.stop_tag(tag.as_deref());
+            match &tag {
+                Some(tag) => info!("Stopped tagging with {}", tag),
+                None => info!("Stopped all tags"),
+            }
+            Ok(json!({ "tags": tags }))
+        }
+        methods::EDITOR_CONTEXT => {
+            let ctx = serde_json::from_value::<EditorContext>(request.params.clone())
+                .map_err(|e| (codes::INVALID_PARAMS, e.to_string()))?
+                .normalized()
+                .map_err(|e| (codes::INVALID_PARAMS, e))?;
+            state
+                .context_db
+                .lock()
+                .unwrap()
+                .upsert_editor_context(&ctx)
+                .map_err(db_error)?;
+            state.metrics.context_updated(ContextSource::Editor);
+            Ok(json!({ "updated": true }))
+        }
+        methods::BROWSER_CONTEXT => {
+            let report = serde_json::from_value::<TabReport>(request.params.clone())
+                .map_err(|e| (codes::INVALID_PARAMS, e.to_string()))?;
+            let rules = state.config.lock().unwrap().privacy.clone();
+            let db = state.context_db.lock().unwrap();
+            let urls = db.url_redactor(rules.url_policy).map_err(db_error)?;
+            let ctx = report
+                .into_context(Utc::now(), &urls, &rules)
+                .map_err(|e| (codes::INVALID_PARAMS, e))?;
+            db.log_browser_context(&ctx).map_err(db_error)?;
+            state.metrics.context_updated(ContextSource::Browser);
+            Ok(json!({ "updated": true }))
+        }
+        methods::SHELL_CONTEXT => {
+            let project = match request.params.get("project") {
+                None | Some(Value::Null) => None,
+                Some(Value::String(project)) if fingerpain_core::is_plausible_name(project) => {
+                    Some(project.trim().to_string())
+                }
+                Some(_) => {
+                    return Err((
+                        codes::INVALID_PARAMS,
+                        "project must be a short name".to_string(),
+                    ))
+                }
+            };
+            // Hooks from before shells were told apart send no pid
+            let shell = match request.params.get("pid") {
+                None | Some(Value::Null) => 0,
+                Some(pid) => pid
+                    .as_u64()
+                    .and_then(|pid| u32::try_from(pid).ok())
+                    .ok_or((
+                        codes::INVALID_PARAMS,
+                        "pid must be a process ID".to_string(),
+                    ))?,
+            };
+            state.set_shell_project(shell, project);
+            state.metrics.context_updated(ContextSource::Shell);
+            Ok(json!({ "updated": true }))
+        }
+        methods::SHUTDOWN => Ok(json!({ "stopping": true })),
+        methods::LIVE => Ok(json!({ "interval_ms": LIVE_INTERVAL.as_millis() as u64 })),
+        other => Err((
+            codes::METHOD_NOT_FOUND,
+            format!("Unknown method: {}", other),
+        )),
+    }
+}
+
+/// When a pause of `seconds` from `now` ends; it must be a time chrono can hold
+fn pause_until(seconds: &Value, now: DateTime<Utc>) -> Result<DateTime<Utc>, (i64, String)> {
+    seconds
+        .as_i64()
+        .filter(|s| *s > 0)
+        .and_then(Duration::try_seconds)
+        .and_then(|duration| now.checked_add_signed(duration))
+        .ok_or((
+            codes::INVALID_PARAMS,
+            "seconds must be a positive integer no larger than a pause can last".to_string(),
+        ))
+}
+
+/// The optional `tag` parameter, which must be a valid tag name
+fn tag_param(request: &RpcRequest) -> Result<Option<String>, (i64, String)> {
+    match request.params.get("tag") {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(tag)) if fingerpain_core::is_valid_tag(tag) => Ok(Some(tag.clone())),
+        Some(_) => Err((
+            codes::INVALID_PARAMS,
+            "tag must be 1-64 letters, digits, '-', '_' or '.'".to_string(),
+        )),
+    }
+}
+
+/// Parse and answer one line of input
+fn handle_line(state: &DaemonState, workers: &Workers, line: &str) -> (RpcResponse, Then) {
+    let request: RpcRequest = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) => {
+            return (
+                RpcResponse::failure(Value::Null, codes::PARSE_ERROR, e.to_string()),
+                Then::Continue,
+            )
+        }
+    };
+
+    if request.jsonrpc != "2.0" {
+        return (
+            RpcResponse::failure(request.id, codes::INVALID_REQUEST, "Expected jsonrpc 2.0"),
+            Then::Continue,
+        );
+    }
+
+    match dispatch(state, workers, &request) {
+        Ok(result) => {
+            let then = match request.method.as_str() {
+                methods::SHUTDOWN => Then::Shutdown,
+                methods::LIVE => Then::StreamLive,
+                _ => Then::Continue,
+            };
+            (RpcResponse::success(request.id, result), then)
+        }
+        Err((code, message)) => (
+            RpcResponse::failure(request.id, code, message),
+            Then::Continue,
+        ),
+    }
+}
+
+/// Send a live snapshot each second until the client hangs up
+#[cfg(unix)]
+fn stream_live(writer: &mut impl std::io::Write, state: &DaemonState) {
+    loop {
+        // On the second, so every client graphs the same seconds
+        let millis = Utc::now().timestamp_subsec_millis().min(999) as u64;
+        std::thread::sleep(LIVE_INTERVAL - StdDuration::from_millis(millis));
+
+        let snapshot = serde_json::to_value(state.live(Utc::now())).unwrap_or_default();
+        let notification = RpcNotification::new(methods::LIVE, snapshot);
+        let mut out = serde_json::to_string(&notification).unwrap_or_default();
+        out.push('\n');
+        if writer.write_all(out.as_bytes()).is_err() {
+            return;
+        }
+    }
+}
+
+/// Answer requests on one connection until the client hangs up
+#[cfg(unix)]
+fn serve(stream: std::os::unix::net::UnixStream, state: Arc<DaemonState>, workers: Arc<Workers>) {
+    use std::io::{BufRead, BufReader, Write};
+    use tracing::warn;
+
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(e) => return warn!("Control connection failed: {}", e),
+    };
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else { return };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let (response, then) = handle_line(&state, &workers, &line);
+        let mut out = serde_json::to_string(&response).unwrap_or_default();
+        out.push('\n');
+        if writer.write_all(out.as_bytes()).is_err() {
+            return;
+        }
+
+        if then == Then::StreamLive {
+            return stream_live(&mut writer, &state);
+        }
+        if then == Then::Shutdown {
+            info!("Shutdown requested over control socket");
+            workers.shutdown();
+            info!("FingerPain daemon stopped");
+            std::process::exit(0);
+        }
+    }
+}
+
+#[cfg(unix)]
+pub fn spawn(state: Arc<DaemonState>, workers: Arc<Workers>) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixListener;
+    use tracing::error;
+
+    let path = fingerpain_core::control::socket_path();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    // We hold the instance lock, so any existing socket is stale
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)?;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+    info!("Control socket listening at {:?}", path);
+
+    std::thread::Builder::new()
+        .name("control".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let (state, workers) = (state.clone(), workers.clone());
+                        std::thread::spawn(move || serve(stream, state, workers));
+                    }
+                    Err(e) => error!("Control socket accept failed: {}", e),
+                }
+            }
+        })?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn spawn(_state: Arc<DaemonState>, _workers: Arc<Workers>) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pause_until_rejects_out_of_range_seconds() {
+        let now = Utc::now();
+        assert_eq!(
+            pause_until(&json!(60), now).unwrap(),
+            now + Duration::seconds(60)
+        );
+        for seconds in [
+            json!(0),
+            json!(-5),
+            json!("60"),
+            json!(i64::MAX),
+            json!(u64::MAX),
+        ] {
+            let (code, _) = pause_until(&seconds, now).unwrap_err();
+            assert_eq!(code, codes::INVALID_PARAMS, "{}", seconds);
+        }
+        // In range for a duration, but past the last time chrono can hold
+        let (code, _) = pause_until(&json!(i64::MAX / 1000), now).unwrap_err();
+        assert_eq!(code, codes::INVALID_PARAMS);
+    }
+}
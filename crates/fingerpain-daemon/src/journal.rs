@@ -0,0 +1,194 @@
+//! On-disk write-ahead buffer for records not yet committed
+//!
+//! The aggregator keeps the current minute in memory and hands completed
+//! minutes to the writer as numbered batches. Every few seconds, and whenever
+//! a batch is handed off or confirmed, it saves the batches still waiting for
+//! the writer and the current minute here. A crash loses at most one
+//! checkpoint interval of counts, and a failed write loses nothing.
+//!
+//! Everything is replayed into the database on the next start. Batch numbers
+//! are committed with the records, so a batch that did reach the database
+//! before the crash is not counted again.
+
+use fingerpain_core::db::Database;
+use fingerpain_core::KeystrokeRecord;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// What the journal holds
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Pending {
+    /// Number the next batch handed to the writer gets; the current minute
+    /// is replayed under it
+    pub next_batch: u64,
+    /// Batches handed to the writer whose commit isn't confirmed yet
+    pub batches: Vec<(u64, Vec<KeystrokeRecord>)>,
+    /// The minute still being counted
+    pub current: Vec<KeystrokeRecord>,
+}
+
+impl Pending {
+    /// Nothing pending, with batches numbered from `next_batch`
+    pub fn starting_at(next_batch: u64) -> Self {
+        Self {
+            next_batch,
+            ..Self::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batches.is_empty() && self.current.is_empty()
+    }
+
+    /// Commit everything the database doesn't have yet, returning how many
+    /// records that was
+    pub fn replay(&self, db: &Database) -> fingerpain_core::db::Result<usize> {
+        let current = (self.next_batch, self.current.clone());
+        let mut replayed = 0;
+        for (batch, records) in self.batches.iter().chain(std::iter::once(&current)) {
+            if !records.is_empty() && db.upsert_keystroke_batch(*batch, records)? {
+                replayed += records.len();
+            }
+        }
+        Ok(replayed)
+    }
+}
+
+/// The journal on disk; versions before batches were numbered held only the
+/// current minute
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Stored {
+    Pending(Pending),
+    Legacy(Vec<KeystrokeRecord>),
+}
+
+pub struct Journal {
+    path: PathBuf,
+}
+
+impl Journal {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Journal in a data directory, next to the database
+    pub fn open_in(data_dir: &Path) -> Self {
+        Self::new(data_dir.join("pending.json"))
+    }
+
+    /// Replace the buffer with what is pending now
+    pub fn save(&self, pending: &Pending) -> io::Result<()> {
+        if pending.is_empty() {
+            return self.clear();
+        }
+
+        // Write-then-rename so a crash never leaves a half-written buffer
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec(pending)?)?;
+        fs::rename(&tmp, &self.path)
+    }
+
+    /// Remove the buffer once everything in it has been committed
+    pub fn clear(&self) -> io::Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Read what a previous run left behind
+    ///
+    /// A journal from before batches were numbered is replayed as one batch
+    /// after `committed`, the last batch the database has.
+    pub fn load(&self, committed: u64) -> io::Result<Pending> {
+        let data = match fs::read(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Pending::default()),
+            Err(e) => return Err(e),
+        };
+        Ok(match serde_json::from_slice(&data)? {
+            Stored::Pending(pending) => pending,
+            Stored::Legacy(current) => Pending {
+                next_batch: committed + 1,
+                batches: Vec::new(),
+                current,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn record(chars: u32) -> KeystrokeRecord {
+        let mut record = KeystrokeRecord::new(Utc::now()).with_app(
+            Some("Editor".to_string()),
+            Some("com.test.editor".to_string()),
+        );
+        record.char_count = chars;
+        record
+    }
+
+    #[test]
+    fn test_save_load_clear() {
+        let path =
+            std::env::temp_dir().join(format!("fingerpain-journal-{}.json", std::process::id()));
+        let journal = Journal::new(path.clone());
+
+        let pending = Pending {
+            next_batch: 3,
+            batches: vec![(2, vec![record(10)])],
+            current: vec![record(42)],
+        };
+        journal.save(&pending).unwrap();
+        let loaded = journal.load(0).unwrap();
+        assert_eq!(loaded.next_batch, 3);
+        assert_eq!(loaded.batches[0].0, 2);
+        assert_eq!(loaded.batches[0].1[0].char_count, 10);
+        assert_eq!(loaded.current[0].char_count, 42);
+
+        // A journal written before batches were numbered
+        fs::write(&path, serde_json::to_vec(&[record(7)]).unwrap()).unwrap();
+        let legacy = journal.load(5).unwrap();
+        assert_eq!(legacy.next_batch, 6);
+        assert_eq!(legacy.current[0].char_count, 7);
+
+        // Saving nothing removes the buffer
+        journal.save(&Pending::starting_at(3)).unwrap();
+        assert!(!path.exists());
+        assert!(journal.load(0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_replay_skips_committed_batches() {
+        let db = Database::open(":memory:").unwrap();
+        let start = Utc::now() - chrono::Duration::hours(1);
+        // Records are stored at the start of their minute, which may be now
+        let end = Utc::now() + chrono::Duration::minutes(1);
+        let chars = |db: &Database| {
+            db.get_stats(start, end, &Default::default())
+                .unwrap()
+                .total_chars
+        };
+
+        // Batch 1 reached the database before the crash, batch 2 didn't
+        db.upsert_keystroke_batch(1, &[record(10)]).unwrap();
+        let pending = Pending {
+            next_batch: 3,
+            batches: vec![(1, vec![record(10)]), (2, vec![record(20)])],
+            current: vec![record(5)],
+        };
+        assert_eq!(pending.replay(&db).unwrap(), 2);
+        assert_eq!(chars(&db), 35);
+
+        // Replaying again after another crash changes nothing
+        assert_eq!(pending.replay(&db).unwrap(), 0);
+        assert_eq!(chars(&db), 35);
+        assert_eq!(db.committed_batch().unwrap(), 3);
+    }
+}
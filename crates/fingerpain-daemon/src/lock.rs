@@ -0,0 +1,40 @@
+//! Single-instance lock
+//!
+//! Two daemons would double-count every keystroke, so startup takes an
+//! exclusive lock on a file in the data directory and holds it until exit.
+
+use anyhow::{bail, Result};
+use fs2::FileExt;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+pub struct InstanceLock {
+    _file: File,
+}
+
+impl InstanceLock {
+    pub fn acquire(dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir)?;
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dir.join("daemon.lock"))?;
+
+        if file.try_lock_exclusive().is_err() {
+            let mut pid = String::new();
+            let _ = file.read_to_string(&mut pid);
+            bail!("FingerPain daemon is already running (pid {})", pid.trim());
+        }
+
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        write!(file, "{}", std::process::id())?;
+        file.flush()?;
+
+        Ok(Self { _file: file })
+    }
+}
@@ -2,193 +2,73 @@
 //!
 //! Background service that listens to keystrokes and records them.
 //! On macOS, rdev::listen requires running on the main thread with CFRunLoop.
-
+//!
+//! The input callback only enqueues events. An aggregation thread turns them
+//! into per-minute records and a writer thread persists them.
+
+mod aggregator;
+mod control;
+mod journal;
+mod lock;
+mod metrics;
+mod reload;
+mod state;
+mod tracker;
+mod writer;
+
+use aggregator::Aggregator;
 use anyhow::Result;
-use fingerpain_core::db::Database;
-use fingerpain_core::KeystrokeRecord;
-use fingerpain_listener::platform;
 use chrono::Utc;
-use rdev::{listen, Event, EventType, Key};
-use std::sync::{Arc, Mutex};
-use std::collections::HashMap;
+use fingerpain_core::db::Database;
+use fingerpain_core::{Config, SessionTracker};
+use fingerpain_listener::{
+    platform, EventQueue, FocusEvent, FocusWatcher, InputEvent, KeyEvent, KeyEventType,
+    TitleRules,
+};
+use journal::{Journal, Pending};
+use lock::InstanceLock;
+use rdev::{listen, Event, EventType};
+use state::{DaemonState, Workers};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 use tracing::{error, info};
-
-/// Tracks keystrokes per minute per app
-struct KeystrokeTracker {
-    db: Database,
-    current_minute: i64,
-    records: HashMap<String, KeystrokeRecord>,
-    pending_word_chars: u32,
-    last_app_check: i64,
-    cached_app: Option<platform::ActiveApp>,
-}
-
-impl KeystrokeTracker {
-    fn new(db: Database) -> Self {
-        Self {
-            db,
-            current_minute: 0,
-            records: HashMap::new(),
-            pending_word_chars: 0,
-            last_app_check: 0,
-            cached_app: None,
-        }
-    }
-
-    fn is_browser(bundle_id: &str) -> bool {
-        matches!(
-            bundle_id,
-            "com.JadeApps.Helium"
-                | "com.google.Chrome"
-                | "org.mozilla.firefox"
-                | "com.apple.Safari"
-        )
-    }
-
-    fn process_key(&mut self, key: Key) {
-        let now = Utc::now();
-        let minute = now.timestamp() / 60;
-        let current_time = now.timestamp();
-
-        // If we moved to a new minute, flush old records
-        if minute != self.current_minute && self.current_minute != 0 {
-            self.flush();
-        }
-        self.current_minute = minute;
-
-        // Check active app every 2 seconds (cache it)
-        if current_time - self.last_app_check >= 2 {
-            self.cached_app = platform::get_active_app().ok();
-            self.last_app_check = current_time;
-        }
-
-        // Determine key type
-        let (is_char, is_word_boundary, is_backspace, is_enter) = match key {
-            Key::Space => (true, true, false, false),
-            Key::Tab => (true, true, false, false),
-            Key::Return => (true, true, false, true),
-            Key::Backspace => (false, false, true, false),
-            Key::KeyA | Key::KeyB | Key::KeyC | Key::KeyD | Key::KeyE |
-            Key::KeyF | Key::KeyG | Key::KeyH | Key::KeyI | Key::KeyJ |
-            Key::KeyK | Key::KeyL | Key::KeyM | Key::KeyN | Key::KeyO |
-            Key::KeyP | Key::KeyQ | Key::KeyR | Key::KeyS | Key::KeyT |
-            Key::KeyU | Key::KeyV | Key::KeyW | Key::KeyX | Key::KeyY |
-            Key::KeyZ => (true, false, false, false),
-            Key::Num0 | Key::Num1 | Key::Num2 | Key::Num3 | Key::Num4 |
-            Key::Num5 | Key::Num6 | Key::Num7 | Key::Num8 | Key::Num9 => (true, false, false, false),
-            Key::Comma | Key::Dot | Key::Slash | Key::SemiColon |
-            Key::Quote | Key::LeftBracket | Key::RightBracket |
-            Key::BackSlash | Key::Minus | Key::Equal | Key::BackQuote => (true, false, false, false),
-            _ => (false, false, false, false),
-        };
-
-        // Skip if not a relevant key
-        if !is_char && !is_backspace {
-            return;
-        }
-
-        // Get app info and browser context
-        let (app_name, bundle_id, browser_domain, browser_url): (Option<String>, Option<String>, Option<String>, Option<String>) =
-            if let Some(ref app) = self.cached_app {
-                let mut domain: Option<String> = None;
-                let mut url: Option<String> = None;
-
-                // If browser, try to fetch latest domain context
-                if Self::is_browser(&app.bundle_id) {
-                    if let Ok(Some(ctx)) = self.db.get_browser_context(&app.bundle_id) {
-                        domain = Some(ctx.domain);
-                        url = Some(ctx.url);
-                    }
+use tracker::KeystrokeTracker;
+
+/// How many times a failed keyboard listener is started again before giving up
+const LISTENER_RETRIES: u32 = 5;
+const LISTENER_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Flush and exit on SIGTERM/SIGINT; reload the config on SIGHUP
+#[cfg(unix)]
+fn spawn_signal_handler(state: Arc<DaemonState>, workers: Arc<Workers>) -> Result<()> {
+    use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+    use signal_hook::iterator::Signals;
+
+    let mut signals = Signals::new([SIGTERM, SIGINT, SIGHUP])?;
+    thread::Builder::new()
+        .name("signals".to_string())
+        .spawn(move || {
+            for signal in signals.forever() {
+                if signal == SIGHUP {
+                    info!("SIGHUP received, reloading config");
+                    let _ = reload::reload(&state, &workers);
+                    continue;
                 }
 
-                (
-                    Some(app.name.clone()),
-                    Some(app.bundle_id.clone()),
-                    domain,
-                    url,
-                )
-            } else {
-                (None, None, None, None)
-            };
-
-        // Create record key from bundle ID (or "unknown" if no app detected)
-        let app_id: String = bundle_id
-            .as_ref()
-            .map(|s| s.as_str())
-            .unwrap_or("unknown")
-            .to_string();
-
-        // Get or create record
-        let record = self.records.entry(app_id.clone()).or_insert_with(|| {
-            let mut r = KeystrokeRecord::new(now);
-            r.app_name = app_name.clone();
-            r.app_bundle_id = bundle_id.clone();
-            r.browser_domain = browser_domain.clone();
-            r.browser_url = browser_url.clone();
-            r
-        });
-
-        // Update browser context if this is a browser (latest info)
-        if let (Some(bd), Some(bu)) = (&browser_domain, &browser_url) {
-            record.browser_domain = Some(bd.clone());
-            record.browser_url = Some(bu.clone());
-        }
-
-        // Update counts
-        if is_char {
-            record.char_count += 1;
-            if !is_word_boundary {
-                self.pending_word_chars += 1;
-            }
-        }
-
-        if is_backspace {
-            record.backspace_count += 1;
-            if self.pending_word_chars > 0 {
-                self.pending_word_chars -= 1;
+                info!("Signal {} received, shutting down", signal);
+                workers.shutdown();
+                info!("FingerPain daemon stopped");
+                std::process::exit(0);
             }
-        }
-
-        if is_enter {
-            record.paragraph_count += 1;
-        }
-
-        // Word completed on boundary if we had pending chars
-        if is_word_boundary && self.pending_word_chars > 0 {
-            record.word_count += 1;
-            self.pending_word_chars = 0;
-        }
-    }
+        })?;
+    Ok(())
+}
 
-    fn flush(&mut self) {
-        for (_, record) in self.records.drain() {
-            if record.char_count > 0 || record.backspace_count > 0 {
-                if let Err(e) = self.db.upsert_keystroke(&record) {
-                    error!("Failed to save keystroke: {}", e);
-                } else {
-                    let app_info = record
-                        .app_name
-                        .as_ref()
-                        .map(|n| n.as_str())
-                        .unwrap_or("Unknown");
-                    let browser_info = record
-                        .browser_domain
-                        .as_ref()
-                        .map(|d| format!(" → {}", d))
-                        .unwrap_or_default();
-                    info!(
-                        "Saved: {} {}{} | {} chars, {} words, {} paragraphs",
-                        app_info,
-                        browser_info,
-                        "",
-                        record.char_count,
-                        record.word_count,
-                        record.paragraph_count
-                    );
-                }
-            }
-        }
-    }
+#[cfg(not(unix))]
+fn spawn_signal_handler(_state: Arc<DaemonState>, _workers: Arc<Workers>) -> Result<()> {
+    Ok(())
 }
 
 fn main() -> Result<()> {
@@ -202,31 +82,157 @@ fn main() -> Result<()> {
 
     info!("FingerPain daemon starting...");
 
+    // An invalid config must stop the daemon rather than record what it excludes
+    let config = Config::load()?;
+    info!("Config loaded from {:?}", Config::path());
+    info!(
+        "Excluding {} apps and {} domains",
+        config.privacy.apps.len(),
+        config.privacy.domains.len()
+    );
+
+    // Refuse to run next to another daemon
+    let _instance = InstanceLock::acquire(&config.data_dir())?;
+
     // Open database
-    let db = Database::open_default()?;
-    info!("Database opened at {:?}", fingerpain_core::db_path());
+    let db = Database::open(config.db_path())?;
+    info!("Database opened at {:?}", config.db_path());
+
+    // Older Linux builds stored window titles as app names
+    #[cfg(target_os = "linux")]
+    match db.migrate_v2_app_names(platform::resolve_app_name) {
+        Ok(0) => {}
+        Ok(n) => info!("Rewrote {} title-based app names", n),
+        Err(e) => error!("App name migration failed: {}", e),
+    }
 
-    // Create tracker wrapped in Arc<Mutex> for callback
-    let tracker = Arc::new(Mutex::new(KeystrokeTracker::new(db)));
-    let tracker_clone = tracker.clone();
+    // Rows from before the URL policy, or under another one, hold too much
+    match db
+        .url_redactor(config.privacy.url_policy)
+        .and_then(|urls| db.redact_urls(&urls))
+    {
+        Ok(0) => {}
+        Ok(n) => info!("Redacted {} stored URLs", n),
+        Err(e) => error!("URL redaction failed: {}", e),
+    }
+    // The web server logs tabs too, and must skip the same domains
+    if let Err(e) = db.set_excluded_domains(&config.privacy.domains) {
+        error!("Failed to store excluded domains: {}", e);
+    }
+
+    // Recover counts from a previous run that did not shut down cleanly
+    let journal = Journal::open_in(&config.data_dir());
+    match journal.load(db.committed_batch()?) {
+        Ok(pending) => {
+            let replayed = pending.replay(&db)?;
+            journal.clear()?;
+            if replayed > 0 {
+                info!("Recovered {} unsaved records from the journal", replayed);
+            }
+        }
+        Err(e) => error!("Failed to read journal: {}", e),
+    }
+    let pending = Pending::starting_at(db.committed_batch()? + 1);
+
+    let (queue, events) = EventQueue::bounded(fingerpain_listener::queue::DEFAULT_CAPACITY);
+
+    // Keep the focused app current without querying the window system per keystroke
+    let focus = FocusWatcher::spawn_with_rules(
+        Duration::from_millis(config.daemon.focus_poll_ms),
+        TitleRules::new(&config.projects)?,
+    );
+    let focus_events = focus.subscribe();
+    queue.send(InputEvent::Focus(FocusEvent {
+        timestamp: Utc::now(),
+        app: focus.current(),
+    }));
+
+    let state = Arc::new(DaemonState::new(
+        queue.clone(),
+        focus.clone(),
+        config.clone(),
+        Database::open(config.db_path())?,
+    ));
+
+    // Writer owns the main connection; the tracker and sessions get their own
+    let (batch_tx, batch_rx) = mpsc::channel();
+    let (ack_tx, ack_rx) = mpsc::channel();
+    let writer = writer::spawn(db, batch_rx, ack_tx, state.clone());
+    let tracker_db = Database::open(config.db_path())?;
+    let sessions_db = Database::open(config.db_path())?;
+    let idle_timeout = chrono::Duration::seconds(config.daemon.idle_timeout_secs as i64);
+    let metrics_port = config.daemon.metrics_port;
+    let (control_tx, control_rx) = mpsc::channel();
+    let aggregator_state = state.clone();
+    let aggregator = thread::Builder::new()
+        .name("aggregator".to_string())
+        .spawn(move || {
+            let aggregator = Aggregator {
+                tracker: KeystrokeTracker::new(tracker_db, &config),
+                sessions: SessionTracker::new(sessions_db).with_idle_timeout(idle_timeout),
+                journal,
+                pending,
+                writer: batch_tx,
+                acks: ack_rx,
+                state: aggregator_state,
+                open_gap: None,
+            };
+            aggregator.run(events, focus_events, control_rx)
+        })?;
+
+    let workers = Arc::new(Workers::new(control_tx, aggregator, writer));
+    spawn_signal_handler(state.clone(), workers.clone())?;
+    if let Err(e) = reload::watch(state.clone(), workers.clone()) {
+        error!("Failed to watch the config file: {}", e);
+    }
+    if let Err(e) = control::spawn(state.clone(), workers.clone()) {
+        error!("Failed to start control socket: {}", e);
+    }
+    if let Some(port) = metrics_port {
+        if let Err(e) = metrics::spawn(port, state.clone()) {
+            error!("Failed to serve metrics on port {}: {}", port, e);
+        }
+    }
 
     info!("Starting keystroke listener (press Ctrl+C to stop)...");
 
-    // This blocks and runs on main thread - required for macOS CGEventTap
-    if let Err(e) = listen(move |event: Event| {
-        if let EventType::KeyPress(key) = event.event_type {
-            if let Ok(mut t) = tracker_clone.lock() {
-                t.process_key(key);
+    // This blocks and runs on main thread - required for macOS CGEventTap.
+    // Keep this callback cheap: no locks, no I/O.
+    let mut restarts = 0;
+    let result = loop {
+        let callback_queue = queue.clone();
+        let result = listen(move |event: Event| {
+            if let EventType::KeyPress(key) = event.event_type {
+                let event_type = KeyEventType::from_key(key);
+                if event_type != KeyEventType::Other {
+                    callback_queue.push(InputEvent::Key(KeyEvent {
+                        timestamp: Utc::now(),
+                        event_type,
+                        app: None,
+                    }));
+                }
             }
+        });
+        match result {
+            Err(e) if restarts < LISTENER_RETRIES => {
+                restarts += 1;
+                error!(
+                    "Listener error: {:?}, restarting ({}/{})",
+                    e, restarts, LISTENER_RETRIES
+                );
+                state.metrics.listener_restarted();
+                thread::sleep(LISTENER_RETRY_DELAY);
+            }
+            result => break result,
         }
-    }) {
-        error!("Listener error: {:?}", e);
-        return Err(anyhow::anyhow!("Failed to start listener: {:?}", e));
-    }
+    };
 
     // Flush remaining data on exit
-    if let Ok(mut t) = tracker.lock() {
-        t.flush();
+    workers.shutdown();
+
+    if let Err(e) = result {
+        error!("Listener error: {:?}", e);
+        return Err(anyhow::anyhow!("Failed to start listener: {:?}", e));
     }
 
     info!("FingerPain daemon stopped");
@@ -0,0 +1,529 @@
+//! Prometheus metrics
+//!
+//! Counters kept since the daemon started, served at `/metrics` on
+//! `127.0.0.1:<daemon.metrics_port>` when that is set. Typing is labelled by
+//! app category only, so no app names or window titles leave the daemon.
+
+use crate::state::DaemonState;
+use fingerpain_core::prometheus::{Exposition, Histogram, MetricType, CONTENT_TYPE};
+use fingerpain_core::{Category, DaemonStatus};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+/// Upper bounds of the flush latency buckets, in seconds
+const FLUSH_BUCKETS: [f64; 11] = [
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+/// Counter families kept per app category, in the order of `Typing::as_array`
+const TYPING_FAMILIES: [(&str, &str); 4] = [
+    (
+        "fingerpain_characters_total",
+        "Characters typed, by app category",
+    ),
+    ("fingerpain_words_total", "Words typed, by app category"),
+    (
+        "fingerpain_backspaces_total",
+        "Backspaces pressed, by app category",
+    ),
+    (
+        "fingerpain_sessions_total",
+        "Typing sessions started, by the category of their first app",
+    ),
+];
+/// How long a scraper may take to send its whole request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Most bytes of request line and headers read from a scraper
+const MAX_REQUEST_BYTES: u64 = 8 * 1024;
+/// Connections served at once; any more are closed straight away
+const MAX_CONNECTIONS: usize = 4;
+
+/// What a failed database write was for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DbWrite {
+    Keystrokes,
+    Gaps,
+    Sessions,
+    Context,
+}
+
+impl DbWrite {
+    const ALL: [DbWrite; 4] = [
+        DbWrite::Keystrokes,
+        DbWrite::Gaps,
+        DbWrite::Sessions,
+        DbWrite::Context,
+    ];
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            DbWrite::Keystrokes => "keystrokes",
+            DbWrite::Gaps => "gaps",
+            DbWrite::Sessions => "sessions",
+            DbWrite::Context => "context",
+        }
+    }
+}
+
+/// Who reported context over the control socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContextSource {
+    Editor,
+    Browser,
+    Shell,
+}
+
+impl ContextSource {
+    const ALL: [ContextSource; 3] = [
+        ContextSource::Editor,
+        ContextSource::Browser,
+        ContextSource::Shell,
+    ];
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            ContextSource::Editor => "editor",
+            ContextSource::Browser => "browser",
+            ContextSource::Shell => "shell",
+        }
+    }
+}
+
+/// Recorded typing in one category
+#[derive(Debug, Clone, Copy, Default)]
+struct Typing {
+    chars: u64,
+    words: u64,
+    backspaces: u64,
+    sessions: u64,
+}
+
+impl Typing {
+    fn as_array(&self) -> [u64; 4] {
+        [self.chars, self.words, self.backspaces, self.sessions]
+    }
+}
+
+#[derive(Debug)]
+pub struct DaemonMetrics {
+    typing: Mutex<BTreeMap<Category, Typing>>,
+    keys_processed: AtomicU64,
+    focus_changes_processed: AtomicU64,
+    db_errors: [AtomicU64; 4],
+    context_updates: [AtomicU64; 3],
+    listener_restarts: AtomicU64,
+    flush_latency: Mutex<Histogram>,
+}
+
+impl DaemonMetrics {
+    pub fn new() -> Self {
+        Self {
+            typing: Mutex::new(BTreeMap::new()),
+            keys_processed: AtomicU64::new(0),
+            focus_changes_processed: AtomicU64::new(0),
+            db_errors: Default::default(),
+            context_updates: Default::default(),
+            listener_restarts: AtomicU64::new(0),
+            flush_latency: Mutex::new(Histogram::new(&FLUSH_BUCKETS)),
+        }
+    }
+
+    /// Count a key event taken off the queue, recorded or not
+    pub fn key_processed(&self) {
+        self.keys_processed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn focus_processed(&self) {
+        self.focus_changes_processed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a recorded key towards its app's category
+    pub fn record_typing(&self, category: Category, chars: u32, words: u32, backspace: bool) {
+        let mut typing = self.typing.lock().unwrap();
+        let typing = typing.entry(category).or_default();
+        typing.chars += chars as u64;
+        typing.words += words as u64;
+        typing.backspaces += backspace as u64;
+    }
+
+    /// Count a session, under the category of the app it started in
+    pub fn session_started(&self, category: Category) {
+        self.typing
+            .lock()
+            .unwrap()
+            .entry(category)
+            .or_default()
+            .sessions += 1;
+    }
+
+    pub fn db_error(&self, write: DbWrite) {
+        self.db_errors[write as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn context_updated(&self, source: ContextSource) {
+        self.context_updates[source as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn listener_restarted(&self) {
+        self.listener_restarts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Time taken to commit one batch of records
+    pub fn flushed_in(&self, elapsed: Duration) {
+        self.flush_latency
+            .lock()
+            .unwrap()
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// The scrape response, with gauges taken from `status`
+    pub fn render(&self, status: &DaemonStatus) -> String {
+        let mut out = Exposition::new();
+
+        let typing = self.typing.lock().unwrap().clone();
+        for (i, (name, help)) in TYPING_FAMILIES.into_iter().enumerate() {
+            out.family(name, MetricType::Counter, help);
+            for category in Category::ALL {
+                let counts = typing.get(&category).copied().unwrap_or_default();
+                let count = counts.as_array()[i];
+                out.sample(name, &[("category", category.as_str())], count as f64);
+            }
+        }
+
+        let name = "fingerpain_events_processed_total";
+        out.family(
+            name,
+            MetricType::Counter,
+            "Input events taken off the queue",
+        )
+        .sample(name, &[("kind", "key")], load(&self.keys_processed))
+        .sample(
+            name,
+            &[("kind", "focus")],
+            load(&self.focus_changes_processed),
+        );
+        out.single(
+            "fingerpain_events_dropped_total",
+            MetricType::Counter,
+            "Input events dropped because the queue was full",
+            status.events_dropped as f64,
+        );
+
+        let latency = self.flush_latency.lock().unwrap().clone();
+        out.histogram(
+            "fingerpain_flush_duration_seconds",
+            "Time taken to write a batch of records to the database",
+            &latency,
+        );
+
+        let name = "fingerpain_db_errors_total";
+        out.family(name, MetricType::Counter, "Failed database writes");
+        for write in DbWrite::ALL {
+            let count = load(&self.db_errors[write as usize]);
+            out.sample(name, &[("operation", write.as_str())], count);
+        }
+
+        out.single(
+            "fingerpain_listener_restarts_total",
+            MetricType::Counter,
+            "Times the keyboard listener failed and was started again",
+            load(&self.listener_restarts),
+        );
+
+        let name = "fingerpain_context_updates_total";
+        out.family(
+            name,
+            MetricType::Counter,
+            "Context reports accepted over the control socket",
+        );
+        for source in ContextSource::ALL {
+            let count = load(&self.context_updates[source as usize]);
+            out.sample(name, &[("source", source.as_str())], count);
+        }
+
+        out.single(
+            "fingerpain_wpm",
+            MetricType::Gauge,
+            "Rolling WPM of the open session",
+            status.current_wpm,
+        );
+        out.single(
+            "fingerpain_paused",
+            MetricType::Gauge,
+            "Whether recording is paused",
+            status.paused as u8 as f64,
+        );
+        out.single(
+            "fingerpain_uptime_seconds",
+            MetricType::Gauge,
+            "Seconds since the daemon started",
+            status.uptime_secs as f64,
+        );
+
+        out.finish()
+    }
+}
+
+impl Default for DaemonMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn load(counter: &AtomicU64) -> f64 {
+    counter.load(Ordering::Relaxed) as f64
+}
+
+/// One of the `MAX_CONNECTIONS` connection slots, given back when dropped
+struct Slot(Arc<AtomicUsize>);
+
+impl Slot {
+    fn take(active: &Arc<AtomicUsize>) -> Option<Self> {
+        if active.fetch_add(1, Ordering::AcqRel) >= MAX_CONNECTIONS {
+            active.fetch_sub(1, Ordering::AcqRel);
+            return None;
+        }
+        Some(Self(active.clone()))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Reads from a stream until a fixed time, however the reads are spread out
+struct Deadline<'a> {
+    stream: &'a TcpStream,
+    until: Instant,
+}
+
+impl Read for Deadline<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "request took too long",
+            ));
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        let mut stream = self.stream;
+        stream.read(buf)
+    }
+}
+
+/// Serve `/metrics` on localhost in a background thread
+pub fn spawn(port: u16, state: Arc<DaemonState>) -> std::io::Result<()> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+    info!("Serving metrics at http://127.0.0.1:{}/metrics", port);
+
+    thread::Builder::new()
+        .name("metrics".to_string())
+        .spawn(move || {
+            let active = Arc::new(AtomicUsize::new(0));
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        debug!("Metrics connection failed: {}", e);
+                        continue;
+                    }
+                };
+                // A client that is slow to send its request can't hold up others,
+                // and only a few can be waited on at once
+                let Some(slot) = Slot::take(&active) else {
+                    debug!("Too many metrics connections, closing one");
+                    continue;
+                };
+                let state = state.clone();
+                let spawned = thread::Builder::new()
+                    .name("metrics-conn".to_string())
+                    .spawn(move || {
+                        let _slot = slot;
+                        if let Err(e) = respond(stream, &state) {
+                            debug!("Metrics request failed: {}", e);
+                        }
+                    });
+                if let Err(e) = spawned {
+                    debug!("Failed to spawn metrics connection thread: {}", e);
+                }
+            }
+        })?;
+    Ok(())
+}
+
+/// Answer one HTTP request; only `GET /metrics` is served
+///
+/// As in the web server, `Host` must be `localhost` or an IP address, so a web
+/// page can't read the metrics through a DNS name rebound to 127.0.0.1. A
+/// request that is too long or too slow to arrive is dropped unanswered.
+fn respond(mut stream: TcpStream, state: &DaemonState) -> std::io::Result<()> {
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    let deadline = Deadline {
+        stream: &stream,
+        until: Instant::now() + REQUEST_TIMEOUT,
+    };
+    let mut reader = BufReader::new(deadline.take(MAX_REQUEST_BYTES));
+    let mut request_line = String::new();
+    let mut local_host = false;
+    let mut complete = false;
+    if reader.read_line(&mut request_line)? > 0 {
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 0 {
+            if header.trim_end().is_empty() {
+                complete = true;
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("host") {
+                    local_host = fingerpain_core::is_local_host(value.trim());
+                }
+            }
+            header.clear();
+        }
+    }
+    if !complete {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "request too long or cut off",
+        ));
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let path = target.split('?').next().unwrap_or("");
+    let (status, content_type, body) = match (method, path) {
+        _ if !local_host => (
+            "403 Forbidden",
+            "text/plain",
+            "Host not allowed\n".to_string(),
+        ),
+        ("GET", "/metrics") => (
+            "200 OK",
+            CONTENT_TYPE,
+            state.metrics.render(&state.status()),
+        ),
+        (_, "/metrics") => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "Method not allowed\n".to_string(),
+        ),
+        _ => ("404 Not Found", "text/plain", "Not found\n".to_string()),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = DaemonMetrics::new();
+        metrics.record_typing(Category::Coding, 5, 1, false);
+        metrics.record_typing(Category::Coding, 0, 0, true);
+        metrics.session_started(Category::Coding);
+        metrics.key_processed();
+        metrics.db_error(DbWrite::Gaps);
+        metrics.context_updated(ContextSource::Shell);
+        metrics.flushed_in(Duration::from_millis(3));
+
+        let status = DaemonStatus {
+            pid: 1,
+            version: "0.1.0".to_string(),
+            started_at: chrono::Utc::now(),
+            uptime_secs: 60,
+            events_seen: 2,
+            events_dropped: 0,
+            last_flush: None,
+            current_app: Some("Secret Project - Editor".to_string()),
+            current_wpm: 42.0,
+            paused: false,
+            paused_until: None,
+            not_recording: None,
+            tags: Vec::new(),
+        };
+        let text = metrics.render(&status);
+
+        assert!(text.contains("fingerpain_characters_total{category=\"Coding\"} 5\n"));
+        assert!(text.contains("fingerpain_characters_total{category=\"Writing\"} 0\n"));
+        assert!(text.contains("fingerpain_backspaces_total{category=\"Coding\"} 1\n"));
+        assert!(text.contains("fingerpain_sessions_total{category=\"Coding\"} 1\n"));
+        assert!(text.contains("fingerpain_events_processed_total{kind=\"key\"} 1\n"));
+        assert!(text.contains("fingerpain_db_errors_total{operation=\"gaps\"} 1\n"));
+        assert!(text.contains("fingerpain_context_updates_total{source=\"shell\"} 1\n"));
+        assert!(text.contains("fingerpain_flush_duration_seconds_bucket{le=\"0.005\"} 1\n"));
+        assert!(text.contains("fingerpain_wpm 42\n"));
+        // Typing is labelled by category, never by app
+        assert!(!text.contains("Secret Project"));
+    }
+
+    #[test]
+    fn test_respond_checks_host() {
+        use fingerpain_core::{Config, Database};
+        use fingerpain_listener::{EventQueue, FocusWatcher};
+
+        let (queue, _events) = EventQueue::bounded(16);
+        let state = DaemonState::new(
+            queue,
+            FocusWatcher::spawn(Duration::from_secs(60)),
+            Config::default(),
+            Database::open(":memory:").unwrap(),
+        );
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let get = |host: &str| {
+            let mut client = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+            write!(client, "GET /metrics HTTP/1.1\r\nHost: {}\r\n\r\n", host).unwrap();
+            let (stream, _) = listener.accept().unwrap();
+            respond(stream, &state).unwrap();
+            let mut response = String::new();
+            client.read_to_string(&mut response).unwrap();
+            response
+        };
+
+        assert!(get(&format!("127.0.0.1:{}", port)).starts_with("HTTP/1.1 200 OK"));
+        assert!(get("localhost").contains("fingerpain_wpm"));
+        assert!(get("rebound.example.com").starts_with("HTTP/1.1 403 Forbidden"));
+
+        // Headers that never end are cut off rather than read forever
+        let mut client = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        write!(
+            client,
+            "GET /metrics HTTP/1.1\r\nX-Padding: {}\r\n",
+            "a".repeat(16 * 1024)
+        )
+        .unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let err = respond(stream, &state).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_connection_slots_are_limited() {
+        let active = Arc::new(AtomicUsize::new(0));
+        let slots: Vec<Slot> = (0..MAX_CONNECTIONS)
+            .map(|_| Slot::take(&active).unwrap())
+            .collect();
+        assert!(Slot::take(&active).is_none());
+        drop(slots);
+        assert!(Slot::take(&active).is_some());
+        assert_eq!(active.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,137 @@
+//! Configuration reload
+//!
+//! The config file is re-read when it changes on disk, on SIGHUP, or when a
+//! client asks over the control socket. A new config is validated as a whole
+//! and either replaces the old one or is rejected, never applied in part.
+
+use crate::state::{DaemonState, Workers};
+use fingerpain_core::config::ConfigError;
+use fingerpain_core::Config;
+use fingerpain_listener::TitleRules;
+use notify::{RecursiveMode, Watcher};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+/// Editors write a file in several steps; wait for them to settle
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Settings only read at startup
+const RESTART_REQUIRED: [&str; 3] = ["data_dir", "daemon.focus_poll_ms", "daemon.metrics_port"];
+
+/// Re-read the config and apply it, returning the settings that changed
+///
+/// On error the running configuration is left untouched.
+pub fn reload(state: &DaemonState, workers: &Workers) -> Result<Vec<String>, ConfigError> {
+    let mut current = state.config.lock().unwrap();
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Rejected new config, keeping the old one: {}", e);
+            return Err(e);
+        }
+    };
+
+    let changes = current.diff(&config);
+    if changes.is_empty() {
+        info!("Config reloaded, nothing changed");
+        return Ok(changes);
+    }
+
+    let title_rules = match TitleRules::new(&config.projects) {
+        Ok(rules) => rules,
+        Err(e) => {
+            let e = ConfigError::Invalid(vec![format!("projects.rules: {}", e)]);
+            error!("Rejected new config, keeping the old one: {}", e);
+            return Err(e);
+        }
+    };
+
+    for change in &changes {
+        if RESTART_REQUIRED
+            .iter()
+            .any(|key| change.starts_with(&format!("{}:", key)))
+        {
+            warn!("Config changed: {} (takes effect after a restart)", change);
+        } else {
+            info!("Config changed: {}", change);
+        }
+    }
+
+    state.focus.set_title_rules(title_rules);
+    workers.reconfigure(config.clone());
+    if config.privacy.url_policy != current.privacy.url_policy {
+        redact_urls(state, &config);
+    }
+    if config.privacy.domains != current.privacy.domains {
+        store_excluded_domains(state, &config);
+    }
+    *current = config;
+    Ok(changes)
+}
+
+/// Pass changed domain exclusions on to the web server
+fn store_excluded_domains(state: &DaemonState, config: &Config) {
+    let db = state.context_db.lock().unwrap();
+    if let Err(e) = db.set_excluded_domains(&config.privacy.domains) {
+        error!("Failed to store excluded domains: {}", e);
+    }
+}
+
+/// Bring stored URLs in line with a changed URL policy
+fn redact_urls(state: &DaemonState, config: &Config) {
+    let db = state.context_db.lock().unwrap();
+    match db
+        .url_redactor(config.privacy.url_policy)
+        .and_then(|urls| db.redact_urls(&urls))
+    {
+        Ok(n) => info!("Redacted {} stored URLs", n),
+        Err(e) => error!("URL redaction failed: {}", e),
+    }
+}
+
+/// Reload whenever the config file is written, created or replaced
+pub fn watch(state: Arc<DaemonState>, workers: Arc<Workers>) -> notify::Result<()> {
+    let path = Config::path();
+    // Watch the directory: editors often replace the file rather than write it.
+    // It is created up front so a config written later is still picked up.
+    let Some(dir) = path.parent() else {
+        return Ok(());
+    };
+    std::fs::create_dir_all(dir).map_err(notify::Error::io)?;
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    info!("Watching {:?} for changes", path);
+
+    let file_name = path.file_name().map(|name| name.to_os_string());
+    let touches_config = move |event: &notify::Result<notify::Event>| match event {
+        Ok(event) => {
+            !event.kind.is_access()
+                && event
+                    .paths
+                    .iter()
+                    .any(|p| p.file_name().map(|n| n.to_os_string()) == file_name)
+        }
+        Err(_) => false,
+    };
+
+    std::thread::Builder::new()
+        .name("config-watcher".to_string())
+        .spawn(move || {
+            // Keep the watcher alive for as long as the thread runs
+            let _watcher = watcher;
+            while let Ok(event) = rx.recv() {
+                if !touches_config(&event) {
+                    continue;
+                }
+                while rx.recv_timeout(DEBOUNCE).is_ok() {}
+
+                info!("Config file changed, reloading");
+                let _ = reload(&state, &workers);
+            }
+        })?;
+    Ok(())
+}
@@ -0,0 +1,379 @@
+//! State shared between the daemon's threads and the control socket
+
+use crate::aggregator::Control;
+use crate::metrics::DaemonMetrics;
+use chrono::{DateTime, Utc};
+use fingerpain_core::control::{LiveSession, LiveSnapshot};
+use fingerpain_core::{Category, Config, DaemonStatus, Database, GapReason};
+use fingerpain_listener::{EventQueue, FocusWatcher};
+use std::collections::{HashMap, VecDeque};
+use std::sync::mpsc::Sender;
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Whether recording is paused, and for how long
+#[derive(Debug, Clone, Copy)]
+enum Pause {
+    Off,
+    Indefinite,
+    Until(DateTime<Utc>),
+}
+
+/// Project reported by the shell hook at a prompt
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShellContext {
+    /// Git repository the prompt is in, if any
+    pub project: Option<String>,
+    pub reported_at: Instant,
+    /// The shell's parent processes, one of which is the terminal it runs in
+    pub ancestors: Vec<u32>,
+}
+
+/// What the aggregator last saw, for the live feed
+#[derive(Debug, Default)]
+struct Live {
+    /// Focused app and its category, as classified for recording
+    focused: Option<(String, Category)>,
+    session: Option<LiveSession>,
+    /// Times of recorded keys in about the last second
+    recent_keys: VecDeque<DateTime<Utc>>,
+}
+
+pub struct DaemonState {
+    pub started_at: DateTime<Utc>,
+    pub queue: EventQueue,
+    pub focus: FocusWatcher,
+    /// Configuration currently in effect; held while a reload is applied
+    pub config: Mutex<Config>,
+    /// Stores context reported over the control socket, e.g. by editors
+    pub context_db: Mutex<Database>,
+    pub metrics: DaemonMetrics,
+    pause: Mutex<Pause>,
+    not_recording: Mutex<Option<GapReason>>,
+    /// Active project tags, in the order they were started
+    tags: Mutex<Vec<String>>,
+    /// Latest report from each shell, by the shell's process ID
+    shells: Mutex<HashMap<u32, ShellContext>>,
+    last_flush: Mutex<Option<DateTime<Utc>>>,
+    current_wpm: Mutex<f64>,
+    live: Mutex<Live>,
+}
+
+impl DaemonState {
+    pub fn new(
+        queue: EventQueue,
+        focus: FocusWatcher,
+        config: Config,
+        context_db: Database,
+    ) -> Self {
+        Self {
+            started_at: Utc::now(),
+            queue,
+            focus,
+            config: Mutex::new(config),
+            context_db: Mutex::new(context_db),
+            metrics: DaemonMetrics::new(),
+            pause: Mutex::new(Pause::Off),
+            not_recording: Mutex::new(None),
+            tags: Mutex::new(Vec::new()),
+            shells: Mutex::new(HashMap::new()),
+            last_flush: Mutex::new(None),
+            current_wpm: Mutex::new(0.0),
+            live: Mutex::new(Live::default()),
+        }
+    }
+
+    /// Whether recording is paused; a timed pause ends by itself
+    pub fn is_paused(&self) -> bool {
+        match *self.pause.lock().unwrap() {
+            Pause::Off => false,
+            Pause::Indefinite => true,
+            Pause::Until(until) => Utc::now() < until,
+        }
+    }
+
+    /// Pause recording until `until`, or until resumed if `None`
+    pub fn pause(&self, until: Option<DateTime<Utc>>) {
+        *self.pause.lock().unwrap() = until.map_or(Pause::Indefinite, Pause::Until);
+    }
+
+    pub fn resume(&self) {
+        *self.pause.lock().unwrap() = Pause::Off;
+    }
+
+    fn paused_until(&self) -> Option<DateTime<Utc>> {
+        match *self.pause.lock().unwrap() {
+            Pause::Until(until) if Utc::now() < until => Some(until),
+            _ => None,
+        }
+    }
+
+    /// Record why input is currently being discarded, if it is
+    pub fn set_not_recording(&self, reason: Option<GapReason>) {
+        *self.not_recording.lock().unwrap() = reason;
+    }
+
+    pub fn tags(&self) -> Vec<String> {
+        self.tags.lock().unwrap().clone()
+    }
+
+    /// Add a tag, returning the active tags
+    pub fn start_tag(&self, tag: &str) -> Vec<String> {
+        let mut tags = self.tags.lock().unwrap();
+        if !tags.iter().any(|t| t == tag) {
+            tags.push(tag.to_string());
+        }
+        tags.clone()
+    }
+
+    /// Remove a tag, or all of them if `None`, returning the active tags
+    pub fn stop_tag(&self, tag: Option<&str>) -> Vec<String> {
+        let mut tags = self.tags.lock().unwrap();
+        match tag {
+            Some(tag) => tags.retain(|t| t != tag),
+            None => tags.clear(),
+        }
+        tags.clone()
+    }
+
+    /// The latest report, however old, from a shell running in the `terminal`
+    /// process
+    ///
+    /// When no reported shell runs in it (as under tmux or ssh), or the
+    /// terminal's process isn't known, the latest report from any shell.
+    pub fn shell_context(&self, terminal: Option<u32>) -> Option<ShellContext> {
+        let shells = self.shells.lock().unwrap();
+        let in_terminal = terminal.and_then(|terminal| {
+            shells
+                .values()
+                .filter(|shell| shell.ancestors.contains(&terminal))
+                .max_by_key(|shell| shell.reported_at)
+        });
+        in_terminal
+            .or_else(|| shells.values().max_by_key(|shell| shell.reported_at))
+            .cloned()
+    }
+
+    /// Record the project `shell` is in; reports that no longer count are dropped
+    pub fn set_shell_project(&self, shell: u32, project: Option<String>) {
+        let ttl = Duration::from_secs(self.config.lock().unwrap().daemon.shell_context_ttl_secs);
+        // Read once here rather than on every key
+        let ancestors = if shell == 0 {
+            Vec::new()
+        } else {
+            fingerpain_listener::platform::process_ancestors(shell)
+        };
+        let mut shells = self.shells.lock().unwrap();
+        shells.retain(|_, report| report.reported_at.elapsed() < ttl);
+        shells.insert(
+            shell,
+            ShellContext {
+                project,
+                reported_at: Instant::now(),
+                ancestors,
+            },
+        );
+    }
+
+    pub fn record_flush(&self, at: DateTime<Utc>) {
+        *self.last_flush.lock().unwrap() = Some(at);
+    }
+
+    pub fn set_wpm(&self, wpm: f64) {
+        *self.current_wpm.lock().unwrap() = wpm;
+    }
+
+    /// Count a recorded key towards the live feed
+    pub fn record_key(&self, at: DateTime<Utc>) {
+        let mut live = self.live.lock().unwrap();
+        let cutoff = at - chrono::Duration::seconds(1);
+        while live.recent_keys.front().is_some_and(|key| *key <= cutoff) {
+            live.recent_keys.pop_front();
+        }
+        live.recent_keys.push_back(at);
+    }
+
+    pub fn set_live(&self, focused: Option<(String, Category)>, session: Option<LiveSession>) {
+        let mut live = self.live.lock().unwrap();
+        live.focused = focused;
+        live.session = session;
+    }
+
+    /// One second of the live feed, ending at `at`
+    pub fn live(&self, at: DateTime<Utc>) -> LiveSnapshot {
+        let live = self.live.lock().unwrap();
+        let since = at - chrono::Duration::seconds(1);
+        let keys = live
+            .recent_keys
+            .iter()
+            .filter(|key| **key > since && **key <= at)
+            .count();
+        let paused = self.is_paused();
+        let not_recording = *self.not_recording.lock().unwrap();
+        // Nothing is said about an excluded app or private window, or while paused
+        let focused = live
+            .focused
+            .clone()
+            .filter(|_| !paused && not_recording.is_none());
+        let (current_app, category) = focused.unzip();
+        LiveSnapshot {
+            at,
+            current_app,
+            category,
+            keys_last_second: keys as u32,
+            wpm: *self.current_wpm.lock().unwrap(),
+            session: live.session.clone(),
+            paused,
+            not_recording,
+        }
+    }
+
+    pub fn status(&self) -> DaemonStatus {
+        let now = Utc::now();
+        let paused = self.is_paused();
+        let not_recording = *self.not_recording.lock().unwrap();
+        DaemonStatus {
+            pid: std::process::id(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            started_at: self.started_at,
+            uptime_secs: (now - self.started_at).num_seconds().max(0) as u64,
+            events_seen: self.queue.pushed(),
+            events_dropped: self.queue.dropped(),
+            last_flush: *self.last_flush.lock().unwrap(),
+            // As in the live feed, the app is not named while nothing is recorded
+            current_app: self
+                .focus
+                .current()
+                .map(|app| app.name)
+                .filter(|_| !paused && not_recording.is_none()),
+            current_wpm: *self.current_wpm.lock().unwrap(),
+            paused,
+            paused_until: self.paused_until(),
+            not_recording,
+            tags: self.tags(),
+        }
+    }
+}
+
+/// Background threads that must finish before the process exits
+pub struct Workers {
+    control: Sender<Control>,
+    threads: Mutex<Option<(JoinHandle<()>, JoinHandle<()>)>>,
+}
+
+impl Workers {
+    pub fn new(
+        control: Sender<Control>,
+        aggregator: JoinHandle<()>,
+        writer: JoinHandle<()>,
+    ) -> Self {
+        Self {
+            control,
+            threads: Mutex::new(Some((aggregator, writer))),
+        }
+    }
+
+    /// Ask the aggregator to write the current minute without stopping
+    pub fn flush(&self) {
+        let _ = self.control.send(Control::Flush);
+    }
+
+    /// Hand a validated configuration to the aggregator
+    pub fn reconfigure(&self, config: Config) {
+        let _ = self.control.send(Control::Reconfigure(Box::new(config)));
+    }
+
+    /// Flush everything and wait for the writer to commit it
+    pub fn shutdown(&self) {
+        let Some((aggregator, writer)) = self.threads.lock().unwrap().take() else {
+            return;
+        };
+        let _ = self.control.send(Control::Shutdown);
+        let _ = aggregator.join();
+        let _ = writer.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fingerpain_listener::platform::ActiveApp;
+
+    fn state() -> DaemonState {
+        state_focused_on(None)
+    }
+
+    fn state_focused_on(app: Option<ActiveApp>) -> DaemonState {
+        let (queue, _events) = EventQueue::bounded(16);
+        DaemonState::new(
+            queue,
+            FocusWatcher::fixed(app),
+            Config::default(),
+            Database::open(":memory:").unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_live_hides_app_while_not_recording() {
+        let state = state_focused_on(Some(ActiveApp {
+            name: "1Password".to_string(),
+            bundle_id: "1password".to_string(),
+            project: None,
+            pid: None,
+        }));
+        let focused = Some(("1Password".to_string(), Category::Other));
+        state.set_live(focused, None);
+        let snapshot = state.live(Utc::now());
+        assert_eq!(snapshot.current_app.as_deref(), Some("1Password"));
+        assert_eq!(state.status().current_app.as_deref(), Some("1Password"));
+
+        state.set_not_recording(Some(GapReason::Excluded));
+        let snapshot = state.live(Utc::now());
+        assert_eq!(snapshot.current_app, None);
+        assert_eq!(snapshot.category, None);
+        assert_eq!(state.status().current_app, None);
+
+        state.set_not_recording(None);
+        state.pause(None);
+        assert_eq!(state.live(Utc::now()).current_app, None);
+        assert_eq!(state.status().current_app, None);
+    }
+
+    #[test]
+    fn test_shell_reports_are_kept_per_shell() {
+        let state = state();
+        let project = |state: &DaemonState| state.shell_context(None).and_then(|s| s.project);
+        assert_eq!(project(&state), None);
+
+        state.set_shell_project(100, Some("fingerpain".to_string()));
+        state.set_shell_project(200, Some("website".to_string()));
+        assert_eq!(project(&state), Some("website".to_string()));
+        assert_eq!(state.shells.lock().unwrap().len(), 2);
+
+        // A shell's next prompt replaces only its own report
+        state.set_shell_project(100, None);
+        assert_eq!(state.shells.lock().unwrap().len(), 2);
+        assert_eq!(state.shell_context(None).unwrap().project, None);
+        state.set_shell_project(200, Some("website".to_string()));
+        assert_eq!(project(&state), Some("website".to_string()));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_shell_report_from_focused_terminal_wins() {
+        let state = state();
+        let project = |terminal| state.shell_context(terminal).and_then(|s| s.project);
+
+        // This test stands in for a shell run by its parent; a later report
+        // from elsewhere doesn't count while that parent is focused
+        let terminal = std::os::unix::process::parent_id();
+        state.set_shell_project(std::process::id(), Some("fingerpain".to_string()));
+        state.set_shell_project(0, Some("website".to_string()));
+        assert_eq!(project(Some(terminal)), Some("fingerpain".to_string()));
+
+        // A terminal with no reporting shell falls back to the latest report
+        assert_eq!(project(Some(u32::MAX)), Some("website".to_string()));
+        assert_eq!(project(None), Some("website".to_string()));
+    }
+}
@@ -0,0 +1,670 @@
+//! Per-minute aggregation of key events
+//!
+//! Runs on the aggregation thread, never on the input callback.
+
+use crate::state::ShellContext;
+use chrono::{DateTime, Utc};
+use fingerpain_core::db::Database;
+use fingerpain_core::{
+    AppClassifier, BrowserContext, BrowserRegistry, Category, Config, DomainCount, EditorContext,
+    ExclusionRules, GapReason, KeystrokeRecord,
+};
+use fingerpain_listener::{platform, FocusEvent, KeyEvent, KeyEventType};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+/// How long a context lookup is reused before re-reading the database
+const CONTEXT_CACHE_TTL: Duration = Duration::from_secs(1);
+
+/// Caches the latest browser and editor context to avoid a query per keystroke
+struct ContextCache {
+    db: Database,
+    browsers: BrowserRegistry,
+    entries: HashMap<String, (Instant, Option<BrowserContext>)>,
+    /// Last report seen per browser, kept after the database prunes it
+    reported: HashMap<String, BrowserContext>,
+    /// Tabs not reported for this long are no longer credited
+    browser_ttl: chrono::Duration,
+    /// Editor app IDs or names mapped to the name their plugin reports
+    editors: BTreeMap<String, String>,
+    editor_entries: HashMap<String, (Instant, Option<EditorContext>)>,
+    /// Editors not reported for this long are no longer credited
+    editor_ttl: chrono::Duration,
+}
+
+impl ContextCache {
+    fn is_browser(&self, bundle_id: &str) -> bool {
+        self.browsers.is_browser(bundle_id)
+    }
+
+    /// Focused tab of a browser, unless it wasn't reported within the TTL
+    fn get(&mut self, bundle_id: &str) -> Option<BrowserContext> {
+        let since = Utc::now() - self.browser_ttl;
+        self.latest(bundle_id).filter(|ctx| ctx.last_seen >= since)
+    }
+
+    /// Last tab a browser reported, however long ago
+    ///
+    /// A report pruned from the database stays known until a newer one
+    /// replaces it, so a private window is never taken for a normal one.
+    fn latest(&mut self, bundle_id: &str) -> Option<BrowserContext> {
+        let browser_name = self.browsers.name(bundle_id)?.to_string();
+        if let Some((fetched, ctx)) = self.entries.get(&browser_name) {
+            if fetched.elapsed() < CONTEXT_CACHE_TTL {
+                return ctx.clone();
+            }
+        }
+
+        let ctx = match self
+            .db
+            .get_browser_context(&self.browsers, bundle_id, DateTime::UNIX_EPOCH)
+        {
+            Ok(Some(ctx)) => {
+                self.reported.insert(browser_name.clone(), ctx.clone());
+                Some(ctx)
+            }
+            _ => self.reported.get(&browser_name).cloned(),
+        };
+        self.entries.insert(browser_name, (Instant::now(), ctx.clone()));
+        ctx
+    }
+
+    fn editor(&mut self, app: &platform::ActiveApp) -> Option<EditorContext> {
+        let editor = self
+            .editors
+            .get(&app.bundle_id)
+            .or_else(|| self.editors.get(&app.name))?;
+        if let Some((fetched, ctx)) = self.editor_entries.get(editor) {
+            if fetched.elapsed() < CONTEXT_CACHE_TTL {
+                return ctx.clone();
+            }
+        }
+
+        let since = Utc::now() - self.editor_ttl;
+        let ctx = self.db.get_editor_context(editor, since).ok().flatten();
+        self.editor_entries
+            .insert(editor.clone(), (Instant::now(), ctx.clone()));
+        ctx
+    }
+
+    fn reset(&mut self, config: &Config) {
+        self.browsers = config.browser_registry();
+        self.editors = config.editors.clone();
+        self.browser_ttl = browser_ttl(config);
+        self.editor_ttl = editor_ttl(config);
+        self.entries.clear();
+        self.editor_entries.clear();
+    }
+}
+
+fn browser_ttl(config: &Config) -> chrono::Duration {
+    chrono::Duration::seconds(config.daemon.browser_context_ttl_secs as i64)
+}
+
+fn editor_ttl(config: &Config) -> chrono::Duration {
+    chrono::Duration::seconds(config.daemon.editor_context_ttl_secs as i64)
+}
+
+/// Tracks keystrokes per minute per app
+pub struct KeystrokeTracker {
+    contexts: ContextCache,
+    rules: ExclusionRules,
+    current_minute: i64,
+    records: HashMap<String, KeystrokeRecord>,
+    pending_word_chars: u32,
+    /// Focused app, updated by the focus watcher the moment it changes
+    current_app: Option<platform::ActiveApp>,
+    /// Records changed since the last `snapshot`
+    dirty: bool,
+    /// Project tags stamped on records from the next key on
+    tags: Vec<String>,
+    /// Tells terminals apart, whose project comes from the shell hook
+    apps: AppClassifier,
+    focused_terminal: bool,
+    shell: Option<ShellContext>,
+    shell_ttl: Duration,
+}
+
+impl KeystrokeTracker {
+    /// Create a tracker; `db` is only read, for browser context
+    pub fn new(db: Database, config: &Config) -> Self {
+        Self {
+            contexts: ContextCache {
+                db,
+                browsers: config.browser_registry(),
+                entries: HashMap::new(),
+                reported: HashMap::new(),
+                browser_ttl: browser_ttl(config),
+                editors: config.editors.clone(),
+                editor_entries: HashMap::new(),
+                editor_ttl: editor_ttl(config),
+            },
+            rules: config.privacy.clone(),
+            current_minute: 0,
+            records: HashMap::new(),
+            pending_word_chars: 0,
+            current_app: None,
+            dirty: false,
+            tags: Vec::new(),
+            apps: config.app_classifier(),
+            focused_terminal: false,
+            shell: None,
+            shell_ttl: Duration::from_secs(config.daemon.shell_context_ttl_secs),
+        }
+    }
+
+    /// Switch to new browser and editor IDs and exclusion rules
+    pub fn apply_config(&mut self, config: &Config) {
+        self.contexts.reset(config);
+        self.rules = config.privacy.clone();
+        self.apps = config.app_classifier();
+        self.shell_ttl = Duration::from_secs(config.daemon.shell_context_ttl_secs);
+    }
+
+    /// Tag keystrokes after this point; a minute keeps every tag it saw
+    pub fn set_tags(&mut self, tags: Vec<String>) {
+        self.tags = tags;
+    }
+
+    /// Use a shell hook report for terminal typing while it is fresh
+    pub fn set_shell_context(&mut self, shell: Option<ShellContext>) {
+        self.shell = shell;
+    }
+
+    /// Attribute keystrokes after this point to the newly focused app
+    pub fn set_focus(&mut self, event: FocusEvent) {
+        if event.app != self.current_app {
+            // A word can't continue across an app switch
+            self.pending_word_chars = 0;
+        }
+        self.focused_terminal = event.app.as_ref().is_some_and(|app| {
+            self.apps.classify(&app.name, &app.bundle_id).1 == Category::Terminal
+        });
+        self.current_app = event.app;
+    }
+
+    /// Focused app under its configured name, and its category
+    pub fn focused(&self) -> Option<(String, Category)> {
+        let app = self.current_app.as_ref()?;
+        Some(self.apps.classify(&app.name, &app.bundle_id))
+    }
+
+    /// Process owning the focused window, to find the shells running in it
+    pub fn focused_pid(&self) -> Option<u32> {
+        self.current_app.as_ref()?.pid
+    }
+
+    /// Project of the last shell prompt, if a terminal is focused and it is fresh
+    fn shell_project(&self) -> Option<Option<String>> {
+        let shell = self.shell.as_ref()?;
+        (self.focused_terminal && shell.reported_at.elapsed() < self.shell_ttl)
+            .then(|| shell.project.clone())
+    }
+
+    /// Why input for the focused app must not be recorded, if it mustn't
+    pub fn exclusion(&mut self) -> Option<GapReason> {
+        let app = self.current_app.as_ref()?;
+        if self.rules.excludes_app(&app.name, &app.bundle_id) {
+            return Some(GapReason::Excluded);
+        }
+
+        // A stale report still counts if it was private or excluded: the
+        // window may not have reported again before the typing started
+        if self.contexts.is_browser(&app.bundle_id) {
+            let ctx = self.contexts.latest(&app.bundle_id)?;
+            if ctx.private {
+                return Some(GapReason::Private);
+            }
+            if ctx.excluded || self.rules.excludes_domain(&ctx.domain) {
+                return Some(GapReason::Excluded);
+            }
+        }
+
+        None
+    }
+
+    /// Hand off the previous minute's records once `now` is in a new minute
+    pub fn roll_over(&mut self, now: DateTime<Utc>) -> Vec<KeystrokeRecord> {
+        let minute = now.timestamp() / 60;
+        let mut completed = Vec::new();
+
+        if minute != self.current_minute && self.current_minute != 0 {
+            completed = self.flush();
+        }
+        self.current_minute = minute;
+
+        completed
+    }
+
+    /// Count a key event, returning the (characters, words) it added
+    ///
+    /// Call `roll_over` with the event's timestamp first.
+    pub fn process(&mut self, event: KeyEvent) -> (u32, u32) {
+        let now: DateTime<Utc> = event.timestamp;
+
+        let (is_char, is_word_boundary, is_backspace, is_enter) = match event.event_type {
+            KeyEventType::Character => (true, false, false, false),
+            KeyEventType::Space | KeyEventType::Tab => (true, true, false, false),
+            KeyEventType::Enter => (true, true, false, true),
+            KeyEventType::Backspace => (false, false, true, false),
+            KeyEventType::Other => return (0, 0),
+        };
+        self.dirty = true;
+
+        // Get app info and browser context
+        let app = event.app.or_else(|| self.current_app.clone());
+        // An editor plugin knows the project best, then the shell, then the title
+        let editor = app.as_ref().and_then(|app| self.contexts.editor(app));
+        let language = editor.as_ref().and_then(|ctx| ctx.language.clone());
+        let project = editor.and_then(|ctx| ctx.project).or_else(|| {
+            self.shell_project()
+                .unwrap_or_else(|| app.as_ref().and_then(|app| app.project.clone()))
+        });
+        // Browser typing goes to the tab focused now; "" if no fresh report says which
+        let mut browser_url = None;
+        let browser_domain = match &app {
+            Some(app) if self.contexts.is_browser(&app.bundle_id) => {
+                Some(match self.contexts.get(&app.bundle_id) {
+                    Some(ctx) => {
+                        browser_url = Some(ctx.url).filter(|url| !url.is_empty());
+                        ctx.domain
+                    }
+                    None => String::new(),
+                })
+            }
+            _ => None,
+        };
+        let (app_name, bundle_id) = match app {
+            Some(app) => (Some(app.name), Some(app.bundle_id)),
+            None => (None, None),
+        };
+
+        // Create record key from bundle ID (or "unknown" if no app detected)
+        let app_id: String = bundle_id.as_deref().unwrap_or("unknown").to_string();
+
+        // Get or create record
+        let record = self.records.entry(app_id).or_insert_with(|| {
+            let mut r = KeystrokeRecord::new(now);
+            r.app_name = app_name.clone();
+            r.app_bundle_id = bundle_id.clone();
+            r
+        });
+
+        // The minute goes to the latest project and language seen in the app
+        if project.is_some() {
+            record.project = project;
+        }
+        if language.is_some() {
+            record.language = language;
+        }
+
+        for tag in &self.tags {
+            if !record.tags.contains(tag) {
+                record.tags.push(tag.clone());
+            }
+        }
+
+        // Update counts
+        if is_char {
+            record.char_count += 1;
+            if !is_word_boundary {
+                self.pending_word_chars += 1;
+            }
+        }
+
+        if is_backspace {
+            record.backspace_count += 1;
+            if self.pending_word_chars > 0 {
+                self.pending_word_chars -= 1;
+            }
+        }
+
+        if is_enter {
+            record.paragraph_count += 1;
+        }
+
+        // Word completed on boundary if we had pending chars
+        let mut words = 0;
+        if is_word_boundary && self.pending_word_chars > 0 {
+            record.word_count += 1;
+            self.pending_word_chars = 0;
+            words = 1;
+        }
+
+        if let (true, Some(domain)) = (is_char, browser_domain) {
+            let index = match record.domains.iter().position(|d| d.domain == domain) {
+                Some(index) => index,
+                None => {
+                    record.domains.push(DomainCount {
+                        domain,
+                        ..Default::default()
+                    });
+                    record.domains.len() - 1
+                }
+            };
+            record.domains[index].char_count += is_char as u32;
+            record.domains[index].word_count += words;
+
+            // The minute's own domain is the one typed in most
+            record.browser_domain = record
+                .domains
+                .iter()
+                .filter(|d| !d.domain.is_empty())
+                .max_by_key(|d| d.char_count)
+                .map(|d| d.domain.clone());
+            if browser_url.is_some() {
+                record.browser_url = browser_url;
+            }
+        }
+
+        (is_char as u32, words)
+    }
+
+    /// Copy of the pending records if they changed since the last snapshot
+    pub fn snapshot(&mut self) -> Option<Vec<KeystrokeRecord>> {
+        if !self.dirty {
+            return None;
+        }
+        self.dirty = false;
+        Some(
+            self.records
+                .values()
+                .filter(|record| record.char_count > 0 || record.backspace_count > 0)
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// Take all non-empty records for the current minute
+    pub fn flush(&mut self) -> Vec<KeystrokeRecord> {
+        self.dirty = false;
+        self.records
+            .drain()
+            .map(|(_, record)| record)
+            .filter(|record| record.char_count > 0 || record.backspace_count > 0)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use fingerpain_listener::ActiveApp;
+
+    /// Chrome's focused tab, reported just now
+    fn tab(domain: &str, private: bool) -> BrowserContext {
+        BrowserContext {
+            browser_name: "Chrome".to_string(),
+            profile: String::new(),
+            window_id: Some(1),
+            domain: domain.to_string(),
+            url: format!("https://{}/", domain),
+            title: String::new(),
+            private,
+            excluded: false,
+            started_at: Utc::now(),
+            last_seen: Utc::now(),
+        }
+    }
+
+    fn key(timestamp: DateTime<Utc>, event_type: KeyEventType) -> KeyEvent {
+        KeyEvent {
+            timestamp,
+            event_type,
+            app: None,
+        }
+    }
+
+    #[test]
+    fn test_focus_switch_splits_records() {
+        let db = Database::open(":memory:").unwrap();
+        let mut tracker = KeystrokeTracker::new(db, &Config::default());
+        let now = Utc::now();
+        tracker.roll_over(now);
+
+        for (bundle_id, at) in [("editor", now), ("terminal", now + Duration::seconds(1))] {
+            tracker.set_focus(FocusEvent {
+                timestamp: at,
+                app: Some(ActiveApp {
+                    name: bundle_id.to_string(),
+                    bundle_id: bundle_id.to_string(),
+                    project: None,
+                    pid: None,
+                }),
+            });
+            for _ in 0..3 {
+                tracker.process(key(at, KeyEventType::Character));
+            }
+            assert_eq!(tracker.process(key(at, KeyEventType::Space)), (1, 1));
+        }
+
+        assert!(tracker.snapshot().is_some());
+        assert!(tracker.snapshot().is_none());
+
+        let records = tracker.roll_over(now + Duration::minutes(1));
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(|r| r.char_count == 4 && r.word_count == 1));
+    }
+
+    #[test]
+    fn test_excluded_app_and_private_window() {
+        let db = Database::open(":memory:").unwrap();
+        db.log_browser_context(&tab("", true)).unwrap();
+        let mut tracker = KeystrokeTracker::new(db, &Config::default());
+
+        let focus = |name: &str, bundle_id: &str| FocusEvent {
+            timestamp: Utc::now(),
+            app: Some(ActiveApp {
+                name: name.to_string(),
+                bundle_id: bundle_id.to_string(),
+                project: None,
+                pid: None,
+            }),
+        };
+
+        tracker.set_focus(focus("1Password", "com.1password.1password"));
+        assert_eq!(tracker.exclusion(), Some(GapReason::Excluded));
+        tracker.set_focus(focus("Google Chrome", "com.google.Chrome"));
+        assert_eq!(tracker.exclusion(), Some(GapReason::Private));
+        tracker.set_focus(focus("Editor", "editor"));
+        assert_eq!(tracker.exclusion(), None);
+    }
+
+    #[test]
+    fn test_expired_private_window_stays_private() {
+        let db = Database::open(":memory:").unwrap();
+        let config = Config::default();
+        let mut old = tab("", true);
+        old.last_seen =
+            Utc::now() - Duration::seconds(config.daemon.browser_context_ttl_secs as i64 * 2);
+        db.log_browser_context(&old).unwrap();
+        let mut tracker = KeystrokeTracker::new(db, &config);
+        tracker.set_focus(FocusEvent {
+            timestamp: Utc::now(),
+            app: Some(ActiveApp {
+                name: "Google Chrome".to_string(),
+                bundle_id: "com.google.Chrome".to_string(),
+                project: None,
+                pid: None,
+            }),
+        });
+        assert_eq!(tracker.exclusion(), Some(GapReason::Private));
+
+        // Only a newer report from a normal window lifts it
+        let db = &tracker.contexts.db;
+        db.log_browser_context(&tab("docs.rs", false)).unwrap();
+        tracker.apply_config(&config);
+        assert_eq!(tracker.exclusion(), None);
+    }
+
+    #[test]
+    fn test_minute_keeps_every_tag_it_saw() {
+        let db = Database::open(":memory:").unwrap();
+        let mut tracker = KeystrokeTracker::new(db, &Config::default());
+        let now = Utc::now();
+        tracker.roll_over(now);
+
+        tracker.set_tags(vec!["client-x".to_string()]);
+        tracker.process(key(now, KeyEventType::Character));
+        tracker.set_tags(Vec::new());
+        tracker.process(key(now, KeyEventType::Character));
+        tracker.set_tags(vec!["review".to_string()]);
+        tracker.process(key(now, KeyEventType::Character));
+
+        let records = tracker.flush();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].tags, ["client-x", "review"]);
+    }
+
+    #[test]
+    fn test_minute_goes_to_latest_project() {
+        let db = Database::open(":memory:").unwrap();
+        let mut tracker = KeystrokeTracker::new(db, &Config::default());
+        let now = Utc::now();
+        tracker.roll_over(now);
+
+        for project in [Some("fingerpain"), None, Some("website")] {
+            tracker.set_focus(FocusEvent {
+                timestamp: now,
+                app: Some(ActiveApp {
+                    name: "Code".to_string(),
+                    bundle_id: "code".to_string(),
+                    project: project.map(String::from),
+                    pid: None,
+                }),
+            });
+            tracker.process(key(now, KeyEventType::Character));
+        }
+
+        let records = tracker.flush();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].project.as_deref(), Some("website"));
+    }
+
+    #[test]
+    fn test_editor_context_names_language_and_project() {
+        let db = Database::open(":memory:").unwrap();
+        db.upsert_editor_context(&EditorContext {
+            editor: "vscode".to_string(),
+            language: Some("rust".to_string()),
+            project: Some("fingerpain".to_string()),
+        })
+        .unwrap();
+        let mut tracker = KeystrokeTracker::new(db, &Config::default());
+        let now = Utc::now();
+        tracker.roll_over(now);
+
+        tracker.set_focus(FocusEvent {
+            timestamp: now,
+            // Not mapped by app ID, but by name
+            app: Some(ActiveApp {
+                name: "Code".to_string(),
+                bundle_id: "com.visualstudio.code.oss".to_string(),
+                project: Some("from-title".to_string()),
+                pid: None,
+            }),
+        });
+        tracker.process(key(now, KeyEventType::Character));
+
+        let records = tracker.flush();
+        assert_eq!(records[0].language.as_deref(), Some("rust"));
+        assert_eq!(records[0].project.as_deref(), Some("fingerpain"));
+    }
+
+    #[test]
+    fn test_terminal_uses_fresh_shell_project() {
+        let db = Database::open(":memory:").unwrap();
+        let mut config = Config::default();
+        config.daemon.shell_context_ttl_secs = 60;
+        let mut tracker = KeystrokeTracker::new(db, &config);
+        let now = Utc::now();
+        tracker.roll_over(now);
+
+        let focus = |bundle_id: &str| FocusEvent {
+            timestamp: now,
+            app: Some(ActiveApp {
+                name: bundle_id.to_string(),
+                bundle_id: bundle_id.to_string(),
+                project: None,
+                pid: None,
+            }),
+        };
+        // `None` if the clock doesn't go back that far, e.g. just after boot
+        let shell = |project: &str, age: u64| {
+            let reported_at = Instant::now().checked_sub(std::time::Duration::from_secs(age))?;
+            Some(ShellContext {
+                project: Some(project.to_string()),
+                reported_at,
+                ancestors: Vec::new(),
+            })
+        };
+
+        tracker.set_shell_context(shell("fingerpain", 5));
+        tracker.set_focus(focus("kitty"));
+        tracker.process(key(now, KeyEventType::Character));
+        tracker.set_focus(focus("slack"));
+        tracker.process(key(now, KeyEventType::Character));
+        let mut records = tracker.flush();
+        records.sort_by(|a, b| a.app_bundle_id.cmp(&b.app_bundle_id));
+        assert_eq!(records[0].project.as_deref(), Some("fingerpain"));
+        assert_eq!(records[1].project, None);
+
+        // A report older than the TTL no longer counts
+        let Some(old) = shell("old", 120) else {
+            return;
+        };
+        tracker.set_shell_context(Some(old));
+        tracker.set_focus(focus("kitty"));
+        tracker.process(key(now, KeyEventType::Character));
+        assert_eq!(tracker.flush()[0].project, None);
+    }
+
+    #[test]
+    fn test_browser_minute_splits_by_focused_domain() {
+        let path =
+            std::env::temp_dir().join(format!("fingerpain-tracker-{}.db", std::process::id()));
+        let extension = Database::open(&path).unwrap();
+        let config = Config::default();
+        let mut tracker = KeystrokeTracker::new(Database::open(&path).unwrap(), &config);
+        let now = Utc::now();
+        tracker.roll_over(now);
+        tracker.set_focus(FocusEvent {
+            timestamp: now,
+            app: Some(ActiveApp {
+                name: "Google Chrome".to_string(),
+                // WM_CLASS, as on Linux
+                bundle_id: "Google-chrome".to_string(),
+                project: None,
+                pid: None,
+            }),
+        });
+
+        let type_in = |tracker: &mut KeystrokeTracker, count: usize| {
+            for _ in 0..count {
+                tracker.process(key(now, KeyEventType::Character));
+            }
+            tracker.process(key(now, KeyEventType::Space));
+        };
+        // No report yet: the typing stays unattributed
+        type_in(&mut tracker, 2);
+        extension.log_browser_context(&tab("github.com", false)).unwrap();
+        tracker.apply_config(&config); // drops the cached lookup
+        type_in(&mut tracker, 4);
+        extension.log_browser_context(&tab("docs.rs", false)).unwrap();
+        tracker.apply_config(&config);
+        type_in(&mut tracker, 9);
+
+        let record = tracker.flush().pop().unwrap();
+        let split: Vec<(&str, u32, u32)> = record
+            .domains
+            .iter()
+            .map(|d| (d.domain.as_str(), d.char_count, d.word_count))
+            .collect();
+        assert_eq!(split, [("", 3, 1), ("github.com", 5, 1), ("docs.rs", 10, 1)]);
+        assert_eq!(record.browser_domain.as_deref(), Some("docs.rs"));
+
+        drop(extension);
+        let _ = std::fs::remove_file(&path);
+    }
+}
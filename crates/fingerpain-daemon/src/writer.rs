@@ -0,0 +1,144 @@
+//! Database writer thread
+//!
+//! Receives completed per-minute records and recording gaps, and writes
+//! everything that is pending in one go. Each committed batch is confirmed
+//! back to the aggregator, which keeps it in the journal until then; a batch
+//! that fails is retried with the next one.
+
+use crate::metrics::DbWrite;
+use crate::state::DaemonState;
+use chrono::Utc;
+use fingerpain_core::db::Database;
+use fingerpain_core::{Gap, KeystrokeRecord};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+/// How soon a batch that failed to commit is tried again
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Work handed to the writer
+pub enum WriteRequest {
+    /// Completed minutes, numbered so a replay never counts them twice
+    Records {
+        batch: u64,
+        records: Vec<KeystrokeRecord>,
+    },
+    /// A new or extended gap; the same gap may be sent several times
+    Gap(Gap),
+}
+
+/// Spawn the writer; it exits once every sender has been dropped
+///
+/// The number of every batch committed is sent on `acks`.
+pub fn spawn(
+    db: Database,
+    requests: Receiver<WriteRequest>,
+    acks: Sender<u64>,
+    state: Arc<DaemonState>,
+) -> JoinHandle<()> {
+    thread::Builder::new()
+        .name("db-writer".to_string())
+        .spawn(move || {
+            // Records that failed to commit, under the highest batch number
+            let mut failed: Option<(u64, Vec<KeystrokeRecord>)> = None;
+            loop {
+                let first = match requests.recv_timeout(RETRY_INTERVAL) {
+                    Ok(request) => Some(request),
+                    Err(RecvTimeoutError::Timeout) if failed.is_some() => None,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => {
+                        // Last try; whatever still fails stays in the journal
+                        if let Some((batch, records)) = failed.take() {
+                            commit(&db, batch, &records, &acks, &state);
+                        }
+                        break;
+                    }
+                };
+
+                // Coalesce whatever else queued up while we were writing
+                let (mut batch, mut records) = failed.take().unwrap_or_default();
+                let mut gaps = Vec::new();
+                for request in first.into_iter().chain(requests.try_iter()) {
+                    match request {
+                        WriteRequest::Records {
+                            batch: n,
+                            records: more,
+                        } => {
+                            batch = batch.max(n);
+                            records.extend(more);
+                        }
+                        WriteRequest::Gap(gap) => gaps.push(gap),
+                    }
+                }
+
+                for gap in &gaps {
+                    if let Err(e) = db.upsert_gap(gap) {
+                        error!("Failed to save gap: {}", e);
+                        state.metrics.db_error(DbWrite::Gaps);
+                    }
+                }
+                if !records.is_empty() && !commit(&db, batch, &records, &acks, &state) {
+                    failed = Some((batch, records));
+                }
+            }
+        })
+        .expect("failed to spawn writer thread")
+}
+
+/// Commit one batch and confirm it, returning false if it should be retried
+fn commit(
+    db: &Database,
+    batch: u64,
+    records: &[KeystrokeRecord],
+    acks: &Sender<u64>,
+    state: &DaemonState,
+) -> bool {
+    if !write_batch(db, batch, records, state) {
+        return false;
+    }
+    state.record_flush(Utc::now());
+    // The aggregator may already be gone at shutdown
+    let _ = acks.send(batch);
+    true
+}
+
+/// Write one batch, returning whether it is in the database
+fn write_batch(
+    db: &Database,
+    batch: u64,
+    records: &[KeystrokeRecord],
+    state: &DaemonState,
+) -> bool {
+    let started = Instant::now();
+    match db.upsert_keystroke_batch(batch, records) {
+        Ok(true) => state.metrics.flushed_in(started.elapsed()),
+        // Already committed before a restart
+        Ok(false) => return true,
+        Err(e) => {
+            error!("Failed to save {} keystroke records: {}", records.len(), e);
+            state.metrics.db_error(DbWrite::Keystrokes);
+            return false;
+        }
+    }
+
+    for record in records {
+        let app_info = record.app_name.as_deref().unwrap_or("Unknown");
+        let browser_info = record
+            .browser_domain
+            .as_ref()
+            .map(|d| format!(" → {}", d))
+            .unwrap_or_default();
+        info!(
+            "Saved: {}{} | {} chars, {} words, {} paragraphs",
+            app_info,
+            browser_info,
+            record.char_count,
+            record.word_count,
+            record.paragraph_count
+        );
+    }
+    true
+}
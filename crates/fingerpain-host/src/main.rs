@@ -0,0 +1,159 @@
+//! FingerPain native messaging host
+//!
+//! Browsers start this binary for the extension and exchange messages over
+//! stdin/stdout, each a JSON document preceded by its length as a 32-bit
+//! native-endian integer. Every message is a tab report, forwarded to the
+//! daemon's control socket, so browser context works without fingerpain-web.
+//! Replies carry the address fingerpain-web is listening on, if it runs, for
+//! the extension's HTTP fallback and pairing.
+//!
+//! stdout carries only protocol frames; anything else goes to stderr, which
+//! browsers write to their own log.
+
+use anyhow::{bail, Result};
+use fingerpain_core::control::{ControlClient, ControlError};
+use fingerpain_core::discovery::WebEndpoint;
+use fingerpain_core::TabReport;
+use serde_json::{json, Value};
+use std::io::{self, ErrorKind, Read, Write};
+
+/// Largest message accepted; tab reports are far smaller
+const MAX_MESSAGE_LEN: usize = 1024 * 1024;
+
+/// Read one message body, or `None` once the browser closes the pipe
+///
+/// The body is parsed by the caller, so a malformed one gets a reply instead
+/// of ending the session.
+fn read_message(input: &mut impl Read) -> Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    match input.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    let len = u32::from_ne_bytes(len) as usize;
+    if len > MAX_MESSAGE_LEN {
+        bail!("message of {} bytes is too long", len);
+    }
+    let mut message = vec![0u8; len];
+    input.read_exact(&mut message)?;
+    Ok(Some(message))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> Result<()> {
+    let message = serde_json::to_vec(message)?;
+    output.write_all(&(message.len() as u32).to_ne_bytes())?;
+    output.write_all(&message)?;
+    output.flush()?;
+    Ok(())
+}
+
+/// Keeps one daemon connection, reconnecting once the daemon has restarted
+#[derive(Default)]
+struct Forwarder {
+    client: Option<ControlClient>,
+}
+
+impl Forwarder {
+    fn forward(&mut self, message: &[u8]) -> Result<(), String> {
+        let report: TabReport = serde_json::from_slice(message).map_err(|e| e.to_string())?;
+
+        // A kept connection may belong to a daemon that has since restarted
+        for _ in 0..2 {
+            let client = match self.client.as_mut() {
+                Some(client) => client,
+                None => self
+                    .client
+                    .insert(ControlClient::connect().map_err(|e| e.to_string())?),
+            };
+            match client.browser_context(&report) {
+                Ok(()) => return Ok(()),
+                Err(e @ ControlError::Rpc { .. }) => return Err(e.to_string()),
+                Err(_) => self.client = None,
+            }
+        }
+        Err(ControlError::NotRunning.to_string())
+    }
+}
+
+/// Answer messages until the browser closes the pipe or framing breaks
+fn serve(input: &mut impl Read, output: &mut impl Write, forwarder: &mut Forwarder) -> Result<()> {
+    while let Some(message) = read_message(input)? {
+        let mut reply = match forwarder.forward(&message) {
+            Ok(()) => json!({ "ok": true }),
+            Err(error) => {
+                eprintln!("fingerpain-browser-host: {}", error);
+                json!({ "ok": false, "error": error })
+            }
+        };
+        if let Some(url) = WebEndpoint::discover().and_then(|endpoint| endpoint.url) {
+            reply["web"] = json!(url);
+        }
+        write_message(output, &reply)?;
+    }
+
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    serve(
+        &mut io::stdin().lock(),
+        &mut io::stdout().lock(),
+        &mut Forwarder::default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_framing() {
+        let mut pipe = Vec::new();
+        write_message(&mut pipe, &json!({ "browser_name": "Chrome" })).unwrap();
+        write_message(&mut pipe, &json!({ "ok": true })).unwrap();
+        assert_eq!(&pipe[..4], &25u32.to_ne_bytes());
+
+        let mut input = pipe.as_slice();
+        assert_eq!(
+            read_message(&mut input).unwrap().as_deref(),
+            Some(&br#"{"browser_name":"Chrome"}"#[..])
+        );
+        assert_eq!(
+            read_message(&mut input).unwrap().as_deref(),
+            Some(&br#"{"ok":true}"#[..])
+        );
+        assert_eq!(read_message(&mut input).unwrap(), None);
+
+        let oversized = ((MAX_MESSAGE_LEN + 1) as u32).to_ne_bytes();
+        assert!(read_message(&mut oversized.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_malformed_message_gets_an_error_reply() {
+        let mut pipe = Vec::new();
+        for body in [&b"not json"[..], br#"{"domain": 1}"#] {
+            pipe.extend_from_slice(&(body.len() as u32).to_ne_bytes());
+            pipe.extend_from_slice(body);
+        }
+
+        let mut replies = Vec::new();
+        serve(
+            &mut pipe.as_slice(),
+            &mut replies,
+            &mut Forwarder::default(),
+        )
+        .unwrap();
+
+        // Both messages were answered; neither reached the daemon
+        let mut replies = replies.as_slice();
+        for _ in 0..2 {
+            let reply: Value =
+                serde_json::from_slice(&read_message(&mut replies).unwrap().unwrap()).unwrap();
+            assert_eq!(reply["ok"], json!(false));
+            assert!(reply["error"].is_string());
+        }
+        assert_eq!(read_message(&mut replies).unwrap(), None);
+    }
+}
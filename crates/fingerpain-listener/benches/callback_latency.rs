@@ -0,0 +1,109 @@
+//! Input callback latency under load
+//!
+//! Compares the old design, where the callback locks state that is also held
+//! during database flushes, with pushing onto the bounded `EventQueue` while a
+//! slow consumer drains it. Run with `cargo bench -p fingerpain-listener`.
+
+use chrono::Utc;
+use fingerpain_listener::{EventQueue, InputEvent, KeyEvent, KeyEventType};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Key presses simulated per scenario
+const EVENTS: usize = 20_000;
+/// Gap between simulated key presses (a very fast typist is ~15 keys/s)
+const KEY_INTERVAL: Duration = Duration::from_micros(50);
+/// Time a simulated flush holds the database
+const FLUSH_TIME: Duration = Duration::from_millis(5);
+/// Time between simulated flushes
+const FLUSH_INTERVAL: Duration = Duration::from_millis(20);
+
+fn key_event() -> KeyEvent {
+    KeyEvent {
+        timestamp: Utc::now(),
+        event_type: KeyEventType::Character,
+        app: None,
+    }
+}
+
+fn report(name: &str, mut samples: Vec<Duration>) {
+    samples.sort();
+    let pct = |p: f64| samples[((samples.len() - 1) as f64 * p) as usize];
+    println!(
+        "{:<24} p50 {:>10.2?}  p99 {:>10.2?}  p99.9 {:>10.2?}  max {:>10.2?}",
+        name,
+        pct(0.50),
+        pct(0.99),
+        pct(0.999),
+        samples[samples.len() - 1]
+    );
+}
+
+/// Callback shares a lock with a thread that periodically performs slow writes
+fn locked_callback() -> Vec<Duration> {
+    let state = Arc::new(Mutex::new(Vec::<KeyEvent>::new()));
+    let done = Arc::new(AtomicBool::new(false));
+
+    let writer_state = state.clone();
+    let writer_done = done.clone();
+    let writer = thread::spawn(move || {
+        while !writer_done.load(Ordering::Relaxed) {
+            thread::sleep(FLUSH_INTERVAL);
+            let mut records = writer_state.lock().unwrap();
+            thread::sleep(FLUSH_TIME);
+            records.clear();
+        }
+    });
+
+    let mut samples = Vec::with_capacity(EVENTS);
+    for _ in 0..EVENTS {
+        let start = Instant::now();
+        state.lock().unwrap().push(key_event());
+        samples.push(start.elapsed());
+        thread::sleep(KEY_INTERVAL);
+    }
+
+    done.store(true, Ordering::Relaxed);
+    writer.join().unwrap();
+    samples
+}
+
+/// Callback only enqueues; the consumer does the slow work
+fn queued_callback() -> (Vec<Duration>, u64) {
+    let (queue, rx) = EventQueue::bounded(fingerpain_listener::queue::DEFAULT_CAPACITY);
+
+    let consumer = thread::spawn(move || {
+        let mut last_flush = Instant::now();
+        for _event in rx {
+            if last_flush.elapsed() >= FLUSH_INTERVAL {
+                thread::sleep(FLUSH_TIME);
+                last_flush = Instant::now();
+            }
+        }
+    });
+
+    let mut samples = Vec::with_capacity(EVENTS);
+    for _ in 0..EVENTS {
+        let start = Instant::now();
+        queue.push(InputEvent::Key(key_event()));
+        samples.push(start.elapsed());
+        thread::sleep(KEY_INTERVAL);
+    }
+
+    let dropped = queue.dropped();
+    drop(queue);
+    consumer.join().unwrap();
+    (samples, dropped)
+}
+
+fn main() {
+    println!("{} key events, flush of {:?} every {:?}\n", EVENTS, FLUSH_TIME, FLUSH_INTERVAL);
+
+    report("mutex + inline flush", locked_callback());
+
+    let (samples, dropped) = queued_callback();
+    report("bounded queue", samples);
+    println!("\nqueue dropped {} events", dropped);
+}
@@ -0,0 +1,172 @@
+//! Long-lived tracking of the focused application
+//!
+//! A background thread keeps the current `ActiveApp` cached so keystroke
+//! handlers never query the window system themselves, and publishes a
+//! `FocusEvent` whenever focus moves to a different app or project.
+
+use crate::platform::{self, ActiveApp, StopSignal};
+use crate::title_rules::TitleRules;
+use chrono::{DateTime, Utc};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+
+/// Delay before reconnecting after the window system connection is lost
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Focus moved to a different application (or to none)
+#[derive(Debug, Clone)]
+pub struct FocusEvent {
+    pub timestamp: DateTime<Utc>,
+    pub app: Option<ActiveApp>,
+}
+
+#[derive(Default)]
+struct Shared {
+    current: Mutex<Option<ActiveApp>>,
+    subscribers: Mutex<Vec<Sender<FocusEvent>>>,
+    rules: RwLock<TitleRules>,
+    /// Signalled by `FocusWatcher::stop`
+    stop: StopSignal,
+}
+
+impl Shared {
+    fn publish(&self, app: Option<ActiveApp>) {
+        let event = FocusEvent {
+            timestamp: Utc::now(),
+            app: app.clone(),
+        };
+        *self.current.lock().unwrap() = app;
+
+        // Drop subscribers whose receiver has gone away
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|tx| tx.send(event.clone()).is_ok());
+    }
+}
+
+/// Watches the focused application from a dedicated thread
+#[derive(Clone)]
+pub struct FocusWatcher {
+    shared: Arc<Shared>,
+}
+
+impl FocusWatcher {
+    /// Start watching focus changes in the background
+    ///
+    /// `poll_interval` is used on platforms that can't notify focus changes.
+    pub fn spawn(poll_interval: Duration) -> Self {
+        Self::spawn_with_rules(poll_interval, TitleRules::default())
+    }
+
+    /// Start watching focus changes, naming projects with `rules`
+    pub fn spawn_with_rules(poll_interval: Duration, rules: TitleRules) -> Self {
+        let shared = Arc::new(Shared {
+            rules: RwLock::new(rules),
+            ..Default::default()
+        });
+        let thread_shared = shared.clone();
+
+        thread::Builder::new()
+            .name("focus-watcher".to_string())
+            .spawn(move || {
+                let shared = thread_shared;
+                while !shared.stop.is_stopped() {
+                    let result =
+                        platform::watch_focus(poll_interval, &shared.rules, &shared.stop, |app| {
+                            shared.publish(app)
+                        });
+                    match result {
+                        Ok(()) => break,
+                        Err(e) => tracing::warn!("{}; retrying in {:?}", e, RETRY_DELAY),
+                    }
+                    thread::sleep(RETRY_DELAY);
+                }
+            })
+            .expect("failed to spawn focus watcher thread");
+
+        Self { shared }
+    }
+
+    /// A watcher that always reports `app` and runs no thread, for tests
+    pub fn fixed(app: Option<ActiveApp>) -> Self {
+        let shared = Arc::new(Shared {
+            current: Mutex::new(app),
+            ..Default::default()
+        });
+        shared.stop.stop();
+        Self { shared }
+    }
+
+    /// The most recently observed focused app
+    pub fn current(&self) -> Option<ActiveApp> {
+        self.shared.current.lock().unwrap().clone()
+    }
+
+    /// Replace the project rules; they apply from the next focus change
+    pub fn set_title_rules(&self, rules: TitleRules) {
+        *self.shared.rules.write().unwrap() = rules;
+    }
+
+    /// Stop watching; every copy of this watcher stops with it
+    ///
+    /// Subscribers get no further events. The thread exits right away on X11,
+    /// and elsewhere at its next poll; one waiting to reconnect to the window
+    /// system exits once the retry delay is up.
+    pub fn stop(&self) {
+        self.shared.stop.stop();
+        self.shared.subscribers.lock().unwrap().clear();
+    }
+
+    /// Receive an event for every subsequent focus change
+    pub fn subscribe(&self) -> Receiver<FocusEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.shared.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app(id: &str) -> ActiveApp {
+        ActiveApp {
+            name: id.to_string(),
+            bundle_id: id.to_string(),
+            project: None,
+            pid: None,
+        }
+    }
+
+    #[test]
+    fn test_publish_updates_cache_and_subscribers() {
+        let watcher = FocusWatcher {
+            shared: Arc::new(Shared::default()),
+        };
+        let rx = watcher.subscribe();
+        let dropped = watcher.subscribe();
+        drop(dropped);
+
+        watcher.shared.publish(Some(app("firefox")));
+        watcher.shared.publish(None);
+
+        assert_eq!(rx.recv().unwrap().app, Some(app("firefox")));
+        assert_eq!(rx.recv().unwrap().app, None);
+        assert_eq!(watcher.current(), None);
+        assert_eq!(watcher.shared.subscribers.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_stop_ends_watching() {
+        let watcher = FocusWatcher::spawn(Duration::from_millis(10));
+        let rx = watcher.subscribe();
+        watcher.stop();
+
+        // Subscribers are let go, and polling stops at its next wake-up
+        while rx.recv().is_ok() {}
+        assert!(watcher.shared.stop.is_stopped());
+    }
+}
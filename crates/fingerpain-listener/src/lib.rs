@@ -3,7 +3,10 @@
 //! Uses the `rdev` crate for capturing keyboard events across macOS, Windows, and Linux.
 
 pub mod counter;
+pub mod focus;
 pub mod platform;
+pub mod queue;
+pub mod title_rules;
 
 use chrono::{DateTime, Utc};
 use fingerpain_core::KeystrokeRecord;
@@ -14,7 +17,10 @@ use std::thread;
 use thiserror::Error;
 
 pub use counter::KeystrokeCounter;
+pub use focus::{FocusEvent, FocusWatcher};
 pub use platform::ActiveApp;
+pub use queue::{EventQueue, InputEvent};
+pub use title_rules::TitleRules;
 
 #[derive(Error, Debug)]
 pub enum ListenerError {
@@ -47,7 +53,7 @@ pub enum KeyEventType {
 }
 
 impl KeyEventType {
-    fn from_key(key: Key) -> Self {
+    pub fn from_key(key: Key) -> Self {
         match key {
             Key::Space => KeyEventType::Space,
             Key::Return => KeyEventType::Enter,
@@ -84,6 +90,8 @@ pub type KeystrokeCallback = Box<dyn Fn(KeyEvent) + Send + 'static>;
 pub struct Listener {
     running: Arc<Mutex<bool>>,
     stop_tx: Option<Sender<()>>,
+    /// Focus watcher started with the listener, stopped with it
+    focus: Option<FocusWatcher>,
 }
 
 impl Listener {
@@ -91,6 +99,7 @@ impl Listener {
         Self {
             running: Arc::new(Mutex::new(false)),
             stop_tx: None,
+            focus: None,
         }
     }
 
@@ -114,15 +123,23 @@ impl Listener {
 
         *running.lock().unwrap() = true;
 
+        let focus = FocusWatcher::spawn(platform::DEFAULT_FOCUS_POLL_INTERVAL);
+        self.focus = Some(focus.clone());
+
         // Start the listener in a separate thread
         thread::spawn(move || {
-            Self::run_listener(callback, running, stop_rx);
+            Self::run_listener(callback, focus, running, stop_rx);
         });
 
         Ok(())
     }
 
-    fn run_listener<F>(callback: F, running: Arc<Mutex<bool>>, _stop_rx: Receiver<()>)
+    fn run_listener<F>(
+        callback: F,
+        focus: FocusWatcher,
+        running: Arc<Mutex<bool>>,
+        _stop_rx: Receiver<()>,
+    )
     where
         F: Fn(KeyEvent) + Send + 'static,
     {
@@ -140,7 +157,7 @@ impl Listener {
 
                 // Only process actual typing keys, not modifiers
                 if event_type != KeyEventType::Other {
-                    let app = platform::get_active_app().ok();
+                    let app = focus.current();
 
                     let key_event = KeyEvent {
                         timestamp: Utc::now(),
@@ -166,6 +183,9 @@ impl Listener {
         if let Some(tx) = self.stop_tx.take() {
             let _ = tx.send(());
         }
+        if let Some(focus) = self.focus.take() {
+            focus.stop();
+        }
     }
 }
 
@@ -186,6 +206,8 @@ pub struct KeystrokeAggregator {
     current_minute: i64,
     records: std::collections::HashMap<String, KeystrokeRecord>,
     counter: KeystrokeCounter,
+    /// Focused app from the latest focus event, used when a key event has none
+    focused_app: Option<ActiveApp>,
 }
 
 impl KeystrokeAggregator {
@@ -194,11 +216,25 @@ impl KeystrokeAggregator {
             current_minute: 0,
             records: std::collections::HashMap::new(),
             counter: KeystrokeCounter::new(),
+            focused_app: None,
         }
     }
 
+    /// Attribute subsequent keystrokes to the newly focused app
+    pub fn set_focus(&mut self, event: FocusEvent) {
+        if event.app != self.focused_app {
+            // A word can't continue across an app switch
+            self.counter.pending_chars = 0;
+        }
+        self.focused_app = event.app;
+    }
+
     /// Process a key event and return any completed records
-    pub fn process(&mut self, event: KeyEvent) -> Vec<KeystrokeRecord> {
+    pub fn process(&mut self, mut event: KeyEvent) -> Vec<KeystrokeRecord> {
+        if event.app.is_none() {
+            event.app = self.focused_app.clone();
+        }
+
         let minute = event.timestamp.timestamp() / 60;
         let mut completed = Vec::new();
 
@@ -225,6 +261,7 @@ impl KeystrokeAggregator {
             if let Some(app) = &event.app {
                 r.app_name = Some(app.name.clone());
                 r.app_bundle_id = Some(app.bundle_id.clone());
+                r.project = app.project.clone();
             }
             r
         });
@@ -0,0 +1,208 @@
+//! Freedesktop `.desktop` entry lookup for resolving Linux app names
+//!
+//! X11 only tells us a window's `WM_CLASS` and owning PID. The human-readable
+//! app name lives in the `.desktop` file installed alongside the application.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+/// The fields of a `[Desktop Entry]` group we care about
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DesktopEntry {
+    /// File name without the `.desktop` suffix (e.g. `google-chrome`)
+    pub id: String,
+    pub name: String,
+    pub exec: Option<String>,
+    pub startup_wm_class: Option<String>,
+}
+
+impl DesktopEntry {
+    /// Parse the `[Desktop Entry]` group of a `.desktop` file
+    pub fn parse(id: &str, contents: &str) -> Option<Self> {
+        let mut in_entry = false;
+        let mut entry = DesktopEntry {
+            id: id.to_string(),
+            ..Default::default()
+        };
+
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') {
+                in_entry = line == "[Desktop Entry]";
+                continue;
+            }
+            if !in_entry {
+                continue;
+            }
+
+            // Localized keys (Name[de]=...) are ignored in favour of the default
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            match key.trim() {
+                "Name" => entry.name = value.trim().to_string(),
+                "Exec" => entry.exec = Some(value.trim().to_string()),
+                "StartupWMClass" => entry.startup_wm_class = Some(value.trim().to_string()),
+                _ => {}
+            }
+        }
+
+        if entry.name.is_empty() {
+            None
+        } else {
+            Some(entry)
+        }
+    }
+
+    /// Basename of the program in the `Exec` line, skipping `env VAR=...` prefixes
+    pub fn exec_name(&self) -> Option<&str> {
+        self.exec
+            .as_deref()?
+            .split_whitespace()
+            .find(|arg| *arg != "env" && !arg.contains('='))
+            .map(|program| program.rsplit('/').next().unwrap_or(program))
+    }
+}
+
+/// Pick the entry that best describes a window with the given class and executable
+///
+/// Matches, in order of preference: `StartupWMClass`, the desktop file id, and
+/// the executable named in `Exec`.
+pub fn find_entry<'a>(
+    entries: &'a [DesktopEntry],
+    wm_class: &str,
+    exe_name: Option<&str>,
+) -> Option<&'a DesktopEntry> {
+    let class = wm_class.to_lowercase();
+
+    entries
+        .iter()
+        .find(|e| {
+            e.startup_wm_class
+                .as_deref()
+                .is_some_and(|c| c.eq_ignore_ascii_case(wm_class))
+        })
+        .or_else(|| {
+            entries.iter().find(|e| {
+                let id = e.id.to_lowercase();
+                id == class || id.rsplit('.').next() == Some(class.as_str())
+            })
+        })
+        .or_else(|| {
+            let exe = exe_name?;
+            entries.iter().find(|e| e.exec_name() == Some(exe))
+        })
+}
+
+/// Directories searched for `.desktop` files, following the XDG base directory spec
+fn application_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+
+    let data_home = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| Path::new(&h).join(".local/share")));
+    if let Some(home) = data_home {
+        dirs.push(home.join("applications"));
+        dirs.push(home.join("flatpak/exports/share/applications"));
+    }
+
+    let data_dirs = std::env::var("XDG_DATA_DIRS")
+        .unwrap_or_else(|_| "/usr/local/share:/usr/share".to_string());
+    for dir in data_dirs.split(':').filter(|d| !d.is_empty()) {
+        dirs.push(Path::new(dir).join("applications"));
+    }
+    dirs.push(PathBuf::from("/var/lib/flatpak/exports/share/applications"));
+    dirs.push(PathBuf::from("/var/lib/snapd/desktop/applications"));
+
+    dirs
+}
+
+fn load_entries() -> Vec<DesktopEntry> {
+    let mut by_id: HashMap<String, DesktopEntry> = HashMap::new();
+
+    for dir in application_dirs() {
+        let Ok(read_dir) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for file in read_dir.flatten() {
+            let path = file.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("desktop") {
+                continue;
+            }
+            let Some(id) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            // Earlier directories take precedence, as in the spec
+            if by_id.contains_key(id) {
+                continue;
+            }
+            if let Some(entry) = fs::read_to_string(&path)
+                .ok()
+                .and_then(|contents| DesktopEntry::parse(id, &contents))
+            {
+                by_id.insert(id.to_string(), entry);
+            }
+        }
+    }
+
+    by_id.into_values().collect()
+}
+
+fn cache() -> &'static Mutex<Option<Vec<DesktopEntry>>> {
+    static CACHE: OnceLock<Mutex<Option<Vec<DesktopEntry>>>> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(None))
+}
+
+/// Resolve the display name for a window class, scanning desktop files on first use
+pub fn lookup_name(wm_class: &str, exe_name: Option<&str>) -> Option<String> {
+    let mut guard = cache().lock().unwrap();
+    let entries = guard.get_or_insert_with(load_entries);
+    find_entry(entries, wm_class, exe_name).map(|e| e.name.clone())
+}
+
+/// Forget cached desktop entries so newly installed apps are picked up
+pub fn clear_cache() {
+    *cache().lock().unwrap() = None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHROME: &str = "[Desktop Entry]\n\
+        Version=1.0\n\
+        Name=Google Chrome\n\
+        Name[de]=Google Chrome (de)\n\
+        Exec=/usr/bin/google-chrome-stable %U\n\
+        StartupWMClass=Google-chrome\n\
+        \n\
+        [Desktop Action new-window]\n\
+        Name=New Window\n";
+
+    #[test]
+    fn test_parse_ignores_actions_and_locales() {
+        let entry = DesktopEntry::parse("google-chrome", CHROME).unwrap();
+        assert_eq!(entry.name, "Google Chrome");
+        assert_eq!(entry.startup_wm_class.as_deref(), Some("Google-chrome"));
+        assert_eq!(entry.exec_name(), Some("google-chrome-stable"));
+    }
+
+    #[test]
+    fn test_find_entry_preference() {
+        let entries = vec![
+            DesktopEntry::parse("google-chrome", CHROME).unwrap(),
+            DesktopEntry::parse("org.gnome.Terminal", "[Desktop Entry]\nName=Terminal\nExec=gnome-terminal\n").unwrap(),
+            DesktopEntry::parse("code", "[Desktop Entry]\nName=Visual Studio Code\nExec=env FOO=1 /usr/share/code/code --unity-launch %F\n").unwrap(),
+        ];
+
+        assert_eq!(find_entry(&entries, "google-chrome", None).unwrap().name, "Google Chrome");
+        assert_eq!(find_entry(&entries, "Terminal", None).unwrap().name, "Terminal");
+        assert_eq!(find_entry(&entries, "Code", Some("code")).unwrap().name, "Visual Studio Code");
+        assert!(find_entry(&entries, "unknown-app", Some("unknown")).is_none());
+    }
+}
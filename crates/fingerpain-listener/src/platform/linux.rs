@@ -1,96 +1,329 @@
 //! Linux-specific active app detection using X11
+//!
+//! The app comes from the window's `WM_CLASS` and owning process. The window
+//! title (`_NET_WM_NAME`) often contains document names or email subjects, so
+//! it is only read for apps a project rule covers, and only the project name
+//! extracted from it is kept.
 
-use super::{ActiveApp, PlatformError};
+use super::{desktop_entry, ActiveApp, PlatformError, StopSignal};
+use crate::title_rules::TitleRules;
+use std::sync::RwLock;
 use x11rb::connection::Connection;
-use x11rb::protocol::xproto::{AtomEnum, ConnectionExt, GetPropertyReply};
+use x11rb::protocol::xproto::{
+    Atom, AtomEnum, ChangeWindowAttributesAux, ClientMessageEvent, ConnectionExt, CreateWindowAux,
+    EventMask, Window, WindowClass,
+};
+use x11rb::protocol::Event;
 use x11rb::rust_connection::RustConnection;
 
-pub fn get_active_app() -> Result<ActiveApp, PlatformError> {
-    // Connect to X11 server
-    let (conn, screen_num) = RustConnection::connect(None)
-        .map_err(|e| PlatformError::GetActiveApp(format!("X11 connection failed: {}", e)))?;
+/// A connection to the X server with the atoms we query already interned
+struct X11Session {
+    conn: RustConnection,
+    root: Window,
+    active_window: Atom,
+    wm_class: Atom,
+    wm_pid: Atom,
+    wm_name: Atom,
+}
 
-    let setup = conn.setup();
-    let screen = &setup.roots[screen_num];
-    let root = screen.root;
+impl X11Session {
+    fn connect() -> Result<Self, PlatformError> {
+        let (conn, screen_num) = RustConnection::connect(None)
+            .map_err(|e| PlatformError::GetActiveApp(format!("X11 connection failed: {}", e)))?;
+        let root = conn.setup().roots[screen_num].root;
 
-    // Get the _NET_ACTIVE_WINDOW atom
-    let active_window_atom = conn
-        .intern_atom(false, b"_NET_ACTIVE_WINDOW")
-        .map_err(|e| PlatformError::GetActiveApp(format!("Failed to intern atom: {}", e)))?
-        .reply()
-        .map_err(|e| PlatformError::GetActiveApp(format!("Failed to get atom reply: {}", e)))?
-        .atom;
+        let active_window = intern(&conn, b"_NET_ACTIVE_WINDOW")?;
+        let wm_class = intern(&conn, b"WM_CLASS")?;
+        let wm_pid = intern(&conn, b"_NET_WM_PID")?;
+        let wm_name = intern(&conn, b"_NET_WM_NAME")?;
 
-    // Get the active window
-    let active_window_reply = conn
-        .get_property(false, root, active_window_atom, AtomEnum::WINDOW, 0, 1)
-        .map_err(|e| PlatformError::GetActiveApp(format!("Failed to get property: {}", e)))?
-        .reply()
-        .map_err(|e| PlatformError::GetActiveApp(format!("Failed to get property reply: {}", e)))?;
+        Ok(Self {
+            conn,
+            root,
+            active_window,
+            wm_class,
+            wm_pid,
+            wm_name,
+        })
+    }
 
-    if active_window_reply.value.len() < 4 {
-        return Err(PlatformError::GetActiveApp("No active window".to_string()));
+    fn get_property(
+        &self,
+        window: Window,
+        property: Atom,
+        type_: AtomEnum,
+        long_length: u32,
+    ) -> Result<Vec<u8>, PlatformError> {
+        Ok(self
+            .conn
+            .get_property(false, window, property, type_, 0, long_length)
+            .map_err(|e| PlatformError::GetActiveApp(format!("Failed to get property: {}", e)))?
+            .reply()
+            .map_err(|e| PlatformError::GetActiveApp(format!("Failed to get property reply: {}", e)))?
+            .value)
     }
 
-    let active_window = u32::from_ne_bytes([
-        active_window_reply.value[0],
-        active_window_reply.value[1],
-        active_window_reply.value[2],
-        active_window_reply.value[3],
-    ]);
+    fn active_window(&self) -> Result<Window, PlatformError> {
+        read_u32(&self.get_property(self.root, self.active_window, AtomEnum::WINDOW, 1)?)
+            .filter(|w| *w != 0)
+            .ok_or_else(|| PlatformError::GetActiveApp("No active window".to_string()))
+    }
 
-    if active_window == 0 {
-        return Err(PlatformError::GetActiveApp("No active window".to_string()));
+    fn active_app(&self, rules: &TitleRules) -> Result<ActiveApp, PlatformError> {
+        self.app_for(self.active_window()?, rules)
     }
 
-    // Get _NET_WM_NAME (UTF-8 window title)
-    let wm_name_atom = conn
-        .intern_atom(false, b"_NET_WM_NAME")
-        .map_err(|e| PlatformError::GetActiveApp(format!("Failed to intern atom: {}", e)))?
-        .reply()
-        .map_err(|e| PlatformError::GetActiveApp(format!("Failed to get atom reply: {}", e)))?
-        .atom;
+    fn app_for(&self, active_window: Window, rules: &TitleRules) -> Result<ActiveApp, PlatformError> {
+        // WM_CLASS contains two null-terminated strings: instance name and class name
+        let class_value = self.get_property(active_window, self.wm_class, AtomEnum::STRING, 1024)?;
+        let class_str = String::from_utf8_lossy(&class_value);
+        let bundle_id = class_str
+            .split('\0')
+            .nth(1)
+            .filter(|c| !c.is_empty())
+            .or_else(|| class_str.split('\0').next())
+            .unwrap_or_default()
+            .to_string();
 
-    let utf8_string_atom = conn
-        .intern_atom(false, b"UTF8_STRING")
-        .map_err(|e| PlatformError::GetActiveApp(format!("Failed to intern atom: {}", e)))?
-        .reply()
-        .map_err(|e| PlatformError::GetActiveApp(format!("Failed to get atom reply: {}", e)))?
-        .atom;
+        // _NET_WM_PID lets us find the executable behind the window
+        let pid = read_u32(&self.get_property(active_window, self.wm_pid, AtomEnum::CARDINAL, 1)?);
 
-    let name_reply = conn
-        .get_property(false, active_window, wm_name_atom, utf8_string_atom, 0, 1024)
-        .map_err(|e| PlatformError::GetActiveApp(format!("Failed to get property: {}", e)))?
-        .reply()
-        .map_err(|e| PlatformError::GetActiveApp(format!("Failed to get property reply: {}", e)))?;
+        let bundle_id = if bundle_id.is_empty() {
+            "unknown".to_string()
+        } else {
+            bundle_id
+        };
+        let name = resolve_name(&bundle_id, pid).unwrap_or_else(|| "Unknown".to_string());
 
-    let name = String::from_utf8_lossy(&name_reply.value).into_owned();
+        // The title only lives long enough to extract the project from it
+        let project = if rules.covers(&name, &bundle_id) {
+            self.title(active_window)
+                .and_then(|title| rules.project(&name, &bundle_id, &title))
+        } else {
+            None
+        };
 
-    // Get WM_CLASS for the bundle_id equivalent
-    let wm_class_atom = conn
-        .intern_atom(false, b"WM_CLASS")
+        Ok(ActiveApp {
+            name,
+            bundle_id,
+            project,
+            pid,
+        })
+    }
+
+    /// `_NET_WM_NAME`, falling back to the legacy `WM_NAME`
+    fn title(&self, window: Window) -> Option<String> {
+        [self.wm_name, AtomEnum::WM_NAME.into()]
+            .into_iter()
+            .filter_map(|atom| self.get_property(window, atom, AtomEnum::ANY, 1024).ok())
+            .find(|value| !value.is_empty())
+            .map(|value| String::from_utf8_lossy(&value).into_owned())
+    }
+
+    /// Move title change notifications from `previous` to `next`
+    ///
+    /// Errors for windows that have gone away arrive as events and are ignored.
+    fn follow_title(&self, previous: Option<Window>, next: Option<Window>) -> Option<Window> {
+        if previous != next {
+            let changes = [
+                (previous, EventMask::NO_EVENT),
+                (next, EventMask::PROPERTY_CHANGE),
+            ];
+            for (window, mask) in changes {
+                if let Some(window) = window {
+                    let _ = self.conn.change_window_attributes(
+                        window,
+                        &ChangeWindowAttributesAux::new().event_mask(mask),
+                    );
+                }
+            }
+            let _ = self.conn.flush();
+        }
+        next
+    }
+}
+
+fn intern(conn: &RustConnection, name: &[u8]) -> Result<Atom, PlatformError> {
+    Ok(conn
+        .intern_atom(false, name)
         .map_err(|e| PlatformError::GetActiveApp(format!("Failed to intern atom: {}", e)))?
         .reply()
         .map_err(|e| PlatformError::GetActiveApp(format!("Failed to get atom reply: {}", e)))?
-        .atom;
+        .atom)
+}
 
-    let class_reply = conn
-        .get_property(false, active_window, wm_class_atom, AtomEnum::STRING, 0, 1024)
-        .map_err(|e| PlatformError::GetActiveApp(format!("Failed to get property: {}", e)))?
-        .reply()
-        .map_err(|e| PlatformError::GetActiveApp(format!("Failed to get property reply: {}", e)))?;
-
-    // WM_CLASS contains two null-terminated strings: instance name and class name
-    let class_str = String::from_utf8_lossy(&class_reply.value);
-    let bundle_id = class_str
-        .split('\0')
-        .nth(1)
-        .unwrap_or(&class_str)
-        .to_string();
-
-    Ok(ActiveApp {
-        name: if name.is_empty() { "Unknown".to_string() } else { name },
-        bundle_id: if bundle_id.is_empty() { "unknown".to_string() } else { bundle_id },
-    })
+fn read_u32(value: &[u8]) -> Option<u32> {
+    value
+        .get(..4)
+        .map(|b| u32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+pub fn get_active_app(rules: &TitleRules) -> Result<ActiveApp, PlatformError> {
+    X11Session::connect()?.active_app(rules)
+}
+
+/// Block on a single X11 connection, reporting every change of the focused app
+///
+/// Subscribes to `PropertyNotify` on the root window and re-reads the active
+/// window only when `_NET_ACTIVE_WINDOW` changes. Title changes are followed
+/// only on an active window that a project rule covers. `stop` is checked
+/// after every event, and wakes the loop by sending a message to a hidden
+/// window of its own.
+pub fn watch_focus<F>(
+    rules: &RwLock<TitleRules>,
+    stop: &StopSignal,
+    on_change: F,
+) -> Result<(), PlatformError>
+where
+    F: FnMut(Option<ActiveApp>),
+{
+    let session = X11Session::connect()?;
+
+    let wake_window = session
+        .conn
+        .generate_id()
+        .map_err(|e| PlatformError::FocusWatch(format!("Failed to create window: {}", e)))?;
+    session
+        .conn
+        .create_window(
+            x11rb::COPY_DEPTH_FROM_PARENT,
+            wake_window,
+            session.root,
+            0,
+            0,
+            1,
+            1,
+            0,
+            WindowClass::INPUT_ONLY,
+            x11rb::COPY_FROM_PARENT,
+            &CreateWindowAux::new(),
+        )
+        .map_err(|e| PlatformError::FocusWatch(format!("Failed to create window: {}", e)))?
+        .check()
+        .map_err(|e| PlatformError::FocusWatch(format!("Failed to create window: {}", e)))?;
+
+    stop.set_wake(Some(Box::new(move || wake(wake_window))));
+    let result = follow_focus(&session, rules, stop, on_change);
+    stop.set_wake(None);
+    result
+}
+
+/// Send `window` an empty message, from a connection of our own since the
+/// watcher's is busy waiting
+fn wake(window: Window) {
+    let Ok((conn, _)) = RustConnection::connect(None) else {
+        return;
+    };
+    let event = ClientMessageEvent::new(32, window, AtomEnum::NONE, [0u32; 5]);
+    let _ = conn.send_event(false, window, EventMask::NO_EVENT, event);
+    let _ = conn.flush();
+}
+
+fn follow_focus<F>(
+    session: &X11Session,
+    rules: &RwLock<TitleRules>,
+    stop: &StopSignal,
+    mut on_change: F,
+) -> Result<(), PlatformError>
+where
+    F: FnMut(Option<ActiveApp>),
+{
+    if stop.is_stopped() {
+        return Ok(());
+    }
+
+    session
+        .conn
+        .change_window_attributes(
+            session.root,
+            &ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE),
+        )
+        .map_err(|e| PlatformError::FocusWatch(format!("Failed to select events: {}", e)))?
+        .check()
+        .map_err(|e| PlatformError::FocusWatch(format!("Failed to select events: {}", e)))?;
+
+    // The focused app and the window whose title is followed, if any
+    let lookup = |window: Option<Window>| {
+        let rules = rules.read().unwrap();
+        let app = window.and_then(|w| session.app_for(w, &rules).ok());
+        let follow = window.filter(|_| {
+            app.as_ref()
+                .is_some_and(|app| rules.covers(&app.name, &app.bundle_id))
+        });
+        (app, follow)
+    };
+
+    let mut window = session.active_window().ok();
+    let (mut current, follow) = lookup(window);
+    let mut titled = session.follow_title(None, follow);
+    on_change(current.clone());
+
+    loop {
+        let event = session
+            .conn
+            .wait_for_event()
+            .map_err(|e| PlatformError::FocusWatch(format!("X11 connection lost: {}", e)))?;
+        if stop.is_stopped() {
+            return Ok(());
+        }
+
+        let Event::PropertyNotify(notify) = event else {
+            continue;
+        };
+        if notify.window == session.root && notify.atom == session.active_window {
+            window = session.active_window().ok();
+        } else if Some(notify.window) != titled
+            || (notify.atom != session.wm_name && notify.atom != Atom::from(AtomEnum::WM_NAME))
+        {
+            continue;
+        }
+
+        let (app, follow) = lookup(window);
+        titled = session.follow_title(titled, follow);
+        if app != current {
+            current = app.clone();
+            on_change(app);
+        }
+    }
+}
+
+/// Parent of a process, read from `/proc/<pid>/stat`
+fn parent_pid(pid: u32) -> Option<u32> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name before the parent may itself contain spaces and ')'
+    let fields = &stat[stat.rfind(')')? + 1..];
+    fields.split_whitespace().nth(1)?.parse().ok()
+}
+
+pub fn process_ancestors(pid: u32) -> Vec<u32> {
+    let mut ancestors: Vec<u32> = Vec::new();
+    let mut pid = pid;
+    while let Some(parent) = parent_pid(pid).filter(|p| *p > 1 && !ancestors.contains(p)) {
+        ancestors.push(parent);
+        pid = parent;
+    }
+    ancestors
+}
+
+/// Executable name for a process, read from `/proc/<pid>/exe`
+fn exe_name(pid: u32) -> Option<String> {
+    let exe = std::fs::read_link(format!("/proc/{}/exe", pid)).ok()?;
+    exe.file_name()?.to_str().map(|s| s.to_string())
+}
+
+/// Resolve a stable display name from the window class and owning process
+///
+/// Prefers the matching `.desktop` entry's `Name`, then the window class, then
+/// the executable name.
+fn resolve_name(wm_class: &str, pid: Option<u32>) -> Option<String> {
+    let exe = pid.and_then(exe_name);
+
+    desktop_entry::lookup_name(wm_class, exe.as_deref())
+        .or_else(|| (wm_class != "unknown").then(|| wm_class.to_string()))
+        .or(exe)
+}
+
+/// Resolve the display name for a previously recorded `WM_CLASS`
+pub fn resolve_app_name(bundle_id: &str) -> Option<String> {
+    resolve_name(bundle_id, None)
 }
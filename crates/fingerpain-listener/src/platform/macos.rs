@@ -38,7 +38,12 @@ pub fn get_active_app() -> Result<ActiveApp, PlatformError> {
             "unknown".to_string()
         };
 
-        Ok(ActiveApp { name, bundle_id })
+        Ok(ActiveApp {
+            name,
+            bundle_id,
+            project: None,
+            pid: None,
+        })
     }
 }
 
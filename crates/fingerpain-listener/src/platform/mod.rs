@@ -1,4 +1,8 @@
 //! Platform-specific functionality for active app detection
+//!
+//! Window titles are only read for apps covered by a project rule, and only to
+//! extract the project name. macOS can't read them without the accessibility
+//! permission, so projects are not detected there yet.
 
 #[cfg(target_os = "macos")]
 mod macos;
@@ -9,42 +13,187 @@ mod windows;
 #[cfg(target_os = "linux")]
 mod linux;
 
+#[cfg(target_os = "linux")]
+mod desktop_entry;
+
+use crate::title_rules::TitleRules;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum PlatformError {
     #[error("Failed to get active app: {0}")]
     GetActiveApp(String),
+    #[error("Focus watch failed: {0}")]
+    FocusWatch(String),
     #[error("Unsupported platform")]
     Unsupported,
 }
 
 /// Information about the currently active application
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ActiveApp {
     /// Display name of the application
     pub name: String,
     /// Bundle ID (macOS), process name (Windows/Linux)
     pub bundle_id: String,
+    /// Project named in the window title by a project rule; never the title
+    pub project: Option<String>,
+    /// Process that owns the window, where it can be told (Linux)
+    pub pid: Option<u32>,
 }
 
-/// Get the currently active application
-#[cfg(target_os = "macos")]
+/// Get the currently active application, without looking for a project
 pub fn get_active_app() -> Result<ActiveApp, PlatformError> {
+    get_active_app_with(&TitleRules::default())
+}
+
+/// Get the currently active application and the project its title names
+#[cfg(target_os = "macos")]
+pub fn get_active_app_with(_rules: &TitleRules) -> Result<ActiveApp, PlatformError> {
     macos::get_active_app()
 }
 
 #[cfg(target_os = "windows")]
-pub fn get_active_app() -> Result<ActiveApp, PlatformError> {
-    windows::get_active_app()
+pub fn get_active_app_with(rules: &TitleRules) -> Result<ActiveApp, PlatformError> {
+    windows::get_active_app(rules)
 }
 
 #[cfg(target_os = "linux")]
-pub fn get_active_app() -> Result<ActiveApp, PlatformError> {
-    linux::get_active_app()
+pub fn get_active_app_with(rules: &TitleRules) -> Result<ActiveApp, PlatformError> {
+    linux::get_active_app(rules)
 }
 
 #[cfg(not(any(target_os = "macos", target_os = "windows", target_os = "linux")))]
-pub fn get_active_app() -> Result<ActiveApp, PlatformError> {
+pub fn get_active_app_with(_rules: &TitleRules) -> Result<ActiveApp, PlatformError> {
     Err(PlatformError::Unsupported)
 }
+
+/// Tells a focus watcher to stop, waking it if it is blocked on the window system
+#[derive(Default)]
+pub struct StopSignal {
+    stopped: AtomicBool,
+    /// Set by a watcher while it blocks where `stopped` isn't looked at
+    wake: Mutex<Option<Box<dyn Fn() + Send>>>,
+}
+
+impl StopSignal {
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        if let Some(wake) = &*self.wake.lock().unwrap() {
+            wake();
+        }
+    }
+
+    /// Have `stop` call `wake`, until replaced with `None`
+    ///
+    /// Check `is_stopped` after setting it: a stop that came first won't call it.
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    fn set_wake(&self, wake: Option<Box<dyn Fn() + Send>>) {
+        *self.wake.lock().unwrap() = wake;
+    }
+}
+
+/// Default for how often platforms without focus-change notifications are polled
+pub const DEFAULT_FOCUS_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Block, calling `on_change` with the initial focused app and on every change,
+/// until `stop` is signalled
+///
+/// `poll_interval` only matters where focus has to be polled. A change of
+/// project within the same app counts as a change.
+#[cfg(target_os = "linux")]
+pub fn watch_focus<F>(
+    _poll_interval: Duration,
+    rules: &RwLock<TitleRules>,
+    stop: &StopSignal,
+    on_change: F,
+) -> Result<(), PlatformError>
+where
+    F: FnMut(Option<ActiveApp>),
+{
+    linux::watch_focus(rules, stop, on_change)
+}
+
+/// Block, calling `on_change` with the initial focused app and on every change,
+/// until `stop` is signalled
+///
+/// `poll_interval` only matters where focus has to be polled. A change of
+/// project within the same app counts as a change.
+#[cfg(not(target_os = "linux"))]
+pub fn watch_focus<F>(
+    poll_interval: Duration,
+    rules: &RwLock<TitleRules>,
+    stop: &StopSignal,
+    on_change: F,
+) -> Result<(), PlatformError>
+where
+    F: FnMut(Option<ActiveApp>),
+{
+    poll_focus(poll_interval, rules, stop, on_change)
+}
+
+/// Fallback focus watcher that polls `get_active_app_with`
+///
+/// It notices a stop at its next poll.
+pub fn poll_focus<F>(
+    interval: Duration,
+    rules: &RwLock<TitleRules>,
+    stop: &StopSignal,
+    mut on_change: F,
+) -> Result<(), PlatformError>
+where
+    F: FnMut(Option<ActiveApp>),
+{
+    let active_app = || get_active_app_with(&rules.read().unwrap()).ok();
+    let mut current = active_app();
+    on_change(current.clone());
+
+    while !stop.is_stopped() {
+        std::thread::sleep(interval);
+        let app = active_app();
+        if app != current {
+            current = app.clone();
+            on_change(app);
+        }
+    }
+    Ok(())
+}
+
+/// Parent, grandparent and so on of a process, up to but not including init
+///
+/// Only Linux can read the process tree; elsewhere this is empty.
+#[cfg(target_os = "linux")]
+pub fn process_ancestors(pid: u32) -> Vec<u32> {
+    linux::process_ancestors(pid)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn process_ancestors(_pid: u32) -> Vec<u32> {
+    Vec::new()
+}
+
+/// Resolve the display name for a recorded bundle ID
+///
+/// Only Linux needs this: older builds stored window titles as app names there.
+#[cfg(target_os = "linux")]
+pub fn resolve_app_name(bundle_id: &str) -> Option<String> {
+    linux::resolve_app_name(bundle_id)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn resolve_app_name(_bundle_id: &str) -> Option<String> {
+    None
+}
+
+/// Drop cached app metadata (such as Linux desktop entries) so it is re-read
+pub fn clear_app_cache() {
+    #[cfg(target_os = "linux")]
+    desktop_entry::clear_cache();
+}
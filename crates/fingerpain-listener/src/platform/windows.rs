@@ -1,12 +1,21 @@
 //! Windows-specific active app detection
+//!
+//! Apps are named from the foreground window's executable: its version
+//! resource's `FileDescription`, else the file name without `.exe`. The window
+//! title is only read for apps a project rule covers, and only the project name
+//! is kept.
 
 use super::{ActiveApp, PlatformError};
-use windows::Win32::Foundation::HWND;
-use windows::Win32::UI::WindowsAndMessaging::{GetForegroundWindow, GetWindowTextW, GetWindowThreadProcessId};
-use std::ffi::OsString;
+use crate::title_rules::TitleRules;
+use std::ffi::{c_void, OsString};
 use std::os::windows::ffi::OsStringExt;
+use windows::core::PCWSTR;
+use windows::Win32::Foundation::{CloseHandle, HANDLE, HWND};
+use windows::Win32::UI::WindowsAndMessaging::{
+    GetForegroundWindow, GetWindowTextW, GetWindowThreadProcessId,
+};
 
-pub fn get_active_app() -> Result<ActiveApp, PlatformError> {
+pub fn get_active_app(rules: &TitleRules) -> Result<ActiveApp, PlatformError> {
     unsafe {
         // Get the foreground window
         let hwnd = GetForegroundWindow();
@@ -16,49 +25,145 @@ pub fn get_active_app() -> Result<ActiveApp, PlatformError> {
             ));
         }
 
-        // Get the window title
-        let mut title_buf = [0u16; 512];
-        let len = GetWindowTextW(hwnd, &mut title_buf);
-        let title = if len > 0 {
-            OsString::from_wide(&title_buf[..len as usize])
-                .to_string_lossy()
-                .into_owned()
-        } else {
-            "Unknown".to_string()
-        };
-
         // Get the process ID
         let mut process_id = 0u32;
         GetWindowThreadProcessId(hwnd, Some(&mut process_id));
 
-        // Get process name from process ID
-        let process_name = get_process_name(process_id).unwrap_or_else(|| "unknown".to_string());
+        // Get process name and path from process ID
+        let (process_name, exe_path) = get_process_image(process_id).unwrap_or_default();
+        let process_name = if process_name.is_empty() {
+            "unknown".to_string()
+        } else {
+            process_name
+        };
+        let name = exe_path
+            .as_deref()
+            .and_then(file_description)
+            .or_else(|| {
+                let stem = std::path::Path::new(&process_name).file_stem()?.to_str()?;
+                (process_name != "unknown").then(|| stem.to_string())
+            })
+            .unwrap_or_else(|| "Unknown".to_string());
+
+        // The title only lives long enough to extract the project from it
+        let project = if rules.covers(&name, &process_name) {
+            window_title(hwnd).and_then(|title| rules.project(&name, &process_name, &title))
+        } else {
+            None
+        };
 
         Ok(ActiveApp {
-            name: title,
+            name,
             bundle_id: process_name,
+            project,
+            pid: None,
         })
     }
 }
 
-fn get_process_name(process_id: u32) -> Option<String> {
-    use windows::Win32::System::ProcessStatus::K32GetModuleBaseNameW;
-    use windows::Win32::System::Threading::{OpenProcess, PROCESS_QUERY_INFORMATION, PROCESS_VM_READ};
+unsafe fn window_title(hwnd: HWND) -> Option<String> {
+    let mut title_buf = [0u16; 512];
+    let len = GetWindowTextW(hwnd, &mut title_buf);
+    (len > 0).then(|| {
+        OsString::from_wide(&title_buf[..len as usize])
+            .to_string_lossy()
+            .into_owned()
+    })
+}
+
+/// Executable name and NUL-terminated full path of a process
+fn get_process_image(process_id: u32) -> Option<(String, Option<Vec<u16>>)> {
+    use windows::Win32::System::ProcessStatus::{K32GetModuleBaseNameW, K32GetModuleFileNameExW};
+    use windows::Win32::System::Threading::{
+        OpenProcess, PROCESS_QUERY_INFORMATION, PROCESS_VM_READ,
+    };
 
     unsafe {
-        let handle = OpenProcess(PROCESS_QUERY_INFORMATION | PROCESS_VM_READ, false, process_id).ok()?;
+        let handle: HANDLE = OpenProcess(
+            PROCESS_QUERY_INFORMATION | PROCESS_VM_READ,
+            false,
+            process_id,
+        )
+        .ok()?;
 
         let mut name_buf = [0u16; 260];
         let len = K32GetModuleBaseNameW(handle, None, &mut name_buf);
+        let name = (len > 0).then(|| {
+            OsString::from_wide(&name_buf[..len as usize])
+                .to_string_lossy()
+                .into_owned()
+        });
 
-        if len > 0 {
-            Some(
-                OsString::from_wide(&name_buf[..len as usize])
-                    .to_string_lossy()
-                    .into_owned(),
-            )
-        } else {
-            None
+        let mut path_buf = [0u16; 1024];
+        let len = K32GetModuleFileNameExW(handle, None, &mut path_buf);
+        let path = (len > 0).then(|| {
+            let mut path = path_buf[..len as usize].to_vec();
+            path.push(0);
+            path
+        });
+
+        let _ = CloseHandle(handle);
+        Some((name?, path))
+    }
+}
+
+/// `FileDescription` from an executable's version resource, e.g. "Google Chrome"
+fn file_description(path: &[u16]) -> Option<String> {
+    use windows::Win32::Storage::FileSystem::{
+        GetFileVersionInfoSizeW, GetFileVersionInfoW, VerQueryValueW,
+    };
+
+    let wide = |text: &str| text.encode_utf16().chain(Some(0)).collect::<Vec<u16>>();
+
+    unsafe {
+        let path = PCWSTR(path.as_ptr());
+        let size = GetFileVersionInfoSizeW(path, None);
+        if size == 0 {
+            return None;
+        }
+        let mut data = vec![0u8; size as usize];
+        GetFileVersionInfoW(path, 0, size, data.as_mut_ptr() as *mut c_void).ok()?;
+
+        // The first language and code page the resource has strings for
+        let mut value: *mut c_void = std::ptr::null_mut();
+        let mut len = 0u32;
+        let query = wide("\\VarFileInfo\\Translation");
+        if !VerQueryValueW(
+            data.as_ptr() as *const c_void,
+            PCWSTR(query.as_ptr()),
+            &mut value,
+            &mut len,
+        )
+        .as_bool()
+            || len < 4
+        {
+            return None;
+        }
+        let language = *(value as *const u16);
+        let code_page = *(value as *const u16).add(1);
+
+        let query = wide(&format!(
+            "\\StringFileInfo\\{:04x}{:04x}\\FileDescription",
+            language, code_page
+        ));
+        if !VerQueryValueW(
+            data.as_ptr() as *const c_void,
+            PCWSTR(query.as_ptr()),
+            &mut value,
+            &mut len,
+        )
+        .as_bool()
+            || len == 0
+        {
+            return None;
         }
+        // `len` counts characters, including the terminating NUL
+        let chars = std::slice::from_raw_parts(value as *const u16, len as usize);
+        let description = OsString::from_wide(chars)
+            .to_string_lossy()
+            .trim_end_matches('\0')
+            .trim()
+            .to_string();
+        (!description.is_empty()).then_some(description)
     }
 }
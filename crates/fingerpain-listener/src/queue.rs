@@ -0,0 +1,105 @@
+//! Bounded hand-off between the input callback and aggregation
+//!
+//! The OS input hook must return quickly or it adds latency to every key
+//! press system-wide. The callback therefore only pushes a compact event onto
+//! a bounded channel; aggregation and database I/O happen on other threads.
+
+use crate::{FocusEvent, KeyEvent};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+
+/// Default queue capacity: several seconds of very fast typing
+pub const DEFAULT_CAPACITY: usize = 4096;
+
+/// An event for the aggregation thread
+#[derive(Debug, Clone)]
+pub enum InputEvent {
+    Key(KeyEvent),
+    Focus(FocusEvent),
+}
+
+/// Producer side of the input event queue
+#[derive(Clone)]
+pub struct EventQueue {
+    tx: SyncSender<InputEvent>,
+    pushed: Arc<AtomicU64>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl EventQueue {
+    /// Create a queue holding at most `capacity` pending events
+    pub fn bounded(capacity: usize) -> (Self, Receiver<InputEvent>) {
+        let (tx, rx) = mpsc::sync_channel(capacity);
+        let queue = Self {
+            tx,
+            pushed: Arc::new(AtomicU64::new(0)),
+            dropped: Arc::new(AtomicU64::new(0)),
+        };
+        (queue, rx)
+    }
+
+    /// Enqueue without blocking; the event is dropped if the queue is full
+    ///
+    /// Safe to call from the input callback. Returns `false` if dropped.
+    pub fn push(&self, event: InputEvent) -> bool {
+        match self.tx.try_send(event) {
+            Ok(()) => {
+                self.pushed.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+        }
+    }
+
+    /// Enqueue, waiting for space; for events that must not be lost
+    pub fn send(&self, event: InputEvent) -> bool {
+        let sent = self.tx.send(event).is_ok();
+        if sent {
+            self.pushed.fetch_add(1, Ordering::Relaxed);
+        }
+        sent
+    }
+
+    /// Number of events accepted so far
+    pub fn pushed(&self) -> u64 {
+        self.pushed.load(Ordering::Relaxed)
+    }
+
+    /// Number of events dropped because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KeyEventType;
+    use chrono::Utc;
+
+    fn key() -> InputEvent {
+        InputEvent::Key(KeyEvent {
+            timestamp: Utc::now(),
+            event_type: KeyEventType::Character,
+            app: None,
+        })
+    }
+
+    #[test]
+    fn test_push_drops_when_full() {
+        let (queue, rx) = EventQueue::bounded(2);
+
+        assert!(queue.push(key()));
+        assert!(queue.push(key()));
+        assert!(!queue.push(key()));
+        assert_eq!(queue.pushed(), 2);
+        assert_eq!(queue.dropped(), 1);
+
+        rx.recv().unwrap();
+        assert!(queue.push(key()));
+    }
+}
@@ -0,0 +1,141 @@
+//! Project names from window titles
+//!
+//! Editors put the open project in their window title, e.g.
+//! `main.rs — fingerpain — Visual Studio Code`. User rules pull only that name
+//! out when focus is captured. The title itself is dropped straight away and
+//! is never stored or sent anywhere. Private browser windows are skipped, so
+//! a rule that captures too much can't store what was open in one.
+
+use fingerpain_core::config::ProjectRules;
+use regex::{Regex, RegexBuilder};
+
+/// Longest project name kept, in characters
+const MAX_PROJECT_LEN: usize = 64;
+
+/// What browsers add to the title of a private window (lowercase)
+const PRIVATE_MARKERS: &[&str] = &["private browsing", "(incognito)", "inprivate"];
+
+struct CompiledRule {
+    app: Option<Regex>,
+    title: Regex,
+}
+
+impl CompiledRule {
+    fn applies_to(&self, app_name: &str, bundle_id: &str) -> bool {
+        self.app
+            .as_ref()
+            .is_none_or(|app| app.is_match(app_name) || app.is_match(bundle_id))
+    }
+}
+
+/// Compiled `[projects]` rules
+#[derive(Default)]
+pub struct TitleRules {
+    rules: Vec<CompiledRule>,
+}
+
+impl TitleRules {
+    pub fn new(rules: &ProjectRules) -> Result<Self, regex::Error> {
+        let rules = rules
+            .rules
+            .iter()
+            .map(|rule| {
+                Ok(CompiledRule {
+                    app: rule.app.as_deref().map(compile).transpose()?,
+                    title: compile(&rule.title)?,
+                })
+            })
+            .collect::<Result<_, regex::Error>>()?;
+        Ok(Self { rules })
+    }
+
+    /// Whether any rule could apply to an app; other apps' titles are never read
+    pub fn covers(&self, app_name: &str, bundle_id: &str) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| rule.applies_to(app_name, bundle_id))
+    }
+
+    /// Project named in `title` by the first matching rule; never one from a
+    /// private window
+    pub fn project(&self, app_name: &str, bundle_id: &str, title: &str) -> Option<String> {
+        if is_private(title) {
+            return None;
+        }
+        self.rules
+            .iter()
+            .filter(|rule| rule.applies_to(app_name, bundle_id))
+            .find_map(|rule| {
+                let captures = rule.title.captures(title)?;
+                let name = captures.name("project").or_else(|| captures.get(1))?;
+                let name = name.as_str().trim();
+                (!name.is_empty()).then(|| name.chars().take(MAX_PROJECT_LEN).collect())
+            })
+    }
+}
+
+fn is_private(title: &str) -> bool {
+    let title = title.to_lowercase();
+    PRIVATE_MARKERS.iter().any(|marker| title.contains(marker))
+}
+
+fn compile(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern).case_insensitive(true).build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fingerpain_core::config::ProjectRule;
+
+    #[test]
+    fn test_project_from_title() {
+        let rule = |app: &str, title: &str| ProjectRule {
+            app: Some(app.to_string()),
+            title: title.to_string(),
+        };
+        let rules = ProjectRules {
+            rules: vec![
+                rule("^code$", "— (?P<project>[^—]+) — Visual Studio Code$"),
+                rule("jetbrains|intellij", r"^([^\[]+) \["),
+            ],
+        };
+        let rules = TitleRules::new(&rules).unwrap();
+
+        assert_eq!(
+            rules.project("Code", "code", "main.rs — fingerpain — Visual Studio Code"),
+            Some("fingerpain".to_string())
+        );
+        assert_eq!(
+            rules.project(
+                "IntelliJ IDEA",
+                "jetbrains-idea",
+                "backend [~/src/backend] – App.kt"
+            ),
+            Some("backend".to_string())
+        );
+        // Rules only look at the apps they name
+        assert!(!rules.covers("Thunderbird", "thunderbird"));
+        assert_eq!(
+            rules.project("Firefox", "firefox", "x — fingerpain — Visual Studio Code"),
+            None
+        );
+        assert_eq!(rules.project("Code", "code", "Welcome"), None);
+
+        // Nothing is taken from private windows, and names are bounded
+        let rules = TitleRules::new(&ProjectRules {
+            rules: vec![rule("firefox", "^(.*)$")],
+        })
+        .unwrap();
+        assert_eq!(
+            rules.project("Firefox", "firefox", "Bank — Private Browsing"),
+            None
+        );
+        assert_eq!(
+            rules
+                .project("Firefox", "firefox", &"x".repeat(200))
+                .map(|name| name.len()),
+            Some(MAX_PROJECT_LEN)
+        );
+    }
+}
@@ -4,27 +4,43 @@
 
 use anyhow::Result;
 use fingerpain_core::{
+    config::Config,
+    control::ControlClient,
     db::Database,
+    discovery::web_url,
     metrics::{Metrics, TimeRange},
 };
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tao::{
-    event::{Event, WindowEvent},
+    event::{Event, StartCause, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     window::WindowBuilder,
 };
+use tracing::error;
 use tray_icon::{
     menu::{Menu, MenuEvent, MenuItem, PredefinedMenuItem},
     TrayIcon, TrayIconBuilder,
 };
 
+/// How often the stats and daemon state in the menu are refreshed
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
 fn main() -> Result<()> {
     tracing_subscriber::fmt()
         .with_env_filter("fingerpain=info")
         .init();
 
-    // Open database
-    let db = Arc::new(Mutex::new(Database::open_default()?));
+    // A broken config file shouldn't take the tray down with it, but its
+    // data directory is unknown then, so there are no stats to show
+    let (web_config, db) = match Config::load() {
+        Ok(config) => (config.web.clone(), Some(Database::open(config.db_path())?)),
+        Err(e) => {
+            error!("Showing no stats until the config is fixed: {}", e);
+            (Config::default().web, None)
+        }
+    };
+    let db = Arc::new(Mutex::new(db));
 
     // Build the event loop
     let event_loop = EventLoop::new();
@@ -47,6 +63,13 @@ fn main() -> Result<()> {
     tray_menu.append(&stats_wpm)?;
     tray_menu.append(&PredefinedMenuItem::separator())?;
 
+    // Daemon state, queried over the control socket
+    let daemon_status = MenuItem::new("Daemon: -", false, None);
+    let toggle_pause = MenuItem::new("Pause Recording", false, None);
+    tray_menu.append(&daemon_status)?;
+    tray_menu.append(&toggle_pause)?;
+    tray_menu.append(&PredefinedMenuItem::separator())?;
+
     let open_dashboard = MenuItem::new("Open Dashboard", true, None);
     tray_menu.append(&open_dashboard)?;
 
@@ -66,6 +89,7 @@ fn main() -> Result<()> {
     // Store menu item IDs for event handling
     let open_dashboard_id = open_dashboard.id().clone();
     let quit_id = quit.id().clone();
+    let toggle_pause_id = toggle_pause.id().clone();
     let stats_chars_id = stats_chars.id().clone();
     let stats_words_id = stats_words.id().clone();
     let stats_wpm_id = stats_wpm.id().clone();
@@ -76,7 +100,10 @@ fn main() -> Result<()> {
     // Update stats periodically
     let update_stats = move || -> Result<()> {
         let db_guard = db_clone.lock().unwrap();
-        let metrics = Metrics::new(&*db_guard);
+        let Some(db) = db_guard.as_ref() else {
+            return Ok(());
+        };
+        let metrics = Metrics::new(db);
         let stats = metrics.stats(TimeRange::Today)?;
 
         stats_chars.set_text(&format!(
@@ -97,27 +124,61 @@ fn main() -> Result<()> {
         Ok(())
     };
 
+    // Reflect whether the daemon is running or paused
+    let update_daemon = move || {
+        match ControlClient::connect().and_then(|mut client| client.status()) {
+            Ok(status) if status.paused => {
+                daemon_status.set_text("Daemon: paused");
+                toggle_pause.set_text("Resume Recording");
+                toggle_pause.set_enabled(true);
+            }
+            Ok(status) => {
+                daemon_status.set_text(&format!("Daemon: running ({:.0} WPM)", status.current_wpm));
+                toggle_pause.set_text("Pause Recording");
+                toggle_pause.set_enabled(true);
+            }
+            Err(_) => {
+                daemon_status.set_text("Daemon: not running");
+                toggle_pause.set_enabled(false);
+            }
+        }
+    };
+
     // Initial update
     let _ = update_stats();
+    update_daemon();
 
     // Set up menu event receiver
     let menu_channel = MenuEvent::receiver();
 
-    // Run event loop
+    // Run event loop, waking up to refresh the menu
+    let mut next_refresh = Instant::now() + REFRESH_INTERVAL;
     event_loop.run(move |event, _, control_flow| {
-        *control_flow = ControlFlow::Wait;
+        *control_flow = ControlFlow::WaitUntil(next_refresh);
 
         // Check for menu events
         if let Ok(event) = menu_channel.try_recv() {
             if event.id == quit_id {
                 *control_flow = ControlFlow::Exit;
             } else if event.id == open_dashboard_id {
-                // Open web dashboard
-                let _ = open::that("http://localhost:7890");
+                // Open web dashboard, wherever fingerpain-web ended up listening
+                let _ = open::that(web_url(&web_config));
+            } else if event.id == toggle_pause_id {
+                if let Ok(mut client) = ControlClient::connect() {
+                    let paused = client.status().map(|s| s.paused).unwrap_or(false);
+                    let _ = if paused { client.resume() } else { client.pause(None) };
+                }
+                update_daemon();
             }
         }
 
         match event {
+            Event::NewEvents(StartCause::ResumeTimeReached { .. }) => {
+                let _ = update_stats();
+                update_daemon();
+                next_refresh = Instant::now() + REFRESH_INTERVAL;
+                *control_flow = ControlFlow::WaitUntil(next_refresh);
+            }
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
                 ..
@@ -0,0 +1,175 @@
+//! Access control for the local API
+//!
+//! Every web page open in the browser can send requests to localhost, so:
+//!
+//! - `Host` must be `localhost` or an IP address, which stops a DNS name
+//!   rebound to 127.0.0.1 from reaching the API with the page's origin.
+//! - A request carrying an `Origin` must come from the dashboard itself or
+//!   from a paired browser extension. There are no CORS headers; extensions
+//!   don't need them and web pages must not get them.
+//! - Anything but a read needs a token, from pairing or `fingerpain auth
+//!   token`. Reads need one too with `web.read_auth`.
+
+use crate::error::{ApiError, ApiJson};
+use crate::AppState;
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, Method},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use fingerpain_core::{is_local_host, is_plausible_name};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// Path where extensions trade a pairing code for a token
+pub const PAIR_PATH: &str = "/api/pair";
+
+/// Schemes of browser extension origins
+const EXTENSION_SCHEMES: [&str; 2] = ["chrome-extension://", "moz-extension://"];
+
+fn is_extension_origin(origin: &str) -> bool {
+    EXTENSION_SCHEMES
+        .iter()
+        .any(|scheme| origin.starts_with(scheme))
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+/// `?token=`, for clients that can't send headers
+fn query_token(query: &str) -> Option<&str> {
+    query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("token="))
+}
+
+/// Check Host, Origin and token before any handler runs
+pub async fn guard(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let headers = request.headers();
+    let Some(host) = headers.get(header::HOST).and_then(|h| h.to_str().ok()) else {
+        return ApiError::bad_request("missing_host", "Missing Host header").into_response();
+    };
+    if !is_local_host(host) {
+        warn!("Refused request for host {}", host);
+        return ApiError::forbidden("Host not allowed").into_response();
+    }
+
+    let origin = headers.get(header::ORIGIN).and_then(|o| o.to_str().ok());
+    let path = request.uri().path();
+    let pairing = path == PAIR_PATH;
+
+    if let Some(origin) = origin {
+        let dashboard = origin == format!("http://{}", host);
+        let allowed = if dashboard || (pairing && is_extension_origin(origin)) {
+            true
+        } else if is_extension_origin(origin) {
+            match state.with_db(|_, db| Ok(db.paired_origins()?)).await {
+                Ok(origins) => origins.iter().any(|o| o == origin),
+                Err(e) => return e.into_response(),
+            }
+        } else {
+            false
+        };
+        if !allowed {
+            warn!("Refused request to {} from origin {}", path, origin);
+            return ApiError::forbidden("Origin not allowed").into_response();
+        }
+    }
+
+    let read = matches!(*request.method(), Method::GET | Method::HEAD);
+    let stats = path.starts_with("/api/") || path == crate::prometheus::METRICS_PATH;
+    let needs_token = !pairing && (!read || (state.read_auth && stats));
+    if needs_token {
+        let token = bearer_token(headers).or_else(|| {
+            (path == crate::live::LIVE_PATH)
+                .then(|| query_token(request.uri().query()?))
+                .flatten()
+        });
+        let Some(token) = token else {
+            return ApiError::unauthorized("API token required").into_response();
+        };
+        let token = token.to_string();
+        let valid = state
+            .with_db(move |_, db| Ok(db.verify_api_token(&token)?))
+            .await;
+        match valid {
+            Ok(Some(_)) => {}
+            Ok(None) => return ApiError::unauthorized("Invalid API token").into_response(),
+            Err(e) => return e.into_response(),
+        }
+    }
+
+    next.run(request).await
+}
+
+#[derive(Deserialize)]
+pub struct PairRequest {
+    code: String,
+    /// What the token is for, e.g. the browser name
+    name: String,
+}
+
+#[derive(Serialize)]
+pub struct PairResponse {
+    token: String,
+}
+
+/// Trade a code from `fingerpain auth pair` for a token
+pub async fn pair_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ApiJson(payload): ApiJson<PairRequest>,
+) -> Result<Json<PairResponse>, ApiError> {
+    if !is_plausible_name(&payload.name) {
+        return Err(ApiError::bad_request(
+            "invalid_name",
+            "name must be a short name",
+        ));
+    }
+    // The guard let only extensions and non-browser clients through
+    let origin = headers
+        .get(header::ORIGIN)
+        .and_then(|o| o.to_str().ok())
+        .filter(|origin| is_extension_origin(origin));
+
+    let origin = origin.map(str::to_string);
+    let token = state
+        .with_db(move |_, db| {
+            Ok(db.redeem_pairing_code(&payload.code, payload.name.trim(), origin.as_deref())?)
+        })
+        .await?
+        .ok_or_else(|| ApiError::forbidden("Wrong or expired pairing code"))?;
+
+    Ok(Json(PairResponse { token }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_hosts() {
+        assert!(is_local_host("localhost:7890"));
+        assert!(is_local_host("127.0.0.1:7890"));
+        assert!(is_local_host("[::1]:7890"));
+        assert!(is_local_host("192.168.1.20"));
+        // DNS rebinding points a name of the attacker's at 127.0.0.1
+        assert!(!is_local_host("evil.example.com:7890"));
+        assert!(!is_local_host("localhost.evil.example.com"));
+
+        assert!(is_extension_origin("moz-extension://0b8f6c1e-4a7d"));
+        assert!(!is_extension_origin("https://evil.example.com"));
+        assert!(!is_extension_origin("null"));
+
+        assert_eq!(query_token("since=1&token=abc"), Some("abc"));
+        assert_eq!(query_token("tokens=abc"), None);
+    }
+}
@@ -0,0 +1,544 @@
+//! FingerPain Web Dashboard
+//!
+//! Local web server for viewing typing statistics with charts. The router is
+//! built here so integration tests can drive it without a socket; the binary
+//! in `main.rs` adds the listener.
+
+mod auth;
+mod error;
+mod live;
+mod prometheus;
+mod v1;
+
+pub use live::{LiveFeed, LiveRelay};
+
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::{self, Next},
+    response::{Html, Json, Response},
+    routing::{get, post},
+    Router,
+};
+use chrono::Utc;
+use error::{ApiError, ApiJson, ApiQuery};
+use fingerpain_core::{
+    config::Config,
+    control::{ControlClient, ControlError},
+    db::{Database, RecordFilter},
+    metrics::{Metrics, TimeRange},
+    AggregatedStats, AppClassifier, AppStats, BrowserRegistry, CategoryStats, DomainGroups,
+    DomainLevel, DomainStats, EditorContext, ExclusionRules, Gap, HourlyStats, LanguageStats,
+    PeakInfo, ProjectStats, TabReport, TagStats, UrlPolicy,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::info;
+
+/// Range names accepted by `?range=`, as listed in error details
+const RANGES: [&str; 12] = [
+    "today",
+    "yesterday",
+    "week",
+    "last-week",
+    "month",
+    "last-month",
+    "year",
+    "last-year",
+    "7d",
+    "30d",
+    "90d",
+    "all",
+];
+
+#[derive(Clone)]
+pub struct AppState {
+    db: Arc<Mutex<Database>>,
+    /// App aliases and categories from the config
+    apps: AppClassifier,
+    /// `privacy.url_policy` at startup, until a reload stores another
+    url_policy: UrlPolicy,
+    /// `privacy.domains` at startup, whose tabs are logged without domain, URL
+    /// or title, until a reload stores others
+    exclusions: ExclusionRules,
+    browsers: BrowserRegistry,
+    domain_groups: DomainGroups,
+    /// Reads need a token too (`web.read_auth`)
+    read_auth: bool,
+    live: LiveRelay,
+}
+
+impl AppState {
+    /// State for `db`, applying the URL policy to URLs stored under an earlier one
+    pub fn new(db: Database, config: &Config) -> fingerpain_core::db::Result<Self> {
+        let urls = db.url_redactor(config.privacy.url_policy)?;
+        let redacted = db.redact_urls(&urls)?;
+        if redacted > 0 {
+            info!("Redacted {} stored URLs", redacted);
+        }
+        db.set_excluded_domains(&config.privacy.domains)?;
+        Ok(Self {
+            db: Arc::new(Mutex::new(db)),
+            apps: config.app_classifier(),
+            url_policy: config.privacy.url_policy,
+            exclusions: config.privacy.clone(),
+            browsers: config.browser_registry(),
+            domain_groups: config.domain_groups(),
+            read_auth: config.web.read_auth,
+            live: LiveRelay::new(),
+        })
+    }
+
+    /// Take the live feed from `live` instead of the daemon's control socket
+    pub fn with_live(mut self, live: LiveRelay) -> Self {
+        self.live = live;
+        self
+    }
+
+    /// Run `f` with the database on a blocking thread
+    ///
+    /// SQLite calls block, for as long as the busy timeout while another
+    /// process writes, so they stay off the async runtime.
+    async fn with_db<T, F>(&self, f: F) -> Result<T, ApiError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&AppState, &Database) -> Result<T, ApiError> + Send + 'static,
+    {
+        let state = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let db = state
+                .db
+                .lock()
+                .map_err(|_| ApiError::database_unavailable())?;
+            f(&state, &db)
+        })
+        .await
+        .map_err(|e| ApiError::internal(e.to_string()))?
+    }
+
+    /// Metrics with the configured app and domain rules and the query's filters
+    fn metrics<'a>(&self, db: &'a Database, query: &RangeQuery) -> Metrics<'a> {
+        let filter = RecordFilter {
+            tag: query.tag.clone(),
+            ..RecordFilter::default()
+        };
+        self.filtered_metrics(db, filter, query.level.unwrap_or_default())
+    }
+
+    /// Metrics with the configured app and domain rules
+    fn filtered_metrics<'a>(
+        &self,
+        db: &'a Database,
+        filter: RecordFilter,
+        level: DomainLevel,
+    ) -> Metrics<'a> {
+        Metrics::new(db)
+            .with_app_rules(self.apps.clone())
+            .with_filter(filter)
+            .with_browsers(self.browsers.clone())
+            .with_domains(self.domain_groups.clone(), level)
+    }
+}
+
+/// Dashboard and API routes, behind the access checks
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/", get(index_handler))
+        .route("/api/stats", get(stats_handler))
+        .route("/api/apps", get(apps_handler))
+        .route("/api/categories", get(categories_handler))
+        .route("/api/projects", get(projects_handler))
+        .route("/api/languages", get(languages_handler))
+        .route("/api/domains", get(domains_handler))
+        .route("/api/hourly", get(hourly_handler))
+        .route("/api/peak", get(peak_handler))
+        .route("/api/daily", get(daily_handler))
+        .route("/api/gaps", get(gaps_handler))
+        .route("/api/tags", get(tags_handler))
+        .route(live::LIVE_PATH, get(live::live_handler))
+        .route(prometheus::METRICS_PATH, get(prometheus::metrics_handler))
+        .route("/api/tags/start", post(tag_start_handler))
+        .route("/api/tags/stop", post(tag_stop_handler))
+        .route("/api/browser-context", post(browser_context_handler))
+        .route("/api/editor-context", post(editor_context_handler))
+        .route(auth::PAIR_PATH, post(auth::pair_handler))
+        .merge(v1::routes())
+        .fallback(not_found_handler)
+        .layer(middleware::from_fn_with_state(state.clone(), auth::guard))
+        .layer(middleware::from_fn(log_request))
+        .with_state(state)
+}
+
+/// Log each request with its status and how long it took; query strings are left out
+async fn log_request(request: Request, next: Next) -> Response {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let started = Instant::now();
+
+    let response = next.run(request).await;
+    info!(
+        "{} {} {} {:.1}ms",
+        method,
+        path,
+        response.status().as_u16(),
+        started.elapsed().as_secs_f64() * 1000.0
+    );
+    response
+}
+
+async fn not_found_handler() -> ApiError {
+    ApiError::new(StatusCode::NOT_FOUND, "not_found", "No such endpoint")
+}
+
+async fn index_handler() -> Html<&'static str> {
+    Html(include_str!("../static/index.html"))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RangeQuery {
+    range: Option<String>,
+    /// Only count typing tagged with this project
+    tag: Option<String>,
+    /// Report browser typing per `host` or per registrable domain (`site`)
+    level: Option<DomainLevel>,
+}
+
+impl RangeQuery {
+    /// `?range=`, or `default` without one
+    fn range(&self, default: TimeRange) -> Result<TimeRange, ApiError> {
+        let Some(name) = self.range.as_deref() else {
+            return Ok(default);
+        };
+        TimeRange::parse(name).ok_or_else(|| {
+            ApiError::bad_request("invalid_range", format!("Unknown range '{}'", name))
+                .with_details(json!({ "range": name, "expected": RANGES }))
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct StatsResponse {
+    stats: AggregatedStats,
+    range: String,
+}
+
+async fn stats_handler(
+    State(state): State<AppState>,
+    ApiQuery(query): ApiQuery<RangeQuery>,
+) -> Result<Json<StatsResponse>, ApiError> {
+    let range = query.range(TimeRange::Today)?;
+    let name = query.range.clone().unwrap_or_else(|| "today".to_string());
+
+    let stats = state
+        .with_db(move |state, db| Ok(state.metrics(db, &query).stats(range)?))
+        .await?;
+
+    Ok(Json(StatsResponse { stats, range: name }))
+}
+
+#[derive(Serialize)]
+struct AppsResponse {
+    apps: Vec<AppStats>,
+}
+
+async fn apps_handler(
+    State(state): State<AppState>,
+    ApiQuery(query): ApiQuery<RangeQuery>,
+) -> Result<Json<AppsResponse>, ApiError> {
+    let range = query.range(TimeRange::ThisWeek)?;
+
+    let apps = state
+        .with_db(move |state, db| Ok(state.metrics(db, &query).app_stats(range)?))
+        .await?;
+
+    Ok(Json(AppsResponse { apps }))
+}
+
+#[derive(Serialize)]
+struct CategoriesResponse {
+    categories: Vec<CategoryStats>,
+}
+
+async fn categories_handler(
+    State(state): State<AppState>,
+    ApiQuery(query): ApiQuery<RangeQuery>,
+) -> Result<Json<CategoriesResponse>, ApiError> {
+    let range = query.range(TimeRange::ThisWeek)?;
+
+    let categories = state
+        .with_db(move |state, db| Ok(state.metrics(db, &query).category_stats(range)?))
+        .await?;
+
+    Ok(Json(CategoriesResponse { categories }))
+}
+
+#[derive(Serialize)]
+struct ProjectsResponse {
+    projects: Vec<ProjectStats>,
+}
+
+async fn projects_handler(
+    State(state): State<AppState>,
+    ApiQuery(query): ApiQuery<RangeQuery>,
+) -> Result<Json<ProjectsResponse>, ApiError> {
+    let range = query.range(TimeRange::ThisWeek)?;
+
+    let projects = state
+        .with_db(move |state, db| Ok(state.metrics(db, &query).project_stats(range)?))
+        .await?;
+
+    Ok(Json(ProjectsResponse { projects }))
+}
+
+#[derive(Serialize)]
+struct LanguagesResponse {
+    languages: Vec<LanguageStats>,
+}
+
+async fn languages_handler(
+    State(state): State<AppState>,
+    ApiQuery(query): ApiQuery<RangeQuery>,
+) -> Result<Json<LanguagesResponse>, ApiError> {
+    let range = query.range(TimeRange::ThisWeek)?;
+
+    let languages = state
+        .with_db(move |state, db| Ok(state.metrics(db, &query).language_stats(range)?))
+        .await?;
+
+    Ok(Json(LanguagesResponse { languages }))
+}
+
+#[derive(Serialize)]
+struct DomainsResponse {
+    domains: Vec<DomainStats>,
+}
+
+async fn domains_handler(
+    State(state): State<AppState>,
+    ApiQuery(query): ApiQuery<RangeQuery>,
+) -> Result<Json<DomainsResponse>, ApiError> {
+    let range = query.range(TimeRange::ThisWeek)?;
+
+    let domains = state
+        .with_db(move |state, db| Ok(state.metrics(db, &query).domain_stats(range)?))
+        .await?;
+
+    Ok(Json(DomainsResponse { domains }))
+}
+
+#[derive(Serialize)]
+struct HourlyResponse {
+    hourly: Vec<HourlyStats>,
+}
+
+async fn hourly_handler(
+    State(state): State<AppState>,
+    ApiQuery(query): ApiQuery<RangeQuery>,
+) -> Result<Json<HourlyResponse>, ApiError> {
+    let range = query.range(TimeRange::ThisMonth)?;
+
+    let hourly = state
+        .with_db(move |state, db| Ok(state.metrics(db, &query).hourly_stats(range)?))
+        .await?;
+
+    Ok(Json(HourlyResponse { hourly }))
+}
+
+#[derive(Serialize)]
+struct PeakResponse {
+    peaks: Vec<PeakInfo>,
+}
+
+async fn peak_handler(
+    State(state): State<AppState>,
+    ApiQuery(query): ApiQuery<RangeQuery>,
+) -> Result<Json<PeakResponse>, ApiError> {
+    let range = query.range(TimeRange::ThisMonth)?;
+
+    let peaks = state
+        .with_db(move |state, db| Ok(state.metrics(db, &query).peak_times(range, 10)?))
+        .await?;
+
+    Ok(Json(PeakResponse { peaks }))
+}
+
+#[derive(Serialize)]
+struct DailyDataPoint {
+    date: String,
+    chars: u64,
+    words: u64,
+}
+
+#[derive(Serialize)]
+struct DailyResponse {
+    data: Vec<DailyDataPoint>,
+}
+
+#[derive(Serialize)]
+struct ContextResponse {
+    success: bool,
+    message: String,
+}
+
+async fn daily_handler(
+    State(state): State<AppState>,
+    ApiQuery(query): ApiQuery<RangeQuery>,
+) -> Result<Json<DailyResponse>, ApiError> {
+    let range = query.range(TimeRange::Last30Days)?;
+
+    let daily = state
+        .with_db(move |state, db| Ok(state.metrics(db, &query).daily_totals(range)?))
+        .await?;
+
+    let data: Vec<DailyDataPoint> = daily
+        .into_iter()
+        .map(|(date, chars, words)| DailyDataPoint {
+            date: date.format("%Y-%m-%d").to_string(),
+            chars,
+            words,
+        })
+        .collect();
+
+    Ok(Json(DailyResponse { data }))
+}
+
+#[derive(Serialize)]
+struct GapsResponse {
+    gaps: Vec<Gap>,
+}
+
+async fn gaps_handler(
+    State(state): State<AppState>,
+    ApiQuery(query): ApiQuery<RangeQuery>,
+) -> Result<Json<GapsResponse>, ApiError> {
+    let range = query.range(TimeRange::Today)?;
+
+    let gaps = state
+        .with_db(move |state, db| Ok(state.metrics(db, &query).gaps(range)?))
+        .await?;
+
+    Ok(Json(GapsResponse { gaps }))
+}
+
+#[derive(Serialize)]
+struct TagsResponse {
+    /// Tags the daemon is stamping right now (empty if it isn't running)
+    active: Vec<String>,
+    tags: Vec<TagStats>,
+}
+
+async fn tags_handler(
+    State(state): State<AppState>,
+    ApiQuery(query): ApiQuery<RangeQuery>,
+) -> Result<Json<TagsResponse>, ApiError> {
+    let range = query.range(TimeRange::ThisWeek)?;
+
+    let tags = state
+        .with_db(move |state, db| Ok(state.metrics(db, &query).tag_stats(range)?))
+        .await?;
+    let active = daemon_call(|client| client.status())
+        .await
+        .map(|status| status.tags)
+        .unwrap_or_default();
+
+    Ok(Json(TagsResponse { active, tags }))
+}
+
+#[derive(Deserialize)]
+struct TagRequest {
+    tag: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ActiveTagsResponse {
+    active: Vec<String>,
+}
+
+async fn tag_start_handler(
+    ApiJson(payload): ApiJson<TagRequest>,
+) -> Result<Json<ActiveTagsResponse>, ApiError> {
+    let tag = payload
+        .tag
+        .filter(|tag| fingerpain_core::is_valid_tag(tag))
+        .ok_or_else(|| ApiError::bad_request("invalid_tag", "Missing or invalid tag"))?;
+    let active = daemon_call(move |client| client.tag_start(&tag)).await?;
+    Ok(Json(ActiveTagsResponse { active }))
+}
+
+async fn tag_stop_handler(
+    ApiJson(payload): ApiJson<TagRequest>,
+) -> Result<Json<ActiveTagsResponse>, ApiError> {
+    let active = daemon_call(move |client| client.tag_stop(payload.tag.as_deref())).await?;
+    Ok(Json(ActiveTagsResponse { active }))
+}
+
+/// Run a control socket request off the async runtime
+async fn daemon_call<T, F>(call: F) -> Result<T, ApiError>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut ControlClient) -> Result<T, ControlError> + Send + 'static,
+{
+    let result = tokio::task::spawn_blocking(move || {
+        ControlClient::connect().and_then(|mut client| call(&mut client))
+    })
+    .await
+    .map_err(|e| ApiError::internal(e.to_string()))?;
+
+    Ok(result?)
+}
+
+async fn browser_context_handler(
+    State(state): State<AppState>,
+    ApiJson(payload): ApiJson<TabReport>,
+) -> Result<Json<ContextResponse>, ApiError> {
+    let ctx = state
+        .with_db(move |state, db| {
+            // The daemon stores the policy and domains it switches to on a
+            // config reload
+            let policy = db.url_policy()?.unwrap_or(state.url_policy);
+            let mut exclusions = state.exclusions.clone();
+            if let Some(domains) = db.excluded_domains()? {
+                exclusions.domains = domains;
+            }
+            let ctx = payload
+                .into_context(Utc::now(), &db.url_redactor(policy)?, &exclusions)
+                .map_err(|message| ApiError::bad_request("invalid_report", message))?;
+            db.log_browser_context(&ctx)?;
+            Ok(ctx)
+        })
+        .await?;
+
+    Ok(Json(ContextResponse {
+        success: true,
+        message: if ctx.private {
+            "Private window"
+        } else if ctx.excluded {
+            "Excluded domain"
+        } else {
+            "Context updated"
+        }
+        .to_string(),
+    }))
+}
+
+/// Context from an editor plugin; stored like the browser's, minus any file name
+async fn editor_context_handler(
+    State(state): State<AppState>,
+    ApiJson(payload): ApiJson<EditorContext>,
+) -> Result<Json<ContextResponse>, ApiError> {
+    let ctx = payload
+        .normalized()
+        .map_err(|message| ApiError::bad_request("invalid_context", message))?;
+
+    state
+        .with_db(move |_, db| Ok(db.upsert_editor_context(&ctx)?))
+        .await?;
+
+    Ok(Json(ContextResponse {
+        success: true,
+        message: "Context updated".to_string(),
+    }))
+}
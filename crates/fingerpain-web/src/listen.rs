@@ -0,0 +1,159 @@
+//! Listening socket: TCP with port fallback, or a Unix socket
+
+use anyhow::{bail, Context, Result};
+use axum::Router;
+use fingerpain_core::config::WebConfig;
+use fingerpain_core::discovery::WebEndpoint;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+
+/// Ports tried after the configured one when it is taken
+const FALLBACK_PORTS: u16 = 10;
+
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener, PathBuf),
+}
+
+impl Listener {
+    /// Bind as configured, moving up a port while the configured one is taken
+    pub async fn bind(config: &WebConfig) -> Result<Self> {
+        if let Some(path) = config.unix_socket_path() {
+            return bind_unix(path);
+        }
+
+        let addr = config
+            .socket_addr()
+            .context("web.bind must be an IP address such as 127.0.0.1")?;
+        let attempts = if config.port_fallback {
+            FALLBACK_PORTS
+        } else {
+            0
+        };
+        for port in addr.port()..=addr.port().saturating_add(attempts) {
+            match TcpListener::bind(SocketAddr::new(addr.ip(), port)).await {
+                Ok(listener) => {
+                    if port != addr.port() {
+                        warn!(
+                            "Port {} is in use, listening on {} instead",
+                            addr.port(),
+                            port
+                        );
+                    }
+                    return Ok(Self::Tcp(listener));
+                }
+                Err(e) if e.kind() == ErrorKind::AddrInUse => continue,
+                Err(e) => return Err(e).with_context(|| format!("failed to listen on {}", addr)),
+            }
+        }
+
+        let running = WebEndpoint::discover()
+            .map(|endpoint| format!(" (fingerpain-web pid {} is running)", endpoint.pid))
+            .unwrap_or_default();
+        if config.port_fallback {
+            bail!(
+                "ports {} to {} on {} are all in use{}; pass --port or set web.port",
+                addr.port(),
+                addr.port().saturating_add(attempts),
+                addr.ip(),
+                running
+            );
+        }
+        bail!(
+            "{} is already in use{}; pass --port, set web.port or enable web.port_fallback",
+            addr,
+            running
+        )
+    }
+
+    /// Endpoint to record in the discovery file
+    pub fn endpoint(&self) -> Result<WebEndpoint> {
+        Ok(match self {
+            Self::Tcp(listener) => WebEndpoint::new(Some(listener.local_addr()?), None),
+            #[cfg(unix)]
+            Self::Unix(_, path) => WebEndpoint::new(None, Some(path)),
+        })
+    }
+
+    /// Serve `app` until Ctrl-C
+    pub async fn serve(self, app: Router) -> Result<()> {
+        match self {
+            Self::Tcp(listener) => {
+                axum::serve(listener, app)
+                    .with_graceful_shutdown(shutdown())
+                    .await?
+            }
+            #[cfg(unix)]
+            Self::Unix(listener, path) => {
+                tokio::select! {
+                    result = serve_unix(&listener, app) => result?,
+                    _ = shutdown() => {}
+                }
+                let _ = std::fs::remove_file(path);
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn shutdown() {
+    let _ = tokio::signal::ctrl_c().await;
+    info!("Shutting down");
+}
+
+#[cfg(unix)]
+fn bind_unix(path: PathBuf) -> Result<Listener> {
+    use std::os::unix::fs::PermissionsExt;
+
+    if path.exists() {
+        // A socket nobody answers on is left over from a server that was killed
+        if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+            bail!(
+                "{} is already in use, see {}",
+                path.display(),
+                fingerpain_core::discovery::discovery_path().display()
+            );
+        }
+        std::fs::remove_file(&path)
+            .with_context(|| format!("failed to remove stale socket {}", path.display()))?;
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let listener = tokio::net::UnixListener::bind(&path)
+        .with_context(|| format!("failed to listen on {}", path.display()))?;
+    // Only the user running the server may connect, whatever the umask
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))
+        .with_context(|| format!("failed to restrict {}", path.display()))?;
+    Ok(Listener::Unix(listener, path))
+}
+
+#[cfg(not(unix))]
+fn bind_unix(_path: PathBuf) -> Result<Listener> {
+    bail!("Unix sockets are not supported on this platform")
+}
+
+/// axum 0.7 only serves TCP itself, so Unix connections go to hyper directly
+#[cfg(unix)]
+async fn serve_unix(listener: &tokio::net::UnixListener, app: Router) -> Result<()> {
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use hyper_util::server::conn::auto::Builder;
+    use hyper_util::service::TowerToHyperService;
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let service = TowerToHyperService::new(app.clone());
+        tokio::spawn(async move {
+            if let Err(e) = Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .await
+            {
+                warn!("Connection error: {}", e);
+            }
+        });
+    }
+}
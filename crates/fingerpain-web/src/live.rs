@@ -0,0 +1,195 @@
+//! Live feed at `/api/live`
+//!
+//! While anyone is watching, one thread follows the daemon's `live` feed over
+//! the control socket and fans each snapshot out to every client, as
+//! server-sent events or, when the request asks for an upgrade, WebSocket
+//! text messages. Both carry the same JSON: a snapshot tagged
+//! `"type": "snapshot"`, or `{"type": "offline"}` while the daemon isn't
+//! running.
+
+use crate::AppState;
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+};
+use fingerpain_core::control::ControlError;
+use fingerpain_core::{ControlClient, LiveSnapshot};
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_stream::{wrappers::BroadcastStream, StreamExt};
+use tracing::{debug, warn};
+
+/// Where the feed is served; the only path accepting `?token=`, since
+/// `EventSource` and browser WebSockets can't send headers
+pub const LIVE_PATH: &str = "/api/live";
+
+/// How long to wait before looking for the daemon again
+const RETRY: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum LiveEvent {
+    Snapshot(LiveSnapshot),
+    Offline,
+}
+
+impl LiveEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Snapshot(_) => "snapshot",
+            Self::Offline => "offline",
+        }
+    }
+}
+
+/// Snapshots from one connection to the daemon, until it goes away
+pub type LiveFeed = Box<dyn Iterator<Item = Result<LiveSnapshot, ControlError>> + Send>;
+
+/// Opens a connection to the daemon's feed
+type Connect = dyn Fn() -> Result<LiveFeed, ControlError> + Send + Sync;
+
+/// Hands the daemon's feed to every subscriber
+#[derive(Clone)]
+pub struct LiveRelay {
+    sender: broadcast::Sender<LiveEvent>,
+    /// Whether the thread following the daemon is running
+    running: Arc<AtomicBool>,
+    connect: Arc<Connect>,
+}
+
+impl LiveRelay {
+    /// Relay the feed of the daemon at the control socket
+    pub fn new() -> Self {
+        Self::with_source(|| {
+            let feed = ControlClient::connect().and_then(ControlClient::live)?;
+            Ok(Box::new(feed) as LiveFeed)
+        })
+    }
+
+    /// Relay the feeds `connect` opens, e.g. a fake daemon in tests
+    pub fn with_source(
+        connect: impl Fn() -> Result<LiveFeed, ControlError> + Send + Sync + 'static,
+    ) -> Self {
+        let (sender, _) = broadcast::channel(16);
+        Self {
+            sender,
+            running: Arc::new(AtomicBool::new(false)),
+            connect: Arc::new(connect),
+        }
+    }
+
+    /// Events from now on, starting the relay thread if it isn't running
+    pub fn subscribe(&self) -> broadcast::Receiver<LiveEvent> {
+        let receiver = self.sender.subscribe();
+        if !self.running.swap(true, Ordering::SeqCst) {
+            let relay = self.clone();
+            let spawned = std::thread::Builder::new()
+                .name("live".to_string())
+                .spawn(move || relay.run());
+            if let Err(e) = spawned {
+                warn!("Failed to start the live feed: {}", e);
+                self.running.store(false, Ordering::SeqCst);
+            }
+        }
+        receiver
+    }
+
+    /// Follow the daemon until nobody is listening
+    fn run(&self) {
+        loop {
+            follow(&self.sender, &*self.connect);
+            self.running.store(false, Ordering::SeqCst);
+            // Someone may have subscribed while the thread was stopping
+            if self.sender.receiver_count() == 0 || self.running.swap(true, Ordering::SeqCst) {
+                break;
+            }
+        }
+    }
+}
+
+impl Default for LiveRelay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Relay the daemon's feed while anyone listens, reconnecting when it restarts
+fn follow(sender: &broadcast::Sender<LiveEvent>, connect: &Connect) {
+    while sender.receiver_count() > 0 {
+        match connect() {
+            Ok(feed) => {
+                for snapshot in feed {
+                    match snapshot {
+                        Ok(snapshot) => {
+                            if sender.send(LiveEvent::Snapshot(snapshot)).is_err() {
+                                return;
+                            }
+                        }
+                        Err(e) => {
+                            warn!("Live feed interrupted: {}", e);
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(e) => debug!("Live feed unavailable: {}", e),
+        }
+        if sender.send(LiveEvent::Offline).is_err() {
+            return;
+        }
+        std::thread::sleep(RETRY);
+    }
+}
+
+/// Server-sent events, or a WebSocket when the request asks for an upgrade
+pub async fn live_handler(
+    State(state): State<AppState>,
+    upgrade: Option<WebSocketUpgrade>,
+) -> Response {
+    let events = state.live.subscribe();
+    match upgrade {
+        Some(upgrade) => upgrade
+            .on_upgrade(|socket| send_to_socket(socket, events))
+            .into_response(),
+        None => {
+            let stream = BroadcastStream::new(events)
+                // A client too slow to keep up just misses seconds
+                .filter_map(Result::ok)
+                .map(|event| Event::default().event(event.name()).json_data(&event));
+            Sse::new(stream)
+                .keep_alive(KeepAlive::default())
+                .into_response()
+        }
+    }
+}
+
+async fn send_to_socket(mut socket: WebSocket, mut events: broadcast::Receiver<LiveEvent>) {
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => {
+                    let Ok(text) = serde_json::to_string(&event) else { continue };
+                    if socket.send(Message::Text(text)).await.is_err() {
+                        return;
+                    }
+                }
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return,
+            },
+            // Clients only ever close the socket
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Prometheus gauges at `/metrics`
+//!
+//! Totals for today and this week, queried from the database on each scrape,
+//! so they survive daemon restarts. Typing is labelled by app category, never
+//! by app name or window title. The daemon's own counters are served by the
+//! daemon itself (`daemon.metrics_port`).
+
+use crate::error::ApiError;
+use crate::AppState;
+use axum::{
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+};
+use fingerpain_core::db::RecordFilter;
+use fingerpain_core::metrics::TimeRange;
+use fingerpain_core::prometheus::{Exposition, MetricType, CONTENT_TYPE};
+use fingerpain_core::{AggregatedStats, CategoryStats, DomainLevel};
+
+pub const METRICS_PATH: &str = "/metrics";
+
+/// Periods reported, by their `period` label
+const PERIODS: [(&str, TimeRange); 2] =
+    [("today", TimeRange::Today), ("week", TimeRange::ThisWeek)];
+
+/// What was typed in one period
+struct Period {
+    name: &'static str,
+    stats: AggregatedStats,
+    categories: Vec<CategoryStats>,
+    sessions: u64,
+}
+
+pub async fn metrics_handler(State(state): State<AppState>) -> Result<Response, ApiError> {
+    let daemon_up = crate::daemon_call(|client| client.status()).await.is_ok();
+
+    let periods = state
+        .with_db(|state, db| {
+            let metrics =
+                state.filtered_metrics(db, RecordFilter::default(), DomainLevel::default());
+            let mut periods = Vec::new();
+            for (name, range) in PERIODS {
+                periods.push(Period {
+                    name,
+                    stats: metrics.stats(range)?,
+                    categories: metrics.category_stats(range)?,
+                    sessions: metrics.session_count(range)?,
+                });
+            }
+            Ok(periods)
+        })
+        .await?;
+
+    let text = render(&periods, daemon_up);
+    Ok(([(header::CONTENT_TYPE, CONTENT_TYPE)], text).into_response())
+}
+
+fn render(periods: &[Period], daemon_up: bool) -> String {
+    let mut out = Exposition::new();
+
+    let name = "fingerpain_characters";
+    out.family(
+        name,
+        MetricType::Gauge,
+        "Characters typed in the period, by app category",
+    );
+    for period in periods {
+        for category in &period.categories {
+            let labels = [
+                ("period", period.name),
+                ("category", category.category.as_str()),
+            ];
+            out.sample(name, &labels, category.total_chars as f64);
+        }
+    }
+    let name = "fingerpain_words";
+    out.family(
+        name,
+        MetricType::Gauge,
+        "Words typed in the period, by app category",
+    );
+    for period in periods {
+        for category in &period.categories {
+            let labels = [
+                ("period", period.name),
+                ("category", category.category.as_str()),
+            ];
+            out.sample(name, &labels, category.total_words as f64);
+        }
+    }
+
+    gauge(
+        &mut out,
+        periods,
+        "fingerpain_backspaces",
+        "Backspaces pressed in the period",
+        |p| Some(p.stats.total_backspaces as f64),
+    );
+    gauge(
+        &mut out,
+        periods,
+        "fingerpain_sessions",
+        "Typing sessions started in the period",
+        |p| Some(p.sessions as f64),
+    );
+    gauge(
+        &mut out,
+        periods,
+        "fingerpain_active_minutes",
+        "Minutes with any typing in the period",
+        |p| Some(p.stats.active_minutes as f64),
+    );
+    // Without sessions there is no speed to report
+    gauge(
+        &mut out,
+        periods,
+        "fingerpain_avg_wpm",
+        "Average WPM of the period's sessions",
+        |p| p.stats.avg_wpm,
+    );
+    gauge(
+        &mut out,
+        periods,
+        "fingerpain_peak_wpm",
+        "Highest WPM reached in the period",
+        |p| p.stats.peak_wpm,
+    );
+
+    out.single(
+        "fingerpain_daemon_up",
+        MetricType::Gauge,
+        "Whether the daemon answered over the control socket",
+        daemon_up as u8 as f64,
+    );
+    out.finish()
+}
+
+/// A gauge family with one sample per period that has a value
+fn gauge(
+    out: &mut Exposition,
+    periods: &[Period],
+    name: &str,
+    help: &str,
+    value: impl Fn(&Period) -> Option<f64>,
+) {
+    out.family(name, MetricType::Gauge, help);
+    for period in periods {
+        if let Some(value) = value(period) {
+            out.sample(name, &[("period", period.name)], value);
+        }
+    }
+}
@@ -0,0 +1,568 @@
+//! Versioned API under `/api/v1`
+//!
+//! Every endpoint takes the same parameters: a period from `start` to `end`
+//! read in the time zone `tz`, `app`, `domain` and `tag` filters, and an
+//! optional `bucket` size that also splits the period into consecutive
+//! buckets aligned to the calendar in `tz`. Answers are an [`Envelope`] with
+//! the resolved period, so clients never have to guess what "today" meant.
+//!
+//! The OpenAPI document at `/api/v1/openapi.json` is generated from these
+//! types.
+
+use crate::error::{ApiError, ApiQuery};
+use crate::AppState;
+use axum::{extract::State, response::Json, routing::get, Router};
+use chrono::{
+    DateTime, Datelike, Days, Duration, FixedOffset, LocalResult, Months, NaiveDate, NaiveDateTime,
+    NaiveTime, TimeZone, Timelike, Utc,
+};
+use chrono_tz::Tz;
+use fingerpain_core::{
+    db::RecordFilter,
+    metrics::{Metrics, TimeRange},
+    AggregatedStats, AppStats, CategoryStats, DomainLevel, DomainStats, Gap, LanguageStats,
+    PeakInfo, ProjectStats, TagStats,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::openapi::security::{Http, HttpAuthScheme, SecurityScheme};
+use utoipa::{IntoParams, Modify, OpenApi, ToSchema};
+
+/// More buckets than this is almost certainly a mistake, e.g. minutes over a year
+const MAX_BUCKETS: usize = 1000;
+
+/// Peak minutes reported per period or bucket
+const PEAK_LIMIT: usize = 10;
+
+/// Start and exclusive end
+type Span = (DateTime<Utc>, DateTime<Utc>);
+
+pub(crate) fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/api/v1/stats", get(stats))
+        .route("/api/v1/apps", get(apps))
+        .route("/api/v1/categories", get(categories))
+        .route("/api/v1/domains", get(domains))
+        .route("/api/v1/projects", get(projects))
+        .route("/api/v1/languages", get(languages))
+        .route("/api/v1/tags", get(tags))
+        .route("/api/v1/peaks", get(peaks))
+        .route("/api/v1/gaps", get(gaps))
+        .route("/api/v1/openapi.json", get(openapi))
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "FingerPain API",
+        description = "Typing statistics from the local FingerPain database"
+    ),
+    paths(stats, apps, categories, domains, projects, languages, tags, peaks, gaps),
+    components(schemas(ApiError, BucketSize, DomainLevel)),
+    modifiers(&TokenAuth),
+    security((), ("token" = []))
+)]
+struct ApiDoc;
+
+/// Tokens are only needed for reads with `web.read_auth`, or from other origins
+struct TokenAuth;
+
+impl Modify for TokenAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        openapi
+            .components
+            .get_or_insert_with(Default::default)
+            .add_security_scheme(
+                "token",
+                SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+            );
+    }
+}
+
+async fn openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// Parameters shared by every endpoint
+#[derive(Deserialize, IntoParams)]
+#[serde(deny_unknown_fields)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct V1Query {
+    /// RFC 3339 timestamp, or a date meaning its midnight in `tz`; defaults to
+    /// the start of the day the period ends in
+    start: Option<String>,
+    /// Exclusive end, in the same forms as `start`; defaults to now
+    end: Option<String>,
+    /// IANA time zone such as `Europe/Berlin`; defaults to the server's
+    tz: Option<String>,
+    /// Only typing in this app, by bundle ID or name
+    app: Option<String>,
+    /// Only typing on this domain or its subdomains
+    domain: Option<String>,
+    /// Only typing tagged with this project
+    tag: Option<String>,
+    /// Also split the period into buckets of this size
+    bucket: Option<BucketSize>,
+    /// Report browser typing per `host` or per registrable domain (`site`)
+    level: Option<DomainLevel>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum BucketSize {
+    Minute,
+    Hour,
+    Day,
+    /// Monday to Sunday
+    Week,
+    Month,
+}
+
+impl BucketSize {
+    /// Start of the bucket holding local time `t`
+    fn floor(self, t: NaiveDateTime) -> NaiveDateTime {
+        let date = t.date();
+        match self {
+            Self::Minute => t
+                .with_second(0)
+                .and_then(|t| t.with_nanosecond(0))
+                .unwrap_or(t),
+            Self::Hour => date.and_time(NaiveTime::from_hms_opt(t.hour(), 0, 0).unwrap()),
+            Self::Day => date.and_time(NaiveTime::MIN),
+            Self::Week => {
+                let monday = date - Days::new(date.weekday().num_days_from_monday().into());
+                monday.and_time(NaiveTime::MIN)
+            }
+            Self::Month => date.with_day(1).unwrap_or(date).and_time(NaiveTime::MIN),
+        }
+    }
+
+    /// Start of the bucket after the one starting at `t`
+    fn next(self, t: NaiveDateTime) -> NaiveDateTime {
+        match self {
+            Self::Minute => t + Duration::minutes(1),
+            Self::Hour => t + Duration::hours(1),
+            Self::Day => t + Days::new(1),
+            Self::Week => t + Days::new(7),
+            Self::Month => t + Months::new(1),
+        }
+    }
+}
+
+/// Answer to every endpoint
+#[derive(Serialize, ToSchema)]
+pub(crate) struct Envelope<T> {
+    period: Period,
+    filters: Filters,
+    /// The whole period
+    data: T,
+    /// Present when a `bucket` size was asked for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    buckets: Option<Vec<Bucket<T>>>,
+}
+
+/// The period as resolved from the parameters
+#[derive(Serialize, ToSchema)]
+pub(crate) struct Period {
+    /// Inclusive, with the offset of `tz`
+    start: DateTime<FixedOffset>,
+    /// Exclusive, with the offset of `tz`
+    end: DateTime<FixedOffset>,
+    tz: String,
+    bucket: Option<BucketSize>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct Filters {
+    app: Option<String>,
+    domain: Option<String>,
+    tag: Option<String>,
+}
+
+/// One slice of the period; the first and last may be partial
+#[derive(Serialize, ToSchema)]
+pub(crate) struct Bucket<T> {
+    start: DateTime<FixedOffset>,
+    end: DateTime<FixedOffset>,
+    data: T,
+}
+
+impl V1Query {
+    fn tz(&self) -> Result<Tz, ApiError> {
+        match self.tz.as_deref() {
+            Some(name) => name.parse().map_err(|_| {
+                ApiError::bad_request("invalid_tz", format!("Unknown time zone '{}'", name))
+                    .with_details(json!({ "tz": name }))
+            }),
+            None => Ok(local_tz()),
+        }
+    }
+
+    /// Start and end of the period
+    fn period(&self, tz: Tz) -> Result<Span, ApiError> {
+        let end = match &self.end {
+            Some(end) => parse_time("end", end, tz)?,
+            None => Utc::now(),
+        };
+        let start = match &self.start {
+            Some(start) => parse_time("start", start, tz)?,
+            None => {
+                let last = (end - Duration::seconds(1))
+                    .with_timezone(&tz)
+                    .naive_local();
+                to_utc(tz, BucketSize::Day.floor(last))
+            }
+        };
+        if start >= end {
+            return Err(
+                ApiError::bad_request("invalid_period", "start must be before end")
+                    .with_details(json!({ "start": start, "end": end })),
+            );
+        }
+        Ok((start, end))
+    }
+
+    fn filter(&self) -> RecordFilter {
+        RecordFilter {
+            tag: self.tag.clone(),
+            app: self.app.clone(),
+            domain: self.domain.clone(),
+        }
+    }
+}
+
+/// The server's time zone, or UTC if it can't be told
+fn local_tz() -> Tz {
+    iana_time_zone::get_timezone()
+        .ok()
+        .and_then(|name| name.parse().ok())
+        .unwrap_or(Tz::UTC)
+}
+
+fn parse_time(parameter: &str, value: &str, tz: Tz) -> Result<DateTime<Utc>, ApiError> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(to_utc(tz, date.and_time(NaiveTime::MIN)));
+    }
+    Err(ApiError::bad_request(
+        "invalid_period",
+        format!(
+            "{} must be an RFC 3339 timestamp or a YYYY-MM-DD date, not '{}'",
+            parameter, value
+        ),
+    )
+    .with_details(json!({ "parameter": parameter, "value": value })))
+}
+
+/// First instant with local time `local` in `tz`
+fn to_utc(tz: Tz, mut local: NaiveDateTime) -> DateTime<Utc> {
+    loop {
+        match tz.from_local_datetime(&local) {
+            LocalResult::Single(time) | LocalResult::Ambiguous(time, _) => {
+                return time.with_timezone(&Utc)
+            }
+            // Skipped when the clocks went forward; offsets change in quarter hours
+            LocalResult::None => local += Duration::minutes(15),
+        }
+    }
+}
+
+/// Consecutive buckets covering `[start, end)`
+fn buckets(
+    size: BucketSize,
+    tz: Tz,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<Vec<Span>, ApiError> {
+    let mut buckets = Vec::new();
+    let mut local = size.floor(start.with_timezone(&tz).naive_local());
+    let mut from = start;
+    while from < end {
+        local = size.next(local);
+        // A boundary inside a repeated hour can land before the bucket started
+        let to = to_utc(tz, local).min(end);
+        if to <= from {
+            continue;
+        }
+        if buckets.len() == MAX_BUCKETS {
+            return Err(ApiError::bad_request(
+                "too_many_buckets",
+                format!("The period holds more than {} buckets", MAX_BUCKETS),
+            )
+            .with_details(json!({ "max": MAX_BUCKETS })));
+        }
+        buckets.push((from, to));
+        from = to;
+    }
+    Ok(buckets)
+}
+
+/// Run `report` over the period and each bucket of it
+async fn answer<T: Send + 'static>(
+    state: &AppState,
+    query: V1Query,
+    report: impl Fn(&Metrics, TimeRange) -> fingerpain_core::db::Result<T> + Send + 'static,
+) -> Result<Json<Envelope<T>>, ApiError> {
+    let tz = query.tz()?;
+    let (start, end) = query.period(tz)?;
+    let slices = query
+        .bucket
+        .map(|size| buckets(size, tz, start, end))
+        .transpose()?;
+
+    let local = move |time: DateTime<Utc>| time.with_timezone(&tz).fixed_offset();
+    let (filter, level) = (query.filter(), query.level.unwrap_or_default());
+    let (data, buckets) = state
+        .with_db(move |state, db| {
+            let metrics = state.filtered_metrics(db, filter, level);
+            let data = report(&metrics, TimeRange::Custom { start, end })?;
+            let buckets = slices
+                .map(|slices| {
+                    slices
+                        .into_iter()
+                        .map(|(start, end)| {
+                            Ok(Bucket {
+                                start: local(start),
+                                end: local(end),
+                                data: report(&metrics, TimeRange::Custom { start, end })?,
+                            })
+                        })
+                        .collect::<Result<Vec<_>, ApiError>>()
+                })
+                .transpose()?;
+            Ok((data, buckets))
+        })
+        .await?;
+
+    Ok(Json(Envelope {
+        period: Period {
+            start: local(start),
+            end: local(end),
+            tz: tz.name().to_string(),
+            bucket: query.bucket,
+        },
+        filters: Filters {
+            app: query.app,
+            domain: query.domain,
+            tag: query.tag,
+        },
+        data,
+        buckets,
+    }))
+}
+
+/// Totals and typing speed
+///
+/// Speed comes from typing sessions, which can't be split by app or domain,
+/// so it is left out when filtering by either.
+#[utoipa::path(
+    get,
+    path = "/api/v1/stats",
+    params(V1Query),
+    responses(
+        (status = 200, body = Envelope<AggregatedStats>),
+        (status = 400, description = "Bad parameters", body = ApiError),
+    )
+)]
+async fn stats(
+    State(state): State<AppState>,
+    ApiQuery(query): ApiQuery<V1Query>,
+) -> Result<Json<Envelope<AggregatedStats>>, ApiError> {
+    answer(&state, query, |metrics, range| metrics.stats(range)).await
+}
+
+/// Typing per app, with browser typing broken down by domain
+#[utoipa::path(
+    get,
+    path = "/api/v1/apps",
+    params(V1Query),
+    responses(
+        (status = 200, body = Envelope<Vec<AppStats>>),
+        (status = 400, description = "Bad parameters", body = ApiError),
+    )
+)]
+async fn apps(
+    State(state): State<AppState>,
+    ApiQuery(query): ApiQuery<V1Query>,
+) -> Result<Json<Envelope<Vec<AppStats>>>, ApiError> {
+    answer(&state, query, |metrics, range| metrics.app_stats(range)).await
+}
+
+/// Typing per app category
+#[utoipa::path(
+    get,
+    path = "/api/v1/categories",
+    params(V1Query),
+    responses(
+        (status = 200, body = Envelope<Vec<CategoryStats>>),
+        (status = 400, description = "Bad parameters", body = ApiError),
+    )
+)]
+async fn categories(
+    State(state): State<AppState>,
+    ApiQuery(query): ApiQuery<V1Query>,
+) -> Result<Json<Envelope<Vec<CategoryStats>>>, ApiError> {
+    answer(&state, query, |metrics, range| {
+        metrics.category_stats(range)
+    })
+    .await
+}
+
+/// Typing per domain across all browsers
+#[utoipa::path(
+    get,
+    path = "/api/v1/domains",
+    params(V1Query),
+    responses(
+        (status = 200, body = Envelope<Vec<DomainStats>>),
+        (status = 400, description = "Bad parameters", body = ApiError),
+    )
+)]
+async fn domains(
+    State(state): State<AppState>,
+    ApiQuery(query): ApiQuery<V1Query>,
+) -> Result<Json<Envelope<Vec<DomainStats>>>, ApiError> {
+    answer(&state, query, |metrics, range| metrics.domain_stats(range)).await
+}
+
+/// Typing per project, from editor plugins or window titles
+#[utoipa::path(
+    get,
+    path = "/api/v1/projects",
+    params(V1Query),
+    responses(
+        (status = 200, body = Envelope<Vec<ProjectStats>>),
+        (status = 400, description = "Bad parameters", body = ApiError),
+    )
+)]
+async fn projects(
+    State(state): State<AppState>,
+    ApiQuery(query): ApiQuery<V1Query>,
+) -> Result<Json<Envelope<Vec<ProjectStats>>>, ApiError> {
+    answer(&state, query, |metrics, range| metrics.project_stats(range)).await
+}
+
+/// Typing per programming language reported by editor plugins
+#[utoipa::path(
+    get,
+    path = "/api/v1/languages",
+    params(V1Query),
+    responses(
+        (status = 200, body = Envelope<Vec<LanguageStats>>),
+        (status = 400, description = "Bad parameters", body = ApiError),
+    )
+)]
+async fn languages(
+    State(state): State<AppState>,
+    ApiQuery(query): ApiQuery<V1Query>,
+) -> Result<Json<Envelope<Vec<LanguageStats>>>, ApiError> {
+    answer(&state, query, |metrics, range| {
+        metrics.language_stats(range)
+    })
+    .await
+}
+
+/// Typing per project tag
+///
+/// Every tag is listed, so `tag` doesn't narrow the answer.
+#[utoipa::path(
+    get,
+    path = "/api/v1/tags",
+    params(V1Query),
+    responses(
+        (status = 200, body = Envelope<Vec<TagStats>>),
+        (status = 400, description = "Bad parameters", body = ApiError),
+    )
+)]
+async fn tags(
+    State(state): State<AppState>,
+    ApiQuery(query): ApiQuery<V1Query>,
+) -> Result<Json<Envelope<Vec<TagStats>>>, ApiError> {
+    answer(&state, query, |metrics, range| metrics.tag_stats(range)).await
+}
+
+/// Busiest minutes
+#[utoipa::path(
+    get,
+    path = "/api/v1/peaks",
+    params(V1Query),
+    responses(
+        (status = 200, body = Envelope<Vec<PeakInfo>>),
+        (status = 400, description = "Bad parameters", body = ApiError),
+    )
+)]
+async fn peaks(
+    State(state): State<AppState>,
+    ApiQuery(query): ApiQuery<V1Query>,
+) -> Result<Json<Envelope<Vec<PeakInfo>>>, ApiError> {
+    answer(&state, query, |metrics, range| {
+        metrics.peak_times(range, PEAK_LIMIT)
+    })
+    .await
+}
+
+/// Stretches during which nothing was recorded
+///
+/// Gaps belong to no app, domain or tag, so filters don't apply to them.
+#[utoipa::path(
+    get,
+    path = "/api/v1/gaps",
+    params(V1Query),
+    responses(
+        (status = 200, body = Envelope<Vec<Gap>>),
+        (status = 400, description = "Bad parameters", body = ApiError),
+    )
+)]
+async fn gaps(
+    State(state): State<AppState>,
+    ApiQuery(query): ApiQuery<V1Query>,
+) -> Result<Json<Envelope<Vec<Gap>>>, ApiError> {
+    answer(&state, query, |metrics, range| metrics.gaps(range)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_buckets_follow_the_local_calendar() {
+        let berlin: Tz = "Europe/Berlin".parse().unwrap();
+
+        // Clocks go back on 2026-10-25, so that day has 25 hours
+        let days = buckets(
+            BucketSize::Day,
+            berlin,
+            parse_time("start", "2026-10-24", berlin).unwrap(),
+            parse_time("end", "2026-10-27", berlin).unwrap(),
+        )
+        .unwrap();
+        let hours: Vec<i64> = days.iter().map(|(s, e)| (*e - *s).num_hours()).collect();
+        assert_eq!(hours, [24, 25, 24]);
+        assert_eq!(days[0].0, utc("2026-10-23T22:00:00Z"));
+
+        // Partial first and last months
+        let months = buckets(
+            BucketSize::Month,
+            berlin,
+            utc("2026-01-15T00:00:00Z"),
+            utc("2026-03-02T00:00:00Z"),
+        )
+        .unwrap();
+        assert_eq!(months.len(), 3);
+        assert_eq!(months[1].0, utc("2026-01-31T23:00:00Z"));
+
+        let too_many = buckets(
+            BucketSize::Minute,
+            berlin,
+            utc("2026-01-01T00:00:00Z"),
+            utc("2026-02-01T00:00:00Z"),
+        );
+        assert!(too_many.is_err());
+    }
+}
@@ -0,0 +1,564 @@
+//! API endpoints against a temporary database
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use chrono::Utc;
+use fingerpain_core::{Config, Database, KeystrokeRecord, UrlPolicy};
+use fingerpain_web::{AppState, LiveRelay};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tower::ServiceExt;
+
+const HOST: &str = "127.0.0.1:7890";
+
+/// Router over a database file that is removed again on drop
+struct TestApi {
+    app: Router,
+    path: PathBuf,
+    token: String,
+    pairing_code: String,
+}
+
+impl TestApi {
+    fn new() -> Self {
+        Self::with_config(&Config::default())
+    }
+
+    fn with_config(config: &Config) -> Self {
+        Self::build(config, None)
+    }
+
+    /// With the live feed taken from `live` instead of the daemon
+    fn with_live(live: LiveRelay) -> Self {
+        Self::build(&Config::default(), Some(live))
+    }
+
+    fn build(config: &Config, live: Option<LiveRelay>) -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "fingerpain-web-test-{}-{}.db",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let db = Database::open(&path).unwrap();
+        // Ranges end at the current second, exclusive
+        let mut record = KeystrokeRecord::new(Utc::now() - chrono::Duration::minutes(1));
+        record.app_name = Some("Terminal".to_string());
+        record.app_bundle_id = Some("com.apple.Terminal".to_string());
+        record.char_count = 120;
+        record.word_count = 24;
+        db.upsert_keystroke(&record).unwrap();
+        let token = db.create_api_token("tests", None).unwrap();
+        let pairing_code = db
+            .create_pairing_code(chrono::Duration::minutes(5))
+            .unwrap();
+
+        // A locked database fails fast instead of after the usual 5 seconds
+        db.set_busy_timeout(std::time::Duration::from_millis(100))
+            .unwrap();
+        let mut state = AppState::new(db, config).unwrap();
+        if let Some(live) = live {
+            state = state.with_live(live);
+        }
+        Self {
+            app: fingerpain_web::router(state),
+            path,
+            token,
+            pairing_code,
+        }
+    }
+
+    async fn send(&self, request: Request<Body>) -> (StatusCode, Value) {
+        let response = self.app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    async fn get(&self, uri: &str) -> (StatusCode, Value) {
+        let request = Request::get(uri).header("host", HOST).body(Body::empty());
+        self.send(request.unwrap()).await
+    }
+
+    async fn post(&self, uri: &str, token: Option<&str>, body: &str) -> (StatusCode, Value) {
+        let mut request = Request::post(uri)
+            .header("host", HOST)
+            .header("content-type", "application/json");
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {}", token));
+        }
+        self.send(request.body(Body::from(body.to_string())).unwrap())
+            .await
+    }
+}
+
+impl Drop for TestApi {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[tokio::test]
+async fn test_read_endpoints() {
+    let api = TestApi::new();
+
+    let (status, body) = api.get("/api/stats?range=all").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["stats"]["total_chars"], 120);
+    assert_eq!(body["range"], "all");
+
+    let (status, body) = api.get("/api/apps?range=all").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["apps"][0]["total_chars"], 120);
+
+    for (uri, key) in [
+        ("/api/stats", "stats"),
+        ("/api/categories", "categories"),
+        ("/api/projects", "projects"),
+        ("/api/languages", "languages"),
+        ("/api/domains?level=host", "domains"),
+        ("/api/hourly", "hourly"),
+        ("/api/peak", "peaks"),
+        ("/api/daily?range=7d", "data"),
+        ("/api/gaps", "gaps"),
+        ("/api/tags?range=all", "tags"),
+    ] {
+        let (status, body) = api.get(uri).await;
+        assert_eq!(status, StatusCode::OK, "{}", uri);
+        assert!(!body[key].is_null(), "{}: {}", uri, body);
+    }
+
+    let response = api
+        .app
+        .clone()
+        .oneshot(
+            Request::get("/")
+                .header("host", HOST)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_bad_parameters() {
+    let api = TestApi::new();
+
+    let (status, body) = api.get("/api/stats?range=fortnight").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "invalid_range");
+    assert_eq!(body["details"]["range"], "fortnight");
+    assert!(body["details"]["expected"]
+        .as_array()
+        .unwrap()
+        .contains(&json!("week")));
+
+    let (status, body) = api.get("/api/apps?rnage=week").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "invalid_parameter");
+    assert!(body["message"].as_str().unwrap().contains("rnage"));
+
+    let (status, body) = api.get("/api/domains?level=planet").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "invalid_parameter");
+
+    let (status, body) = api.get("/api/nothing").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "not_found");
+}
+
+#[tokio::test]
+async fn test_context_endpoints() {
+    let api = TestApi::new();
+    let tab =
+        r#"{"url": "https://github.com/x?token=secret", "title": "x", "browser_name": "Chrome"}"#;
+
+    let (status, body) = api.post("/api/browser-context", None, tab).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["code"], "unauthorized");
+
+    let (status, body) = api.post("/api/browser-context", Some("wrong"), tab).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["message"], "Invalid API token");
+
+    let (status, body) = api
+        .post("/api/browser-context", Some(&api.token), tab)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["success"], true);
+
+    let (status, body) = api
+        .post(
+            "/api/browser-context",
+            Some(&api.token),
+            r#"{"browser_name": ""}"#,
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "invalid_report");
+
+    let (status, body) = api
+        .post("/api/browser-context", Some(&api.token), "{not json")
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "invalid_body");
+
+    let editor = r#"{"editor": "neovim", "language": "rust", "project": "fingerpain"}"#;
+    let (status, _) = api
+        .post("/api/editor-context", Some(&api.token), editor)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Rejected before the daemon is asked
+    let (status, body) = api
+        .post("/api/tags/start", Some(&api.token), r#"{"tag": ""}"#)
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "invalid_tag");
+}
+
+#[tokio::test]
+async fn test_excluded_domain_context() {
+    let mut config = Config::default();
+    config.privacy.domains = vec!["bank.com".to_string()];
+    let api = TestApi::with_config(&config);
+
+    let tab = r#"{"url": "https://login.bank.com/account/42", "title": "Statement",
+        "browser_name": "Chrome", "window_id": 1}"#;
+    let (status, body) = api
+        .post("/api/browser-context", Some(&api.token), tab)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["message"], "Excluded domain");
+
+    // Only a marker that an excluded tab was focused reaches the log
+    let db = Database::open(&api.path).unwrap();
+    // The range excludes its end, which may be the millisecond just logged
+    let end = Utc::now() + chrono::Duration::seconds(1);
+    let logged = db
+        .get_browser_context_history(end - chrono::Duration::hours(1), end)
+        .unwrap();
+    assert_eq!(logged.len(), 1);
+    assert!(logged[0].excluded);
+    assert_eq!(logged[0].domain, "");
+    assert_eq!(logged[0].url, "");
+    assert_eq!(logged[0].title, "");
+}
+
+#[tokio::test]
+async fn test_url_policy_change_applies_to_reports() {
+    let api = TestApi::new();
+    let tab = r#"{"url": "https://github.com/org/repo/issues/42", "title": "Issue 42",
+        "browser_name": "Chrome", "window_id": 1}"#;
+
+    // The daemon switches to a stricter policy on a config reload
+    let db = Database::open(&api.path).unwrap();
+    let urls = db.url_redactor(UrlPolicy::Domain).unwrap();
+    db.redact_urls(&urls).unwrap();
+
+    let (status, _) = api
+        .post("/api/browser-context", Some(&api.token), tab)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let end = Utc::now() + chrono::Duration::seconds(1);
+    let logged = db
+        .get_browser_context_history(end - chrono::Duration::hours(1), end)
+        .unwrap();
+    assert_eq!(logged[0].domain, "github.com");
+    assert_eq!(logged[0].url, "");
+    assert_eq!(logged[0].title, "");
+}
+
+#[tokio::test]
+async fn test_excluded_domains_change_applies_to_reports() {
+    let api = TestApi::new();
+    let tab = r#"{"url": "https://login.bank.com/account/42", "title": "Statement",
+        "browser_name": "Chrome", "window_id": 1}"#;
+
+    // The daemon stores the domains it excludes after a config reload
+    let db = Database::open(&api.path).unwrap();
+    db.set_excluded_domains(&["bank.com".to_string()]).unwrap();
+
+    let (status, body) = api
+        .post("/api/browser-context", Some(&api.token), tab)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["message"], "Excluded domain");
+    let end = Utc::now() + chrono::Duration::seconds(1);
+    let logged = db
+        .get_browser_context_history(end - chrono::Duration::hours(1), end)
+        .unwrap();
+    assert!(logged[0].excluded);
+    assert_eq!(logged[0].domain, "");
+    assert_eq!(logged[0].url, "");
+}
+
+#[tokio::test]
+async fn test_pairing_and_origins() {
+    let api = TestApi::new();
+    let extension = "chrome-extension://abcdefghijklmnopabcdefghijklmnop";
+    let request = |method: &str, uri: &str, origin: &str, body: String| {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("host", HOST)
+            .header("origin", origin)
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+            .unwrap()
+    };
+
+    let (status, body) = api
+        .send(request("GET", "/api/stats", extension, String::new()))
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "forbidden");
+
+    let pair = json!({ "code": api.pairing_code, "name": "Chrome" }).to_string();
+    let (status, body) = api
+        .send(request("POST", "/api/pair", extension, pair))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body["token"].is_string());
+
+    let (status, _) = api
+        .send(request("GET", "/api/stats", extension, String::new()))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = api
+        .send(request(
+            "GET",
+            "/api/stats",
+            "https://evil.example.com",
+            String::new(),
+        ))
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let rebound = Request::get("/api/stats")
+        .header("host", "evil.example.com:7890")
+        .body(Body::empty())
+        .unwrap();
+    let (status, _) = api.send(rebound).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let reused = json!({ "code": api.pairing_code, "name": "Chrome" }).to_string();
+    let (status, body) = api
+        .send(request("POST", "/api/pair", extension, reused))
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["message"], "Wrong or expired pairing code");
+}
+
+#[tokio::test]
+async fn test_locked_database() {
+    let api = TestApi::new();
+
+    // Another process writing, e.g. a long export or a migration
+    let other = rusqlite::Connection::open(&api.path).unwrap();
+    other.execute_batch("BEGIN EXCLUSIVE").unwrap();
+
+    let (status, body) = api.get("/api/stats").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["code"], "database_busy");
+
+    other.execute_batch("COMMIT").unwrap();
+    let (status, _) = api.get("/api/stats").await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_v1_envelopes() {
+    let api = TestApi::new();
+
+    let (status, body) = api
+        .get("/api/v1/stats?start=2020-01-01&tz=Europe/Berlin&app=Terminal&bucket=month")
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["period"]["start"], "2020-01-01T00:00:00+01:00");
+    assert_eq!(body["period"]["tz"], "Europe/Berlin");
+    assert_eq!(body["period"]["bucket"], "month");
+    assert_eq!(body["filters"]["app"], "Terminal");
+    assert_eq!(body["data"]["total_chars"], 120);
+    let buckets = body["buckets"].as_array().unwrap();
+    assert_eq!(buckets[0]["start"], "2020-01-01T00:00:00+01:00");
+    assert_eq!(buckets[1]["start"], "2020-02-01T00:00:00+01:00");
+    let bucketed: u64 = buckets
+        .iter()
+        .map(|b| b["data"]["total_chars"].as_u64().unwrap())
+        .sum();
+    assert_eq!(bucketed, 120);
+
+    let (_, body) = api.get("/api/v1/apps?start=2020-01-01&app=Editor").await;
+    assert_eq!(body["data"], json!([]));
+    assert!(body.get("buckets").is_none());
+
+    for endpoint in [
+        "stats",
+        "apps",
+        "categories",
+        "domains",
+        "projects",
+        "languages",
+        "tags",
+        "peaks",
+        "gaps",
+    ] {
+        let uri = format!("/api/v1/{}?tz=UTC&bucket=hour", endpoint);
+        let (status, body) = api.get(&uri).await;
+        assert_eq!(status, StatusCode::OK, "{}", uri);
+        assert!(!body["data"].is_null(), "{}: {}", uri, body);
+        assert!(body["buckets"].is_array(), "{}: {}", uri, body);
+    }
+}
+
+#[tokio::test]
+async fn test_v1_bad_parameters() {
+    let api = TestApi::new();
+
+    for (uri, code) in [
+        ("/api/v1/stats?tz=Mars/Olympus", "invalid_tz"),
+        ("/api/v1/stats?start=yesterday", "invalid_period"),
+        (
+            "/api/v1/stats?start=2026-02-01&end=2026-01-01",
+            "invalid_period",
+        ),
+        (
+            "/api/v1/stats?start=2020-01-01&bucket=minute",
+            "too_many_buckets",
+        ),
+        ("/api/v1/stats?bucket=fortnight", "invalid_parameter"),
+        ("/api/v1/stats?range=week", "invalid_parameter"),
+    ] {
+        let (status, body) = api.get(uri).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+        assert_eq!(body["code"], code, "{}: {}", uri, body);
+    }
+}
+
+#[tokio::test]
+async fn test_v1_openapi_document() {
+    let api = TestApi::new();
+
+    let (status, doc) = api.get("/api/v1/openapi.json").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(doc["openapi"].as_str().unwrap().starts_with("3."));
+    let stats = &doc["paths"]["/api/v1/stats"]["get"];
+    let params: Vec<&str> = stats["parameters"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| p["name"].as_str().unwrap())
+        .collect();
+    assert!(params.contains(&"start") && params.contains(&"bucket"));
+
+    // Every referenced schema is in the document
+    let text = doc.to_string();
+    for reference in text.split("\"#/components/schemas/").skip(1) {
+        let name = &reference[..reference.find('"').unwrap()];
+        assert!(
+            !doc["components"]["schemas"][name].is_null(),
+            "missing schema {}",
+            name
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_live_events() {
+    use fingerpain_core::control::ControlError;
+    use fingerpain_core::LiveSnapshot;
+    use fingerpain_web::LiveFeed;
+    use http_body_util::BodyExt;
+
+    // A daemon that sends one snapshot and stops, then can't be reached
+    let connected = Arc::new(AtomicUsize::new(0));
+    let connects = connected.clone();
+    let live = LiveRelay::with_source(move || {
+        if connects.fetch_add(1, Ordering::SeqCst) > 0 {
+            return Err(ControlError::NotRunning);
+        }
+        let snapshot = LiveSnapshot {
+            at: Utc::now(),
+            current_app: Some("Terminal".to_string()),
+            category: None,
+            keys_last_second: 3,
+            wpm: 42.0,
+            session: None,
+            paused: false,
+            not_recording: None,
+        };
+        Ok(Box::new(std::iter::once(Ok(snapshot))) as LiveFeed)
+    });
+    let api = TestApi::with_live(live);
+
+    let request = Request::get("/api/live")
+        .header("host", HOST)
+        .body(Body::empty());
+    let response = api.app.clone().oneshot(request.unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "text/event-stream");
+
+    let mut body = response.into_body();
+    let mut events = Vec::new();
+    while events.len() < 2 {
+        let frame = tokio::time::timeout(std::time::Duration::from_secs(5), body.frame())
+            .await
+            .expect("an event within 5 seconds")
+            .unwrap()
+            .unwrap();
+        let text = String::from_utf8(frame.into_data().unwrap().to_vec()).unwrap();
+        if let Some(data) = text.lines().find_map(|line| line.strip_prefix("data: ")) {
+            events.push(serde_json::from_str::<Value>(data).unwrap());
+        }
+    }
+
+    assert_eq!(events[0]["type"], "snapshot");
+    assert_eq!(events[0]["current_app"], "Terminal");
+    assert_eq!(events[0]["keys_last_second"], 3);
+    // The feed ending is reported as the daemon going offline
+    assert_eq!(events[1]["type"], "offline");
+    assert_eq!(connected.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_metrics() {
+    let api = TestApi::new();
+    let request = Request::get("/metrics")
+        .header("host", HOST)
+        .body(Body::empty());
+    let response = api.app.clone().oneshot(request.unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain; version=0.0.4"));
+
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let text = String::from_utf8(body.to_vec()).unwrap();
+    assert!(
+        text.contains("# TYPE fingerpain_characters gauge\n"),
+        "{}",
+        text
+    );
+    assert!(
+        text.contains("fingerpain_characters{period=\"week\",category=\"Terminal\"} 120\n"),
+        "{}",
+        text
+    );
+    assert!(text.contains("fingerpain_daemon_up "), "{}", text);
+    // Labelled by category, never by app
+    assert!(!text.contains("com.apple.Terminal"), "{}", text);
+}
@@ -2,39 +2,130 @@
  * FingerPain Browser Tracker
  *
  * This service worker tracks the currently active tab and sends context updates
- * to the FingerPain daemon via the browser-context API endpoint.
+ * to the FingerPain daemon, through the native messaging host if it is installed
+ * (`fingerpain install-browser-host`) and otherwise via the browser-context API
+ * endpoint of fingerpain-web. The focused tab is repeated every minute, as the
+ * daemon stops crediting tabs it hasn't heard of for a few minutes.
+ *
+ * The same script runs in Firefox; see manifest.firefox.json.
  */
 
+const DEFAULT_API_BASE = 'http://127.0.0.1:7890';
+const NATIVE_HOST = 'com.fingerpain.browser_host';
+// How long to use HTTP after the native host failed before trying it again
+const NATIVE_RETRY_MS = 10 * 60 * 1000;
+
 let currentTabId = null;
 let currentUrl = null;
 let browserName = 'Chrome'; // Default to Chrome
+let apiBase = DEFAULT_API_BASE;
+let apiToken = '';
+// Address the native host found fingerpain-web at, used unless one was set on the options page
+let discoveredApiBase = null;
+let profileId = '';
+let nativePort = null;
+let nativeRetryAt = 0;
+// Last report sent to the native host that it hasn't answered yet
+let unconfirmed = null;
+
+// Address of fingerpain-web, set on the options page to match `[web]` in config.toml,
+// and the token it gave this extension when paired
+chrome.storage.local.get({ apiBase: DEFAULT_API_BASE, apiToken: '' }, (items) => {
+  apiBase = items.apiBase;
+  apiToken = items.apiToken;
+});
+chrome.storage.onChanged.addListener((changes, area) => {
+  if (area === 'local' && changes.apiBase) {
+    apiBase = changes.apiBase.newValue || DEFAULT_API_BASE;
+  }
+  if (area === 'local' && changes.apiToken) {
+    apiToken = changes.apiToken.newValue || '';
+  }
+});
+
+// Each profile has its own storage, so a random ID tells profiles apart
+chrome.storage.local.get({ profileId: '' }, (items) => {
+  profileId = items.profileId || crypto.randomUUID();
+  if (!items.profileId) {
+    chrome.storage.local.set({ profileId });
+  }
+});
 
-// Detect browser name from user agent
-if (navigator.userAgent.includes('Helium')) {
+// Detect browser name; these must match the names in FingerPain's browser registry
+if (navigator.userAgent.includes('Firefox')) {
+  browserName = 'Firefox';
+} else if (navigator.userAgent.includes('Helium')) {
   browserName = 'Helium';
+} else if (navigator.userAgent.includes('Edg/')) {
+  browserName = 'Edge';
+} else if (navigator.brave) {
+  browserName = 'Brave';
 }
 
 /**
- * Send context update to the local FingerPain API
+ * Send a report to the native host; false if it is known not to work
  */
-async function updateContext(url, title, browserNameOverride = null) {
-  const name = browserNameOverride || browserName;
+function sendNative(report) {
+  if (!nativePort) {
+    if (Date.now() < nativeRetryAt) {
+      return false;
+    }
+    try {
+      nativePort = chrome.runtime.connectNative(NATIVE_HOST);
+    } catch (err) {
+      nativeRetryAt = Date.now() + NATIVE_RETRY_MS;
+      return false;
+    }
+    nativePort.onMessage.addListener((reply) => {
+      unconfirmed = null;
+      if (reply.web) {
+        discoveredApiBase = reply.web;
+      }
+      if (!reply.ok) {
+        console.debug('FingerPain host:', reply.error);
+      }
+    });
+    nativePort.onDisconnect.addListener(() => {
+      nativePort = null;
+      nativeRetryAt = Date.now() + NATIVE_RETRY_MS;
+      // Not installed or gone: whatever it didn't answer goes over HTTP
+      if (unconfirmed) {
+        const report = unconfirmed;
+        unconfirmed = null;
+        sendHttp(report);
+      }
+    });
+  }
 
+  unconfirmed = report;
+  nativePort.postMessage(report);
+  return true;
+}
+
+/**
+ * Address of fingerpain-web
+ */
+function webBase() {
+  return apiBase === DEFAULT_API_BASE && discoveredApiBase ? discoveredApiBase : apiBase;
+}
+
+/**
+ * Send a report to the local FingerPain API
+ */
+async function sendHttp(report) {
   try {
-    const response = await fetch('http://127.0.0.1:7890/api/browser-context', {
+    const response = await fetch(`${webBase()}/api/browser-context`, {
       method: 'POST',
       headers: {
         'Content-Type': 'application/json',
+        'Authorization': `Bearer ${apiToken}`,
       },
-      body: JSON.stringify({
-        url: url,
-        title: title,
-        browser_name: name,
-        timestamp: Date.now(),
-      }),
+      body: JSON.stringify(report),
     });
 
-    if (!response.ok) {
+    if (response.status === 401 || response.status === 403) {
+      console.warn('FingerPain refused browser context; pair the extension on its options page');
+    } else if (!response.ok) {
       console.warn('Failed to update browser context:', response.status);
     }
   } catch (err) {
@@ -43,15 +134,56 @@ async function updateContext(url, title, browserNameOverride = null) {
   }
 }
 
+/**
+ * Send context update to the daemon
+ */
+async function updateContext(url, title, incognito = false, windowId = null, browserNameOverride = null) {
+  // Never send anything about a private window except that it is one
+  const report = {
+    url: incognito ? '' : url,
+    title: incognito ? '' : title,
+    browser_name: browserNameOverride || browserName,
+    profile: profileId,
+    window_id: windowId,
+    incognito: incognito,
+    timestamp: Date.now(),
+  };
+
+  if (!sendNative(report)) {
+    await sendHttp(report);
+  }
+}
+
+/**
+ * Report a tab if its window has focus; other windows' tabs are not being typed in
+ */
+async function reportTab(tab) {
+  const win = await chrome.windows.get(tab.windowId);
+  if (!win.focused) {
+    return;
+  }
+  currentTabId = tab.id;
+  currentUrl = tab.url;
+  await updateContext(tab.url, tab.title, tab.incognito, tab.windowId);
+}
+
+/**
+ * Report the active tab of the focused window, if the browser has focus
+ */
+async function reportFocusedTab() {
+  const tabs = await chrome.tabs.query({ active: true, lastFocusedWindow: true });
+  if (tabs.length > 0) {
+    await reportTab(tabs[0]);
+  }
+}
+
 /**
  * Handle tab activation (user switches tabs)
  */
 chrome.tabs.onActivated.addListener(async (activeInfo) => {
   try {
     const tab = await chrome.tabs.get(activeInfo.tabId);
-    currentTabId = tab.id;
-    currentUrl = tab.url;
-    await updateContext(tab.url, tab.title);
+    await reportTab(tab);
   } catch (err) {
     console.error('Error in onActivated:', err);
   }
@@ -68,22 +200,43 @@ chrome.tabs.onUpdated.addListener(async (tabId, changeInfo, tab) => {
 
   // Only process URL changes (navigation)
   if (changeInfo.url) {
-    currentUrl = changeInfo.url;
-    await updateContext(tab.url, tab.title);
+    try {
+      await reportTab(tab);
+    } catch (err) {
+      console.error('Error in onUpdated:', err);
+    }
   }
 });
 
 /**
- * Handle window focus changes
+ * Handle window focus changes, including between windows and profiles
  */
 chrome.windows.onFocusChanged.addListener(async (windowId) => {
-  if (windowId !== chrome.windows.WINDOW_ID_NONE && currentTabId) {
-    try {
-      const tab = await chrome.tabs.get(currentTabId);
-      await updateContext(tab.url, tab.title);
-    } catch (err) {
-      console.error('Error in onFocusChanged:', err);
+  if (windowId === chrome.windows.WINDOW_ID_NONE) {
+    return;
+  }
+  try {
+    const tabs = await chrome.tabs.query({ active: true, windowId });
+    if (tabs.length > 0) {
+      await reportTab(tabs[0]);
     }
+  } catch (err) {
+    console.error('Error in onFocusChanged:', err);
+  }
+});
+
+/**
+ * Repeat the focused tab so the daemon knows it is still current
+ */
+chrome.alarms.create('heartbeat', { periodInMinutes: 1 });
+chrome.alarms.onAlarm.addListener(async (alarm) => {
+  if (alarm.name !== 'heartbeat') {
+    return;
+  }
+  try {
+    await reportFocusedTab();
+  } catch (err) {
+    console.error('Error in heartbeat:', err);
   }
 });
 
@@ -92,13 +245,7 @@ chrome.windows.onFocusChanged.addListener(async (windowId) => {
  */
 chrome.runtime.onStartup.addListener(async () => {
   try {
-    const tabs = await chrome.tabs.query({ active: true, currentWindow: true });
-    if (tabs.length > 0) {
-      const tab = tabs[0];
-      currentTabId = tab.id;
-      currentUrl = tab.url;
-      await updateContext(tab.url, tab.title);
-    }
+    await reportFocusedTab();
   } catch (err) {
     console.error('Error on startup:', err);
   }
@@ -109,16 +256,35 @@ chrome.runtime.onStartup.addListener(async () => {
  */
 chrome.runtime.onInstalled.addListener(async () => {
   try {
-    const tabs = await chrome.tabs.query({ active: true, currentWindow: true });
-    if (tabs.length > 0) {
-      const tab = tabs[0];
-      currentTabId = tab.id;
-      currentUrl = tab.url;
-      await updateContext(tab.url, tab.title);
-    }
+    await reportFocusedTab();
   } catch (err) {
     console.error('Error on install:', err);
   }
 });
 
 console.log('FingerPain Browser Tracker initialized. Tracking:', browserName);
+
+/**
+ * Pair with fingerpain-web using a code from `fingerpain auth pair` (sent by the options page)
+ */
+chrome.runtime.onMessage.addListener((message, sender, sendResponse) => {
+  if (message.type !== 'pair') {
+    return false;
+  }
+  fetch(`${webBase()}/api/pair`, {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ code: message.code, name: browserName }),
+  })
+    .then(async (response) => {
+      if (!response.ok) {
+        sendResponse({ ok: false, error: response.status === 403 ? 'Wrong or expired code' : `HTTP ${response.status}` });
+        return;
+      }
+      const { token } = await response.json();
+      chrome.storage.local.set({ apiToken: token }, () => sendResponse({ ok: true }));
+    })
+    .catch(() => sendResponse({ ok: false, error: 'fingerpain-web is not running' }));
+  // The response is sent asynchronously
+  return true;
+});
@@ -0,0 +1,43 @@
+/**
+ * Options page: where to send browser context, and pairing with fingerpain-web
+ */
+
+const DEFAULT_API_BASE = 'http://127.0.0.1:7890';
+
+const input = document.getElementById('api-base');
+const status = document.getElementById('status');
+
+chrome.storage.local.get({ apiBase: DEFAULT_API_BASE }, (items) => {
+  input.value = items.apiBase;
+});
+
+document.getElementById('save').addEventListener('click', () => {
+  const apiBase = (input.value.trim() || DEFAULT_API_BASE).replace(/\/+$/, '');
+  chrome.storage.local.set({ apiBase }, () => {
+    input.value = apiBase;
+    status.textContent = ' Saved';
+    setTimeout(() => { status.textContent = ''; }, 1500);
+  });
+});
+
+const pairCode = document.getElementById('pair-code');
+const pairStatus = document.getElementById('pair-status');
+
+chrome.storage.local.get({ apiToken: '' }, (items) => {
+  pairStatus.textContent = items.apiToken ? ' Paired' : ' Not paired';
+});
+
+// The service worker pairs, as it knows the browser name to register the token under
+document.getElementById('pair').addEventListener('click', () => {
+  const code = pairCode.value.trim();
+  if (!code) {
+    return;
+  }
+  pairStatus.textContent = ' Pairing…';
+  chrome.runtime.sendMessage({ type: 'pair', code }, (reply) => {
+    pairStatus.textContent = reply && reply.ok ? ' Paired' : ` ${reply ? reply.error : 'Failed'}`;
+    if (reply && reply.ok) {
+      pairCode.value = '';
+    }
+  });
+});
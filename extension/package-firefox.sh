@@ -0,0 +1,20 @@
+#!/bin/sh
+# Assemble the Firefox variant of the extension, which shares everything but
+# the manifest with the Chromium one.
+#
+# Usage: ./package-firefox.sh [output dir]   (default: ../target/extension-firefox)
+set -eu
+
+src=$(cd "$(dirname "$0")" && pwd)
+out=${1:-"$src/../target/extension-firefox"}
+
+rm -rf "$out"
+mkdir -p "$out"
+cp -R "$src/background.js" "$src/options.html" "$src/options.js" "$src/icons" "$out/"
+cp "$src/manifest.firefox.json" "$out/manifest.json"
+
+if command -v zip >/dev/null 2>&1; then
+    (cd "$out" && zip -qr ../fingerpain-firefox.xpi .)
+    echo "Packaged $(dirname "$out")/fingerpain-firefox.xpi"
+fi
+echo "Firefox extension in $out"